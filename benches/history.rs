@@ -0,0 +1,24 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use scores_ws::bench;
+
+fn insert_and_replay(c: &mut Criterion) {
+    const LEN: u64 = 100_000;
+
+    let batches = bench::nearly_sorted(20, LEN);
+    let len = usize::try_from(LEN).unwrap();
+    assert_eq!(
+        bench::history(&batches, len),
+        bench::btreeset(&batches, len)
+    );
+
+    let mut group = c.benchmark_group("insert_and_replay");
+    group.sample_size(10);
+
+    group.bench_function("history", |b| b.iter(|| bench::history(&batches, len)));
+    group.bench_function("btreeset", |b| b.iter(|| bench::btreeset(&batches, len)));
+
+    group.finish();
+}
+
+criterion_group!(benches, insert_and_replay);
+criterion_main!(benches);
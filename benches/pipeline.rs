@@ -0,0 +1,65 @@
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use scores_ws::bench::{self, FanOut};
+use tokio::runtime::Runtime;
+
+fn deserialize(c: &mut Criterion) {
+    let mut group = c.benchmark_group("deserialize");
+
+    // The osu!api responds with up to 1000 scores
+    for len in [100, 1000, 10_000] {
+        let bytes = bench::response(len);
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+
+        group.bench_with_input(BenchmarkId::from_parameter(len), &bytes, |b, bytes| {
+            b.iter(|| bench::deserialize(bytes.clone()));
+        });
+    }
+
+    group.finish();
+}
+
+fn fan_out(c: &mut Criterion) {
+    const SCORES: u64 = 1000;
+
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("fan_out");
+    group.throughput(Throughput::Elements(SCORES));
+
+    for clients in [1, 100, 1000] {
+        // Every fourth client only receives some fields
+        for projected in [false, true] {
+            let mut fan_out = rt.block_on(FanOut::new(clients, projected, SCORES));
+            let _guard = rt.enter();
+
+            let id = if projected {
+                BenchmarkId::new("projected", clients)
+            } else {
+                BenchmarkId::new("full", clients)
+            };
+
+            // Only broadcasting is measured, not creating and receiving batches
+            group.bench_function(id, |b| {
+                b.iter_custom(|iters| {
+                    let mut elapsed = Duration::ZERO;
+
+                    for _ in 0..iters {
+                        let batch = fan_out.next_batch();
+                        let start = Instant::now();
+                        fan_out.broadcast(batch);
+                        elapsed += start.elapsed();
+                        fan_out.drain();
+                    }
+
+                    elapsed
+                });
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, deserialize, fan_out);
+criterion_main!(benches);
@@ -0,0 +1,21 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use scores_ws::bench;
+
+fn split(c: &mut Criterion) {
+    let bytes = bench::objects(1000);
+    let mut group = c.benchmark_group("split");
+    group.throughput(Throughput::Bytes(bytes.len() as u64));
+
+    group.bench_function("scalar", |b| {
+        b.iter(|| bench::split(bytes.clone(), false));
+    });
+
+    group.bench_function("simd", |b| {
+        b.iter(|| bench::split(bytes.clone(), true));
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, split);
+criterion_main!(benches);
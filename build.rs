@@ -0,0 +1,34 @@
+fn main() {
+    #[cfg(feature = "grpc")]
+    grpc();
+}
+
+/// Generates the gRPC service of `proto/scores.proto` and its file descriptor
+/// set for server reflection.
+///
+/// The proto file is parsed through `protox` so that `protoc` doesn't need to
+/// be installed.
+#[cfg(feature = "grpc")]
+fn grpc() {
+    use std::{env, fs, path::PathBuf};
+
+    use prost::Message;
+
+    println!("cargo:rerun-if-changed=proto/scores.proto");
+
+    let file_descriptors =
+        protox::compile(["scores.proto"], ["proto"]).expect("Failed to parse proto/scores.proto");
+
+    let out_dir = PathBuf::from(env::var("OUT_DIR").expect("Missing OUT_DIR"));
+
+    fs::write(
+        out_dir.join("scores_ws_descriptor.bin"),
+        file_descriptors.encode_to_vec(),
+    )
+    .expect("Failed to write file descriptor set");
+
+    tonic_build::configure()
+        .build_client(false)
+        .compile_fds(file_descriptors)
+        .expect("Failed to generate gRPC service");
+}
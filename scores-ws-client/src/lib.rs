@@ -0,0 +1,328 @@
+//! Client for [`scores-ws`] that handles the handshake, reconnects with a
+//! backoff, and resumes from the last received score so that no scores are
+//! missed in between.
+//!
+//! ```no_run
+//! use futures_util::StreamExt;
+//! use scores_ws_client::ScoresWsClient;
+//!
+//! # async fn run() {
+//! let mut scores = ScoresWsClient::connect("ws://127.0.0.1:7727")
+//!     .resume(123)
+//!     .fields(["id", "user_id", "pp"])
+//!     .filter("user.country_code", ["DE", "FR"]);
+//!
+//! while let Some(score) = scores.next().await {
+//!     println!("{}: {}", score.id(), score.as_str());
+//! }
+//! # }
+//! ```
+//!
+//! With the `serde` feature, scores can be deserialized through
+//! `Score::parse`, e.g. into the provided `model::OsuScore`.
+//!
+//! Resuming requires the `resume` operation if `scores-ws` restricts
+//! operations; otherwise the client falls back to `"connect"`.
+//!
+//! [`scores-ws`]: https://github.com/MaxOhn/scores-ws
+
+#![warn(clippy::pedantic, clippy::missing_const_for_fn)]
+
+#[cfg(feature = "serde")]
+pub mod model;
+mod score;
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures_util::{stream::BoxStream, SinkExt, Stream, StreamExt};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    tungstenite::{client::IntoClientRequest, Error, Message},
+    MaybeTlsStream, WebSocketStream,
+};
+
+pub use self::score::Score;
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_mins(1);
+
+/// Stream of scores from `scores-ws`.
+///
+/// Configure it through its methods, then poll it as [`Stream`]. The
+/// connection is only established once the stream is first polled and the
+/// stream never ends; connection errors are retried instead.
+pub struct ScoresWsClient {
+    config: Config,
+    scores: Option<BoxStream<'static, Score>>,
+}
+
+#[derive(Clone)]
+struct Config {
+    url: String,
+    key: Option<String>,
+    resume_id: Option<u64>,
+    /// Query parameters other than the key.
+    params: Vec<(String, String)>,
+}
+
+impl ScoresWsClient {
+    /// `url` is `scores-ws`' address, e.g. `ws://127.0.0.1:7727`.
+    pub fn connect(url: impl Into<String>) -> Self {
+        Self {
+            config: Config {
+                url: url.into(),
+                key: None,
+                resume_id: None,
+                params: Vec::new(),
+            },
+            scores: None,
+        }
+    }
+
+    /// Key of the `[auth]` section in `scores-ws`' config.
+    #[must_use]
+    pub fn key(mut self, key: impl Into<String>) -> Self {
+        self.config.key = Some(key.into());
+
+        self
+    }
+
+    /// Receive scores after this score id instead of only new ones.
+    #[must_use]
+    pub const fn resume(mut self, score_id: u64) -> Self {
+        self.config.resume_id = Some(score_id);
+
+        self
+    }
+
+    /// Only receive these top-level fields of scores; `id` is always
+    /// included since it's required to resume.
+    #[must_use]
+    pub fn fields<I>(self, fields: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let mut list = String::from("id");
+
+        for field in fields {
+            let field = field.as_ref();
+
+            if field != "id" {
+                list.push(',');
+                list.push_str(field);
+            }
+        }
+
+        self.param("fields", list)
+    }
+
+    /// Only receive scores whose field, nested ones separated by dots, has
+    /// one of the given values.
+    #[must_use]
+    pub fn filter<I>(self, field: &str, values: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let values = join(values);
+
+        self.param(format!("filter.{field}"), values)
+    }
+
+    /// Only receive scores of these rulesets, e.g. `"mania"`.
+    #[must_use]
+    pub fn rulesets<I>(self, rulesets: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let rulesets = join(rulesets);
+
+        self.param("rulesets", rulesets)
+    }
+
+    fn param(mut self, key: impl Into<String>, value: String) -> Self {
+        self.config.params.push((key.into(), value));
+
+        self
+    }
+}
+
+impl Stream for ScoresWsClient {
+    type Item = Score;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        this.scores
+            .get_or_insert_with(|| Connection::new(this.config.clone()).scores().boxed())
+            .poll_next_unpin(cx)
+    }
+}
+
+struct Connection {
+    config: Config,
+    ws: Option<WsStream>,
+    /// Id of the last received score.
+    last_id: Option<u64>,
+    /// Whether `scores-ws` denied resuming.
+    resume_denied: bool,
+    backoff: Duration,
+}
+
+impl Connection {
+    const fn new(config: Config) -> Self {
+        Self {
+            last_id: config.resume_id,
+            config,
+            ws: None,
+            resume_denied: false,
+            backoff: INITIAL_BACKOFF,
+        }
+    }
+
+    fn scores(self) -> impl Stream<Item = Score> {
+        futures_util::stream::unfold(self, |mut conn| async move {
+            let score = conn.next_score().await;
+
+            Some((score, conn))
+        })
+    }
+
+    async fn next_score(&mut self) -> Score {
+        loop {
+            if self.ws.is_none() {
+                let Ok(ws) = self.open().await else {
+                    self.wait().await;
+
+                    continue;
+                };
+
+                self.ws = Some(ws);
+            }
+
+            let Some(ws) = self.ws.as_mut() else {
+                continue;
+            };
+
+            match ws.next().await {
+                Some(Ok(Message::Binary(bytes))) => {
+                    let Some(score) = Score::new(bytes) else {
+                        continue;
+                    };
+
+                    self.last_id = Some(score.id());
+                    self.backoff = INITIAL_BACKOFF;
+
+                    return score;
+                }
+                Some(Ok(Message::Text(text))) => {
+                    if text.contains(r#""code":"PERMISSION_DENIED""#) {
+                        self.resume_denied = true;
+                    }
+                }
+                Some(Ok(_)) => {}
+                Some(Err(_)) | None => {
+                    self.ws = None;
+                    self.wait().await;
+                }
+            }
+        }
+    }
+
+    async fn open(&self) -> Result<WsStream, Error> {
+        let mut req = self.config.url().into_client_request()?;
+
+        if let Some(ref key) = self.config.key {
+            if let Ok(value) = format!("Bearer {key}").parse() {
+                req.headers_mut().insert("authorization", value);
+            }
+        }
+
+        let (mut ws, _) = tokio_tungstenite::connect_async(req).await?;
+
+        let initial = match self.last_id {
+            Some(id) if !self.resume_denied => id.to_string(),
+            _ => "connect".to_owned(),
+        };
+
+        ws.send(Message::from(initial)).await?;
+
+        Ok(ws)
+    }
+
+    async fn wait(&mut self) {
+        tokio::time::sleep(self.backoff).await;
+        self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+impl Config {
+    fn url(&self) -> String {
+        let mut url = self.url.clone();
+
+        // Query parameters require a path
+        let has_path = url
+            .split_once("://")
+            .is_none_or(|(_, rest)| rest.contains('/'));
+
+        if !has_path {
+            url.push('/');
+        }
+
+        for (key, value) in &self.params {
+            url.push(if url.contains('?') { '&' } else { '?' });
+            url.push_str(key);
+            url.push('=');
+            url.push_str(value);
+        }
+
+        url
+    }
+}
+
+fn join<I>(values: I) -> String
+where
+    I: IntoIterator,
+    I::Item: AsRef<str>,
+{
+    let mut joined = String::new();
+
+    for value in values {
+        if !joined.is_empty() {
+            joined.push(',');
+        }
+
+        joined.push_str(value.as_ref());
+    }
+
+    joined
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn url() {
+        let client = ScoresWsClient::connect("ws://127.0.0.1:7727/")
+            .fields(["user_id", "id", "pp"])
+            .filter("user.country_code", ["DE", "FR"])
+            .rulesets(["mania"]);
+
+        assert_eq!(
+            client.config.url(),
+            "ws://127.0.0.1:7727/?fields=id,user_id,pp&filter.user.country_code=DE,FR&rulesets=mania"
+        );
+
+        let client = ScoresWsClient::connect("ws://127.0.0.1:7727").rulesets(["osu"]);
+        assert_eq!(client.config.url(), "ws://127.0.0.1:7727/?rulesets=osu");
+    }
+}
@@ -0,0 +1,179 @@
+//! Typed model of the scores that osu! provides.
+//!
+//! Fields that are not always present, e.g. because `scores-ws` was asked to
+//! only send some fields, are optional or fall back to their default.
+
+use serde::Deserialize;
+
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Clone, Debug, Deserialize)]
+pub struct OsuScore {
+    pub id: u64,
+    #[serde(default)]
+    pub user_id: u32,
+    #[serde(default)]
+    pub beatmap_id: u32,
+    /// 0: osu, 1: taiko, 2: fruits, 3: mania
+    #[serde(default)]
+    pub ruleset_id: u8,
+    #[serde(default)]
+    pub rank: Option<Grade>,
+    #[serde(default)]
+    pub passed: bool,
+    /// Between 0 and 1.
+    #[serde(default)]
+    pub accuracy: f64,
+    #[serde(default)]
+    pub max_combo: u32,
+    #[serde(default)]
+    pub total_score: u64,
+    #[serde(default)]
+    pub legacy_total_score: Option<u64>,
+    #[serde(default)]
+    pub legacy_score_id: Option<u64>,
+    /// `None` for unranked beatmaps or if it's not calculated yet.
+    #[serde(default)]
+    pub pp: Option<f32>,
+    #[serde(default)]
+    pub mods: Vec<Mod>,
+    #[serde(default)]
+    pub statistics: Statistics,
+    #[serde(default)]
+    pub maximum_statistics: Statistics,
+    #[serde(default)]
+    pub is_perfect_combo: bool,
+    #[serde(default)]
+    pub has_replay: bool,
+    #[serde(default)]
+    pub ranked: bool,
+    /// e.g. `2025-01-09T12:34:56Z`
+    #[serde(default)]
+    pub started_at: Option<String>,
+    #[serde(default)]
+    pub ended_at: Option<String>,
+    #[serde(default)]
+    pub beatmap: Option<Beatmap>,
+    #[serde(default)]
+    pub beatmapset: Option<Beatmapset>,
+    #[serde(default)]
+    pub user: Option<User>,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Eq)]
+pub enum Grade {
+    F,
+    D,
+    C,
+    B,
+    A,
+    S,
+    SH,
+    X,
+    XH,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Mod {
+    /// e.g. `HD`
+    pub acronym: String,
+}
+
+/// Amount of hits per judgement.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Statistics {
+    pub perfect: u32,
+    pub great: u32,
+    pub good: u32,
+    pub ok: u32,
+    pub meh: u32,
+    pub miss: u32,
+    pub large_tick_hit: u32,
+    pub large_tick_miss: u32,
+    pub small_tick_hit: u32,
+    pub small_tick_miss: u32,
+    pub slider_tail_hit: u32,
+    pub large_bonus: u32,
+    pub small_bonus: u32,
+    pub ignore_hit: u32,
+    pub ignore_miss: u32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Beatmap {
+    pub id: u32,
+    #[serde(default)]
+    pub beatmapset_id: u32,
+    /// Name of the difficulty.
+    #[serde(default)]
+    pub version: String,
+    #[serde(default)]
+    pub mode: String,
+    /// e.g. `ranked` or `loved`
+    #[serde(default)]
+    pub status: String,
+    #[serde(default)]
+    pub difficulty_rating: f32,
+    #[serde(default)]
+    pub total_length: u32,
+    #[serde(default)]
+    pub checksum: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Beatmapset {
+    pub id: u32,
+    #[serde(default)]
+    pub artist: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub creator: String,
+    #[serde(default)]
+    pub user_id: u32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct User {
+    pub id: u32,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub country_code: String,
+    #[serde(default)]
+    pub avatar_url: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::de::from_slice;
+
+    use super::*;
+
+    #[test]
+    fn score() {
+        let json = br#"{"classic_total_score":123,"preserve":true,"processed":true,"ranked":true,
+            "maximum_statistics":{"great":10,"legacy_combo_increase":5},
+            "mods":[{"acronym":"HD"},{"acronym":"DT","settings":{"speed_change":1.3}}],
+            "statistics":{"great":9,"miss":1},"beatmap_id":2,"best_id":null,"id":1,"rank":"SH",
+            "type":"solo_score","user_id":3,"accuracy":0.9,"build_id":null,
+            "ended_at":"2025-01-09T12:34:56Z","has_replay":false,"is_perfect_combo":false,
+            "legacy_perfect":false,"legacy_score_id":null,"legacy_total_score":0,"max_combo":8,
+            "passed":true,"pp":null,"ruleset_id":0,"started_at":null,"total_score":456,
+            "replay":false,"current_user_attributes":{"pin":null},
+            "user":{"avatar_url":"https://a.ppy.sh/3","country_code":"DE","id":3,"username":"a"}}"#;
+
+        let score: OsuScore = from_slice(json).unwrap();
+
+        assert_eq!(score.id, 1);
+        assert_eq!(score.rank, Some(Grade::SH));
+        assert_eq!(score.mods[1].acronym, "DT");
+        assert_eq!(score.statistics.miss, 1);
+        assert_eq!(score.pp, None);
+        assert_eq!(score.user.unwrap().country_code, "DE");
+        assert!(score.beatmap.is_none());
+
+        let projected: OsuScore = from_slice(br#"{"id":4,"pp":12.5}"#).unwrap();
+        assert_eq!(projected.pp, Some(12.5));
+    }
+}
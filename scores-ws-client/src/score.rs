@@ -0,0 +1,116 @@
+use bytes::Bytes;
+
+/// A score as sent by `scores-ws`; its JSON is kept as is.
+#[derive(Clone, Debug)]
+pub struct Score {
+    id: u64,
+    bytes: Bytes,
+}
+
+impl Score {
+    /// Returns `None` if the JSON has no top-level `id`.
+    pub(crate) fn new(bytes: Bytes) -> Option<Self> {
+        let id = top_level_id(&bytes)?;
+
+        Some(Self { id, bytes })
+    }
+
+    pub const fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The score's JSON.
+    pub const fn bytes(&self) -> &Bytes {
+        &self.bytes
+    }
+
+    /// The score's JSON as string.
+    pub fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.bytes).unwrap_or_default()
+    }
+
+    pub fn into_bytes(self) -> Bytes {
+        self.bytes
+    }
+
+    /// Deserializes the score's JSON, e.g. into [`OsuScore`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the JSON does not fit `T`, e.g. because a
+    /// required field was not among the requested fields.
+    ///
+    /// [`OsuScore`]: crate::model::OsuScore
+    #[cfg(feature = "serde")]
+    pub fn parse<T: serde::de::DeserializeOwned>(&self) -> Result<T, serde_json::Error> {
+        serde_json::from_slice(&self.bytes)
+    }
+}
+
+/// The value of the `id` field of the outermost object, skipping nested
+/// objects such as `beatmap` which have an `id` of their own.
+fn top_level_id(bytes: &[u8]) -> Option<u64> {
+    const KEY: &[u8] = br#""id""#;
+
+    let mut depth = 0_usize;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (i, &byte) in bytes.iter().enumerate() {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+
+            continue;
+        }
+
+        match byte {
+            b'"' if depth == 1 && bytes[i..].starts_with(KEY) => {
+                let value = bytes[i + KEY.len()..]
+                    .trim_ascii_start()
+                    .strip_prefix(b":")?;
+                let value = value.trim_ascii_start();
+                let len = value
+                    .iter()
+                    .take_while(|byte| byte.is_ascii_digit())
+                    .count();
+
+                return std::str::from_utf8(&value[..len]).ok()?.parse().ok();
+            }
+            b'"' => in_string = true,
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' => depth = depth.checked_sub(1)?,
+            _ => {}
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn id() {
+        let bytes = br#"{"beatmap":{"id":2},"name":"\"id\":3","id": 4,"user":{"id":5}}"#;
+        assert_eq!(top_level_id(bytes), Some(4));
+
+        assert_eq!(top_level_id(br#"{"user":{"id":5}}"#), None);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn parse() {
+        let score = Score::new(Bytes::from_static(br#"{"id":4,"pp":123.4,"rank":"S"}"#)).unwrap();
+        let parsed: crate::model::OsuScore = score.parse().unwrap();
+        assert_eq!(parsed.id, 4);
+
+        let score = Score::new(Bytes::from_static(br#"{"id":4,"pp":"x"}"#)).unwrap();
+        assert!(score.parse::<crate::model::OsuScore>().is_err());
+    }
+}
@@ -0,0 +1,191 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Mutex,
+};
+
+/// Upper bound for the amount of names so that clients cannot grow the map
+/// indefinitely.
+const MAX_CURSORS: usize = 10_000;
+
+/// Upper bound for the amount of unacknowledged scores tracked per client.
+const MAX_UNACKED: usize = 100_000;
+
+/// Name of a delivery cursor, scoped to the key that the client authenticated
+/// with so that clients cannot move each other's cursors.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct CursorName {
+    name: Box<str>,
+    key: Option<Box<str>>,
+}
+
+impl CursorName {
+    /// Unscoped name; returns `None` if the name may not be used for a
+    /// cursor.
+    pub fn new(name: &str) -> Option<Self> {
+        Self::is_valid(name).then(|| Self {
+            name: Box::from(name),
+            key: None,
+        })
+    }
+
+    /// Whether the name may be used for a cursor.
+    pub fn is_valid(name: &str) -> bool {
+        (1..=64).contains(&name.len())
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    }
+
+    #[must_use]
+    pub fn with_key(mut self, key: Option<Box<str>>) -> Self {
+        self.key = key;
+
+        self
+    }
+
+    /// The name without its scope so that keys don't end up in logs.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Delivery cursors of clients in ack mode, identified by a name they choose.
+///
+/// A cursor only advances when the client acknowledged all scores up to it so
+/// that a reconnecting client is sent all scores it did not acknowledge yet.
+pub struct AckCursors {
+    cursors: Mutex<HashMap<CursorName, u64>>,
+}
+
+impl AckCursors {
+    pub fn new() -> Self {
+        Self {
+            cursors: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The id up to which all scores were acknowledged.
+    pub fn get(&self, name: &CursorName) -> Option<u64> {
+        self.cursors.lock().unwrap().get(name).copied()
+    }
+
+    /// Advances the cursor unless it's already past the given id.
+    pub fn advance(&self, name: &CursorName, score_id: u64) {
+        let mut cursors = self.cursors.lock().unwrap();
+
+        if let Some(cursor) = cursors.get_mut(name) {
+            *cursor = (*cursor).max(score_id);
+
+            return;
+        }
+
+        if cursors.len() >= MAX_CURSORS {
+            // Evict the cursor that is furthest behind
+            let oldest = cursors
+                .iter()
+                .min_by_key(|(_, &cursor)| cursor)
+                .map(|(name, _)| name.clone());
+
+            if let Some(oldest) = oldest {
+                cursors.remove(&oldest);
+            }
+        }
+
+        cursors.insert(name.clone(), score_id);
+    }
+}
+
+/// Scores that were sent to a client in ack mode but not acknowledged yet.
+///
+/// Clients may acknowledge scores in any order; the low-water mark only
+/// passes scores once all scores below them were acknowledged.
+#[derive(Default)]
+pub struct Unacked {
+    sent: BTreeSet<u64>,
+    /// Acknowledged ids above the low-water mark.
+    acked: BTreeSet<u64>,
+    /// Lowest id that was sent while too many scores were unacknowledged.
+    untracked: Option<u64>,
+}
+
+impl Unacked {
+    pub fn sent(&mut self, score_id: u64) {
+        if self.sent.len() < MAX_UNACKED {
+            self.sent.insert(score_id);
+        } else {
+            // Untracked scores can't be acknowledged so the low-water mark
+            // stays below them and they're sent again after reconnecting
+            self.untracked = Some(self.untracked.map_or(score_id, |id| id.min(score_id)));
+        }
+    }
+
+    /// Acknowledges the score and returns the new low-water mark if it
+    /// advanced. Scores that weren't sent are ignored.
+    pub fn ack(&mut self, score_id: u64) -> Option<u64> {
+        if !self.sent.remove(&score_id) {
+            return None;
+        }
+
+        self.acked.insert(score_id);
+
+        let bound = self
+            .sent
+            .first()
+            .copied()
+            .into_iter()
+            .chain(self.untracked)
+            .min();
+
+        let low = match bound {
+            Some(bound) => self.acked.range(..bound).next_back(),
+            None => self.acked.last(),
+        };
+
+        let low = *low?;
+        self.acked = self.acked.split_off(&(low + 1));
+
+        Some(low)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn advance_only() {
+        let acks = AckCursors::new();
+        let a = CursorName::new("a").unwrap();
+        assert_eq!(acks.get(&a), None);
+
+        acks.advance(&a, 5);
+        acks.advance(&a, 3);
+        assert_eq!(acks.get(&a), Some(5));
+
+        acks.advance(&a, 7);
+        assert_eq!(acks.get(&a), Some(7));
+
+        let scoped = a.clone().with_key(Some(Box::from("key")));
+        assert_eq!(acks.get(&scoped), None);
+
+        assert!(CursorName::is_valid("consumer-1"));
+        assert!(!CursorName::is_valid(""));
+        assert!(!CursorName::is_valid("a b"));
+    }
+
+    #[test]
+    fn low_water_mark() {
+        let mut unacked = Unacked::default();
+
+        for id in [1, 2, 3, 5] {
+            unacked.sent(id);
+        }
+
+        assert_eq!(unacked.ack(2), None);
+        assert_eq!(unacked.ack(4), None);
+        assert_eq!(unacked.ack(1), Some(2));
+        assert_eq!(unacked.ack(5), None);
+        assert_eq!(unacked.ack(3), Some(5));
+        assert_eq!(unacked.ack(3), None);
+    }
+}
@@ -0,0 +1,87 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::osu::Score;
+
+/// Derives `user_active` events from scores, at most one per user per window.
+pub struct ActivityTracker {
+    window: Duration,
+    /// When each user's last event was emitted.
+    last_emitted: Mutex<HashMap<u64, Instant>>,
+}
+
+impl ActivityTracker {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            last_emitted: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns events as JSON for all users whose window has passed.
+    pub fn track<'a>(&self, scores: impl Iterator<Item = &'a Score>, now: Instant) -> Vec<String> {
+        let mut last_emitted = self.last_emitted.lock().unwrap();
+
+        // Forget users whose window has passed to keep the map small
+        last_emitted.retain(|_, emitted_at| now.duration_since(*emitted_at) < self.window);
+
+        let mut events = Vec::new();
+
+        // Iterate newest scores first so that `last_score_id` is the latest
+        for score in scores.collect::<Vec<_>>().into_iter().rev() {
+            let Some(user_id) = score.user_id() else {
+                continue;
+            };
+
+            if last_emitted.contains_key(&user_id) {
+                continue;
+            }
+
+            last_emitted.insert(user_id, now);
+
+            events.push(format!(
+                r#"{{"event":"user_active","user_id":{user_id},"last_score_id":{}}}"#,
+                score.id()
+            ));
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+
+    #[test]
+    fn once_per_window() {
+        let tracker = ActivityTracker::new(Duration::from_secs(30));
+        let now = Instant::now();
+
+        let scores = [
+            Score::new(1, Bytes::from_static(br#"{"id":1,"user_id":10}"#)),
+            Score::new(2, Bytes::from_static(br#"{"id":2,"user_id":20}"#)),
+            Score::new(3, Bytes::from_static(br#"{"id":3,"user_id":10}"#)),
+        ];
+
+        let events = tracker.track(scores.iter(), now);
+        assert_eq!(
+            events,
+            [
+                r#"{"event":"user_active","user_id":10,"last_score_id":3}"#,
+                r#"{"event":"user_active","user_id":20,"last_score_id":2}"#,
+            ]
+        );
+
+        let later = now + Duration::from_secs(15);
+        assert!(tracker.track(scores.iter(), later).is_empty());
+
+        let much_later = now + Duration::from_secs(30);
+        assert_eq!(tracker.track(scores[..1].iter(), much_later).len(), 1);
+    }
+}
@@ -0,0 +1,231 @@
+use std::{borrow::Cow, convert::Infallible, future, net::SocketAddr, sync::Arc, time::Duration};
+
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::{
+    body::Incoming,
+    header::{HeaderValue, CONTENT_TYPE},
+    server::conn::http1,
+    service::service_fn,
+    Method, Request, Response, StatusCode,
+};
+use hyper_util::rt::{TokioIo, TokioTimer};
+use tokio::net::TcpListener;
+
+use crate::{context::Context, logging, osu::RULESETS, state::Phase};
+
+/// Connections that didn't send their request headers within this time are
+/// closed.
+const HEADER_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+type Body = Full<Bytes>;
+
+/// Decoded `key=value` pairs of the query string.
+type Params<'a> = [(Cow<'a, str>, Cow<'a, str>)];
+
+/// Runs a minimal HTTP server to inspect and operate `scores-ws` at runtime.
+///
+/// `GET /health` fails if a running loop did not succeed within
+/// `health_max_intervals` many of its intervals.
+pub async fn run(ctx: Arc<Context>, listener: TcpListener, health_max_intervals: u32) {
+    let mut http = http1::Builder::new();
+    http.timer(TokioTimer::new())
+        .header_read_timeout(HEADER_READ_TIMEOUT);
+
+    while let Ok((stream, addr)) = listener.accept().await {
+        let ctx = Arc::clone(&ctx);
+
+        let service = service_fn(move |req| {
+            let res = handle(&ctx, &req, addr, health_max_intervals);
+
+            future::ready(Ok::<_, Infallible>(res))
+        });
+
+        let conn = http.serve_connection(TokioIo::new(stream), service);
+
+        tokio::spawn(async move {
+            if let Err(err) = conn.await {
+                warn!(?err, %addr, "Failed to serve admin connection");
+            }
+        });
+    }
+}
+
+fn handle(
+    ctx: &Context,
+    req: &Request<Incoming>,
+    addr: SocketAddr,
+    health_max_intervals: u32,
+) -> Response<Body> {
+    let method = req.method();
+    let path = req.uri().path();
+    let query = req.uri().query().unwrap_or_default();
+
+    debug!(%addr, %method, path, query, "Admin request");
+
+    let params: Vec<_> = form_urlencoded::parse(query.as_bytes()).collect();
+
+    route(ctx, method, path, &params, health_max_intervals)
+}
+
+fn route(
+    ctx: &Context,
+    method: &Method,
+    path: &str,
+    params: &Params<'_>,
+    health_max_intervals: u32,
+) -> Response<Body> {
+    match (method, path) {
+        (&Method::GET, "/status") => json(ctx.status()),
+        (&Method::GET, "/health") => health(ctx, health_max_intervals),
+        (&Method::GET, "/clients") => json(ctx.clients_json()),
+        (&Method::GET, "/top") => json(ctx.top_json()),
+        (&Method::GET, "/legacy") => legacy_to_id(ctx, params),
+        (&Method::GET, "/loops") => json(ctx.loops().to_json()),
+        (&Method::POST, "/loops/start") => set_loop_running(ctx, params, true),
+        (&Method::POST, "/loops/stop") => set_loop_running(ctx, params, false),
+        (&Method::GET, "/state") => json(ctx.state().to_json()),
+        (&Method::POST, "/state/drain") => transition(ctx, Phase::Draining),
+        (&Method::POST, "/state/resume") => transition(ctx, Phase::Warmup),
+        (&Method::GET, "/log") => log_level(),
+        (&Method::POST, "/log") => set_log_level(params),
+        #[cfg(feature = "chaos")]
+        (&Method::GET, "/chaos") => json(crate::chaos::CHAOS.to_json()),
+        #[cfg(feature = "chaos")]
+        (&Method::POST, "/chaos") => {
+            let params = params.iter().map(|(key, value)| (&**key, &**value));
+
+            match crate::chaos::CHAOS.update(params) {
+                Ok(()) => json(crate::chaos::CHAOS.to_json()),
+                Err(err) => bad_request(err),
+            }
+        }
+        _ => not_found(),
+    }
+}
+
+fn health(ctx: &Context, max_intervals: u32) -> Response<Body> {
+    let phase = ctx.state().phase();
+    let healthy = phase.is_ready() && ctx.loops().is_healthy(max_intervals);
+    let body = format!(
+        r#"{{"healthy":{healthy},"phase":"{phase}","loops":{}}}"#,
+        ctx.loops().to_json()
+    );
+
+    let mut res = json(body);
+
+    if !healthy {
+        *res.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+    }
+
+    res
+}
+
+fn transition(ctx: &Context, to: Phase) -> Response<Body> {
+    if ctx.state().transition(to) {
+        json(ctx.state().to_json())
+    } else {
+        let from = ctx.state().phase();
+
+        bad_request(format!("Cannot transition from {from} to {to}"))
+    }
+}
+
+fn log_level() -> Response<Body> {
+    let level = logging::level().unwrap_or("off");
+
+    json(format!(r#"{{"level":"{level}"}}"#))
+}
+
+fn set_log_level(params: &Params<'_>) -> Response<Body> {
+    let Some(level) = param(params, "level") else {
+        return bad_request("Missing query parameter `level`".to_owned());
+    };
+
+    match logging::set_level(level) {
+        Ok(()) => log_level(),
+        Err(err) => bad_request(err),
+    }
+}
+
+fn legacy_to_id(ctx: &Context, params: &Params<'_>) -> Response<Body> {
+    let ruleset = param(params, "ruleset");
+    let legacy_score_id = param(params, "legacy_score_id").and_then(|id| id.parse::<u64>().ok());
+
+    let (Some(ruleset), Some(legacy_score_id)) = (ruleset, legacy_score_id) else {
+        return bad_request("Missing query parameters `ruleset` and `legacy_score_id`".to_owned());
+    };
+
+    let Some(ruleset_id) = RULESETS
+        .iter()
+        .position(|&name| name == ruleset)
+        .and_then(|idx| u8::try_from(idx).ok())
+    else {
+        return bad_request(format!("Unknown ruleset `{ruleset}`"));
+    };
+
+    match ctx.legacy_to_id(ruleset_id, legacy_score_id) {
+        Some(id) => json(format!(
+            r#"{{"id":{id},"legacy_score_id":{legacy_score_id},"ruleset":"{ruleset}"}}"#
+        )),
+        None => not_found(),
+    }
+}
+
+fn set_loop_running(ctx: &Context, params: &Params<'_>, running: bool) -> Response<Body> {
+    let Some(label) = param(params, "label") else {
+        return bad_request("Missing query parameter `label`".to_owned());
+    };
+
+    match ctx.loops().get(label) {
+        Some(handle) => {
+            handle.set_running(running);
+
+            json(handle.to_json())
+        }
+        None => not_found(),
+    }
+}
+
+/// The last value of the query parameter.
+fn param<'a>(params: &'a Params<'_>, key: &str) -> Option<&'a str> {
+    params
+        .iter()
+        .rev()
+        .find_map(|(param, value)| (param == key).then_some(&**value))
+}
+
+fn json(body: String) -> Response<Body> {
+    respond(StatusCode::OK, "application/json", body)
+}
+
+fn bad_request(body: String) -> Response<Body> {
+    respond(StatusCode::BAD_REQUEST, "text/plain", body)
+}
+
+fn not_found() -> Response<Body> {
+    respond(StatusCode::NOT_FOUND, "text/plain", "Not found".to_owned())
+}
+
+fn respond(status: StatusCode, content_type: &'static str, body: String) -> Response<Body> {
+    let mut res = Response::new(Full::from(body));
+    *res.status_mut() = status;
+    res.headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_params() {
+        let params: Vec<_> = form_urlencoded::parse(b"label=a%20b&level=info&label=c+d").collect();
+
+        assert_eq!(param(&params, "label"), Some("c d"));
+        assert_eq!(param(&params, "level"), Some("info"));
+        assert_eq!(param(&params, "ruleset"), None);
+    }
+}
@@ -0,0 +1,168 @@
+use std::{collections::HashSet, fmt::Write, sync::Mutex};
+
+use crate::osu::{Score, RULESETS};
+
+/// Rolls scores up into statistics that are emitted once per window.
+pub struct Aggregator {
+    window: Mutex<Window>,
+}
+
+#[derive(Default)]
+struct Window {
+    /// Unix timestamp in seconds of when the window started.
+    started_at: u64,
+    scores: u64,
+    rulesets: [u64; RULESETS.len()],
+    users: HashSet<u64>,
+    pps: Vec<f64>,
+    /// Id, user id, and pp of the score with the most pp.
+    top: Option<(u64, Option<u64>, f64)>,
+}
+
+impl Aggregator {
+    pub fn new(now: u64) -> Self {
+        Self {
+            window: Mutex::new(Window {
+                started_at: now,
+                ..Window::default()
+            }),
+        }
+    }
+
+    pub fn track<'a>(&self, scores: impl Iterator<Item = &'a Score>) {
+        let mut window = self.window.lock().unwrap();
+
+        for score in scores {
+            window.scores += 1;
+
+            if let Some(count) = score
+                .ruleset_id()
+                .and_then(|id| window.rulesets.get_mut(usize::from(id)))
+            {
+                *count += 1;
+            }
+
+            if let Some(user_id) = score.user_id() {
+                window.users.insert(user_id);
+            }
+
+            let Some(pp) = score.pp() else {
+                continue;
+            };
+
+            window.pps.push(pp);
+
+            if window.top.is_none_or(|(.., top_pp)| pp > top_pp) {
+                window.top = Some((score.id(), score.user_id(), pp));
+            }
+        }
+    }
+
+    /// Returns the current window's rollup as JSON and starts a new window.
+    pub fn flush(&self, now: u64) -> String {
+        let mut window = std::mem::replace(
+            &mut *self.window.lock().unwrap(),
+            Window {
+                started_at: now,
+                ..Window::default()
+            },
+        );
+
+        let mut json = format!(
+            r#"{{"type":"stats","window_start":{},"window_secs":{},"scores":{},"rulesets":{{"#,
+            window.started_at,
+            now.saturating_sub(window.started_at),
+            window.scores,
+        );
+
+        for (i, (name, count)) in RULESETS.iter().zip(window.rulesets).enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+
+            let _ = write!(json, r#""{name}":{count}"#);
+        }
+
+        let _ = write!(json, r#"}},"unique_users":{},"pp":"#, window.users.len());
+
+        window.pps.sort_unstable_by(f64::total_cmp);
+
+        if let (Some(&min), Some(&max)) = (window.pps.first(), window.pps.last()) {
+            #[allow(clippy::cast_precision_loss)]
+            let avg = window.pps.iter().sum::<f64>() / window.pps.len() as f64;
+
+            let percentile = |p: usize| window.pps[(window.pps.len() - 1) * p / 100];
+
+            let _ = write!(
+                json,
+                r#"{{"min":{min},"max":{max},"avg":{avg:.2},"p50":{},"p90":{},"p99":{}}}"#,
+                percentile(50),
+                percentile(90),
+                percentile(99),
+            );
+        } else {
+            json.push_str("null");
+        }
+
+        json.push_str(r#","top_score":"#);
+
+        match window.top {
+            Some((id, user_id, pp)) => {
+                let _ = write!(json, r#"{{"id":{id},"user_id":"#);
+
+                match user_id {
+                    Some(user_id) => {
+                        let _ = write!(json, "{user_id}");
+                    }
+                    None => json.push_str("null"),
+                }
+
+                let _ = write!(json, r#","pp":{pp}}}"#);
+            }
+            None => json.push_str("null"),
+        }
+
+        json.push('}');
+
+        json
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+
+    #[test]
+    fn rollup() {
+        let aggregator = Aggregator::new(100);
+
+        let scores = [
+            Score::new(
+                1,
+                Bytes::from_static(br#"{"id":1,"pp":100.5,"ruleset_id":0,"user_id":10}"#),
+            ),
+            Score::new(
+                2,
+                Bytes::from_static(br#"{"id":2,"pp":null,"ruleset_id":3,"user_id":10}"#),
+            ),
+            Score::new(
+                3,
+                Bytes::from_static(br#"{"id":3,"pp":300,"ruleset_id":0,"user_id":20}"#),
+            ),
+        ];
+
+        aggregator.track(scores.iter());
+
+        assert_eq!(
+            aggregator.flush(160),
+            r#"{"type":"stats","window_start":100,"window_secs":60,"scores":3,"rulesets":{"osu":2,"taiko":0,"fruits":0,"mania":1},"unique_users":2,"pp":{"min":100.5,"max":300,"avg":200.25,"p50":100.5,"p90":100.5,"p99":100.5},"top_score":{"id":3,"user_id":20,"pp":300}}"#
+        );
+
+        assert_eq!(
+            aggregator.flush(220),
+            r#"{"type":"stats","window_start":160,"window_secs":60,"scores":0,"rulesets":{"osu":0,"taiko":0,"fruits":0,"mania":0},"unique_users":0,"pp":null,"top_score":null}"#
+        );
+    }
+}
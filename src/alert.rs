@@ -0,0 +1,86 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::osu::Score;
+
+/// Picks the scores of a client subscribed to alerts, i.e. scores with at
+/// least `min_pp`.
+///
+/// Once a user's score on a beatmap was picked, their scores on the same
+/// beatmap are skipped until the window has passed so that a player grinding
+/// a map doesn't trigger a notification for each attempt.
+pub struct Alerts {
+    min_pp: f64,
+    window: Duration,
+    /// When the last alert for each user and beatmap was picked.
+    alerted: HashMap<(u64, u64), Instant>,
+}
+
+impl Alerts {
+    pub fn new(min_pp: f64, window: Duration) -> Self {
+        Self {
+            min_pp,
+            window,
+            alerted: HashMap::new(),
+        }
+    }
+
+    /// Whether the score crosses the threshold and wasn't alerted recently.
+    pub fn check(&mut self, score: &Score, now: Instant) -> bool {
+        if score.pp().is_none_or(|pp| pp < self.min_pp) {
+            return false;
+        }
+
+        let key = (
+            score.user_id().unwrap_or(0),
+            score.beatmap_id().unwrap_or(0),
+        );
+
+        if let Some(alerted_at) = self.alerted.get(&key) {
+            if now.duration_since(*alerted_at) < self.window {
+                return false;
+            }
+        }
+
+        // Alerts are rare so forgetting old ones here keeps the map small
+        self.alerted
+            .retain(|_, alerted_at| now.duration_since(*alerted_at) < self.window);
+        self.alerted.insert(key, now);
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+
+    #[test]
+    fn threshold_and_window() {
+        let mut alerts = Alerts::new(700.0, Duration::from_hours(1));
+        let now = Instant::now();
+
+        let score = |id, user_id, beatmap_id, pp| {
+            let json =
+                format!(r#"{{"id":{id},"user_id":{user_id},"beatmap_id":{beatmap_id},"pp":{pp}}}"#);
+
+            Score::new(id, Bytes::from(json))
+        };
+
+        assert!(!alerts.check(&score(1, 2, 3, 699.9), now));
+        assert!(alerts.check(&score(2, 2, 3, 700.0), now));
+        assert!(!alerts.check(&score(3, 2, 3, 750.0), now));
+        assert!(alerts.check(&score(4, 2, 4, 750.0), now));
+        assert!(alerts.check(&score(5, 5, 3, 750.0), now));
+
+        let later = now + Duration::from_hours(1);
+        assert!(alerts.check(&score(6, 2, 3, 720.0), later));
+
+        let null = Score::new(7, Bytes::from_static(br#"{"id":7,"pp":null}"#));
+        assert!(!alerts.check(&null, later));
+    }
+}
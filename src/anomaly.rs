@@ -0,0 +1,153 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+
+use crate::{config::AnomalyConfig, osu::Score};
+
+/// Users without a score for this many seconds are forgotten.
+const FORGET_AFTER_SECS: u64 = 3600;
+
+/// Flags users whose scores arrive at implausible rates or whose pp jumps
+/// far beyond what they've set before and derives `anomaly` events from them.
+///
+/// Timestamps are taken from the scores' `ended_at` because fetched batches
+/// would otherwise look like bursts.
+pub struct AnomalyDetector {
+    max_scores_per_minute: usize,
+    max_pp_jump: f64,
+    cooldown_secs: u64,
+    users: Mutex<HashMap<u64, UserState>>,
+}
+
+#[derive(Default)]
+struct UserState {
+    /// `ended_at` of the user's scores within the last minute.
+    recent: VecDeque<u64>,
+    /// Highest pp among the user's scores since they're tracked.
+    max_pp: Option<f64>,
+    rate_flagged_at: Option<u64>,
+    pp_flagged_at: Option<u64>,
+}
+
+impl AnomalyDetector {
+    pub fn new(config: &AnomalyConfig) -> Self {
+        Self {
+            max_scores_per_minute: config.max_scores_per_minute,
+            max_pp_jump: config.max_pp_jump,
+            cooldown_secs: config.cooldown_secs,
+            users: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns events as JSON for all scores that are flagged, at most one per
+    /// user and reason within the cooldown.
+    pub fn track<'a>(&self, scores: impl Iterator<Item = &'a Score>) -> Vec<String> {
+        let mut users = self.users.lock().unwrap();
+        let mut events = Vec::new();
+        let mut newest = 0;
+
+        for score in scores {
+            let (Some(user_id), Some(ended_at)) = (score.user_id(), score.ended_at()) else {
+                continue;
+            };
+
+            newest = newest.max(ended_at);
+            let user = users.entry(user_id).or_default();
+
+            user.recent.push_back(ended_at);
+            user.recent.retain(|&at| at + 60 > ended_at);
+
+            let cooled_down = |flagged_at: Option<u64>| {
+                flagged_at.is_none_or(|at| at + self.cooldown_secs <= ended_at)
+            };
+
+            if user.recent.len() > self.max_scores_per_minute && cooled_down(user.rate_flagged_at) {
+                user.rate_flagged_at = Some(ended_at);
+
+                events.push(format!(
+                    r#"{{"type":"anomaly","reason":"score_rate","user_id":{user_id},"score_id":{},"scores_per_minute":{}}}"#,
+                    score.id(),
+                    user.recent.len()
+                ));
+            }
+
+            let Some(pp) = score.pp() else {
+                continue;
+            };
+
+            if let Some(max_pp) = user.max_pp {
+                if pp - max_pp > self.max_pp_jump && cooled_down(user.pp_flagged_at) {
+                    user.pp_flagged_at = Some(ended_at);
+
+                    events.push(format!(
+                        r#"{{"type":"anomaly","reason":"pp_jump","user_id":{user_id},"score_id":{},"pp":{pp},"previous_max_pp":{max_pp}}}"#,
+                        score.id()
+                    ));
+                }
+            }
+
+            user.max_pp = Some(user.max_pp.map_or(pp, |max_pp| max_pp.max(pp)));
+        }
+
+        users.retain(|_, user| {
+            user.recent
+                .back()
+                .is_some_and(|&at| at + FORGET_AFTER_SECS > newest)
+        });
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+
+    fn score(id: u64, user_id: u64, secs: u64, pp: f64) -> Score {
+        let json = format!(
+            r#"{{"id":{id},"user_id":{user_id},"pp":{pp},"ended_at":"2025-01-09T12:{:02}:{:02}Z"}}"#,
+            secs / 60,
+            secs % 60
+        );
+
+        Score::new(id, Bytes::from(json))
+    }
+
+    #[test]
+    fn flags() {
+        let detector = AnomalyDetector::new(&AnomalyConfig {
+            max_scores_per_minute: 3,
+            max_pp_jump: 200.0,
+            cooldown_secs: 600,
+        });
+
+        // Four scores within a minute, then a fifth that's still flagged but
+        // within the cooldown
+        let scores: Vec<_> = (0..5).map(|i| score(i, 10, i * 10, 100.0)).collect();
+        let events = detector.track(scores.iter());
+        assert_eq!(
+            events,
+            [
+                r#"{"type":"anomaly","reason":"score_rate","user_id":10,"score_id":3,"scores_per_minute":4}"#
+            ]
+        );
+
+        // Spread out scores aren't flagged
+        let scores: Vec<_> = (0..5).map(|i| score(i, 20, i * 30, 100.0)).collect();
+        assert!(detector.track(scores.iter()).is_empty());
+
+        let events = detector.track([score(5, 20, 200, 350.5)].iter());
+        assert_eq!(
+            events,
+            [
+                r#"{"type":"anomaly","reason":"pp_jump","user_id":20,"score_id":5,"pp":350.5,"previous_max_pp":100}"#
+            ]
+        );
+
+        // The first score of a user has nothing to compare against
+        assert!(detector.track([score(6, 30, 0, 900.0)].iter()).is_empty());
+    }
+}
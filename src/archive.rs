@@ -0,0 +1,272 @@
+use std::{
+    io::Write,
+    sync::OnceLock,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use eyre::{Context as _, Result};
+use flate2::{write::GzEncoder, Compression};
+use http_body_util::Full;
+use hyper::{
+    header::{CONTENT_LENGTH, CONTENT_TYPE},
+    Request,
+};
+use rusty_s3::{Bucket, Credentials, S3Action, UrlStyle};
+use time::OffsetDateTime;
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    oneshot,
+};
+
+use crate::{config::ArchiveConfig, http::HttpClient};
+
+static ARCHIVE: OnceLock<Archive> = OnceLock::new();
+
+/// Amount of response bodies that may queue up before they are dropped.
+const QUEUE_LEN: usize = 64;
+const UPLOAD_ATTEMPTS: usize = 3;
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+/// Validity of the presigned url of an upload.
+const SIGNATURE_TTL: Duration = Duration::from_secs(60);
+
+/// Uploads raw api responses to S3-compatible storage in hourly gzip chunks
+/// with one response body per line.
+struct Archive {
+    tx: mpsc::Sender<Message>,
+}
+
+enum Message {
+    Body(Bytes),
+    Shutdown(oneshot::Sender<()>),
+}
+
+/// Starts archiving response bodies passed to [`push`].
+pub fn init(config: ArchiveConfig) -> Result<()> {
+    let uploader = Uploader::new(config)?;
+    let (tx, rx) = mpsc::channel(QUEUE_LEN);
+
+    info!(
+        bucket = uploader.bucket.name(),
+        prefix = uploader.prefix.as_ref(),
+        "Archiving api responses"
+    );
+
+    tokio::spawn(run(uploader, rx));
+    let _ = ARCHIVE.set(Archive { tx });
+
+    Ok(())
+}
+
+/// Archives the response body if archiving is enabled.
+pub fn push(bytes: &Bytes) {
+    let Some(archive) = ARCHIVE.get() else { return };
+
+    if let Err(TrySendError::Full(_)) = archive.tx.try_send(Message::Body(bytes.clone())) {
+        warn!("Archive is lagging behind; dropping response");
+    }
+}
+
+/// Uploads the current chunk, if any, instead of waiting for the full hour.
+pub async fn shutdown() {
+    let Some(archive) = ARCHIVE.get() else { return };
+    let (tx, rx) = oneshot::channel();
+
+    if archive.tx.send(Message::Shutdown(tx)).await.is_ok() {
+        let _ = rx.await;
+    }
+}
+
+async fn run(uploader: Uploader, mut rx: mpsc::Receiver<Message>) {
+    let mut chunk: Option<Chunk> = None;
+
+    loop {
+        let until_next_hour = Duration::from_secs(3600 - unix_secs() % 3600);
+
+        tokio::select! {
+            msg = rx.recv() => match msg {
+                Some(Message::Body(bytes)) => {
+                    let chunk = chunk.get_or_insert_with(Chunk::new);
+
+                    if let Err(err) = chunk.write(&bytes) {
+                        warn!(?err, "Failed to compress response");
+                    }
+                }
+                Some(Message::Shutdown(tx)) => {
+                    if let Some(chunk) = chunk.take() {
+                        uploader.upload(chunk, 1).await;
+                    }
+
+                    let _ = tx.send(());
+
+                    break;
+                }
+                None => break,
+            },
+            () = tokio::time::sleep(until_next_hour) => {
+                if let Some(chunk) = chunk.take() {
+                    uploader.upload(chunk, UPLOAD_ATTEMPTS).await;
+                }
+            }
+        }
+    }
+}
+
+struct Chunk {
+    gzip: GzEncoder<Vec<u8>>,
+    /// Unix timestamp of the first response.
+    started_at: u64,
+    responses: usize,
+}
+
+impl Chunk {
+    fn new() -> Self {
+        Self {
+            gzip: GzEncoder::new(Vec::new(), Compression::default()),
+            started_at: unix_secs(),
+            responses: 0,
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        // Compressing megabytes would block the runtime for too long
+        tokio::task::block_in_place(|| {
+            self.gzip.write_all(bytes)?;
+            self.gzip.write_all(b"\n")
+        })
+        .context("Failed to compress")?;
+
+        self.responses += 1;
+
+        Ok(())
+    }
+
+    /// e.g. `{prefix}/2025/01/09/12-1736426096.ndjson.gz`
+    fn key(&self, prefix: &str) -> String {
+        let started_at = i64::try_from(self.started_at)
+            .ok()
+            .and_then(|secs| OffsetDateTime::from_unix_timestamp(secs).ok())
+            .unwrap_or(OffsetDateTime::UNIX_EPOCH);
+
+        format!(
+            "{prefix}/{:04}/{:02}/{:02}/{:02}-{}.ndjson.gz",
+            started_at.year(),
+            u8::from(started_at.month()),
+            started_at.day(),
+            started_at.hour(),
+            self.started_at
+        )
+    }
+}
+
+struct Uploader {
+    bucket: Bucket,
+    credentials: Credentials,
+    prefix: Box<str>,
+    client: HttpClient,
+}
+
+impl Uploader {
+    fn new(config: ArchiveConfig) -> Result<Self> {
+        let endpoint = config
+            .endpoint
+            .parse()
+            .context("Invalid `archive.endpoint`")?;
+
+        let bucket = Bucket::new(
+            endpoint,
+            UrlStyle::Path,
+            String::from(config.bucket),
+            String::from(config.region),
+        )
+        .context("Invalid `archive.bucket`")?;
+
+        let credentials = Credentials::new(
+            String::from(config.access_key_id),
+            String::from(config.secret_access_key),
+        );
+
+        Ok(Self {
+            bucket,
+            credentials,
+            prefix: config.prefix,
+            client: HttpClient::new()?,
+        })
+    }
+
+    async fn upload(&self, chunk: Chunk, attempts: usize) {
+        let key = chunk.key(&self.prefix);
+        let responses = chunk.responses;
+
+        let body = match chunk.gzip.finish() {
+            Ok(body) => Bytes::from(body),
+            Err(err) => return error!(?err, key, responses, "Failed to finish archive chunk"),
+        };
+
+        for attempt in 1..=attempts {
+            match tokio::time::timeout(UPLOAD_TIMEOUT, self.put(&key, body.clone())).await {
+                Ok(Ok(())) => {
+                    return info!(key, responses, bytes = body.len(), "Uploaded archive chunk");
+                }
+                Ok(Err(err)) => warn!(?err, attempt, "Failed to upload archive chunk"),
+                Err(_) => warn!(attempt, "Timeout while uploading archive chunk"),
+            }
+
+            if attempt < attempts {
+                tokio::time::sleep(RETRY_DELAY).await;
+            }
+        }
+
+        error!(key, responses, "Dropping archive chunk");
+    }
+
+    async fn put(&self, key: &str, body: Bytes) -> Result<()> {
+        let url = self
+            .bucket
+            .put_object(Some(&self.credentials), key)
+            .sign(SIGNATURE_TTL);
+
+        let req = Request::put(url.as_str())
+            .header(CONTENT_TYPE, "application/gzip")
+            .header(CONTENT_LENGTH, body.len())
+            .body(Full::new(body))
+            .context("Failed to create upload request")?;
+
+        let (bytes, status) = self.client.send(req).await?;
+
+        if !status.is_success() {
+            bail!(
+                "Storage responded with {status}: {}",
+                String::from_utf8_lossy(&bytes).trim_end()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key() {
+        let chunk = Chunk {
+            gzip: GzEncoder::new(Vec::new(), Compression::default()),
+            started_at: 1_736_426_096,
+            responses: 0,
+        };
+
+        assert_eq!(
+            chunk.key("responses"),
+            "responses/2025/01/09/12-1736426096.ndjson.gz"
+        );
+    }
+}
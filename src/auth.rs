@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use tokio_tungstenite::tungstenite::http::Request;
+
+use crate::{config::AuthConfig, event::ErrorFrame};
+
+/// Operations that a client may be allowed to use.
+#[derive(Copy, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(u8)]
+pub enum Op {
+    /// Initial message `"connect"`
+    Connect = 1 << 0,
+    /// Initial message containing a score id and message `{"replay":...}`
+    Resume = 1 << 1,
+    /// Initial message `"late"`
+    Late = 1 << 2,
+    /// Message `"stats"`
+    Stats = 1 << 3,
+    /// Initial message `"user_active"`
+    UserActive = 1 << 4,
+    /// Initial message `{"subscribe":"stats"}`
+    Aggregates = 1 << 5,
+    /// Initial message `"anomalies"`
+    Anomalies = 1 << 6,
+    /// Initial message `{"subscribe":"alerts","min_pp":<pp>}`
+    Alerts = 1 << 7,
+}
+
+#[derive(Copy, Clone)]
+pub struct Permissions(u8);
+
+impl Permissions {
+    pub const ALL: Self = Self(u8::MAX);
+
+    fn new(ops: &[Op]) -> Self {
+        Self(ops.iter().fold(0, |bits, &op| bits | op as u8))
+    }
+
+    pub const fn allows(self, op: Op) -> bool {
+        self.0 & op as u8 > 0
+    }
+}
+
+/// Registry of client keys and their permissions.
+pub struct Auth {
+    /// Permissions of clients without a key; `None` if a key is required.
+    anonymous: Option<Permissions>,
+    keys: HashMap<Box<str>, Permissions>,
+}
+
+impl Auth {
+    pub fn new(config: Option<AuthConfig>) -> Self {
+        let Some(config) = config else {
+            return Self {
+                anonymous: Some(Permissions::ALL),
+                keys: HashMap::new(),
+            };
+        };
+
+        let keys = config
+            .keys
+            .into_iter()
+            .map(|key| {
+                let permissions = key
+                    .permissions
+                    .as_deref()
+                    .map_or(Permissions::ALL, Permissions::new);
+
+                (key.key, permissions)
+            })
+            .collect();
+
+        let anonymous = (!config.require_key).then(|| Permissions::new(&config.anonymous));
+
+        Self { anonymous, keys }
+    }
+
+    pub fn permissions(&self, key: Option<&str>) -> Result<Permissions, ErrorFrame> {
+        match key {
+            Some(key) => self.keys.get(key).copied().ok_or(ErrorFrame::INVALID_KEY),
+            None => self.anonymous.ok_or(ErrorFrame::AUTH_REQUIRED),
+        }
+    }
+
+    /// Extracts the key from either the `key` query parameter of the request's
+    /// uri or from its `Authorization: Bearer` header.
+    pub fn key_from_request<B>(req: &Request<B>) -> Option<Box<str>> {
+        let query_key = req.uri().query().and_then(|query| {
+            query
+                .split('&')
+                .find_map(|param| param.strip_prefix("key="))
+        });
+
+        let key = query_key.or_else(|| {
+            req.headers()
+                .get("authorization")?
+                .to_str()
+                .ok()?
+                .strip_prefix("Bearer ")
+        });
+
+        key.map(Box::from)
+    }
+}
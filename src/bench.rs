@@ -0,0 +1,195 @@
+//! Setup for the benchmarks in `benches/` of the hot paths between receiving
+//! an api response and queueing its scores for clients.
+//!
+//! Run them with `cargo bench --features bench`, adding the `simd` feature to
+//! compare the splitting of scores with and without SIMD.
+
+#![allow(clippy::missing_panics_doc, clippy::must_use_candidate)]
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tokio::sync::mpsc;
+
+use crate::{
+    auth::Permissions,
+    client::{Client, Fields, Receiver},
+    config::Setup,
+    context::Context,
+    history::History,
+    listener::Peer,
+    osu::{Score, Scores, ScoresDeserializer as Deserializer},
+    sink::Sinks,
+};
+
+/// Rough but realistic score of the osu!api.
+pub fn score(id: u64, username: &str) -> String {
+    format!(
+        r#"{{"classic_total_score":1234567,"preserve":true,"processed":true,"ranked":true,"maximum_statistics":{{"great":1000,"ignore_hit":50,"slider_tail_hit":200}},"mods":[{{"acronym":"HD"}},{{"acronym":"DT","settings":{{"speed_change":1.5}}}}],"statistics":{{"ok":10,"meh":2,"miss":1,"great":980}},"beatmap_id":123456,"best_id":null,"id":{id},"rank":"A","type":"solo_score","user_id":2,"accuracy":0.98,"ended_at":"2025-01-09T12:34:56Z","has_replay":false,"max_combo":1234,"passed":true,"pp":123.45,"ruleset_id":0,"started_at":"2025-01-09T12:30:00Z","total_score":1234567,"user":{{"avatar_url":"https://a.ppy.sh/2?1.jpeg","country_code":"DE","id":2,"is_active":true,"is_bot":false,"username":"{username}"}}}}"#
+    )
+}
+
+/// Response of the scores endpoint with `len` scores.
+pub fn response(len: u64) -> Bytes {
+    let scores: Vec<_> = (0..len)
+        .map(|id| score(id, &format!("user {id}")))
+        .collect();
+
+    Bytes::from(format!(
+        r#"{{"scores":[{}],"cursor":{{"id":{len}}},"cursor_string":"abc"}}"#,
+        scores.join(",")
+    ))
+}
+
+/// `len` scores separated by commas.
+pub fn objects(len: u64) -> Bytes {
+    let scores: Vec<_> = (0..len)
+        .map(|id| score(id, &format!("user {id}")))
+        .collect();
+
+    Bytes::from(scores.join(","))
+}
+
+/// Splits the objects through the SIMD or scalar path and returns their
+/// amount.
+#[cfg(feature = "simd")]
+pub fn split(bytes: Bytes, simd: bool) -> usize {
+    Deserializer::split_objects(bytes, simd).unwrap().len()
+}
+
+/// Deserializes the response and returns the amount of scores.
+pub fn deserialize(bytes: Bytes) -> usize {
+    let mut scores = Scores::new();
+    Deserializer::new(bytes).deserialize(&mut scores).unwrap();
+
+    scores.len()
+}
+
+/// Context whose registered clients receive every broadcasted score.
+pub struct FanOut {
+    ctx: Arc<Context>,
+    receivers: Vec<Receiver>,
+    scores: Scores,
+    round: u64,
+}
+
+impl FanOut {
+    /// Every fourth client only receives some fields if `projected`.
+    ///
+    /// Must be called within a tokio runtime.
+    pub async fn new(clients: u64, projected: bool, scores: u64) -> Self {
+        let setup: Setup = toml::from_str("interval = 1").unwrap();
+        let fields: Fields = Arc::from([Box::from("id"), Box::from("user_id")]);
+        let ctx = Arc::new(Context::new(&setup, None, None, Sinks::default()));
+
+        let (added, receivers): (Vec<Arc<Client>>, Vec<Receiver>) = (0..clients)
+            .map(|i| {
+                let (tx, rx) = mpsc::unbounded_channel();
+                let fields = (projected && i % 4 == 0).then(|| Arc::clone(&fields));
+                let client = Arc::new(Client::new(tx, Permissions::ALL, fields, None));
+                ctx.add_client(Peer::Unix(i), &client, None);
+
+                (client, rx)
+            })
+            .unzip();
+
+        // Let the clients finish replaying the empty history
+        while added.iter().any(|client| client.is_replaying()) {
+            tokio::task::yield_now().await;
+        }
+
+        let mut template = Scores::new();
+        Deserializer::new(response(scores))
+            .deserialize(&mut template)
+            .unwrap();
+
+        Self {
+            ctx,
+            receivers,
+            scores: template,
+            round: 0,
+        }
+    }
+
+    /// Scores with distinct ids per call like consecutive fetches.
+    pub fn next_batch(&mut self) -> Scores {
+        let offset = self.round * self.scores.len() as u64;
+        self.round += 1;
+
+        self.scores
+            .iter()
+            .map(|score| Score::new(offset + score.id(), score.bytes().clone()))
+            .collect()
+    }
+
+    pub fn broadcast(&self, mut batch: Scores) {
+        let first = batch.first().unwrap().clone();
+        self.ctx.broadcast(&mut batch, &first);
+    }
+
+    /// Empties the queues of all clients.
+    pub fn drain(&mut self) {
+        for rx in &mut self.receivers {
+            let mut received = 0;
+
+            while rx.try_recv().is_ok() {
+                received += 1;
+            }
+
+            assert_eq!(received, self.scores.len());
+        }
+    }
+}
+
+/// Batches of `len` scores each where one in every 50 scores arrives late.
+pub fn nearly_sorted(rounds: u64, len: u64) -> Vec<Vec<Score>> {
+    (0..rounds)
+        .map(|round| {
+            let start = round * len;
+            let mut ids: Vec<_> = (start..start + len).collect();
+
+            for chunk in ids.chunks_mut(50) {
+                chunk.rotate_left(1);
+            }
+
+            ids.into_iter()
+                .map(|id| Score::new(id, Bytes::new()))
+                .collect()
+        })
+        .collect()
+}
+
+/// Inserts the batches into a history of the given length, then replays the
+/// last batch and returns the sum of its ids.
+pub fn history(batches: &[Vec<Score>], len: usize) -> u64 {
+    let mut history = History::new(len, None);
+
+    for batch in batches {
+        history.extend(batch.iter().cloned());
+    }
+
+    let start = batches.last().unwrap().iter().map(Score::id).min().unwrap();
+
+    history
+        .snapshot()
+        .range_from(start)
+        .map(|score| score.id())
+        .sum()
+}
+
+/// Same as [`history`] but with a `BTreeSet` that is trimmed to the length.
+pub fn btreeset(batches: &[Vec<Score>], len: usize) -> u64 {
+    let mut set = Scores::new();
+
+    for batch in batches {
+        set.extend(batch.iter().cloned());
+
+        while set.len() > len {
+            set.pop_first();
+        }
+    }
+
+    let start = batches.last().unwrap().iter().map(Score::id).min().unwrap();
+
+    set.range(Score::only_id(start)..).map(Score::id).sum()
+}
@@ -0,0 +1,146 @@
+use std::{
+    io::Error as IoError,
+    sync::atomic::{AtomicU64, Ordering::Relaxed},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use eyre::Result;
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+
+pub static CHAOS: Chaos = Chaos::new();
+
+/// Fault injection to exercise the error handling of `scores-ws`.
+///
+/// All probabilities start off at `0` and can be adjusted at runtime through
+/// the admin API.
+pub struct Chaos {
+    api_failure: Probability,
+    api_latency: Probability,
+    api_latency_ms: AtomicU64,
+    truncated_body: Probability,
+    client_send_error: Probability,
+}
+
+impl Chaos {
+    const fn new() -> Self {
+        Self {
+            api_failure: Probability::zero(),
+            api_latency: Probability::zero(),
+            api_latency_ms: AtomicU64::new(1000),
+            truncated_body: Probability::zero(),
+            client_send_error: Probability::zero(),
+        }
+    }
+
+    /// Potentially delays and then fails an api request.
+    pub async fn before_request(&self) -> Result<()> {
+        if self.api_latency.roll() {
+            let ms = self.api_latency_ms.load(Relaxed);
+            warn!("Chaos: Delaying api request by {ms}ms");
+            tokio::time::sleep(Duration::from_millis(ms)).await;
+        }
+
+        if self.api_failure.roll() {
+            bail!("Chaos: Injected api failure");
+        }
+
+        Ok(())
+    }
+
+    /// Potentially cuts off the api response body at a random position.
+    pub fn response_body(&self, bytes: Bytes) -> Bytes {
+        if bytes.is_empty() || !self.truncated_body.roll() {
+            return bytes;
+        }
+
+        let len = rand::random::<usize>() % bytes.len();
+        warn!(
+            "Chaos: Truncating response body from {} to {len} bytes",
+            bytes.len()
+        );
+
+        bytes.slice(..len)
+    }
+
+    /// Potentially fails to forward a message to a client.
+    // The error type is dictated by the websocket sink
+    #[allow(clippy::result_large_err)]
+    pub fn client_send(msg: Message) -> Result<Message, WsError> {
+        if CHAOS.client_send_error.roll() {
+            warn!("Chaos: Injected client send error");
+
+            return Err(WsError::Io(IoError::other("injected client send error")));
+        }
+
+        Ok(msg)
+    }
+
+    pub fn to_json(&self) -> String {
+        format!(
+            r#"{{"api_failure":{},"api_latency":{},"api_latency_ms":{},"truncated_body":{},"client_send_error":{}}}"#,
+            self.api_failure.get(),
+            self.api_latency.get(),
+            self.api_latency_ms.load(Relaxed),
+            self.truncated_body.get(),
+            self.client_send_error.get(),
+        )
+    }
+
+    /// Applies `key=value` pairs; unmentioned settings stay the same.
+    pub fn update<'a>(
+        &self,
+        params: impl Iterator<Item = (&'a str, &'a str)>,
+    ) -> Result<(), String> {
+        for (key, value) in params {
+            let probability = match key {
+                "api_failure" => &self.api_failure,
+                "api_latency" => &self.api_latency,
+                "truncated_body" => &self.truncated_body,
+                "client_send_error" => &self.client_send_error,
+                "api_latency_ms" => {
+                    let ms = value
+                        .parse()
+                        .map_err(|_| format!("Invalid milliseconds `{value}`"))?;
+
+                    self.api_latency_ms.store(ms, Relaxed);
+
+                    continue;
+                }
+                _ => return Err(format!("Unknown chaos setting `{key}`")),
+            };
+
+            match value.parse::<f64>() {
+                Ok(p) if (0.0..=1.0).contains(&p) => probability.set(p),
+                _ => return Err(format!("`{key}` must be a probability between 0 and 1")),
+            }
+        }
+
+        info!("Chaos settings: {}", self.to_json());
+
+        Ok(())
+    }
+}
+
+/// An `f64` between `0.0` and `1.0` stored as bits.
+struct Probability(AtomicU64);
+
+impl Probability {
+    const fn zero() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Relaxed))
+    }
+
+    fn set(&self, p: f64) {
+        self.0.store(p.to_bits(), Relaxed);
+    }
+
+    fn roll(&self) -> bool {
+        let p = self.get();
+
+        p > 0.0 && rand::random::<f64>() < p
+    }
+}
@@ -0,0 +1,219 @@
+use std::fs;
+
+use eyre::{Context as _, ContextCompat, Result};
+
+use crate::{
+    config::{Config, Setup},
+    osu::Osu,
+};
+
+/// Rough size of a score as returned by the osu!api.
+const AVG_SCORE_BYTES: usize = 1500;
+
+/// Rough size of a score in the history if `setup.compress_history` is set.
+const AVG_COMPRESSED_SCORE_BYTES: usize = 500;
+
+/// The osu!api rejects requests beyond this.
+const MAX_REQUESTS_PER_MINUTE: u64 = 1200;
+
+/// The osu!api asks to stay below this.
+const RECOMMENDED_REQUESTS_PER_MINUTE: u64 = 60;
+
+/// Checks `config.toml` beyond parsing it and, with `credentials`, requests a
+/// token from the osu!api.
+///
+/// Invoked through `scores-ws check-config [--credentials]`.
+pub fn run(credentials: bool) -> Result<()> {
+    let config = Config::parse()?;
+    let report = Report::new(&config, total_memory());
+
+    for note in &report.notes {
+        println!("note: {note}");
+    }
+
+    for warning in &report.warnings {
+        println!("warning: {warning}");
+    }
+
+    for error in &report.errors {
+        println!("error: {error}");
+    }
+
+    if credentials {
+        let osu = config
+            .osu
+            .context("`--credentials` requires the section `[osu]`")?;
+
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .context("Failed to build runtime")?
+            .block_on(Osu::new(osu)?.check_credentials())
+            .context("Failed to request a token from the osu!api")?;
+
+        println!("Requested a token from the osu!api");
+    }
+
+    if !report.errors.is_empty() {
+        bail!("`config.toml` has {} error(s)", report.errors.len());
+    }
+
+    println!("`config.toml` is valid");
+
+    Ok(())
+}
+
+#[derive(Default)]
+struct Report {
+    notes: Vec<String>,
+    warnings: Vec<String>,
+    errors: Vec<String>,
+}
+
+impl Report {
+    /// `total_memory` in bytes if known.
+    fn new(config: &Config, total_memory: Option<usize>) -> Self {
+        let mut report = Self::default();
+
+        if let Some(ref osu) = config.osu {
+            let interval = config.setup.interval;
+
+            if interval == 0 {
+                report
+                    .errors
+                    .push("`setup.interval` must be positive".to_owned());
+            } else if !(15..=150).contains(&interval) {
+                report.warnings.push(format!(
+                    "`setup.interval` of {interval} seconds is outside of the recommended range 15-150"
+                ));
+            }
+
+            let requests_per_minute = match osu.users {
+                Some(ref users) => {
+                    let cycle_secs =
+                        users.ids.len() as u64 * 60 / u64::from(users.requests_per_minute.max(1));
+
+                    if cycle_secs > interval {
+                        report.warnings.push(format!(
+                            "Polling all {} users takes {cycle_secs} seconds which is longer than `setup.interval`",
+                            users.ids.len()
+                        ));
+                    }
+
+                    u64::from(users.requests_per_minute)
+                }
+                // Each fetch requests up to `concurrent_pages` pages at once
+                None => (osu.concurrent_pages as u64 * 60).div_ceil(interval.max(1)),
+            };
+
+            // Catching up on startup pages with its own pace
+            let requests_per_minute = match (config.setup.resume_score_id, &osu.users) {
+                (Some(_), None) => {
+                    requests_per_minute.max(u64::from(osu.catch_up.requests_per_minute))
+                }
+                _ => requests_per_minute,
+            };
+
+            if requests_per_minute > MAX_REQUESTS_PER_MINUTE {
+                report.errors.push(format!(
+                    "Up to {requests_per_minute} requests per minute exceed the osu!api limit of {MAX_REQUESTS_PER_MINUTE}"
+                ));
+            } else if requests_per_minute > RECOMMENDED_REQUESTS_PER_MINUTE {
+                report.warnings.push(format!(
+                    "Up to {requests_per_minute} requests per minute exceed the recommended {RECOMMENDED_REQUESTS_PER_MINUTE}"
+                ));
+            }
+        }
+
+        report.check_history(&config.setup, total_memory);
+
+        report
+    }
+
+    fn check_history(&mut self, setup: &Setup, total_memory: Option<usize>) {
+        let score_bytes = if setup.compress_history {
+            AVG_COMPRESSED_SCORE_BYTES
+        } else {
+            AVG_SCORE_BYTES
+        };
+
+        let estimate = setup.history_length.saturating_mul(score_bytes);
+        let bytes = setup
+            .history_max_bytes
+            .map_or(estimate, |max| max.min(estimate));
+
+        self.notes.push(format!(
+            "The history takes about {} MiB once full",
+            bytes / 1024 / 1024
+        ));
+
+        let Some(total) = total_memory else {
+            return;
+        };
+
+        if bytes > total {
+            self.errors.push(format!(
+                "The history exceeds the total memory of {} MiB; lower `setup.history_length` or set `setup.history_max_bytes`",
+                total / 1024 / 1024
+            ));
+        } else if bytes > total / 2 {
+            self.warnings.push(format!(
+                "The history takes more than half of the total memory of {} MiB",
+                total / 1024 / 1024
+            ));
+        }
+    }
+}
+
+/// Total memory in bytes according to `/proc/meminfo`.
+fn total_memory() -> Option<usize> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+
+    let kib: usize = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse()
+        .ok()?;
+
+    Some(kib * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report() {
+        let config = |toml: &str| toml::from_str::<Config>(toml).unwrap();
+
+        let valid = config(
+            "[setup]\ninterval = 60\nhistory_length = 1000\n\
+            [osu]\nclient_id = 1\nclient_secret = \"a\"",
+        );
+
+        let report = Report::new(&valid, Some(1 << 30));
+        assert!(report.errors.is_empty());
+        assert!(report.warnings.is_empty());
+
+        let invalid = config(
+            "[setup]\ninterval = 1\nhistory_length = 1000000\n\
+            [osu]\nclient_id = 1\nclient_secret = \"a\"\nconcurrent_pages = 8\n\
+            [osu.users]\nids = [2, 3, 4]\nrequests_per_minute = 2",
+        );
+
+        let report = Report::new(&invalid, Some(1 << 30));
+        assert_eq!(report.errors.len(), 1, "{:?}", report.errors);
+        assert_eq!(report.warnings.len(), 2, "{:?}", report.warnings);
+
+        let zero = config(
+            "[setup]\ninterval = 0\nhistory_length = 1000\n\
+            [osu]\nclient_id = 1\nclient_secret = \"a\"",
+        );
+
+        let report = Report::new(&zero, Some(1 << 30));
+        assert!(report.errors[0].contains("`setup.interval`"));
+    }
+}
@@ -0,0 +1,177 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use crate::{daemon::Daemon, scaffold::Language};
+
+/// Fetches osu! scores and forwards them through websockets.
+#[derive(Parser)]
+#[command(version, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    serve: ServeArgs,
+}
+
+impl Cli {
+    /// Arguments without a command, e.g. only options, are those of `serve`.
+    pub fn into_command(self) -> Command {
+        self.command.unwrap_or(Command::Serve(self.serve))
+    }
+}
+
+/// What the binary should do based on its arguments.
+#[derive(Subcommand)]
+pub enum Command {
+    /// Fetch scores and serve them to clients; the default command
+    Serve(ServeArgs),
+    /// Check `config.toml` without starting
+    #[command(alias = "validate-config")]
+    CheckConfig {
+        /// Also request a token from the osu!api
+        #[arg(long)]
+        credentials: bool,
+    },
+    /// Hand the scores of an NDJSON file to the configured sinks
+    Replay {
+        file: PathBuf,
+        /// Only hand them to this sink instead of all; may be repeated
+        #[arg(long = "sink", value_name = "NAME")]
+        sinks: Vec<Box<str>>,
+    },
+    /// Hand the scores of the dead-letter file to their sinks again
+    Redeliver,
+    /// Generate a consumer project
+    Scaffold {
+        language: Language,
+        /// Directory to generate the project in
+        #[arg(long, default_value = "scores-ws-consumer")]
+        out: PathBuf,
+        /// Comma separated fields that the consumer receives; must include `id`
+        #[arg(long, value_name = "A,B,...")]
+        fields: Option<String>,
+    },
+    /// Install or uninstall the Windows service
+    Service {
+        action: ServiceAction,
+        /// Name of the service
+        #[arg(long, default_value = "scores-ws")]
+        name: String,
+        /// Working directory of the service, i.e. the one that contains
+        /// `config.toml`; passed on by `install`
+        #[arg(long)]
+        dir: Option<PathBuf>,
+    },
+}
+
+#[derive(Args)]
+pub struct ServeArgs {
+    /// Show a live dashboard instead of the logs
+    #[arg(long, conflicts_with = "daemon")]
+    pub tui: bool,
+    /// Run in the background; unix only
+    #[arg(long)]
+    daemon: bool,
+    /// Where `--daemon` writes its pid
+    #[arg(long, requires = "daemon", default_value = "scores-ws.pid")]
+    pid_file: PathBuf,
+    /// Where `--daemon` writes stdout and stderr
+    #[arg(long, requires = "daemon", default_value = "scores-ws.out")]
+    out_file: PathBuf,
+}
+
+impl ServeArgs {
+    /// Returns `None` if `--daemon` isn't specified.
+    pub fn daemon(self) -> Option<Daemon> {
+        self.daemon
+            .then(|| Daemon::new(self.pid_file, self.out_file))
+    }
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+pub enum ServiceAction {
+    Install,
+    Uninstall,
+    /// Only meant for the service manager
+    #[value(hide = true)]
+    Run,
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn commands() {
+        <Cli as CommandFactory>::command().debug_assert();
+
+        let parse = |args: &[&str]| {
+            Cli::try_parse_from(std::iter::once("scores-ws").chain(args.iter().copied()))
+                .map(Cli::into_command)
+        };
+
+        assert!(matches!(
+            parse(&[]),
+            Ok(Command::Serve(ServeArgs {
+                tui: false,
+                daemon: false,
+                ..
+            }))
+        ));
+        assert!(matches!(
+            parse(&["--tui"]),
+            Ok(Command::Serve(ServeArgs { tui: true, .. }))
+        ));
+
+        match parse(&["serve", "--pid-file", "a.pid", "--daemon"]) {
+            Ok(Command::Serve(args)) => {
+                assert_eq!(args.pid_file, PathBuf::from("a.pid"));
+                assert_eq!(args.out_file, PathBuf::from("scores-ws.out"));
+                assert!(args.daemon().is_some());
+            }
+            _ => panic!("expected serve"),
+        }
+
+        assert!(parse(&["serve", "--tui", "--daemon"]).is_err());
+        assert!(parse(&["--pid-file", "a.pid"]).is_err());
+        assert!(matches!(
+            parse(&["check-config"]),
+            Ok(Command::CheckConfig { credentials: false })
+        ));
+        assert!(matches!(
+            parse(&["validate-config", "--credentials"]),
+            Ok(Command::CheckConfig { credentials: true })
+        ));
+        assert!(parse(&["check-config", "x"]).is_err());
+
+        match parse(&["replay", "scores.ndjson", "--sink", "postgres"]) {
+            Ok(Command::Replay { file, sinks }) => {
+                assert_eq!(file, PathBuf::from("scores.ndjson"));
+                assert_eq!(sinks, [Box::from("postgres")]);
+            }
+            _ => panic!("expected replay"),
+        }
+
+        assert!(parse(&["replay"]).is_err());
+        assert!(matches!(
+            parse(&["scaffold", "python", "--fields", "id,pp"]),
+            Ok(Command::Scaffold {
+                language: Language::Python,
+                fields: Some(_),
+                ..
+            })
+        ));
+        assert!(parse(&["scaffold", "java"]).is_err());
+        assert!(matches!(
+            parse(&["service", "install", "--name", "scores"]),
+            Ok(Command::Service {
+                action: ServiceAction::Install,
+                ..
+            })
+        ));
+        assert!(parse(&["unknown"]).is_err());
+    }
+}
@@ -0,0 +1,921 @@
+use std::{
+    sync::{
+        atomic::{
+            AtomicBool, AtomicU64, AtomicU8, AtomicUsize,
+            Ordering::{Acquire, Relaxed, Release},
+        },
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::{
+    ack::Unacked,
+    alert::Alerts,
+    auth::Permissions,
+    filter::{Beatmaps, Condition, Filter},
+    history::Snapshot,
+    limiter::TokenBucket,
+    mods::ModUpdate,
+    osu::{Score, RULESETS},
+};
+
+pub type Sender = mpsc::UnboundedSender<Message>;
+pub type Receiver = mpsc::UnboundedReceiver<Message>;
+
+/// Where a score that is sent to a client comes from.
+#[derive(Copy, Clone)]
+pub enum Origin {
+    /// Replayed from the history
+    History,
+    /// Broadcasted as soon as it was fetched
+    Live,
+}
+
+impl Origin {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::History => "history",
+            Self::Live => "live",
+        }
+    }
+}
+
+/// Top-level score fields that a client is interested in.
+///
+/// Shared so that clients with the same fields can be recognized cheaply.
+pub type Fields = Arc<[Box<str>]>;
+
+/// Kinds of messages a client can be subscribed to.
+#[derive(Copy, Clone)]
+#[repr(u8)]
+pub enum Topic {
+    /// Regular scores
+    Scores = 1 << 0,
+    /// Scores whose `ended_at` was too far in the past when they were fetched
+    Late = 1 << 1,
+    /// `user_active` events derived from regular scores
+    UserActive = 1 << 2,
+    /// Rollups of regular scores per window
+    Aggregates = 1 << 3,
+    /// `anomaly` events derived from regular scores
+    Anomalies = 1 << 4,
+    /// Regular scores that cross a client's pp threshold
+    Alerts = 1 << 5,
+}
+
+/// Handle to a connected websocket client.
+///
+/// Messages are queued through the channel and forwarded to the websocket by
+/// the client's own task. Keeping track of how many messages are currently
+/// queued lets us tell how far behind a client is.
+pub struct Client {
+    tx: Sender,
+    queued: AtomicUsize,
+    /// Highest amount of messages that were queued at once.
+    max_queued: AtomicUsize,
+    /// Total size of the queued messages.
+    queued_bytes: AtomicUsize,
+    sent_scores: AtomicU64,
+    connected_at: Instant,
+    topics: AtomicU8,
+    permissions: Permissions,
+    /// Only these fields are sent if specified.
+    fields: RwLock<Option<Fields>>,
+    /// Scores are sent only after this delay if specified.
+    delay: Option<Duration>,
+    /// Sequence number of the next delayed score to send.
+    next_seq: AtomicU64,
+    /// Id of the last score that was queued; `0` if there was none.
+    last_score_id: AtomicU64,
+    /// Id of the last checkpoint that was queued; `0` if there was none.
+    checkpoint_id: AtomicU64,
+    /// Only scores of users in this partition are sent if specified.
+    partition: RwLock<Option<Partition>>,
+    /// Only scores that match all of its conditions are sent.
+    filter: RwLock<Filter>,
+    /// Only this share of scores is sent if specified.
+    sample: RwLock<Option<Sample>>,
+    /// At most this many scores are sent per second if specified.
+    rate_cap: Mutex<Option<RateCap>>,
+    rulesets: AtomicU8,
+    /// Whether the history is currently being replayed to the client.
+    replaying: AtomicBool,
+    /// Scores that were broadcasted while the history was replayed.
+    pending: Mutex<Vec<Score>>,
+    /// Threshold and recent alerts if subscribed to alerts.
+    alerts: Mutex<Option<Alerts>>,
+    /// Identity of the consumer through the query parameter `client_name`.
+    name: Option<Box<str>>,
+    /// Whether scores are only sent in increasing order of their id.
+    ordered: bool,
+    /// Whether scores are wrapped with metadata.
+    meta: bool,
+    /// Sequence number of the last score that was sent with metadata.
+    meta_seq: AtomicU64,
+    /// Whether the history is replayed as a single compressed blob.
+    compressed_replay: bool,
+    /// Sent scores that weren't acknowledged yet if in ack mode.
+    unacked: Option<Mutex<Unacked>>,
+}
+
+impl Client {
+    pub fn new(
+        tx: Sender,
+        permissions: Permissions,
+        fields: Option<Fields>,
+        delay: Option<Duration>,
+    ) -> Self {
+        Self {
+            tx,
+            queued: AtomicUsize::new(0),
+            max_queued: AtomicUsize::new(0),
+            queued_bytes: AtomicUsize::new(0),
+            sent_scores: AtomicU64::new(0),
+            connected_at: Instant::now(),
+            topics: AtomicU8::new(Topic::Scores as u8),
+            permissions,
+            fields: RwLock::new(fields),
+            delay,
+            next_seq: AtomicU64::new(0),
+            last_score_id: AtomicU64::new(0),
+            checkpoint_id: AtomicU64::new(0),
+            partition: RwLock::new(None),
+            filter: RwLock::new(Filter::default()),
+            sample: RwLock::new(None),
+            rate_cap: Mutex::new(None),
+            rulesets: AtomicU8::new(Rulesets::ALL.0),
+            replaying: AtomicBool::new(false),
+            pending: Mutex::new(Vec::new()),
+            alerts: Mutex::new(None),
+            name: None,
+            ordered: false,
+            meta: false,
+            meta_seq: AtomicU64::new(0),
+            compressed_replay: false,
+            unacked: None,
+        }
+    }
+
+    #[must_use]
+    pub fn with_name(mut self, name: Option<Box<str>>) -> Self {
+        self.name = name;
+
+        self
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    #[must_use]
+    pub const fn with_ordered(mut self, ordered: bool) -> Self {
+        self.ordered = ordered;
+
+        self
+    }
+
+    #[must_use]
+    pub const fn with_meta(mut self, meta: bool) -> Self {
+        self.meta = meta;
+
+        self
+    }
+
+    #[must_use]
+    pub const fn with_compressed_replay(mut self, compressed_replay: bool) -> Self {
+        self.compressed_replay = compressed_replay;
+
+        self
+    }
+
+    pub const fn compressed_replay(&self) -> bool {
+        self.compressed_replay
+    }
+
+    #[must_use]
+    pub fn with_ack(mut self, ack: bool) -> Self {
+        self.unacked = ack.then(Mutex::default);
+
+        self
+    }
+
+    /// Acknowledges the score and returns the id up to which all sent scores
+    /// were acknowledged if it advanced.
+    pub fn ack(&self, score_id: u64) -> Option<u64> {
+        self.unacked.as_ref()?.lock().unwrap().ack(score_id)
+    }
+
+    pub const fn is_ordered(&self) -> bool {
+        self.ordered
+    }
+
+    pub const fn delay(&self) -> Option<Duration> {
+        self.delay
+    }
+
+    pub fn next_seq(&self) -> u64 {
+        self.next_seq.load(Relaxed)
+    }
+
+    pub fn set_next_seq(&self, seq: u64) {
+        self.next_seq.store(seq, Relaxed);
+    }
+
+    pub const fn permissions(&self) -> Permissions {
+        self.permissions
+    }
+
+    pub fn is_subscribed(&self, topic: Topic) -> bool {
+        self.topics.load(Relaxed) & topic as u8 > 0
+    }
+
+    /// Replaces all current subscriptions with the given topic.
+    pub fn subscribe_only(&self, topic: Topic) {
+        self.topics.store(topic as u8, Relaxed);
+    }
+
+    pub fn send(&self, msg: Message) {
+        let len = msg.len();
+
+        if self.tx.send(msg).is_ok() {
+            let queued = self.queued.fetch_add(1, Relaxed) + 1;
+            self.max_queued.fetch_max(queued, Relaxed);
+            self.queued_bytes.fetch_add(len, Relaxed);
+        }
+    }
+
+    /// Amount of messages that are queued but weren't sent yet.
+    pub fn lag(&self) -> usize {
+        self.queued.load(Relaxed)
+    }
+
+    pub fn max_lag(&self) -> usize {
+        self.max_queued.load(Relaxed)
+    }
+
+    /// Approximate bytes held by messages that weren't sent yet.
+    pub fn queued_bytes(&self) -> usize {
+        self.queued_bytes.load(Relaxed)
+    }
+
+    pub fn sent_scores(&self) -> u64 {
+        self.sent_scores.load(Relaxed)
+    }
+
+    /// Sends the score, projected onto the client's fields if specified.
+    ///
+    /// Unprojected scores share their bytes with all clients anyway and
+    /// projections are shared with clients that have the same fields.
+    ///
+    /// While the history is being replayed, scores are held back until the
+    /// replay finished so that the client receives them in order.
+    pub fn send_score(&self, score: &Score, projections: &mut Projections) {
+        if self.replaying.load(Acquire) {
+            let mut pending = self.pending.lock().unwrap();
+
+            // Check again in case the replay finished meanwhile
+            if self.replaying.load(Acquire) {
+                return pending.push(score.clone());
+            }
+        }
+
+        self.send_ordered(score, projections, Origin::Live);
+    }
+
+    /// Must be called before the client is registered if it's sent the
+    /// history.
+    pub fn start_replay(&self) {
+        self.replaying.store(true, Release);
+    }
+
+    #[cfg(any(test, feature = "bench"))]
+    pub fn is_replaying(&self) -> bool {
+        self.replaying.load(Acquire)
+    }
+
+    /// Sends a score of the history; same as [`Client::send_score`] but not
+    /// held back.
+    ///
+    /// Ordered clients don't receive scores whose id is not greater than the
+    /// one of their last score.
+    pub fn send_replayed(&self, score: &Score, projections: &mut Projections) {
+        self.send_ordered(score, projections, Origin::History);
+    }
+
+    /// Same as [`Client::send_replayed`] but appends the score as a line to
+    /// `ndjson` instead of sending it and returns whether it did.
+    pub fn collect_replayed(
+        &self,
+        score: &Score,
+        projections: &mut Projections,
+        ndjson: &mut Vec<u8>,
+    ) -> bool {
+        if self.ordered && score.id() <= self.last_score_id.load(Relaxed) {
+            return false;
+        }
+
+        let Some(bytes) = self.matching_bytes(score, projections, Origin::History) else {
+            return false;
+        };
+
+        ndjson.extend_from_slice(&bytes);
+        ndjson.push(b'\n');
+        self.last_score_id.store(score.id(), Relaxed);
+        self.track_unacked(score);
+
+        true
+    }
+
+    fn send_ordered(&self, score: &Score, projections: &mut Projections, origin: Origin) {
+        if self.ordered && score.id() <= self.last_score_id.load(Relaxed) {
+            return;
+        }
+
+        if self.send_matching(score, projections, origin) {
+            self.last_score_id.store(score.id(), Relaxed);
+        }
+    }
+
+    /// Sends the score if it matches the client's rulesets, partition, and
+    /// filter, and passes its sampling, and returns whether it did.
+    ///
+    /// Unlike [`Client::send_replayed`], the client's last score id stays as
+    /// is so that requesting older scores again doesn't move its cursor back.
+    pub fn send_matching(
+        &self,
+        score: &Score,
+        projections: &mut Projections,
+        origin: Origin,
+    ) -> bool {
+        match self.matching_bytes(score, projections, origin) {
+            Some(bytes) => {
+                self.send(Message::Binary(bytes));
+                self.track_unacked(score);
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn track_unacked(&self, score: &Score) {
+        if let Some(ref unacked) = self.unacked {
+            unacked.lock().unwrap().sent(score.id());
+        }
+    }
+
+    /// The bytes to send for the score if it matches the client and passes
+    /// its sampling and rate cap.
+    fn matching_bytes(
+        &self,
+        score: &Score,
+        projections: &mut Projections,
+        origin: Origin,
+    ) -> Option<Bytes> {
+        if !Rulesets(self.rulesets.load(Relaxed)).contains(score) {
+            return None;
+        }
+
+        if let Some(partition) = *self.partition.read().unwrap() {
+            if !partition.contains(score.user_id().unwrap_or(0)) {
+                return None;
+            }
+        }
+
+        if !self.filter.read().unwrap().matches(score) {
+            return None;
+        }
+
+        if let Some(sample) = *self.sample.read().unwrap() {
+            if !sample.contains(score.id()) {
+                return None;
+            }
+        }
+
+        // Checked last so that skipped scores don't use up the rate
+        if let Some(ref mut rate_cap) = *self.rate_cap.lock().unwrap() {
+            if !rate_cap.try_acquire(Instant::now()) {
+                return None;
+            }
+        }
+
+        let bytes = match *self.fields.read().unwrap() {
+            Some(ref fields) => projections.get(score, fields),
+            None => score.bytes().clone(),
+        };
+
+        if self.meta {
+            Some(self.wrap_meta(score, &bytes, origin))
+        } else {
+            Some(bytes)
+        }
+    }
+
+    /// Wraps the score's bytes into
+    /// `{"meta":{"fetched_at":...,"seq":...,"ruleset":...,"origin":...},"score":...}`.
+    fn wrap_meta(&self, score: &Score, bytes: &[u8], origin: Origin) -> Bytes {
+        let seq = self.meta_seq.fetch_add(1, Relaxed) + 1;
+        let mut buf = itoa::Buffer::new();
+        let mut json = Vec::with_capacity(bytes.len() + 112);
+
+        json.extend_from_slice(br#"{"meta":{"fetched_at":"#);
+
+        match score.fetched_at() {
+            0 => json.extend_from_slice(b"null"),
+            fetched_at => json.extend_from_slice(buf.format(fetched_at).as_bytes()),
+        }
+
+        json.extend_from_slice(br#","seq":"#);
+        json.extend_from_slice(buf.format(seq).as_bytes());
+        json.extend_from_slice(br#","ruleset":"#);
+
+        match score
+            .ruleset_id()
+            .and_then(|id| RULESETS.get(usize::from(id)))
+        {
+            Some(ruleset) => {
+                json.push(b'"');
+                json.extend_from_slice(ruleset.as_bytes());
+                json.push(b'"');
+            }
+            None => json.extend_from_slice(b"null"),
+        }
+
+        json.extend_from_slice(br#","origin":""#);
+        json.extend_from_slice(origin.as_str().as_bytes());
+        json.extend_from_slice(br#""},"score":"#);
+        json.extend_from_slice(bytes);
+        json.push(b'}');
+
+        Bytes::from(json)
+    }
+
+    /// Sends the scores that were held back during the replay unless they
+    /// were already part of the replayed history and returns their amount.
+    pub fn finish_replay(&self, history: &Snapshot, projections: &mut Projections) -> usize {
+        let mut pending = self.pending.lock().unwrap();
+        let mut sent = 0;
+
+        for score in pending.drain(..) {
+            if !history.contains(score.id()) {
+                self.send_ordered(&score, projections, Origin::Live);
+                sent += 1;
+            }
+        }
+
+        self.replaying.store(false, Release);
+
+        sent
+    }
+
+    /// Whether the client's connection is gone.
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+
+    pub fn last_score_id(&self) -> Option<u64> {
+        Some(self.last_score_id.load(Relaxed)).filter(|&id| id > 0)
+    }
+
+    /// The id of the last queued score if it changed since the previous
+    /// checkpoint.
+    ///
+    /// A checkpoint is queued behind that score so the client has received
+    /// all scores up to it once the checkpoint arrives.
+    pub fn next_checkpoint(&self) -> Option<u64> {
+        let id = self.last_score_id()?;
+
+        (self.checkpoint_id.swap(id, Relaxed) != id).then_some(id)
+    }
+
+    /// Specifies which fields of scores should be sent; `None` for all.
+    pub fn set_fields(&self, fields: Option<Fields>) {
+        *self.fields.write().unwrap() = fields;
+    }
+
+    pub fn set_partition(&self, partition: Partition) {
+        *self.partition.write().unwrap() = Some(partition);
+    }
+
+    /// Only sends this share of scores; `None` for all.
+    pub fn set_sample(&self, sample: Option<Sample>) {
+        *self.sample.write().unwrap() = sample;
+    }
+
+    /// Sends at most this many scores per second; `None` for no limit.
+    pub fn set_rate_cap(&self, rate_cap: Option<RateCap>) {
+        *self.rate_cap.lock().unwrap() = rate_cap;
+    }
+
+    pub fn set_alerts(&self, alerts: Alerts) {
+        *self.alerts.lock().unwrap() = Some(alerts);
+    }
+
+    /// Whether the score should be sent as an alert.
+    pub fn is_alert(&self, score: &Score) -> bool {
+        self.alerts
+            .lock()
+            .unwrap()
+            .as_mut()
+            .is_some_and(|alerts| alerts.check(score, Instant::now()))
+    }
+
+    pub fn set_rulesets(&self, rulesets: Rulesets) {
+        self.rulesets.store(rulesets.0, Relaxed);
+    }
+
+    pub fn set_condition(&self, condition: Condition) {
+        self.filter.write().unwrap().set(condition);
+    }
+
+    pub fn set_beatmaps(&self, beatmaps: Beatmaps) {
+        self.filter.write().unwrap().set_beatmaps(beatmaps);
+    }
+
+    pub fn update_mods(&self, update: ModUpdate) {
+        self.filter.write().unwrap().update_mods(update);
+    }
+
+    /// Must be called whenever a message was taken out of the channel.
+    pub fn dequeued(&self, msg: &Message) {
+        self.queued.fetch_sub(1, Relaxed);
+        self.queued_bytes.fetch_sub(msg.len(), Relaxed);
+
+        if let Message::Binary(_) = msg {
+            self.sent_scores.fetch_add(1, Relaxed);
+        }
+    }
+
+    /// Statistics of this client as JSON.
+    pub fn stats(&self, cursor_id: Option<u64>) -> String {
+        let sent = self.sent_scores();
+        let lag = self.lag();
+        let max_lag = self.max_lag();
+        let uptime = self.connected_at.elapsed().as_secs();
+
+        let mut json = format!(
+            r#"{{"sent":{sent},"lag":{lag},"max_lag":{max_lag},"uptime_secs":{uptime},"cursor_id":"#
+        );
+
+        match cursor_id {
+            Some(id) => json.push_str(itoa::Buffer::new().format(id)),
+            None => json.push_str("null"),
+        }
+
+        json.push('}');
+
+        json
+    }
+}
+
+/// Projections of a score onto fields, built once per distinct fields.
+#[derive(Default)]
+pub struct Projections {
+    score_id: u64,
+    projected: Vec<(Fields, Bytes)>,
+}
+
+impl Projections {
+    fn get(&mut self, score: &Score, fields: &Fields) -> Bytes {
+        if self.score_id != score.id() {
+            self.score_id = score.id();
+            self.projected.clear();
+        }
+
+        let cached = self
+            .projected
+            .iter()
+            .find(|(cached, _)| Arc::ptr_eq(cached, fields) || **cached == **fields);
+
+        if let Some((_, bytes)) = cached {
+            return bytes.clone();
+        }
+
+        let bytes = score.project(fields);
+        self.projected.push((Arc::clone(fields), bytes.clone()));
+
+        bytes
+    }
+}
+
+/// Rulesets whose scores a client is interested in.
+#[derive(Copy, Clone)]
+pub struct Rulesets(u8);
+
+impl Rulesets {
+    pub const ALL: Self = Self(0);
+
+    /// Returns `None` if a name is unknown. No names at all mean all rulesets.
+    pub fn from_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Option<Self> {
+        names.into_iter().try_fold(Self::ALL, |Self(bits), name| {
+            let id = RULESETS.iter().position(|&ruleset| ruleset == name)?;
+
+            Some(Self(bits | 1 << id))
+        })
+    }
+
+    /// Whether the score's `ruleset_id` is among the rulesets.
+    pub fn contains(self, score: &Score) -> bool {
+        self.0 == Self::ALL.0
+            || score
+                .ruleset_id()
+                .and_then(|id| 1_u8.checked_shl(u32::from(id)))
+                .is_some_and(|bit| self.0 & bit > 0)
+    }
+}
+
+/// Subset of users so that multiple consumers can each receive a disjoint
+/// share of scores.
+#[derive(Copy, Clone)]
+pub struct Partition {
+    index: u64,
+    count: u64,
+}
+
+impl Partition {
+    /// Returns `None` unless `index` is less than `count`.
+    pub const fn new(index: u64, count: u64) -> Option<Self> {
+        if index < count {
+            Some(Self { index, count })
+        } else {
+            None
+        }
+    }
+
+    /// Whether the user belongs to this partition.
+    ///
+    /// User ids are mixed through splitmix64 first so that partitions are
+    /// evenly sized regardless of patterns in ids.
+    pub const fn contains(self, user_id: u64) -> bool {
+        splitmix64(user_id) % self.count == self.index
+    }
+}
+
+/// Share of scores for clients that don't need every score, e.g. for
+/// analytics.
+#[derive(Copy, Clone)]
+pub struct Sample {
+    /// Scores whose mixed id is at most this are sent.
+    threshold: u64,
+}
+
+impl Sample {
+    /// Returns `None` unless `ratio` is greater than 0 and at most 1.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    pub fn new(ratio: f64) -> Option<Self> {
+        (ratio > 0.0 && ratio <= 1.0).then_some(Self {
+            // Saturates to `u64::MAX` for a ratio of 1
+            threshold: (ratio * u64::MAX as f64) as u64,
+        })
+    }
+
+    /// Whether the score belongs to the sample.
+    ///
+    /// Ids are mixed so that the same scores are sampled for every client.
+    pub const fn contains(self, score_id: u64) -> bool {
+        splitmix64(score_id) <= self.threshold
+    }
+}
+
+/// Limits the amount of scores per second, allowing bursts of up to a
+/// second's worth.
+pub struct RateCap {
+    max_per_sec: f64,
+    bucket: TokenBucket,
+}
+
+impl RateCap {
+    /// Returns `None` if `max_per_sec` is 0.
+    pub fn new(max_per_sec: u32) -> Option<Self> {
+        let max_per_sec = f64::from(max_per_sec);
+
+        (max_per_sec > 0.0).then(|| Self {
+            max_per_sec,
+            bucket: TokenBucket::full(max_per_sec),
+        })
+    }
+
+    /// Whether another score may be sent at `now`; counts it if so.
+    fn try_acquire(&mut self, now: Instant) -> bool {
+        self.bucket
+            .try_take(self.max_per_sec, self.max_per_sec, now)
+    }
+}
+
+const fn splitmix64(n: u64) -> u64 {
+    let mut hash = n.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+
+    hash ^ (hash >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::history::History;
+
+    #[test]
+    fn partitions_are_disjoint() {
+        let partitions: Vec<_> = (0..8).map(|i| Partition::new(i, 8).unwrap()).collect();
+
+        for user_id in 0..1000 {
+            let count = partitions
+                .iter()
+                .filter(|partition| partition.contains(user_id))
+                .count();
+
+            assert_eq!(count, 1);
+        }
+
+        assert!(Partition::new(8, 8).is_none());
+    }
+
+    #[test]
+    fn sampling() {
+        let sample = Sample::new(0.1).unwrap();
+        let sampled = (0..10_000).filter(|&id| sample.contains(id)).count();
+        assert!((800..1200).contains(&sampled), "{sampled}");
+
+        let all = Sample::new(1.0).unwrap();
+        assert!((0..1000).all(|id| all.contains(id)));
+        assert!(Sample::new(0.0).is_none());
+
+        let mut rate_cap = RateCap::new(2).unwrap();
+        let start = Instant::now();
+        assert!(rate_cap.try_acquire(start));
+        assert!(rate_cap.try_acquire(start));
+        assert!(!rate_cap.try_acquire(start));
+        assert!(rate_cap.try_acquire(start + Duration::from_millis(500)));
+        assert!(!rate_cap.try_acquire(start + Duration::from_millis(500)));
+        assert!(RateCap::new(0).is_none());
+    }
+
+    #[test]
+    fn hold_back_during_replay() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let client = Client::new(tx, Permissions::ALL, None, None);
+        let mut projections = Projections::default();
+
+        let mut history = History::new(10, None);
+        history.extend([1, 2].map(Score::only_id));
+        let snapshot = history.snapshot();
+
+        client.start_replay();
+        client.send_score(&Score::only_id(2), &mut projections);
+        client.send_score(&Score::only_id(3), &mut projections);
+
+        for score in snapshot.range_from(0) {
+            client.send_replayed(&score, &mut projections);
+        }
+
+        assert_eq!(client.finish_replay(&snapshot, &mut projections), 1);
+        client.send_score(&Score::only_id(4), &mut projections);
+
+        assert_eq!(client.last_score_id(), Some(4));
+        assert_eq!(std::iter::from_fn(|| rx.try_recv().ok()).count(), 4);
+    }
+
+    #[test]
+    fn ordered() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let client = Client::new(tx, Permissions::ALL, None, None).with_ordered(true);
+        let mut projections = Projections::default();
+
+        for id in [2, 1, 3, 3] {
+            client.send_score(&Score::only_id(id), &mut projections);
+        }
+
+        assert_eq!(client.last_score_id(), Some(3));
+        assert_eq!(std::iter::from_fn(|| rx.try_recv().ok()).count(), 2);
+    }
+
+    #[test]
+    fn collect_replay() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let client = Client::new(tx, Permissions::ALL, None, None)
+            .with_ordered(true)
+            .with_compressed_replay(true);
+        let mut projections = Projections::default();
+        let mut ndjson = Vec::new();
+
+        for id in [1, 3, 2] {
+            let score = Score::new(id, Bytes::from(format!(r#"{{"id":{id}}}"#)));
+            client.collect_replayed(&score, &mut projections, &mut ndjson);
+        }
+
+        assert_eq!(ndjson, b"{\"id\":1}\n{\"id\":3}\n".as_slice());
+        assert_eq!(client.last_score_id(), Some(3));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn lag() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let client = Client::new(tx, Permissions::ALL, None, None);
+        let mut projections = Projections::default();
+
+        for id in 1..=3 {
+            client.send_score(&Score::only_id(id), &mut projections);
+        }
+
+        for _ in 0..2 {
+            client.dequeued(&rx.try_recv().unwrap());
+        }
+
+        assert_eq!(client.lag(), 1);
+        assert!(client
+            .stats(None)
+            .starts_with(r#"{"sent":2,"lag":1,"max_lag":3,"#));
+
+        client.send(Message::Text("hello".into()));
+        assert_eq!(client.queued_bytes(), 5);
+        client.dequeued(&rx.try_recv().unwrap());
+        client.dequeued(&rx.try_recv().unwrap());
+        assert_eq!(client.queued_bytes(), 0);
+    }
+
+    #[test]
+    fn meta() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let client = Client::new(tx, Permissions::ALL, None, None).with_meta(true);
+        let mut projections = Projections::default();
+
+        let score = Score::new(1, Bytes::from_static(br#"{"id":1,"ruleset_id":3}"#));
+        client.send_replayed(&score, &mut projections);
+        client.send_score(&Score::only_id(2), &mut projections);
+
+        let Some(Message::Binary(bytes)) = rx.try_recv().ok() else {
+            panic!("expected score");
+        };
+
+        let expected = format!(
+            r#"{{"meta":{{"fetched_at":{},"seq":1,"ruleset":"mania","origin":"history"}},"score":{{"id":1,"ruleset_id":3}}}}"#,
+            score.fetched_at()
+        );
+        assert_eq!(bytes, expected.as_bytes());
+
+        let Some(Message::Binary(bytes)) = rx.try_recv().ok() else {
+            panic!("expected score");
+        };
+
+        let meta = br#"{"meta":{"fetched_at":null,"seq":2,"ruleset":null,"origin":"live"}"#;
+        assert!(bytes.starts_with(meta));
+    }
+
+    #[test]
+    fn checkpoints() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let client = Client::new(tx, Permissions::ALL, None, None);
+        let mut projections = Projections::default();
+
+        assert_eq!(client.next_checkpoint(), None);
+
+        client.send_score(&Score::only_id(5), &mut projections);
+        assert_eq!(client.next_checkpoint(), Some(5));
+        assert_eq!(client.next_checkpoint(), None);
+
+        client.send_score(&Score::only_id(6), &mut projections);
+        assert_eq!(client.next_checkpoint(), Some(6));
+    }
+
+    #[test]
+    fn shared_projections() {
+        let score = Score::new(1, Bytes::from_static(br#"{"id":1,"pp":2}"#));
+        let fields: Fields = [Box::from("pp")].into();
+        let same: Fields = [Box::from("pp")].into();
+
+        let mut projections = Projections::default();
+        let projected = projections.get(&score, &fields);
+        assert_eq!(projected, br#"{"pp":2}"#.as_slice());
+        assert_eq!(projections.get(&score, &same).as_ptr(), projected.as_ptr());
+
+        let other = Score::new(2, Bytes::from_static(br#"{"id":2,"pp":3}"#));
+        assert_eq!(projections.get(&other, &same), br#"{"pp":3}"#.as_slice());
+    }
+
+    #[test]
+    fn rulesets() {
+        let score = |json: &'static str| Score::new(1, Bytes::from_static(json.as_bytes()));
+        let mania = score(r#"{"id":1,"ruleset_id":3}"#);
+        let unknown = score(r#"{"id":1}"#);
+
+        let rulesets = Rulesets::from_names(["taiko", "mania"]).unwrap();
+        assert!(rulesets.contains(&mania));
+        assert!(!rulesets.contains(&unknown));
+        assert!(!Rulesets::from_names(["osu"]).unwrap().contains(&mania));
+        assert!(Rulesets::from_names([]).unwrap().contains(&unknown));
+        assert!(Rulesets::from_names(["catch"]).is_none());
+    }
+}
@@ -1,80 +1,1281 @@
 use std::{
-    fs::File,
-    io::Read,
+    fs,
+    io::ErrorKind,
     net::{IpAddr, Ipv4Addr},
+    path::PathBuf,
+    time::Duration,
 };
 
-use eyre::Context;
+use eyre::{Context, Report, Result};
 use serde::Deserialize;
+use tokio::time::{Interval, MissedTickBehavior};
+
+use crate::{auth::Op, logging, osu::RULESETS};
+
+/// Records the formatted problem unless the condition holds.
+macro_rules! check {
+    ($problems:expr, $valid:expr, $($arg:tt)*) => {{
+        let valid: bool = $valid;
+
+        if !valid {
+            $problems.push(format!($($arg)*));
+        }
+    }};
+}
 
 #[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     pub setup: Setup,
-    pub osu: OsuConfig,
+    pub osu: Option<OsuConfig>,
+    pub admin: Option<AdminConfig>,
+    pub redis: Option<RedisConfig>,
+    pub auth: Option<AuthConfig>,
+    pub grpc: Option<GrpcConfig>,
+    #[serde(default)]
+    pub discord: Vec<DiscordConfig>,
+    pub postgres: Option<PostgresConfig>,
+    pub clickhouse: Option<ClickHouseConfig>,
+    pub mqtt: Option<MqttConfig>,
+    pub ndjson: Option<NdjsonConfig>,
+    pub archive: Option<ArchiveConfig>,
+    pub dead_letter: Option<DeadLetterConfig>,
+    pub pp: Option<PpConfig>,
+    /// Other `scores-ws` instances whose scores are joined instead of
+    /// fetching them.
+    #[serde(default)]
+    pub upstreams: Vec<UpstreamConfig>,
 }
 
 impl Config {
-    pub fn parse() -> Self {
-        let mut file = File::open("./config.toml").unwrap_or_else(|_| {
-            panic!("Be sure a file `config.toml` is in the same directory as this binary")
-        });
+    /// Reads and validates `./config.toml`.
+    ///
+    /// All invalid values are reported at once rather than only the first.
+    pub fn parse() -> Result<Self> {
+        let content = fs::read_to_string("./config.toml").map_err(|err| match err.kind() {
+            ErrorKind::NotFound => {
+                eyre!("Be sure a file `config.toml` is in the same directory as this binary")
+            }
+            _ => Report::new(err).wrap_err("Failed to read file `config.toml`"),
+        })?;
+
+        let config: Self =
+            toml::from_str(&content).context("Failed to deserialize file `config.toml`")?;
 
-        let mut content = String::new();
+        let problems = config.problems();
 
-        file.read_to_string(&mut content)
-            .context("Failed to read file `config.toml`")
-            .unwrap();
+        if !problems.is_empty() {
+            let mut msg = format!("Found {} problem(s) in `config.toml`:", problems.len());
 
-        let config: Self = toml::from_str(&content)
-            .context("Failed to deserialize file `config.toml`")
-            .unwrap();
+            for problem in problems {
+                msg.push_str("\n  - ");
+                msg.push_str(&problem);
+            }
 
-        Self::assert_valid_str(
+            bail!(msg);
+        }
+
+        Ok(config)
+    }
+
+    /// Describes each invalid value.
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        Self::check_str(
+            &mut problems,
             "setup.log",
-            &config.setup.log,
-            &["info", "warn", "error", "debug", "trace", "off"],
+            &self.setup.log,
+            &logging::LEVELS,
+        );
+
+        check!(
+            problems,
+            self.setup.interval > 0,
+            "`setup.interval` must be positive"
         );
 
-        if let Some(ruleset) = config.osu.ruleset.as_deref() {
-            Self::assert_valid_str("osu.ruleset", ruleset, &["osu", "taiko", "fruits", "mania"]);
+        if let Some(ref listen) = self.setup.listen {
+            check!(
+                problems,
+                self.setup.unix_path().is_some_and(|path| !path.is_empty()),
+                "Unexpected value `{listen}` for `setup.listen`; must be of the form `unix:/path/to/socket`"
+            );
+        }
+
+        for listener in &self.setup.listeners {
+            match (listener.port, listener.listen.as_deref()) {
+                (Some(_), None) => {}
+                (None, Some(listen)) => check!(
+                    problems,
+                    listener.unix_path().is_some_and(|path| !path.is_empty()),
+                    "Unexpected value `{listen}` for `listen` of `setup.listeners`; must be of the form `unix:/path/to/socket`"
+                ),
+                _ => problems.push(
+                    "Each of `setup.listeners` must specify either `port` or `listen`".to_owned(),
+                ),
+            }
+        }
+
+        if let Some(ref pp) = self.pp {
+            check!(
+                problems,
+                pp.timeout_secs > 0,
+                "`pp.timeout_secs` must be positive"
+            );
+        }
+
+        if let Some(limit) = self.setup.memory_soft_limit_bytes {
+            check!(
+                problems,
+                self.setup
+                    .history_max_bytes
+                    .is_none_or(|max_bytes| max_bytes < limit),
+                "`setup.history_max_bytes` must be less than `setup.memory_soft_limit_bytes`"
+            );
+        }
+
+        if let Some(ref anomalies) = self.setup.anomalies {
+            check!(
+                problems,
+                anomalies.max_pp_jump >= 0.0,
+                "`setup.anomalies.max_pp_jump` must not be negative"
+            );
+        }
+
+        let role = self.setup.role;
+
+        if !matches!(role, Role::Both) {
+            match (role, self.redis.as_ref().map(|redis| redis.mode)) {
+                (_, None) => {
+                    problems.push("`setup.role` requires the section `[redis]`".to_owned());
+                }
+                (Role::Fetcher, Some(Some(RedisMode::Consume))) => problems.push(
+                    "`redis.mode = \"consume\"` conflicts with `setup.role = \"fetcher\"`"
+                        .to_owned(),
+                ),
+                (Role::Server, Some(Some(RedisMode::Publish))) => problems.push(
+                    "`redis.mode = \"publish\"` conflicts with `setup.role = \"server\"`"
+                        .to_owned(),
+                ),
+                _ => {}
+            }
         }
 
-        config
+        let consumes_redis = self
+            .redis
+            .as_ref()
+            .is_some_and(|redis| matches!(redis.mode(role), RedisMode::Consume));
+
+        if let Some(ref redis) = self.redis {
+            Self::check_label(&mut problems, "redis.label", &redis.label);
+
+            check!(
+                problems,
+                redis
+                    .lease_secs
+                    .is_none_or(|secs| secs > self.setup.interval),
+                "`redis.lease_secs` must be longer than `setup.interval`"
+            );
+        }
+
+        self.check_upstreams(&mut problems, consumes_redis);
+
+        match self.osu {
+            Some(_) if !self.upstreams.is_empty() => {
+                problems.push("`[[upstreams]]` conflicts with the section `[osu]`".to_owned());
+            }
+            Some(ref osu) => Self::check_osu(&mut problems, osu, self.setup.interval),
+            None if consumes_redis || !self.upstreams.is_empty() => {}
+            None => problems.push("Missing section `[osu]`".to_owned()),
+        }
+
+        self.check_sinks(&mut problems);
+
+        problems
     }
 
-    fn assert_valid_str(key: &str, value: &str, valid: &[&str]) {
-        if valid.contains(&value) {
+    fn check_upstreams(&self, problems: &mut Vec<String>, consumes_redis: bool) {
+        if self.upstreams.is_empty() {
             return;
         }
 
-        panic!("Unexpected value `{value}` for `{key}` in `config.toml`; must be any of {valid:?}");
+        if consumes_redis {
+            problems.push("`[[upstreams]]` conflicts with consuming from redis".to_owned());
+        }
+
+        for (i, upstream) in self.upstreams.iter().enumerate() {
+            Self::check_label(problems, "upstreams.label", &upstream.label);
+
+            check!(
+                problems,
+                upstream.url.starts_with("ws://") || upstream.url.starts_with("wss://"),
+                "Unexpected value `{}` for `upstreams.url`; must start with `ws://` or `wss://`",
+                upstream.url
+            );
+
+            check!(
+                problems,
+                self.upstreams[..i]
+                    .iter()
+                    .all(|other| other.label != upstream.label),
+                "Duplicate label `{}` in `[[upstreams]]`",
+                upstream.label
+            );
+        }
+    }
+
+    fn check_osu(problems: &mut Vec<String>, osu: &OsuConfig, interval: u64) {
+        if let Some(ruleset) = osu.ruleset.as_deref() {
+            Self::check_str(problems, "osu.ruleset", ruleset, &RULESETS);
+        }
+
+        if let Some(label) = osu.label.as_deref() {
+            Self::check_label(problems, "osu.label", label);
+        }
+
+        check!(
+            problems,
+            (1..=8).contains(&osu.concurrent_pages),
+            "`osu.concurrent_pages` must be between 1 and 8"
+        );
+        check!(
+            problems,
+            (1..=1000).contains(&osu.id_threshold),
+            "`osu.id_threshold` must be between 1 and 1000"
+        );
+        check!(
+            problems,
+            osu.page_delay_ms < interval.saturating_mul(1000),
+            "`osu.page_delay_ms` must be shorter than `setup.interval`"
+        );
+
+        for (key, url) in [
+            ("osu.api_url", &osu.api_url),
+            ("osu.token_url", &osu.token_url),
+        ] {
+            check!(
+                problems,
+                url.starts_with("https://") && url.parse::<hyper::Uri>().is_ok(),
+                "Unexpected value `{url}` for `{key}`; must be an https url"
+            );
+        }
+
+        if let Some(ref suffix) = osu.user_agent_suffix {
+            check!(
+                problems,
+                !suffix.is_empty() && suffix.chars().all(|c| c.is_ascii_graphic() || c == ' '),
+                "`osu.user_agent_suffix` must only contain printable ascii characters"
+            );
+        }
+
+        let http = &osu.http;
+
+        check!(
+            problems,
+            http.idle_timeout_secs > 0
+                && http.keep_alive_timeout_secs > 0
+                && http.keep_alive_interval_secs != Some(0),
+            "`osu.http.idle_timeout_secs`, `osu.http.keep_alive_interval_secs`, and `osu.http.keep_alive_timeout_secs` must be positive"
+        );
+
+        let retry = &osu.retry;
+
+        check!(
+            problems,
+            retry.initial_secs > 0 && retry.initial_secs <= retry.max_secs,
+            "`osu.retry.initial_secs` must be positive and at most `osu.retry.max_secs`"
+        );
+        check!(
+            problems,
+            retry.multiplier >= 1.0,
+            "`osu.retry.multiplier` must be at least 1.0"
+        );
+        check!(
+            problems,
+            (0.0..=1.0).contains(&retry.jitter),
+            "`osu.retry.jitter` must be between 0.0 and 1.0"
+        );
+        check!(
+            problems,
+            retry.timeout_secs > 0,
+            "`osu.retry.timeout_secs` must be positive"
+        );
+        check!(
+            problems,
+            retry.budget != Some(0) && retry.cooldown_secs > 0,
+            "`osu.retry.budget` and `osu.retry.cooldown_secs` must be positive"
+        );
+        check!(
+            problems,
+            retry.maintenance_after > 0 && retry.probe_secs > 0,
+            "`osu.retry.maintenance_after` and `osu.retry.probe_secs` must be positive"
+        );
+
+        check!(
+            problems,
+            osu.catch_up.requests_per_minute > 0 && osu.catch_up.log_every_pages > 0,
+            "`osu.catch_up.requests_per_minute` and `osu.catch_up.log_every_pages` must be positive"
+        );
+
+        if let Some(ref users) = osu.users {
+            check!(
+                problems,
+                !users.ids.is_empty(),
+                "`osu.users.ids` must not be empty"
+            );
+            check!(
+                problems,
+                users.requests_per_minute > 0,
+                "`osu.users.requests_per_minute` must be positive"
+            );
+        }
+    }
+
+    fn check_sinks(&self, problems: &mut Vec<String>) {
+        for discord in &self.discord {
+            for ruleset in &discord.rulesets {
+                Self::check_str(problems, "discord.rulesets", ruleset, &RULESETS);
+            }
+        }
+
+        if let Some(ref postgres) = self.postgres {
+            // The table name is also part of index names
+            Self::check_label(problems, "postgres.table", &postgres.table);
+            check!(
+                problems,
+                postgres.batch_size > 0 && postgres.maintenance_interval_secs > 0,
+                "`postgres.batch_size` and `postgres.maintenance_interval_secs` must be positive"
+            );
+            check!(
+                problems,
+                postgres.max_size_mb != Some(0),
+                "`postgres.max_size_mb` must be positive"
+            );
+        }
+
+        if let Some(ref clickhouse) = self.clickhouse {
+            Self::check_label(problems, "clickhouse.table", &clickhouse.table);
+            check!(
+                problems,
+                clickhouse.batch_size > 0 && clickhouse.flush_interval_secs > 0,
+                "`clickhouse.batch_size` and `clickhouse.flush_interval_secs` must be positive"
+            );
+            check!(
+                problems,
+                clickhouse.max_buffered >= clickhouse.batch_size,
+                "`clickhouse.max_buffered` must be at least `clickhouse.batch_size`"
+            );
+            check!(
+                problems,
+                clickhouse.maintenance_interval_secs > 0 && clickhouse.max_size_mb != Some(0),
+                "`clickhouse.maintenance_interval_secs` and `clickhouse.max_size_mb` must be positive"
+            );
+        }
+
+        if let Some(ref archive) = self.archive {
+            // Both are part of the request path as is
+            let is_valid = |s: &str, slash: bool| {
+                !s.is_empty()
+                    && !s.starts_with('/')
+                    && !s.ends_with('/')
+                    && s.chars().all(|c| {
+                        c.is_ascii_alphanumeric()
+                            || matches!(c, '-' | '_' | '.')
+                            || (slash && c == '/')
+                    })
+            };
+
+            check!(
+                problems,
+                is_valid(&archive.bucket, false),
+                "`archive.bucket` may only contain ascii alphanumeric characters, `-`, `_`, or `.`"
+            );
+            check!(
+                problems,
+                is_valid(&archive.prefix, true),
+                "`archive.prefix` may only contain ascii alphanumeric characters, `-`, `_`, `.`, or inner `/`"
+            );
+        }
+
+        if let Some(ref mqtt) = self.mqtt {
+            check!(
+                problems,
+                !mqtt.topic_prefix.is_empty() && !mqtt.topic_prefix.contains(['+', '#']),
+                "`mqtt.topic_prefix` must not be empty or contain wildcards"
+            );
+            check!(
+                problems,
+                mqtt.password.is_none() || mqtt.username.is_some(),
+                "`mqtt.password` requires `mqtt.username`"
+            );
+            check!(
+                problems,
+                mqtt.keep_alive_secs >= 5,
+                "`mqtt.keep_alive_secs` must be at least 5"
+            );
+        }
+    }
+
+    fn check_label(problems: &mut Vec<String>, key: &str, label: &str) {
+        let is_valid = !label.is_empty()
+            && label
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+        check!(
+            problems,
+            is_valid,
+            "Unexpected value `{label}` for `{key}`; must only contain alphanumeric characters, `-`, or `_`"
+        );
+    }
+
+    fn check_str(problems: &mut Vec<String>, key: &str, value: &str, valid: &[&str]) {
+        check!(
+            problems,
+            valid.contains(&value),
+            "Unexpected value `{value}` for `{key}`; must be any of {valid:?}"
+        );
     }
 }
 
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Setup {
     #[serde(default = "Setup::default_log")]
     pub log: Box<str>,
-    #[serde(default = "Setup::default_ip_addr")]
-    pub ip_addr: IpAddr,
+    #[serde(default = "Setup::default_ip_addrs")]
+    pub ip_addr: IpAddrs,
     #[serde(default = "Setup::default_port")]
     pub port: u16,
+    pub listen: Option<Box<str>>,
     #[serde(default = "Setup::default_interval")]
     pub interval: u64,
+    #[serde(default)]
+    pub missed_ticks: MissedTicks,
     #[serde(default = "Setup::default_history_length")]
     pub history_length: usize,
+    pub history_max_bytes: Option<usize>,
+    #[serde(default)]
+    pub compress_history: bool,
+    #[serde(default)]
+    pub legacy_score_ids: bool,
+    pub memory_soft_limit_bytes: Option<usize>,
     pub resume_score_id: Option<u64>,
+    pub max_score_age: Option<u64>,
+    #[serde(default)]
+    pub forward_late_scores: bool,
+    pub max_connections_per_ip: Option<usize>,
+    pub max_clients: Option<usize>,
+    pub max_client_lag: Option<usize>,
+    pub messages_per_second: Option<f64>,
+    #[serde(default = "Setup::default_message_burst")]
+    pub message_burst: u32,
+    pub broadcast_delay_secs: Option<u64>,
+    pub session_grace_secs: Option<u64>,
+    #[serde(default)]
+    pub unique_client_names: bool,
+    pub checkpoint_interval_secs: Option<u64>,
+    #[serde(default)]
+    pub broadcast_missed_estimate: bool,
+    #[serde(default = "Setup::default_reorder_window_ms")]
+    pub reorder_window_ms: u64,
+    pub dedup_file: Option<PathBuf>,
+    pub dedup_window: Option<usize>,
+    #[serde(default)]
+    pub validate_scores: bool,
+    pub quarantine_file: Option<PathBuf>,
+    #[serde(default = "Setup::default_user_active_window_secs")]
+    pub user_active_window_secs: u64,
+    #[serde(default = "Setup::default_alert_window_secs")]
+    pub alert_window_secs: u64,
+    #[serde(default = "Setup::default_top_scores")]
+    pub top_scores: usize,
+    #[serde(default = "Setup::default_top_scores_window_secs")]
+    pub top_scores_window_secs: u64,
+    #[serde(default)]
+    pub role: Role,
+    #[serde(default)]
+    pub listeners: Vec<ListenerConfig>,
+    pub logging: Option<LoggingConfig>,
+    pub anomalies: Option<AnomalyConfig>,
 }
 
 #[allow(clippy::module_name_repetitions)]
 #[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// Directory in which log files are written; no files if `None`.
+    pub directory: Option<PathBuf>,
+    #[serde(default)]
+    pub rotation: Rotation,
+    #[serde(default = "LoggingConfig::default_max_file_mb")]
+    pub max_file_mb: u64,
+    #[serde(default = "LoggingConfig::default_max_files")]
+    pub max_files: usize,
+    #[serde(default)]
+    pub format: LogFormat,
+    #[serde(default = "LoggingConfig::default_stdout")]
+    pub stdout: bool,
+}
+
+impl LoggingConfig {
+    const fn default_max_file_mb() -> u64 {
+        100
+    }
+
+    const fn default_max_files() -> usize {
+        7
+    }
+
+    const fn default_stdout() -> bool {
+        true
+    }
+}
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AnomalyConfig {
+    /// Users with more scores within a minute are flagged.
+    #[serde(default = "AnomalyConfig::default_max_scores_per_minute")]
+    pub max_scores_per_minute: usize,
+    /// Users whose pp exceeds their previous maximum by more are flagged.
+    #[serde(default = "AnomalyConfig::default_max_pp_jump")]
+    pub max_pp_jump: f64,
+    /// Seconds until a user is flagged for the same reason again.
+    #[serde(default = "AnomalyConfig::default_cooldown_secs")]
+    pub cooldown_secs: u64,
+}
+
+impl AnomalyConfig {
+    const fn default_max_scores_per_minute() -> usize {
+        12
+    }
+
+    const fn default_max_pp_jump() -> f64 {
+        300.0
+    }
+
+    const fn default_cooldown_secs() -> u64 {
+        600
+    }
+}
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(not(feature = "archive"), allow(dead_code))]
+pub struct ArchiveConfig {
+    /// e.g. `https://s3.eu-central-1.amazonaws.com`
+    pub endpoint: Box<str>,
+    #[serde(default = "ArchiveConfig::default_region")]
+    pub region: Box<str>,
+    pub bucket: Box<str>,
+    #[serde(default = "ArchiveConfig::default_prefix")]
+    pub prefix: Box<str>,
+    pub access_key_id: Box<str>,
+    pub secret_access_key: Box<str>,
+}
+
+impl ArchiveConfig {
+    fn default_region() -> Box<str> {
+        Box::from("us-east-1")
+    }
+
+    fn default_prefix() -> Box<str> {
+        Box::from("responses")
+    }
+}
+
+/// Rotated like log files.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NdjsonConfig {
+    /// File to append to or `-` for stdout.
+    pub path: PathBuf,
+    #[serde(default)]
+    pub rotation: Rotation,
+    #[serde(default = "LoggingConfig::default_max_file_mb")]
+    pub max_file_mb: u64,
+    #[serde(default = "LoggingConfig::default_max_files")]
+    pub max_files: usize,
+}
+
+/// Where scores go that sinks failed to deliver.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeadLetterConfig {
+    pub path: PathBuf,
+    /// Additional attempts for failed writes of sinks that don't buffer.
+    #[serde(default = "DeadLetterConfig::default_retries")]
+    pub retries: u32,
+    #[serde(default = "DeadLetterConfig::default_retry_delay_secs")]
+    pub retry_delay_secs: u64,
+}
+
+impl DeadLetterConfig {
+    const fn default_retries() -> u32 {
+        2
+    }
+
+    const fn default_retry_delay_secs() -> u64 {
+        5
+    }
+}
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpstreamConfig {
+    /// e.g. `wss://scores.example.com/?key=abc`
+    pub url: Box<str>,
+    pub label: Box<str>,
+}
+
+/// External command that calculates the pp of scores without any.
+#[cfg_attr(not(feature = "pp"), allow(dead_code))]
+#[allow(clippy::module_name_repetitions)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PpConfig {
+    pub command: PathBuf,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Scores are broadcasted without pp if the command takes longer.
+    #[serde(default = "PpConfig::default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+impl PpConfig {
+    const fn default_timeout_secs() -> u64 {
+        5
+    }
+}
+
+#[derive(Copy, Clone, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rotation {
+    Never,
+    #[default]
+    Daily,
+    Size,
+}
+
+#[derive(Copy, Clone, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// One or more addresses such as `"127.0.0.1"` or `["0.0.0.0", "::"]`.
+#[derive(Deserialize)]
+#[serde(try_from = "OneOrMany")]
+pub struct IpAddrs(Box<[IpAddr]>);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(IpAddr),
+    Many(Box<[IpAddr]>),
+}
+
+impl TryFrom<OneOrMany> for IpAddrs {
+    type Error = &'static str;
+
+    fn try_from(addrs: OneOrMany) -> Result<Self, Self::Error> {
+        match addrs {
+            OneOrMany::One(addr) => Ok(Self(Box::new([addr]))),
+            OneOrMany::Many(addrs) if addrs.is_empty() => Err("`ip_addr` must not be empty"),
+            OneOrMany::Many(addrs) => Ok(Self(addrs)),
+        }
+    }
+}
+
+impl IpAddrs {
+    pub fn iter(&self) -> impl Iterator<Item = IpAddr> + '_ {
+        self.0.iter().copied()
+    }
+
+    /// Whether multiple addresses are specified.
+    pub const fn is_multiple(&self) -> bool {
+        self.0.len() > 1
+    }
+
+    pub fn first(&self) -> IpAddr {
+        self.0.first().copied().unwrap_or(Setup::default_ip_addr())
+    }
+}
+
+/// An additional websocket listener.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ListenerConfig {
+    #[serde(default = "Setup::default_ip_addrs")]
+    pub ip_addr: IpAddrs,
+    pub port: Option<u16>,
+    pub listen: Option<Box<str>>,
+    /// Whether clients are subject to the `[auth]` section.
+    #[serde(default = "ListenerConfig::default_auth")]
+    pub auth: bool,
+}
+
+impl ListenerConfig {
+    /// The socket path of `listen` if it's set.
+    pub fn unix_path(&self) -> Option<&str> {
+        self.listen.as_deref()?.strip_prefix("unix:")
+    }
+
+    const fn default_auth() -> bool {
+        true
+    }
+}
+
+/// Which parts of `scores-ws` an instance runs.
+#[derive(Copy, Clone, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Fetch scores and publish them to the redis stream without serving
+    /// websocket clients
+    Fetcher,
+    /// Serve websocket clients with scores consumed from the redis stream
+    Server,
+    #[default]
+    Both,
+}
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct OsuConfig {
     pub client_id: u64,
     pub client_secret: Box<str>,
     pub ruleset: Option<Box<str>>,
+    pub label: Option<Box<str>>,
+    #[serde(default)]
+    pub retry: RetryConfig,
+    #[serde(default)]
+    pub catch_up: CatchUpConfig,
+    pub users: Option<UsersConfig>,
+    pub user_info: Option<UserInfoConfig>,
+    #[serde(default = "OsuConfig::default_concurrent_pages")]
+    pub concurrent_pages: usize,
+    #[serde(default = "OsuConfig::default_id_threshold")]
+    pub id_threshold: u64,
+    #[serde(default = "OsuConfig::default_page_delay_ms")]
+    pub page_delay_ms: u64,
+    #[serde(default)]
+    pub passed_only: bool,
+    #[serde(default)]
+    pub http_version: HttpVersion,
+    #[serde(default)]
+    pub http: HttpConfig,
+    #[serde(default = "OsuConfig::default_api_url")]
+    pub api_url: Box<str>,
+    #[serde(default = "OsuConfig::default_token_url")]
+    pub token_url: Box<str>,
+    /// Appended to the `User-Agent` header to identify the operator.
+    pub user_agent_suffix: Option<Box<str>>,
+}
+
+impl OsuConfig {
+    const fn default_concurrent_pages() -> usize {
+        1
+    }
+
+    const fn default_id_threshold() -> u64 {
+        900
+    }
+
+    const fn default_page_delay_ms() -> u64 {
+        1000
+    }
+
+    fn default_api_url() -> Box<str> {
+        Box::from("https://osu.ppy.sh/api/v2")
+    }
+
+    fn default_token_url() -> Box<str> {
+        Box::from("https://osu.ppy.sh/oauth/token")
+    }
+
+    /// The configured label or one based on the ruleset.
+    pub fn label(&self) -> Box<str> {
+        match (&self.label, &self.ruleset) {
+            (Some(label), _) => label.clone(),
+            (None, Some(ruleset)) => format!("osu-{ruleset}").into_boxed_str(),
+            (None, None) => Box::from("osu"),
+        }
+    }
+}
+
+/// HTTP version used for requests to the osu!api.
+#[derive(Copy, Clone, Default, Deserialize)]
+pub enum HttpVersion {
+    #[default]
+    #[serde(rename = "2")]
+    Http2,
+    #[serde(rename = "1.1")]
+    Http1,
+    /// Negotiated through ALPN, preferring HTTP/2
+    #[serde(rename = "auto")]
+    Auto,
+}
+
+/// Polls the recent scores of specific users instead of all scores.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UsersConfig {
+    pub ids: Box<[u32]>,
+    #[serde(default = "UsersConfig::default_requests_per_minute")]
+    pub requests_per_minute: u32,
+}
+
+impl UsersConfig {
+    const fn default_requests_per_minute() -> u32 {
+        60
+    }
+}
+
+/// Adds up-to-date info about each score's user from the osu!api's users
+/// endpoint.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserInfoConfig {
+    /// Seconds for which a user's info is reused.
+    #[serde(default = "UserInfoConfig::default_ttl_secs")]
+    pub ttl_secs: u64,
+    /// Requests of up to 50 users each per fetch; other users are left out.
+    #[serde(default = "UserInfoConfig::default_requests_per_fetch")]
+    pub requests_per_fetch: usize,
+}
+
+impl UserInfoConfig {
+    const fn default_ttl_secs() -> u64 {
+        3600
+    }
+
+    const fn default_requests_per_fetch() -> usize {
+        4
+    }
+}
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetryConfig {
+    #[serde(default = "RetryConfig::default_initial_secs")]
+    pub initial_secs: u64,
+    #[serde(default = "RetryConfig::default_max_secs")]
+    pub max_secs: u64,
+    #[serde(default = "RetryConfig::default_multiplier")]
+    pub multiplier: f64,
+    /// Fraction by which each delay is randomly shortened.
+    #[serde(default)]
+    pub jitter: f64,
+    #[serde(default = "RetryConfig::default_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Consecutive failures after which fetching is paused for
+    /// `cooldown_secs`; never paused if not specified.
+    pub budget: Option<u32>,
+    #[serde(default = "RetryConfig::default_cooldown_secs")]
+    pub cooldown_secs: u64,
+    /// Consecutive 503 responses after which the osu!api is considered down,
+    /// e.g. for maintenance, and only probed every `probe_secs`.
+    #[serde(default = "RetryConfig::default_maintenance_after")]
+    pub maintenance_after: u32,
+    #[serde(default = "RetryConfig::default_probe_secs")]
+    pub probe_secs: u64,
+}
+
+impl RetryConfig {
+    const fn default_initial_secs() -> u64 {
+        2
+    }
+
+    const fn default_max_secs() -> u64 {
+        120
+    }
+
+    const fn default_multiplier() -> f64 {
+        2.0
+    }
+
+    const fn default_timeout_secs() -> u64 {
+        10
+    }
+
+    const fn default_cooldown_secs() -> u64 {
+        300
+    }
+
+    const fn default_maintenance_after() -> u32 {
+        3
+    }
+
+    const fn default_probe_secs() -> u64 {
+        60
+    }
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            initial_secs: Self::default_initial_secs(),
+            max_secs: Self::default_max_secs(),
+            multiplier: Self::default_multiplier(),
+            jitter: 0.0,
+            timeout_secs: Self::default_timeout_secs(),
+            budget: None,
+            cooldown_secs: Self::default_cooldown_secs(),
+            maintenance_after: Self::default_maintenance_after(),
+            probe_secs: Self::default_probe_secs(),
+        }
+    }
+}
+
+/// Paging through the scores after `setup.resume_score_id` on startup.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CatchUpConfig {
+    #[serde(default = "CatchUpConfig::default_requests_per_minute")]
+    pub requests_per_minute: u32,
+    /// Progress is logged whenever this many more pages were fetched.
+    #[serde(default = "CatchUpConfig::default_log_every_pages")]
+    pub log_every_pages: u64,
+}
+
+impl CatchUpConfig {
+    const fn default_requests_per_minute() -> u32 {
+        60
+    }
+
+    const fn default_log_every_pages() -> u64 {
+        10
+    }
+}
+
+impl Default for CatchUpConfig {
+    fn default() -> Self {
+        Self {
+            requests_per_minute: Self::default_requests_per_minute(),
+            log_every_pages: Self::default_log_every_pages(),
+        }
+    }
+}
+
+/// Connection pool options of the osu!api client.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HttpConfig {
+    /// Unlimited if not specified.
+    pub max_idle_connections: Option<usize>,
+    #[serde(default = "HttpConfig::default_idle_timeout_secs")]
+    pub idle_timeout_secs: u64,
+    /// HTTP/2 pings are disabled if not specified.
+    pub keep_alive_interval_secs: Option<u64>,
+    #[serde(default = "HttpConfig::default_keep_alive_timeout_secs")]
+    pub keep_alive_timeout_secs: u64,
+}
+
+impl HttpConfig {
+    const fn default_idle_timeout_secs() -> u64 {
+        90
+    }
+
+    const fn default_keep_alive_timeout_secs() -> u64 {
+        20
+    }
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            max_idle_connections: None,
+            idle_timeout_secs: Self::default_idle_timeout_secs(),
+            keep_alive_interval_secs: None,
+            keep_alive_timeout_secs: Self::default_keep_alive_timeout_secs(),
+        }
+    }
+}
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdminConfig {
+    #[serde(default = "Setup::default_ip_addr")]
+    pub ip_addr: IpAddr,
+    pub port: u16,
+    #[serde(default = "AdminConfig::default_health_max_intervals")]
+    pub health_max_intervals: u32,
+}
+
+impl AdminConfig {
+    const fn default_health_max_intervals() -> u32 {
+        3
+    }
+}
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+pub struct GrpcConfig {
+    #[serde(default = "Setup::default_ip_addr")]
+    pub ip_addr: IpAddr,
+    pub port: u16,
+    pub broadcast_delay_secs: Option<u64>,
+}
+
+/// A Discord webhook that is notified about scores matching all of the
+/// specified conditions.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DiscordConfig {
+    pub webhook_url: Box<str>,
+    pub min_pp: Option<f64>,
+    #[serde(default)]
+    pub user_ids: Box<[u64]>,
+    #[serde(default)]
+    pub rulesets: Box<[Box<str>]>,
+}
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(not(feature = "postgres"), allow(dead_code))]
+pub struct PostgresConfig {
+    pub addr: Box<str>,
+    pub user: Box<str>,
+    pub password: Option<Box<str>>,
+    pub database: Box<str>,
+    /// Whether to connect through TLS.
+    #[serde(default)]
+    pub tls: bool,
+    #[serde(default = "PostgresConfig::default_table")]
+    pub table: Box<str>,
+    /// Maximum amount of scores per `INSERT`.
+    #[serde(default = "PostgresConfig::default_batch_size")]
+    pub batch_size: usize,
+    /// The oldest scores are deleted once the table takes up more space.
+    pub max_size_mb: Option<u64>,
+    #[serde(default = "PostgresConfig::default_maintenance_interval_secs")]
+    pub maintenance_interval_secs: u64,
+}
+
+impl PostgresConfig {
+    fn default_table() -> Box<str> {
+        Box::from("scores")
+    }
+
+    const fn default_batch_size() -> usize {
+        1000
+    }
+
+    const fn default_maintenance_interval_secs() -> u64 {
+        3600
+    }
+}
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClickHouseConfig {
+    /// Address of the HTTP interface, e.g. `http://127.0.0.1:8123`.
+    pub url: Box<str>,
+    #[serde(default = "ClickHouseConfig::default_user")]
+    pub user: Box<str>,
+    pub password: Option<Box<str>>,
+    #[serde(default = "ClickHouseConfig::default_user")]
+    pub database: Box<str>,
+    #[serde(default = "PostgresConfig::default_table")]
+    pub table: Box<str>,
+    #[serde(default = "ClickHouseConfig::default_batch_size")]
+    pub batch_size: usize,
+    #[serde(default = "ClickHouseConfig::default_flush_interval_secs")]
+    pub flush_interval_secs: u64,
+    /// Scores of failed inserts are kept for the next attempt until this
+    /// many are buffered.
+    #[serde(default = "ClickHouseConfig::default_max_buffered")]
+    pub max_buffered: usize,
+    /// The oldest scores are deleted once the table takes up more space.
+    pub max_size_mb: Option<u64>,
+    #[serde(default = "PostgresConfig::default_maintenance_interval_secs")]
+    pub maintenance_interval_secs: u64,
+}
+
+impl ClickHouseConfig {
+    fn default_user() -> Box<str> {
+        Box::from("default")
+    }
+
+    const fn default_batch_size() -> usize {
+        10_000
+    }
+
+    const fn default_flush_interval_secs() -> u64 {
+        10
+    }
+
+    const fn default_max_buffered() -> usize {
+        100_000
+    }
+}
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MqttConfig {
+    pub addr: Box<str>,
+    #[serde(default = "MqttConfig::default_client_id")]
+    pub client_id: Box<str>,
+    pub username: Option<Box<str>>,
+    pub password: Option<Box<str>>,
+    #[serde(default = "MqttConfig::default_topic_prefix")]
+    pub topic_prefix: Box<str>,
+    /// Whether scores are also published to a topic of their user.
+    #[serde(default)]
+    pub user_topics: bool,
+    #[serde(default = "MqttConfig::default_keep_alive_secs")]
+    pub keep_alive_secs: u16,
+    #[serde(default)]
+    pub tls: bool,
+}
+
+impl MqttConfig {
+    fn default_client_id() -> Box<str> {
+        Box::from(env!("CARGO_PKG_NAME"))
+    }
+
+    fn default_topic_prefix() -> Box<str> {
+        Box::from("scores")
+    }
+
+    const fn default_keep_alive_secs() -> u16 {
+        60
+    }
+}
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthConfig {
+    #[serde(default)]
+    pub require_key: bool,
+    #[serde(default = "AuthConfig::default_anonymous")]
+    pub anonymous: Vec<Op>,
+    #[serde(default)]
+    pub keys: Vec<KeyConfig>,
+}
+
+impl AuthConfig {
+    fn default_anonymous() -> Vec<Op> {
+        vec![Op::Connect, Op::Resume]
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyConfig {
+    pub key: Box<str>,
+    pub permissions: Option<Vec<Op>>,
+}
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RedisConfig {
+    pub addr: Box<str>,
+    pub password: Option<Box<str>>,
+    #[serde(default)]
+    pub tls: bool,
+    #[serde(default = "RedisConfig::default_stream")]
+    pub stream: Box<str>,
+    pub mode: Option<RedisMode>,
+    #[serde(default = "Setup::default_history_length")]
+    pub max_len: usize,
+    #[serde(default = "RedisConfig::default_label")]
+    pub label: Box<str>,
+    pub lease_secs: Option<u64>,
+}
+
+impl RedisConfig {
+    /// The mode implied by the role or otherwise the configured one.
+    pub fn mode(&self, role: Role) -> RedisMode {
+        match role {
+            Role::Fetcher => RedisMode::Publish,
+            Role::Server => RedisMode::Consume,
+            Role::Both => self.mode.unwrap_or(RedisMode::Publish),
+        }
+    }
+
+    fn default_label() -> Box<str> {
+        Box::from("redis")
+    }
+
+    fn default_stream() -> Box<str> {
+        Box::from("scores-ws")
+    }
+}
+
+#[derive(Copy, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RedisMode {
+    /// Fetch scores from the osu!api and add them to the stream
+    Publish,
+    /// Read scores from the stream instead of fetching them
+    Consume,
+}
+
+/// What happens to fetch intervals that passed while still fetching, e.g.
+/// while catching up on many scores.
+#[derive(Copy, Clone, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MissedTicks {
+    /// Fetch back-to-back until caught up with the original schedule
+    Burst,
+    /// Fetch right away, then continue one interval later
+    #[default]
+    Delay,
+    /// Wait for the next tick of the original schedule
+    Skip,
+}
+
+impl From<MissedTicks> for MissedTickBehavior {
+    fn from(missed_ticks: MissedTicks) -> Self {
+        match missed_ticks {
+            MissedTicks::Burst => Self::Burst,
+            MissedTicks::Delay => Self::Delay,
+            MissedTicks::Skip => Self::Skip,
+        }
+    }
 }
 
 impl Setup {
+    /// Interval in which scores are fetched.
+    pub fn fetch_interval(&self) -> Interval {
+        let mut interval = tokio::time::interval(Duration::from_secs(self.interval));
+        interval.set_missed_tick_behavior(self.missed_ticks.into());
+
+        interval
+    }
+
+    /// The socket path of `listen` if it's set.
+    pub fn unix_path(&self) -> Option<&str> {
+        self.listen.as_deref()?.strip_prefix("unix:")
+    }
+
     fn default_log() -> Box<str> {
         Box::from("info")
     }
@@ -83,6 +1284,10 @@ impl Setup {
         IpAddr::V4(Ipv4Addr::LOCALHOST)
     }
 
+    fn default_ip_addrs() -> IpAddrs {
+        IpAddrs(Box::new([Self::default_ip_addr()]))
+    }
+
     const fn default_port() -> u16 {
         7277
     }
@@ -94,4 +1299,75 @@ impl Setup {
     const fn default_history_length() -> usize {
         100_000
     }
+
+    const fn default_message_burst() -> u32 {
+        10
+    }
+
+    const fn default_user_active_window_secs() -> u64 {
+        600
+    }
+
+    const fn default_alert_window_secs() -> u64 {
+        3600
+    }
+
+    const fn default_top_scores() -> usize {
+        10
+    }
+
+    const fn default_top_scores_window_secs() -> u64 {
+        86_400
+    }
+
+    const fn default_reorder_window_ms() -> u64 {
+        1000
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn problems() {
+        let config: Config = toml::from_str(
+            "[setup]\nlog = \"loud\"\nrole = \"server\"\n\
+            [osu]\nclient_id = 1\nclient_secret = \"a\"\nconcurrent_pages = 9",
+        )
+        .unwrap();
+
+        let problems = config.problems();
+        assert_eq!(problems.len(), 3, "{problems:?}");
+        assert!(problems[0].contains("`setup.log`"));
+
+        assert!(toml::from_str::<Config>("[setup]\nintervall = 5").is_err());
+
+        let config: Config =
+            toml::from_str("[setup]\ninterval = 0\n[osu]\nclient_id = 1\nclient_secret = \"a\"")
+                .unwrap();
+
+        let problems = config.problems();
+        assert!(
+            problems
+                .iter()
+                .any(|problem| problem.contains("`setup.interval`")),
+            "{problems:?}"
+        );
+    }
+
+    #[test]
+    fn upstreams() {
+        let config: Config = toml::from_str(
+            "[setup]\n\
+            [[upstreams]]\nurl = \"ws://a:7727\"\nlabel = \"a\"\n\
+            [[upstreams]]\nurl = \"http://b\"\nlabel = \"a\"",
+        )
+        .unwrap();
+
+        let problems = config.problems();
+        assert_eq!(problems.len(), 2, "{problems:?}");
+        assert!(problems[0].contains("`upstreams.url`"));
+        assert!(problems[1].contains("Duplicate label"));
+    }
 }
@@ -1,79 +1,413 @@
 use std::{
-    net::SocketAddr,
-    sync::{Arc, Mutex},
-    time::Duration,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering::Relaxed},
+        Arc, Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
 };
 
 use eyre::Result;
-use futures_util::{stream::SplitSink, SinkExt, StreamExt, TryStreamExt};
+use futures_util::{
+    stream::{SplitSink, SplitStream},
+    SinkExt, StreamExt,
+};
 use papaya::HashMap;
-use tokio::{net::TcpStream, sync::mpsc};
-use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
+use tokio::{
+    sync::mpsc,
+    time::{Interval, MissedTickBehavior},
+};
+use tokio_tungstenite::{
+    tungstenite::{
+        handshake::server::{Request, Response},
+        protocol::{frame::coding::CloseCode, CloseFrame},
+        Message,
+    },
+    WebSocketStream,
+};
+use tracing::instrument;
 
 use crate::{
-    config::Setup,
-    event::Event,
-    osu::{FetchResult, Osu, Score, Scores},
+    ack::{AckCursors, CursorName},
+    activity::ActivityTracker,
+    aggregate::Aggregator,
+    alert::Alerts,
+    anomaly::AnomalyDetector,
+    auth::{Auth, Op, Permissions},
+    client::{
+        Client, Fields, Origin, Partition, Projections, RateCap, Receiver, Rulesets, Sample, Topic,
+    },
+    config::{AuthConfig, Setup, UsersConfig},
+    dedup::Dedup,
+    delay::DelayQueue,
+    event::{Command, ErrorFrame, Event, ResumeCursors},
+    filter::{Beatmaps, Condition},
+    history::History,
+    latency::Latency,
+    limiter::{ConnectionPermit, RateLimiter},
+    listener::{Peer, Stream},
+    loops::{unix_now, Health, LoopHandle, Loops},
+    mods::ModUpdate,
+    osu::{unix_millis, FetchResult, Osu, Score, ScoreSource, Scores},
+    pp::PpHook,
+    redis::{Lease, ScoreStream},
+    reorder::ReorderBuffer,
+    session::{Parked, Sessions},
+    sink::Sinks,
+    state::ServerState,
+    top::TopScores,
+    user_info::UserInfo,
+    validate::Validator,
 };
 
-type Sender = mpsc::UnboundedSender<Message>;
-type Outgoing = SplitSink<WebSocketStream<TcpStream>, Message>;
+type Outgoing = SplitSink<WebSocketStream<Stream>, Message>;
+type Incoming = SplitStream<WebSocketStream<Stream>>;
 
 const SECOND: Duration = Duration::from_secs(1);
+/// Replayed scores after which the task yields to the runtime.
+const YIELD_EVERY: usize = 1000;
+
+enum Disconnect {
+    /// The client sent `"disconnect"`
+    Requested,
+    RateLimited,
+    /// The client sent nothing within its idle timeout
+    Idle,
+    /// The client sent a close frame
+    Closed,
+    /// More messages than allowed are queued for the client
+    Lagging(usize),
+}
+
+/// Session a client asked for through the query parameter `session`.
+enum SessionRequest {
+    New,
+    Resume(Box<str>),
+}
+
+/// A client whose queue is forwarded to its connection.
+struct Connection {
+    client: Arc<Client>,
+    rx: Receiver,
+    ack: Option<CursorName>,
+    idle_timeout: Option<Duration>,
+    /// Token to resume the session with if the connection drops.
+    session: Option<Box<str>>,
+}
+
+/// Options that a client specified through query parameters.
+struct ConnectOptions {
+    /// Whether the client is greeted with information about the history.
+    hello: bool,
+    fields: Option<Fields>,
+    idle_timeout: Option<Duration>,
+    /// Name of the delivery cursor in ack mode.
+    ack: Option<CursorName>,
+    client_name: Option<Box<str>>,
+    ordered: bool,
+    /// Whether scores are wrapped with metadata.
+    meta: bool,
+    partition: Option<Partition>,
+    sample: Option<Sample>,
+    max_per_sec: Option<u32>,
+    conditions: Vec<Condition>,
+    beatmaps: Vec<Beatmaps>,
+    mods: ModUpdate,
+    rulesets: Rulesets,
+    session: Option<SessionRequest>,
+}
+
+impl ConnectOptions {
+    /// Parses the query of the websocket request. Unknown or invalid
+    /// parameters are ignored.
+    fn from_query(query: Option<&str>) -> Self {
+        let mut options = Self {
+            hello: false,
+            fields: None,
+            idle_timeout: None,
+            ack: None,
+            client_name: None,
+            ordered: false,
+            meta: false,
+            partition: None,
+            sample: None,
+            max_per_sec: None,
+            conditions: Vec::new(),
+            beatmaps: Vec::new(),
+            mods: ModUpdate::default(),
+            rulesets: Rulesets::ALL,
+            session: None,
+        };
+        let mut partition = (None, None);
+
+        let params = query.into_iter().flat_map(|q| q.split('&'));
+
+        for param in params {
+            match param.split_once('=').unwrap_or((param, "")) {
+                ("hello", "" | "true") => options.hello = true,
+                ("ordered", "" | "true") => options.ordered = true,
+                ("meta", "" | "true") => options.meta = true,
+                ("passed", "" | "true") => {
+                    options
+                        .conditions
+                        .extend(Condition::new("passed", ["true"]));
+                }
+                ("session", "" | "true") => options.session = Some(SessionRequest::New),
+                ("session", token) => {
+                    options.session = Some(SessionRequest::Resume(Box::from(token)));
+                }
+                ("ack", name) => options.ack = CursorName::new(name),
+                ("client_name", name) if CursorName::is_valid(name) => {
+                    options.client_name = Some(Box::from(name));
+                }
+                ("partition", index) => partition.0 = index.parse().ok(),
+                ("of", count) => partition.1 = count.parse().ok(),
+                ("sample", ratio) => options.sample = ratio.parse().ok().and_then(Sample::new),
+                ("max_per_sec", count) => options.max_per_sec = count.parse().ok(),
+                ("rulesets", names) => {
+                    let names = names.split(',').filter(|name| !name.is_empty());
+                    options.rulesets = Rulesets::from_names(names).unwrap_or(Rulesets::ALL);
+                }
+                ("beatmaps", ids) => {
+                    let ids = Beatmaps::parse_ids(ids);
+                    options.beatmaps.extend(ids.map(Beatmaps::Maps));
+                }
+                ("beatmapsets", ids) => {
+                    let ids = Beatmaps::parse_ids(ids);
+                    options.beatmaps.extend(ids.map(Beatmaps::Sets));
+                }
+                ("mods_include", list) => {
+                    options.mods.include = ModUpdate::parse_acronyms(list.split(','));
+                }
+                ("mods_exclude", list) => {
+                    options.mods.exclude = ModUpdate::parse_acronyms(list.split(','));
+                }
+                (key, list) if key.starts_with("filter.") => {
+                    let condition = Condition::from_query(&key["filter.".len()..], list);
+                    options.conditions.extend(condition);
+                }
+                ("idle_minutes", minutes) => {
+                    options.idle_timeout = minutes
+                        .parse()
+                        .ok()
+                        .filter(|&minutes| minutes > 0)
+                        .map(|minutes: u64| Duration::from_secs(minutes * 60));
+                }
+                ("fields", list) => {
+                    options.fields = Some(
+                        list.split(',')
+                            .filter(|field| !field.is_empty())
+                            .map(Box::from)
+                            .collect::<Fields>(),
+                    )
+                    .filter(|fields| !fields.is_empty());
+                }
+                _ => {}
+            }
+        }
+
+        if let (Some(index), Some(count)) = partition {
+            options.partition = Partition::new(index, count);
+        }
+
+        options
+    }
+}
 
 pub struct Context {
-    clients: HashMap<SocketAddr, Sender>,
-    history: Mutex<Scores>,
-    max_history_len: usize,
+    clients: HashMap<Peer, Arc<Client>>,
+    auth: Auth,
+    limiter: Arc<RateLimiter>,
+    history: Mutex<History>,
+    /// The fetch loop's current cursor id; `0` if there is none.
+    cursor_id: AtomicU64,
+    /// Maximum age in seconds of a score's `ended_at` when it's fetched.
+    max_score_age: Option<u64>,
+    forward_late_scores: bool,
+    loops: Loops,
+    /// Delay for websocket clients.
+    broadcast_delay: Option<Duration>,
+    delayed: DelayQueue,
+    /// Scores for clients with the `ordered` option.
+    reordered: ReorderBuffer,
+    activity: ActivityTracker,
+    anomalies: Option<AnomalyDetector>,
+    top: TopScores,
+    latency: Latency,
+    /// Set once the osu! client exists, if configured.
+    user_info: OnceLock<UserInfo>,
+    /// Set on startup if the `pp` feature is enabled and configured.
+    pp: OnceLock<PpHook>,
+    /// Window in which alerts for the same user and beatmap are skipped.
+    alert_window: Duration,
+    state: ServerState,
+    acks: AckCursors,
+    aggregator: Aggregator,
+    validator: Option<Validator>,
+    /// Parked sessions of clients whose connection dropped.
+    sessions: Option<Sessions>,
+    sinks: Sinks,
+    /// Whether a client replaces older connections with the same name.
+    unique_client_names: bool,
+    /// Whether clients are notified about scores that were likely missed.
+    broadcast_missed_estimate: bool,
+    /// Clients with more queued messages are disconnected.
+    max_client_lag: Option<usize>,
+    /// The history is trimmed if it and the clients' queues take more bytes.
+    memory_soft_limit: Option<usize>,
+    /// Total amount of broadcasted scores.
+    broadcasted: AtomicU64,
 }
 
 impl Context {
-    pub fn new(setup: &Setup) -> Self {
+    /// `max_broadcast_delay` must be the longest delay of all listeners.
+    pub fn new(
+        setup: &Setup,
+        auth: Option<AuthConfig>,
+        max_broadcast_delay: Option<Duration>,
+        sinks: Sinks,
+    ) -> Self {
         Self {
-            history: Mutex::new(Scores::new()),
+            history: Mutex::new(
+                History::new(setup.history_length, setup.history_max_bytes)
+                    .with_compression(setup.compress_history)
+                    .with_legacy_ids(setup.legacy_score_ids),
+            ),
             clients: HashMap::new(),
-            max_history_len: setup.history_length,
+            auth: Auth::new(auth),
+            limiter: Arc::new(RateLimiter::new(setup)),
+            cursor_id: AtomicU64::new(setup.resume_score_id.unwrap_or(0)),
+            max_score_age: setup.max_score_age.map(|minutes| minutes * 60),
+            forward_late_scores: setup.forward_late_scores,
+            loops: Loops::new(),
+            broadcast_delay: setup.broadcast_delay_secs.map(Duration::from_secs),
+            delayed: DelayQueue::new(max_broadcast_delay),
+            reordered: ReorderBuffer::new(Duration::from_millis(setup.reorder_window_ms)),
+            activity: ActivityTracker::new(Duration::from_secs(setup.user_active_window_secs)),
+            anomalies: setup.anomalies.as_ref().map(AnomalyDetector::new),
+            alert_window: Duration::from_secs(setup.alert_window_secs),
+            top: TopScores::new(setup.top_scores, setup.top_scores_window_secs),
+            latency: Latency::new(),
+            user_info: OnceLock::new(),
+            pp: OnceLock::new(),
+            state: ServerState::new(),
+            acks: AckCursors::new(),
+            aggregator: Aggregator::new(unix_now()),
+            validator: setup
+                .validate_scores
+                .then(|| Validator::new(setup.quarantine_file.clone())),
+            sessions: setup
+                .session_grace_secs
+                .map(|secs| Sessions::new(Duration::from_secs(secs))),
+            sinks,
+            unique_client_names: setup.unique_client_names,
+            broadcast_missed_estimate: setup.broadcast_missed_estimate,
+            max_client_lag: setup.max_client_lag,
+            memory_soft_limit: setup.memory_soft_limit_bytes,
+            broadcasted: AtomicU64::new(0),
         }
     }
 
-    pub async fn fetch_scores(ctx: Arc<Self>, osu: Osu, interval: u64, mut cursor_id: Option<u64>) {
-        let Context {
-            clients,
-            history,
-            max_history_len,
-        } = &*ctx;
+    pub const fn loops(&self) -> &Loops {
+        &self.loops
+    }
+
+    pub fn set_user_info(&self, user_info: UserInfo) {
+        let _ = self.user_info.set(user_info);
+    }
+
+    #[cfg_attr(not(feature = "pp"), allow(dead_code))]
+    pub fn set_pp_hook(&self, pp: PpHook) {
+        let _ = self.pp.set(pp);
+    }
+
+    pub const fn state(&self) -> &ServerState {
+        &self.state
+    }
+
+    pub async fn fetch_scores<S: ScoreSource>(
+        ctx: Arc<Self>,
+        handle: Arc<LoopHandle>,
+        source: Arc<S>,
+        mut interval: Interval,
+        mut cursor_id: Option<u64>,
+        mut stream: Option<ScoreStream>,
+        mut dedup: Option<Dedup>,
+    ) {
+        const SCORES_THRESHOLD: usize = 850;
 
-        info!("Fetching scores every {interval} seconds...");
+        info!("Fetching scores every {:?}...", interval.period());
 
-        let mut interval = tokio::time::interval(Duration::from_secs(interval));
         let mut scores = Scores::new();
+        let mut caught_up = false;
 
         loop {
             interval.tick().await;
+            let started_at = Instant::now();
+
+            if handle.wait_until_running().await {
+                // Don't catch up on all ticks that were missed while stopped
+                interval.reset();
+            }
+
+            if !Self::hold_lease(stream.as_mut(), handle.health(), &mut cursor_id).await {
+                continue;
+            }
 
             let prev_cursor_id = cursor_id;
 
-            if let FetchResult::CursorTooOld = osu.fetch_scores(&mut scores, cursor_id).await {
-                if cursor_id.take().is_none() {
+            if let FetchResult::CursorTooOld =
+                source.fetch(&mut scores, cursor_id, handle.health()).await
+            {
+                let Some(expired_cursor_id) = cursor_id.take() else {
                     // This should never happen; bug in osu! api
                     error!("\"cursor too old\" but no cursor specified");
 
                     continue;
-                }
+                };
 
                 tokio::time::sleep(SECOND).await;
 
-                if let FetchResult::CursorTooOld = osu.fetch_scores(&mut scores, cursor_id).await {
+                if let FetchResult::CursorTooOld =
+                    source.fetch(&mut scores, cursor_id, handle.health()).await
+                {
                     // We took the cursor id out previously so this is the same case as above
                     error!("\"cursor too old\" but no cursor specified");
 
                     continue;
                 }
+
+                ctx.report_missed_scores(handle.health(), expired_cursor_id, &scores);
+            }
+
+            // Resuming far behind pages through the backlog in its own phase
+            // instead of gathering all of it within this tick
+            let resumed_far_behind = !std::mem::replace(&mut caught_up, true)
+                && source.catch_up().is_some()
+                && scores.len() >= SCORES_THRESHOLD
+                && prev_cursor_id
+                    .zip(scores.last())
+                    .is_some_and(|(prev, last)| last.id() >= prev + source.id_threshold());
+
+            if let Some(prev_cursor_id) = prev_cursor_id.filter(|_| resumed_far_behind) {
+                let newest = ctx
+                    .catch_up(
+                        &*source,
+                        handle.health(),
+                        &mut scores,
+                        prev_cursor_id,
+                        stream.as_mut(),
+                        dedup.as_mut(),
+                    )
+                    .await;
+
+                cursor_id = Some(newest);
+
+                continue;
             }
 
             loop {
-                const SCORES_THRESHOLD: usize = 850;
-                const ID_THRESHOLD: u64 = 900;
+                let id_threshold = source.id_threshold();
 
                 let next_cursor_id = scores.last().map(Score::id);
                 debug!(?next_cursor_id);
@@ -88,28 +422,32 @@ impl Context {
                     .replace(next_cursor_id)
                     .is_none_or(|prev_cursor_id| {
                         scores.len() < SCORES_THRESHOLD
-                            || next_cursor_id < prev_cursor_id + ID_THRESHOLD
+                            || next_cursor_id < prev_cursor_id + id_threshold
                     })
                 {
                     // If either `cursor_id` was `None`, or we did not receive
                     // at least `SCORES_THRESHOLD` many new scores, or the range
-                    // of most recent score ids is smaller than `ID_THRESHOLD`,
+                    // of most recent score ids is smaller than `id_threshold`,
                     // we stop fetching more scores.
                     //
                     // In other words: `SCORES_THRESHOLD` is only relevant for
                     // the first iteration since `scores.len()` considers scores
-                    // from all iterations. Our `ID_THRESHOLD` needs to be large
-                    // enough so that within our sleep interval (1 second),
-                    // it's very unlikely that the difference to the next score
-                    // id will be greater than our threshold. Additionally,
-                    // the threshold may not be larger than the maximum amount
-                    // of scores sent by the endpoint which is 1000.
+                    // from all iterations. Our `id_threshold` needs to be large
+                    // enough so that within our page delay (1 second by
+                    // default), it's very unlikely that the difference to the
+                    // next score id will be greater than our threshold.
+                    // Additionally, the threshold may not be larger than the
+                    // maximum amount of scores sent by the endpoint which is
+                    // 1000.
                     break;
                 }
 
-                tokio::time::sleep(SECOND).await;
+                tokio::time::sleep(source.page_delay()).await;
 
-                if let FetchResult::CursorTooOld = osu.fetch_scores(&mut scores, cursor_id).await {
+                if let FetchResult::CursorTooOld = source
+                    .fetch_pages(&mut scores, next_cursor_id, id_threshold, handle.health())
+                    .await
+                {
                     // This should never happen
                     error!("The newly fetched cursor id {next_cursor_id} was too old");
 
@@ -117,127 +455,1707 @@ impl Context {
                 }
             }
 
-            let range = scores.range(Score::only_id(prev_cursor_id.map_or(0, |id| id + 1))..);
+            // Only now that the cursor is determined
+            if source.passed_only() {
+                scores.retain(Score::passed);
+            }
+
+            let start = Score::only_id(prev_cursor_id.map_or(0, |id| id + 1));
+            ctx.forward(&mut scores, &start, stream.as_mut(), dedup.as_mut())
+                .await;
+            ctx.cursor_id.store(cursor_id.unwrap_or(0), Relaxed);
+
+            if let Some((stream, cursor_id)) = stream
+                .as_mut()
+                .filter(|stream| stream.has_lease())
+                .zip(cursor_id)
+            {
+                stream.store_cursor(cursor_id).await;
+            }
+
+            let elapsed = started_at.elapsed();
+
+            if elapsed > interval.period() {
+                warn!(?elapsed, "Fetching took longer than the interval");
+            }
+        }
+    }
+
+    /// Forwards the first page of scores after the cursor that was resumed
+    /// from, then pages through the rest of the backlog paced by
+    /// `osu.catch_up` until a page covers less than `id_threshold` ids.
+    ///
+    /// Unlike regular ticks, each page is forwarded right away instead of
+    /// gathering the backlog first so that it may be arbitrarily large.
+    ///
+    /// Returns the id of the newest score.
+    async fn catch_up<S: ScoreSource>(
+        &self,
+        source: &S,
+        health: &Health,
+        scores: &mut Scores,
+        mut cursor_id: u64,
+        mut stream: Option<&mut ScoreStream>,
+        mut dedup: Option<&mut Dedup>,
+    ) -> u64 {
+        let Some(config) = source.catch_up() else {
+            return cursor_id;
+        };
+
+        let id_threshold = source.id_threshold();
+        let pages_per_fetch = source.concurrent_pages() as u64;
+        let delay = Duration::from_secs(60 * pages_per_fetch) / config.requests_per_minute;
+        let started_at = Instant::now();
+        let mut pages = 1;
+        let mut logged_pages = 0;
+        let mut sent = 0;
+
+        info!(cursor_id, "Catching up on scores...");
+
+        loop {
+            let Some(newest) = scores.last().map(Score::id) else {
+                break;
+            };
+
+            let covered = newest.saturating_sub(cursor_id);
+            let behind_secs = scores
+                .last()
+                .and_then(Score::ended_at)
+                .map(|ended_at| unix_now().saturating_sub(ended_at));
+
+            if source.passed_only() {
+                scores.retain(Score::passed);
+            }
+
+            sent += scores.len();
+            let start = Score::only_id(cursor_id + 1);
+
+            self.forward(scores, &start, stream.as_deref_mut(), dedup.as_deref_mut())
+                .await;
+
+            cursor_id = newest;
+            self.cursor_id.store(newest, Relaxed);
+
+            if let Some(stream) = stream.as_deref_mut().filter(|stream| stream.has_lease()) {
+                stream.store_cursor(newest).await;
+            }
+
+            if covered < id_threshold {
+                break;
+            }
+
+            if pages >= logged_pages + config.log_every_pages {
+                logged_pages = pages;
+
+                info!(
+                    pages,
+                    scores = sent,
+                    cursor_id,
+                    ?behind_secs,
+                    "Catching up on scores..."
+                );
+            }
+
+            tokio::time::sleep(delay).await;
+
+            if let FetchResult::CursorTooOld = source
+                .fetch_pages(scores, cursor_id, id_threshold, health)
+                .await
+            {
+                // The next regular fetch reports the missed scores
+                warn!(cursor_id, "Cursor expired while catching up");
+
+                break;
+            }
+
+            pages += pages_per_fetch;
+        }
+
+        info!(
+            pages,
+            scores = sent,
+            elapsed = ?started_at.elapsed(),
+            "Caught up on scores"
+        );
+
+        cursor_id
+    }
+
+    /// Estimates how many scores were missed after a cursor expired based on
+    /// the id gap to the oldest score that was fetched without it.
+    ///
+    /// Score ids are shared across rulesets so the estimate also includes
+    /// scores of other rulesets if `osu.ruleset` is specified.
+    fn report_missed_scores(&self, health: &Health, expired_cursor_id: u64, scores: &Scores) {
+        let Some(oldest) = scores.first() else { return };
+        let missed = oldest.id().saturating_sub(expired_cursor_id + 1);
 
-            let pin = clients.pin();
-            let mut sent = 0;
+        if missed == 0 {
+            return;
+        }
+
+        health.missed_scores(missed);
+
+        warn!(
+            missed,
+            after = expired_cursor_id,
+            until = oldest.id(),
+            "Likely missed scores; consider reducing the interval"
+        );
+
+        if !self.broadcast_missed_estimate {
+            return;
+        }
+
+        let msg = format!(
+            r#"{{"type":"missed_estimate","after":{expired_cursor_id},"until":{},"count":{missed}}}"#,
+            oldest.id()
+        );
+        let msg = Message::Text(msg.into());
+
+        for client in self.clients.pin().values() {
+            if client.is_subscribed(Topic::Scores) {
+                client.send(msg.clone());
+            }
+        }
+    }
+
+    /// Polls the recent scores of each configured user in turn instead of
+    /// fetching all scores.
+    pub async fn poll_users(
+        ctx: Arc<Self>,
+        handle: Arc<LoopHandle>,
+        osu: Arc<Osu>,
+        users: UsersConfig,
+        mut interval: Interval,
+        mut stream: Option<ScoreStream>,
+        mut dedup: Option<Dedup>,
+    ) {
+        info!(
+            users = users.ids.len(),
+            "Polling scores of users every {:?}...",
+            interval.period()
+        );
+
+        // Spreads requests evenly instead of bursting at the start of a cycle
+        let mut rate_limit =
+            tokio::time::interval(Duration::from_mins(1) / users.requests_per_minute);
+        rate_limit.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        // The endpoint has no cursor so the newest score id of each user is
+        // tracked to skip already forwarded scores, starting off at
+        // `resume_score_id`
+        let mut cursors = vec![ctx.cursor_id.load(Relaxed); users.ids.len()];
+        let mut scores = Scores::new();
 
-            for score in range {
-                sent += 1;
+        loop {
+            interval.tick().await;
+
+            for (&user_id, cursor) in users.ids.iter().zip(cursors.iter_mut()) {
+                handle.wait_until_running().await;
+                rate_limit.tick().await;
+
+                match osu
+                    .fetch_user_scores(user_id, &mut scores, handle.health())
+                    .await
+                {
+                    FetchResult::Ok => {}
+                    FetchResult::NotFound => {
+                        warn!(user_id, "User not found, skipping");
+
+                        continue;
+                    }
+                    // The endpoint has no cursor so the osu!api changed;
+                    // continue from the newest forwarded score
+                    FetchResult::CursorTooOld => {
+                        *cursor = ctx.cursor_id.load(Relaxed);
+                        scores.clear();
+                        warn!(
+                            user_id,
+                            cursor = *cursor,
+                            "Unexpected \"cursor too old\" for user scores, resetting its cursor"
+                        );
+
+                        continue;
+                    }
+                }
 
-                for tx in pin.values() {
-                    let _: Result<_, _> = tx.send(score.as_message());
+                scores.retain(|score| score.id > *cursor);
+
+                let Some(last) = scores.last() else {
+                    continue;
+                };
+
+                *cursor = last.id;
+                ctx.cursor_id.fetch_max(last.id, Relaxed);
+
+                if osu.passed_only() {
+                    scores.retain(Score::passed);
                 }
+
+                let start = Score::only_id(0);
+                ctx.forward(&mut scores, &start, stream.as_mut(), dedup.as_mut())
+                    .await;
+            }
+        }
+    }
+
+    /// Reads scores from a redis stream instead of fetching them.
+    pub async fn consume_scores(
+        ctx: Arc<Self>,
+        handle: Arc<LoopHandle>,
+        mut stream: ScoreStream,
+        mut dedup: Option<Dedup>,
+    ) {
+        info!("Consuming scores from redis...");
+
+        let mut scores = Scores::new();
+
+        loop {
+            handle.wait_until_running().await;
+            stream.read(&mut scores, handle.health()).await;
+
+            if let Some(score) = scores.last() {
+                ctx.cursor_id.store(score.id, Relaxed);
             }
 
-            info!("Sent {sent} scores to {} client(s)", clients.len());
+            ctx.remove_invalid_scores(&mut scores).await;
+            ctx.filter_late_scores(&mut scores);
 
-            let mut history = history.lock().unwrap();
-            history.append(&mut scores);
+            let start = Score::only_id(0);
 
-            while history.len() > *max_history_len {
-                history.pop_first();
+            if let Some(ref mut dedup) = dedup {
+                ctx.deduplicate(dedup, &mut scores, &start).await;
             }
 
-            debug!(history_len = history.len());
+            ctx.broadcast(&mut scores, &start);
         }
     }
 
-    pub async fn handle_connection(ctx: Arc<Self>, (stream, addr): (TcpStream, SocketAddr)) {
-        trace!(%addr, "Incoming TCP connection from");
+    /// Merges and deduplicates the scores of all upstreams and forwards them.
+    pub async fn join_upstreams(
+        ctx: Arc<Self>,
+        mut rx: mpsc::Receiver<Scores>,
+        mut stream: Option<ScoreStream>,
+        mut dedup: Dedup,
+    ) {
+        while let Some(mut scores) = rx.recv().await {
+            // Other upstreams may have sent scores in the meantime
+            while let Ok(mut more) = rx.try_recv() {
+                scores.append(&mut more);
+            }
+
+            if let Some(score) = scores.last() {
+                ctx.cursor_id.fetch_max(score.id, Relaxed);
+            }
 
-        let ws_stream = match tokio_tungstenite::accept_async(stream).await {
-            Ok(stream) => stream,
-            Err(err) => return error!(?err, "Error during the websocket handshake"),
+            let start = Score::only_id(0);
+            ctx.forward(&mut scores, &start, stream.as_mut(), Some(&mut dedup))
+                .await;
+        }
+    }
+
+    /// Returns whether this instance may fetch, i.e. it either holds the lease
+    /// or there is none. Upon acquiring the lease, fetching continues from the
+    /// stored cursor.
+    async fn hold_lease(
+        stream: Option<&mut ScoreStream>,
+        health: &Health,
+        cursor_id: &mut Option<u64>,
+    ) -> bool {
+        let Some(stream) = stream.filter(|stream| stream.has_lease()) else {
+            return true;
         };
 
-        trace!(%addr, "WebSocket connection established");
+        match stream.lease().await {
+            Lease::Acquired { cursor_id: stored } => {
+                health.standby(false);
+
+                if stored.is_some() {
+                    *cursor_id = stored;
+                }
 
-        let (tx, mut rx) = mpsc::unbounded_channel();
-        ctx.clients.pin().insert(addr, tx.clone());
+                true
+            }
+            Lease::Renewed => true,
+            Lease::Standby => {
+                // Waiting is all a standby is supposed to do
+                health.standby(true);
+                health.success();
 
-        let (mut outgoing, mut incoming) = ws_stream.split();
+                false
+            }
+        }
+    }
 
-        let initial_fut = tokio::time::timeout(Duration::from_secs(5), incoming.next());
+    /// Filters, deduplicates, and publishes fetched scores, then broadcasts
+    /// all scores from `start` onwards.
+    #[instrument(level = "debug", skip_all, fields(scores = scores.len()))]
+    async fn forward(
+        &self,
+        scores: &mut Scores,
+        start: &Score,
+        stream: Option<&mut ScoreStream>,
+        dedup: Option<&mut Dedup>,
+    ) {
+        self.remove_invalid_scores(scores).await;
+        self.filter_late_scores(scores);
 
-        let Ok(initial) = initial_fut.await else {
-            let err = "Require initial message containing either `\"connect\"` \
-                or a score id to resume from";
-            let _: Result<_, _> = outgoing.send(Message::Text(err.into())).await;
-            info!("Disconnecting from {addr} due to missing initial message");
+        if let Some(dedup) = dedup {
+            self.deduplicate(dedup, scores, start).await;
+        }
+
+        if let Some(user_info) = self.user_info.get() {
+            user_info.enrich(scores, start).await;
+        }
+
+        if let Some(pp) = self.pp.get() {
+            pp.fill(scores, start).await;
+        }
+
+        if let Some(stream) = stream {
+            stream.publish(scores.range(start..)).await;
+        }
 
+        self.broadcast(scores, start);
+    }
+
+    /// Moves scores that were broadcasted before, possibly before a restart,
+    /// straight into the history and persists the ids of all others if the
+    /// dedup has a file.
+    async fn deduplicate(&self, dedup: &mut Dedup, scores: &mut Scores, start: &Score) {
+        let known = dedup.remove_known(scores, start);
+
+        if !known.is_empty() {
+            debug!(count = known.len(), "Skipping already broadcasted scores");
+
+            // Newly connecting clients may still want them
+            self.history.lock().unwrap().extend(known);
+        } else if scores.range(start..).next().is_none() {
+            return;
+        }
+
+        let Some(path) = dedup.path() else {
             return;
         };
 
-        let resume_id = match initial.map(|res| res.map(Event::try_from)) {
-            Some(Ok(Ok(Event::Connect))) => {
-                info!(%addr, "Connect");
+        let tmp = path.with_extension("tmp");
 
-                None
-            }
-            Some(Ok(Ok(Event::Resume { score_id }))) => {
-                info!(score_id, %addr, "Resume");
+        let res = async {
+            tokio::fs::write(&tmp, dedup.to_bytes()).await?;
+            tokio::fs::rename(&tmp, path).await
+        };
 
-                Some(score_id)
-            }
-            Some(Ok(Err(err))) => {
-                let _: Result<_, _> = outgoing.send(Message::Text(err.to_string().into())).await;
+        if let Err(err) = res.await {
+            warn!(?err, "Failed to persist ids of broadcasted scores");
+        }
+    }
 
-                return;
-            }
-            Some(Err(err)) => return error!(?err, "Failed to receive initial message"),
-            None => return,
+    async fn remove_invalid_scores(&self, scores: &mut Scores) {
+        if let Some(ref validator) = self.validator {
+            validator.remove_invalid(scores).await;
+        }
+    }
+
+    /// Removes scores that exceed the configured `max_score_age` and forwards
+    /// them to clients that are subscribed to late scores.
+    fn filter_late_scores(&self, scores: &mut Scores) {
+        let Some(max_score_age) = self.max_score_age else {
+            return;
         };
 
-        ctx.send_history(resume_id, addr, &tx);
+        let late = Self::remove_late_scores(scores, max_score_age);
 
-        let forward_fut = futures_util::stream::poll_fn(|cx| rx.poll_recv(cx))
-            .map(Ok)
-            .forward(&mut outgoing);
+        if late.is_empty() {
+            return;
+        }
 
-        let await_disconnect = incoming.try_any(|msg| {
-            let bytes = match msg {
-                Message::Text(ref bytes) => bytes.as_bytes(),
-                Message::Binary(ref bytes) => bytes,
-                _ => return futures_util::future::ready(false),
-            };
+        debug!(count = late.len(), "Removed late scores");
 
-            futures_util::future::ready(bytes == b"disconnect")
-        });
+        if !self.forward_late_scores {
+            return;
+        }
+
+        let pin = self.clients.pin();
+        let mut projections = Projections::default();
 
-        tokio::select! {
-            _ = forward_fut => {},
-            res = await_disconnect => {
-                if matches!(res, Ok(true)) {
-                    ctx.process_disconnect(&mut outgoing).await;
+        for score in &late {
+            for client in pin.values() {
+                if client.is_subscribed(Topic::Late) {
+                    client.send_score(score, &mut projections);
                 }
-            },
+            }
         }
+    }
+
+    /// Sends JSON events to all clients subscribed to the topic.
+    fn send_events(&self, events: Vec<String>, topic: Topic) {
+        let pin = self.clients.pin();
 
-        info!("{addr} disconnected");
-        ctx.clients.pin().remove(&addr);
+        for event in events {
+            // Text frames share their bytes when cloned
+            let msg = Message::Text(event.into());
+
+            for client in pin.values() {
+                if client.is_subscribed(topic) {
+                    client.send(msg.clone());
+                }
+            }
+        }
     }
 
-    fn send_history(&self, resume_id: Option<u64>, addr: SocketAddr, tx: &Sender) {
-        let range = Score::only_id(resume_id.map_or(0, |id| id + 1))..;
+    /// Sends all scores starting from `start` to clients and moves all scores
+    /// into the history.
+    pub fn broadcast(&self, scores: &mut Scores, start: &Score) {
+        let _span = debug_span!("broadcast", scores = scores.len()).entered();
+
+        // Scores are added to the history before they're sent so that clients
+        // registering meanwhile receive them through either
+        {
+            let mut history = self.history.lock().unwrap();
+            history.extend(scores.iter().cloned());
+
+            debug!(history_len = history.len(), history_bytes = history.bytes());
+        }
+
+        let pin = self.clients.pin();
+        let mut projections = Projections::default();
         let mut sent = 0;
 
-        for score in self.history.lock().unwrap().range(range) {
+        for score in scores.range(start..) {
             sent += 1;
-            let _: Result<_, _> = tx.send(score.as_message());
+
+            for client in pin.values() {
+                let live = client.is_subscribed(Topic::Scores)
+                    && client.delay().is_none()
+                    && !client.is_ordered();
+
+                if live || (client.is_subscribed(Topic::Alerts) && client.is_alert(score)) {
+                    client.send_score(score, &mut projections);
+                }
+            }
+        }
+
+        let latency = self.latency.track(scores.range(start..), unix_millis());
+
+        info!(
+            latency_median_ms = ?latency.map(|latency| latency.median_ms),
+            latency_max_ms = ?latency.map(|latency| latency.max_ms),
+            "Sent {sent} scores to {} client(s)",
+            pin.len()
+        );
+        self.broadcasted.fetch_add(sent, Relaxed);
+
+        let events = self.activity.track(scores.range(start..), Instant::now());
+        self.send_events(events, Topic::UserActive);
+
+        if let Some(ref detector) = self.anomalies {
+            let events = detector.track(scores.range(start..));
+            self.send_events(events, Topic::Anomalies);
         }
 
-        info!(%addr, "Sent {sent} scores from the history");
+        self.aggregator.track(scores.range(start..));
+        self.top.track(scores.range(start..), unix_now());
+        self.sinks.send(scores.range(start..));
+        self.delayed.push(scores.range(start..));
+        self.reordered.push(scores.range(start..));
+        scores.clear();
     }
 
-    async fn process_disconnect(&self, outgoing: &mut Outgoing) {
-        info!("Processing disconnect...");
+    /// Sends scores to ordered clients once they were held back long enough
+    /// to be sorted.
+    pub async fn deliver_ordered(ctx: Arc<Self>) {
+        let mut interval = tokio::time::interval(Duration::from_millis(100));
 
-        let id = self.history.lock().unwrap().last().map_or(0, Score::id);
-        let msg = Message::Text(itoa::Buffer::new().format(id).into());
+        loop {
+            interval.tick().await;
+            let scores = ctx.reordered.release(Instant::now());
 
-        if let Err(err) = outgoing.send(msg).await {
-            warn!(?err, "Failed to send score id {id} on disconnect");
+            if scores.is_empty() {
+                continue;
+            }
+
+            let pin = ctx.clients.pin();
+            let mut projections = Projections::default();
+
+            for score in &scores {
+                for client in pin.values() {
+                    if client.is_subscribed(Topic::Scores)
+                        && client.delay().is_none()
+                        && client.is_ordered()
+                    {
+                        client.send_score(score, &mut projections);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sends scores to delayed clients once they're due.
+    pub async fn deliver_delayed(ctx: Arc<Self>) {
+        let mut interval = tokio::time::interval(SECOND);
+
+        loop {
+            interval.tick().await;
+            let now = Instant::now();
+
+            for client in ctx.clients.pin().values() {
+                if let Some(delay) = client.delay() {
+                    if client.is_subscribed(Topic::Scores) {
+                        ctx.delayed.deliver(client, delay, now);
+                    }
+                }
+            }
+
+            ctx.delayed.prune(now);
+        }
+    }
+
+    /// Sends a rollup of the past minute's scores to subscribed clients.
+    pub async fn emit_aggregates(ctx: Arc<Self>) {
+        let mut interval = tokio::time::interval(Duration::from_mins(1));
+
+        // The first tick completes immediately
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+            let msg = Message::Text(ctx.aggregator.flush(unix_now()).into());
+
+            for client in ctx.clients.pin().values() {
+                if client.is_subscribed(Topic::Aggregates) {
+                    client.send(msg.clone());
+                }
+            }
+        }
+    }
+
+    /// Periodically sends clients `{"type":"checkpoint","id":123}` with the
+    /// id of their last queued score, unless it didn't change.
+    pub async fn emit_checkpoints(ctx: Arc<Self>, period: Duration) {
+        let mut interval = tokio::time::interval(period);
+
+        // The first tick completes immediately
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+
+            for client in ctx.clients.pin().values() {
+                if !client.is_subscribed(Topic::Scores) {
+                    continue;
+                }
+
+                if let Some(id) = client.next_checkpoint() {
+                    let msg = format!(r#"{{"type":"checkpoint","id":{id}}}"#);
+                    client.send(Message::Text(msg.into()));
+                }
+            }
         }
     }
+
+    /// Updates the server phase based on the health of loops and notifies
+    /// clients whenever a loop paused fetching.
+    pub async fn evaluate_state(ctx: Arc<Self>) {
+        let mut interval = tokio::time::interval(SECOND);
+        let mut circuit_open = false;
+        let mut osu_down = false;
+        let mut over_memory_limit = false;
+
+        loop {
+            interval.tick().await;
+            ctx.state.evaluate(&ctx.loops);
+
+            if let Some(limit) = ctx.memory_soft_limit {
+                over_memory_limit = ctx.enforce_memory_limit(limit, over_memory_limit);
+            }
+
+            let prev = std::mem::replace(&mut circuit_open, ctx.loops.any_circuit_open());
+
+            if circuit_open && !prev {
+                ctx.send_to_all(r#"{"type":"degraded"}"#);
+            }
+
+            let prev = std::mem::replace(&mut osu_down, ctx.loops.any_osu_down());
+
+            match (prev, osu_down) {
+                (false, true) => ctx.send_to_all(r#"{"type":"status","state":"osu_down"}"#),
+                (true, false) => ctx.send_to_all(r#"{"type":"status","state":"recovered"}"#),
+                _ => {}
+            }
+        }
+    }
+
+    /// Trims the history if it and the clients' queues exceed the soft
+    /// memory limit and returns whether they did.
+    ///
+    /// Clients are only warned when the limit wasn't exceeded before.
+    fn enforce_memory_limit(&self, limit: usize, was_exceeded: bool) -> bool {
+        let (history_bytes, queued_bytes) = self.memory_usage();
+        let bytes = history_bytes + queued_bytes;
+
+        if bytes <= limit {
+            return false;
+        }
+
+        // Leave room for the history to grow again before the next trim
+        let max_history_bytes = limit.saturating_sub(queued_bytes) / 2;
+        let evicted = self.history.lock().unwrap().trim_bytes(max_history_bytes);
+
+        if evicted > 0 || !was_exceeded {
+            warn!(bytes, limit, evicted, "Exceeded the soft memory limit");
+        }
+
+        if !was_exceeded {
+            let json = format!(
+                r#"{{"type":"warning","reason":"memory","bytes":{bytes},"limit":{limit}}}"#
+            );
+            let msg = Message::Text(json.into());
+
+            for client in self.clients.pin().values() {
+                client.send(msg.clone());
+            }
+        }
+
+        true
+    }
+
+    fn send_to_all(&self, json: &'static str) {
+        let msg = Message::Text(json.into());
+
+        for client in self.clients.pin().values() {
+            client.send(msg.clone());
+        }
+    }
+
+    /// Asks all clients to close their connection.
+    pub fn close_clients(&self) {
+        let close = Message::Close(Some(CloseFrame {
+            code: CloseCode::Away,
+            reason: "Server is shutting down".into(),
+        }));
+
+        for client in self.clients.pin().values() {
+            client.send(close.clone());
+        }
+    }
+
+    /// Clients of listeners without `auth` are granted all permissions.
+    pub async fn handle_connection(ctx: Arc<Self>, (stream, addr): (Stream, Peer), auth: bool) {
+        trace!(%addr, "Incoming connection");
+
+        let phase = ctx.state.phase();
+
+        if !phase.accepts_connections() {
+            return info!(%addr, %phase, "Rejecting connection");
+        }
+
+        let Some(permit) = ctx.limiter.connect(addr.ip()) else {
+            return warn!(%addr, "Rejecting connection due to rate limits");
+        };
+
+        let Some((mut ws_stream, permissions, mut options)) =
+            ctx.accept_websocket(stream, addr, auth).await
+        else {
+            return;
+        };
+
+        if ctx.limiter.exceeds_max_clients() {
+            warn!(%addr, "Rejecting connection because the maximum amount of clients is reached");
+
+            let close = Message::Close(Some(CloseFrame {
+                code: CloseCode::Again,
+                reason: "Too many clients, try again later".into(),
+            }));
+
+            let _: Result<_, _> = ws_stream.send(close).await;
+
+            return;
+        }
+
+        let (mut outgoing, mut incoming) = ws_stream.split();
+
+        let connection = match options.session.take() {
+            Some(SessionRequest::Resume(token)) => {
+                ctx.resume_session(token, addr, &mut outgoing).await
+            }
+            session => {
+                let session = session
+                    .and(ctx.sessions.as_ref())
+                    .and_then(|_| Sessions::new_token());
+
+                ctx.connect_client(
+                    addr,
+                    permissions,
+                    options,
+                    session,
+                    &mut incoming,
+                    &mut outgoing,
+                )
+                .await
+            }
+        };
+
+        let Some(mut connection) = connection else {
+            return;
+        };
+
+        let dropped = ctx
+            .serve_client(addr, &mut connection, &mut incoming, &mut outgoing)
+            .await;
+
+        match connection.session.take() {
+            Some(token) if dropped => Self::park_session(&ctx, addr, token, connection, permit),
+            _ => {
+                info!("{addr} disconnected");
+                ctx.remove_client(addr);
+            }
+        }
+    }
+
+    /// Receives the initial message, then creates and registers the client.
+    async fn connect_client(
+        self: &Arc<Self>,
+        addr: Peer,
+        permissions: Permissions,
+        options: ConnectOptions,
+        session: Option<Box<str>>,
+        incoming: &mut Incoming,
+        outgoing: &mut Outgoing,
+    ) -> Option<Connection> {
+        let event = self.receive_initial(incoming, outgoing, addr).await?;
+
+        if !permissions.allows(event.op()) {
+            ErrorFrame::PERMISSION_DENIED.send(outgoing).await;
+            info!(%addr, "Disconnecting due to missing permission");
+
+            return None;
+        }
+
+        if matches!(event, Event::ResumeLegacy { .. })
+            && !self.history.lock().unwrap().maps_legacy_ids()
+        {
+            ErrorFrame::LEGACY_IDS_DISABLED.send(outgoing).await;
+            info!(%addr, "Disconnecting due to unmapped legacy score ids");
+
+            return None;
+        }
+
+        if let Some(ref token) = session {
+            if let Err(err) = outgoing.send(self.session_message(token, false)).await {
+                warn!(?err, %addr, "Failed to send session token");
+
+                return None;
+            }
+        }
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let client = Client::new(tx, permissions, options.fields, self.broadcast_delay)
+            .with_name(options.client_name)
+            .with_ordered(options.ordered)
+            .with_meta(options.meta)
+            .with_compressed_replay(event.compressed_replay())
+            .with_ack(options.ack.is_some());
+        let client = Arc::new(client);
+
+        if let Some(partition) = options.partition {
+            client.set_partition(partition);
+        }
+
+        client.set_rulesets(options.rulesets);
+        client.set_sample(options.sample);
+        client.set_rate_cap(options.max_per_sec.and_then(RateCap::new));
+
+        for condition in options.conditions {
+            client.set_condition(condition);
+        }
+
+        for beatmaps in options.beatmaps {
+            client.set_beatmaps(beatmaps);
+        }
+
+        client.update_mods(options.mods);
+
+        let resume = self.subscribe(&client, event, addr, options.ack.as_ref());
+
+        self.check_client_name(addr, &client);
+        self.add_client(addr, &client, resume);
+
+        Some(Connection {
+            client,
+            rx,
+            ack: options.ack,
+            idle_timeout: options.idle_timeout,
+            session,
+        })
+    }
+
+    /// Continues a parked session with its queue as is.
+    async fn resume_session(
+        &self,
+        token: Box<str>,
+        addr: Peer,
+        outgoing: &mut Outgoing,
+    ) -> Option<Connection> {
+        let parked = self
+            .sessions
+            .as_ref()
+            .and_then(|sessions| sessions.resume(&token));
+
+        let Some(parked) = parked else {
+            ErrorFrame::SESSION_EXPIRED.send(outgoing).await;
+            info!(%addr, "Disconnecting due to unknown session");
+
+            return None;
+        };
+
+        info!(%addr, prev_addr = %parked.addr, "Resume session");
+
+        {
+            let clients = self.clients.pin();
+            clients.remove(&parked.addr);
+            clients.insert(addr, Arc::clone(&parked.client));
+        }
+
+        let connection = Connection {
+            client: parked.client,
+            rx: parked.rx,
+            ack: parked.ack,
+            idle_timeout: parked.idle_timeout,
+            session: Some(token),
+        };
+
+        let msg = self.session_message(connection.session.as_deref()?, true);
+
+        if let Err(err) = outgoing.send(msg).await {
+            warn!(?err, %addr, "Failed to confirm resumed session");
+        }
+
+        Some(connection)
+    }
+
+    /// Forwards the client's queue until either side disconnects.
+    ///
+    /// Returns `true` if the connection dropped without a disconnect.
+    async fn serve_client(
+        &self,
+        addr: Peer,
+        connection: &mut Connection,
+        incoming: &mut Incoming,
+        outgoing: &mut Outgoing,
+    ) -> bool {
+        let Connection {
+            client,
+            rx,
+            ack,
+            idle_timeout,
+            ..
+        } = connection;
+
+        let messages = futures_util::stream::poll_fn(|cx| rx.poll_recv(cx))
+            .inspect(|msg| client.dequeued(msg));
+
+        #[cfg(not(feature = "chaos"))]
+        let forward_fut = messages.map(Ok).forward(&mut *outgoing);
+        #[cfg(feature = "chaos")]
+        let forward_fut = messages
+            .map(crate::chaos::Chaos::client_send)
+            .forward(&mut *outgoing);
+
+        tokio::pin!(forward_fut);
+
+        let await_disconnect = async {
+            loop {
+                let next = match *idle_timeout {
+                    Some(timeout) => match tokio::time::timeout(timeout, incoming.next()).await {
+                        Ok(next) => next,
+                        Err(_) => return Some(Disconnect::Idle),
+                    },
+                    None => incoming.next().await,
+                };
+
+                let Some(Ok(msg)) = next else {
+                    return None;
+                };
+
+                if let Message::Close(_) = msg {
+                    return Some(Disconnect::Closed);
+                }
+
+                if !self.limiter.message(addr.ip()) {
+                    return Some(Disconnect::RateLimited);
+                }
+
+                match Command::parse(&msg) {
+                    Some(Command::Disconnect) => return Some(Disconnect::Requested),
+                    Some(command) => {
+                        self.process_command(client, command, ack.as_ref()).await;
+                    }
+                    None => {}
+                }
+            }
+        };
+
+        let disconnect = tokio::select! {
+            _ = &mut forward_fut => None,
+            disconnect = await_disconnect => disconnect,
+            lag = self.await_lagging(client) => Some(Disconnect::Lagging(lag)),
+        };
+
+        match disconnect {
+            Some(Disconnect::Requested) => self.process_disconnect(outgoing).await,
+            Some(Disconnect::RateLimited) => self.process_rate_limited(addr, outgoing).await,
+            Some(Disconnect::Idle) => {
+                info!(%addr, "Disconnecting due to inactivity");
+                client.send(self.idle_close_frame(client));
+
+                // Forward the remaining queue including the close frame
+                let _ = tokio::time::timeout(Duration::from_secs(5), forward_fut).await;
+            }
+            Some(Disconnect::Lagging(lag)) => self.process_lagging(addr, lag, outgoing).await,
+            Some(Disconnect::Closed) => {}
+            None => return true,
+        }
+
+        false
+    }
+
+    /// Resolves with the client's lag once it exceeds `max_client_lag`.
+    async fn await_lagging(&self, client: &Client) -> usize {
+        let Some(max_lag) = self.max_client_lag else {
+            return std::future::pending().await;
+        };
+
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+
+        loop {
+            interval.tick().await;
+            let lag = client.lag();
+
+            if lag > max_lag {
+                return lag;
+            }
+        }
+    }
+
+    /// Keeps the client registered so it can resume its session within the
+    /// grace period unless its queue exceeds `max_client_lag` before.
+    fn park_session(
+        ctx: &Arc<Self>,
+        addr: Peer,
+        token: Box<str>,
+        connection: Connection,
+        permit: ConnectionPermit,
+    ) {
+        let Some(ref sessions) = ctx.sessions else {
+            return;
+        };
+
+        info!(%addr, "Connection dropped; parking session");
+
+        let Connection {
+            client,
+            rx,
+            ack,
+            idle_timeout,
+            ..
+        } = connection;
+
+        let parked = Parked::new(addr, Arc::clone(&client), rx, ack, idle_timeout, permit);
+        let epoch = sessions.park(token.clone(), parked);
+        let grace = sessions.grace();
+        let ctx = Arc::clone(ctx);
+
+        tokio::spawn(async move {
+            let lag = tokio::select! {
+                () = tokio::time::sleep(grace) => None,
+                lag = ctx.await_lagging(&client) => Some(lag),
+            };
+
+            let expired = ctx
+                .sessions
+                .as_ref()
+                .and_then(|sessions| sessions.expire(&token, epoch));
+
+            if let Some(parked) = expired {
+                match lag {
+                    Some(lag) => info!(addr = %parked.addr, lag, "Evicting lagging parked session"),
+                    None => info!(addr = %parked.addr, "Session expired"),
+                }
+
+                ctx.remove_client(parked.addr);
+            }
+        });
+    }
+
+    /// `{"type":"session","token":"...","grace_secs":60,"resumed":false}`
+    fn session_message(&self, token: &str, resumed: bool) -> Message {
+        let grace = self.sessions.as_ref().map_or(0, |s| s.grace().as_secs());
+
+        let json = format!(
+            r#"{{"type":"session","token":"{token}","grace_secs":{grace},"resumed":{resumed}}}"#
+        );
+
+        Message::Text(json.into())
+    }
+
+    /// Waits for the client's initial message.
+    async fn receive_initial(
+        &self,
+        incoming: &mut Incoming,
+        outgoing: &mut Outgoing,
+        addr: Peer,
+    ) -> Option<Event> {
+        let initial_fut = tokio::time::timeout(Duration::from_secs(5), incoming.next());
+
+        let Ok(initial) = initial_fut.await else {
+            ErrorFrame::INITIAL_TIMEOUT.send(outgoing).await;
+            info!("Disconnecting from {addr} due to missing initial message");
+
+            return None;
+        };
+
+        if !self.limiter.message(addr.ip()) {
+            self.process_rate_limited(addr, outgoing).await;
+
+            return None;
+        }
+
+        match initial {
+            Some(Ok(msg)) => match Event::try_from(msg) {
+                Ok(event) => Some(event),
+                Err(err) => {
+                    err.send(outgoing).await;
+
+                    None
+                }
+            },
+            Some(Err(err)) => {
+                error!(?err, "Failed to receive initial message");
+
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Handles all commands except for `"disconnect"`.
+    async fn process_command(&self, client: &Client, command: Command, ack: Option<&CursorName>) {
+        match command {
+            Command::Disconnect => {}
+            Command::Stats | Command::Top if !client.permissions().allows(Op::Stats) => {
+                client.send(ErrorFrame::PERMISSION_DENIED.to_message());
+            }
+            Command::Stats => {
+                let stats = client.stats(self.cursor_id());
+                client.send(Message::Text(stats.into()));
+            }
+            Command::Top => {
+                let json = self.top.to_json(unix_now());
+                client.send(Message::Text(json.into()));
+            }
+            Command::Cursor => {
+                let newest = self.history.lock().unwrap().last_id();
+
+                let json = match newest {
+                    Some(id) => format!(r#"{{"type":"cursor","newest_score_id":{id}}}"#),
+                    None => r#"{"type":"cursor","newest_score_id":null}"#.to_owned(),
+                };
+
+                client.send(Message::Text(json.into()));
+            }
+            Command::Fields(fields) => {
+                client.set_fields(Some(fields).filter(|fields| !fields.is_empty()));
+            }
+            Command::Partition(partition) => client.set_partition(partition),
+            Command::Filter(condition) => client.set_condition(condition),
+            Command::Beatmaps(beatmaps) => client.set_beatmaps(beatmaps),
+            Command::Mods(update) => client.update_mods(update),
+            Command::Rulesets(rulesets) => client.set_rulesets(rulesets),
+            Command::Sample(sample) => client.set_sample(Some(sample)),
+            Command::MaxPerSec(count) => client.set_rate_cap(RateCap::new(count)),
+            Command::Ack(score_id) => {
+                if let Some((name, cursor)) = ack.zip(client.ack(score_id)) {
+                    self.acks.advance(name, cursor);
+                }
+            }
+            Command::Replay { .. } if !client.permissions().allows(Op::Resume) => {
+                client.send(ErrorFrame::PERMISSION_DENIED.to_message());
+            }
+            Command::Replay { from, to } => self.replay_range(client, from, to).await,
+        }
+    }
+
+    /// Sends the scores of the history from `from` to `to` again, followed by
+    /// `{"type":"replay_done","from":1,"to":5,"sent":3}`.
+    ///
+    /// Scores broadcasted meanwhile are not held back since the client
+    /// receives them regardless of the replay.
+    async fn replay_range(&self, client: &Client, from: u64, to: u64) {
+        let history = self.history.lock().unwrap().snapshot();
+
+        if let Some(oldest) = history.first_id().filter(|&oldest| from < oldest) {
+            let notice = format!(r#"{{"type":"replay_truncated","oldest":{oldest}}}"#);
+            client.send(Message::Text(notice.into()));
+        }
+
+        let mut projections = Projections::default();
+        let mut sent = 0;
+        let scores = history
+            .range_from(from)
+            .take_while(|score| score.id() <= to);
+
+        for (i, score) in scores.enumerate() {
+            sent += usize::from(client.send_matching(&score, &mut projections, Origin::History));
+
+            if (i + 1) % YIELD_EVERY == 0 {
+                if client.is_closed() {
+                    return;
+                }
+
+                tokio::task::yield_now().await;
+            }
+        }
+
+        let done = format!(r#"{{"type":"replay_done","from":{from},"to":{to},"sent":{sent}}}"#);
+        client.send(Message::Text(done.into()));
+    }
+
+    /// Subscribes the client based on its initial message and returns the
+    /// score id to resume from.
+    ///
+    /// Clients in ack mode that connect resume from their ack cursor.
+    fn subscribe(
+        &self,
+        client: &Client,
+        event: Event,
+        addr: Peer,
+        ack: Option<&CursorName>,
+    ) -> Option<ResumeCursors> {
+        match event {
+            Event::Connect { .. } => {
+                let cursor = ack.and_then(|name| self.acks.get(name));
+                info!(%addr, ack = ack.map(CursorName::name), cursor, "Connect");
+
+                cursor.map(ResumeCursors::global)
+            }
+            Event::Resume { score_id, .. } => {
+                info!(score_id, %addr, "Resume");
+
+                Some(ResumeCursors::global(score_id))
+            }
+            Event::ResumeLegacy {
+                ruleset_id,
+                legacy_score_id,
+                ..
+            } => {
+                // Unknown legacy ids resume from the start of the history
+                // which also notifies the client that it's truncated
+                let score_id = self.legacy_to_id(ruleset_id, legacy_score_id);
+
+                info!(ruleset_id, legacy_score_id, score_id, %addr, "Resume by legacy id");
+
+                Some(ResumeCursors::global(score_id.unwrap_or(0)))
+            }
+            Event::ResumeRulesets { cursors } => {
+                info!(oldest = cursors.oldest(), %addr, "Resume per ruleset");
+
+                Some(cursors)
+            }
+            Event::Late => {
+                info!(%addr, "Late");
+                client.subscribe_only(Topic::Late);
+
+                None
+            }
+            Event::UserActive => {
+                info!(%addr, "User active");
+                client.subscribe_only(Topic::UserActive);
+
+                None
+            }
+            Event::Aggregates => {
+                info!(%addr, "Stats");
+                client.subscribe_only(Topic::Aggregates);
+
+                None
+            }
+            Event::Anomalies => {
+                info!(%addr, "Anomalies");
+                client.subscribe_only(Topic::Anomalies);
+
+                None
+            }
+            Event::Alerts { min_pp } => {
+                info!(%addr, min_pp, "Alerts");
+                client.set_alerts(Alerts::new(min_pp, self.alert_window));
+                client.subscribe_only(Topic::Alerts);
+
+                None
+            }
+        }
+    }
+
+    /// Registers a client and, if it's subscribed to scores, starts replaying
+    /// the history to it.
+    pub fn add_client(
+        self: &Arc<Self>,
+        addr: Peer,
+        client: &Arc<Client>,
+        resume: Option<ResumeCursors>,
+    ) {
+        let replay = client.is_subscribed(Topic::Scores);
+
+        if replay {
+            client.start_replay();
+        }
+
+        self.clients.pin().insert(addr, Arc::clone(client));
+
+        if !replay {
+            return;
+        }
+
+        // Scores that are not yet due for a delayed client must not be sent
+        // through the history either
+        let not_due = match client.delay() {
+            Some(delay) => {
+                let (next_seq, not_due) = self.delayed.not_due(delay, Instant::now());
+                client.set_next_seq(next_seq);
+
+                not_due
+            }
+            None => Vec::new(),
+        };
+
+        let fut = Arc::clone(self).replay_history(resume, addr, Arc::clone(client), not_due);
+        tokio::spawn(fut);
+    }
+
+    pub fn remove_client(&self, addr: Peer) {
+        self.clients.pin().remove(&addr);
+    }
+
+    /// Warns about or closes other connections with the client's name.
+    fn check_client_name(&self, addr: Peer, client: &Client) {
+        let Some(name) = client.name() else { return };
+
+        for (&prev_addr, prev) in &self.clients.pin() {
+            if prev_addr == addr || prev.name() != Some(name) {
+                continue;
+            }
+
+            if self.unique_client_names {
+                info!(%addr, %prev_addr, name, "Closing older connection with the same client name");
+
+                prev.send(Message::Close(Some(CloseFrame {
+                    code: CloseCode::Policy,
+                    reason: "Replaced by a newer connection with the same client_name".into(),
+                })));
+            } else {
+                warn!(%addr, %prev_addr, name, "Multiple connections with the same client name");
+            }
+        }
+    }
+
+    pub fn broadcasted(&self) -> u64 {
+        self.broadcasted.load(Relaxed)
+    }
+
+    /// Amount of scores in the history and their total bytes.
+    pub fn history_size(&self) -> (usize, usize) {
+        let history = self.history.lock().unwrap();
+
+        (history.len(), history.bytes())
+    }
+
+    /// The id of the score in the history with the given ruleset and legacy
+    /// score id if legacy score ids are mapped.
+    pub fn legacy_to_id(&self, ruleset_id: u8, legacy_score_id: u64) -> Option<u64> {
+        self.history
+            .lock()
+            .unwrap()
+            .legacy_to_id(ruleset_id, legacy_score_id)
+    }
+
+    /// Approximate bytes held by the history and by messages queued up for
+    /// clients.
+    pub fn memory_usage(&self) -> (usize, usize) {
+        let history_bytes = self.history.lock().unwrap().bytes();
+
+        let queued_bytes = self
+            .clients
+            .pin()
+            .values()
+            .map(|client| client.queued_bytes())
+            .sum();
+
+        (history_bytes, queued_bytes)
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn for_each_client(&self, mut f: impl FnMut(&Peer, &Client)) {
+        for (addr, client) in &self.clients.pin() {
+            f(addr, client);
+        }
+    }
+
+    /// Connected clients with their name and statistics as JSON.
+    pub fn clients_json(&self) -> String {
+        let cursor_id = self.cursor_id();
+        let mut json = String::from("[");
+
+        for (addr, client) in &self.clients.pin() {
+            if json.len() > 1 {
+                json.push(',');
+            }
+
+            let _ = write!(json, r#"{{"addr":"{addr}","name":"#);
+
+            match client.name() {
+                Some(name) => {
+                    let _ = write!(json, r#""{name}""#);
+                }
+                None => json.push_str("null"),
+            }
+
+            let _ = write!(json, r#","stats":{}}}"#, client.stats(cursor_id));
+        }
+
+        json.push(']');
+
+        json
+    }
+
+    #[cfg(feature = "grpc")]
+    pub const fn auth(&self) -> &Auth {
+        &self.auth
+    }
+
+    #[cfg(feature = "grpc")]
+    pub const fn limiter(&self) -> &Arc<RateLimiter> {
+        &self.limiter
+    }
+
+    /// Performs the websocket handshake and authorizes the client.
+    ///
+    /// Clients that connect with the query parameter `hello` are greeted with
+    /// information about the history.
+    async fn accept_websocket(
+        &self,
+        stream: Stream,
+        addr: Peer,
+        auth: bool,
+    ) -> Option<(WebSocketStream<Stream>, Permissions, ConnectOptions)> {
+        let mut key = None;
+        let mut options = ConnectOptions::from_query(None);
+
+        #[allow(clippy::result_large_err)]
+        let callback = |req: &Request, res: Response| {
+            key = Auth::key_from_request(req);
+            options = ConnectOptions::from_query(req.uri().query());
+
+            Ok(res)
+        };
+
+        let mut ws_stream = match tokio_tungstenite::accept_hdr_async(stream, callback).await {
+            Ok(stream) => stream,
+            Err(err) => {
+                error!(?err, "Error during the websocket handshake");
+
+                return None;
+            }
+        };
+
+        trace!(%addr, "WebSocket connection established");
+
+        let permissions = if auth {
+            self.auth.permissions(key.as_deref())
+        } else {
+            Ok(Permissions::ALL)
+        };
+
+        match permissions {
+            Ok(permissions) => {
+                if auth {
+                    options.ack = options.ack.map(|ack| ack.with_key(key));
+                }
+
+                if options.hello {
+                    let msg = Message::Text(self.hello().into());
+
+                    if let Err(err) = ws_stream.send(msg).await {
+                        warn!(?err, %addr, "Failed to send hello");
+
+                        return None;
+                    }
+                }
+
+                Some((ws_stream, permissions, options))
+            }
+            Err(err) => {
+                err.send(&mut ws_stream).await;
+                info!(%addr, "Disconnecting due to invalid authorization");
+
+                None
+            }
+        }
+    }
+
+    /// Removes and returns all scores whose `ended_at` is more than
+    /// `max_age` seconds in the past.
+    fn remove_late_scores(scores: &mut Scores, max_age: u64) -> Vec<Score> {
+        let now = unix_now();
+
+        let mut late = Vec::new();
+
+        scores.retain(|score| match score.ended_at() {
+            Some(ended_at) if ended_at + max_age < now => {
+                late.push(score.clone());
+
+                false
+            }
+            _ => true,
+        });
+
+        late
+    }
+
+    /// The top scores of the rolling window as JSON.
+    pub fn top_json(&self) -> String {
+        self.top.to_json(unix_now())
+    }
+
+    /// General information about the current state as JSON.
+    pub fn status(&self) -> String {
+        let (history_len, history_bytes) = self.history_size();
+
+        let (queued, max_lag, queued_bytes) = self
+            .clients
+            .pin()
+            .values()
+            .map(|client| (client.lag(), client.queued_bytes()))
+            .fold((0, 0, 0), |(sum, max, bytes), (lag, queued_bytes)| {
+                (sum + lag, max.max(lag), bytes + queued_bytes)
+            });
+
+        let mut json = format!(
+            r#"{{"phase":"{}","clients":{},"queued":{queued},"queued_bytes":{queued_bytes},"max_lag":{max_lag},"history_len":{history_len},"history_bytes":{history_bytes},"latency":{},"cursor_id":"#,
+            self.state.phase(),
+            self.clients.len(),
+            self.latency.to_json()
+        );
+
+        match self.cursor_id() {
+            Some(id) => json.push_str(itoa::Buffer::new().format(id)),
+            None => json.push_str("null"),
+        }
+
+        json.push('}');
+
+        json
+    }
+
+    /// Bounds of the history as JSON so that clients can decide how to
+    /// resume before sending their initial message.
+    fn hello(&self) -> String {
+        let history = self.history.lock().unwrap();
+        let oldest = history.first();
+        let newest = history.last();
+
+        let span = oldest
+            .as_ref()
+            .and_then(Score::ended_at)
+            .zip(newest.as_ref().and_then(Score::ended_at))
+            .map(|(oldest, newest)| newest.saturating_sub(oldest));
+
+        let mut buf = itoa::Buffer::new();
+        let mut json = format!(
+            r#"{{"type":"hello","phase":"{}","oldest_score_id":"#,
+            self.state.phase()
+        );
+
+        let fields = [
+            (oldest.as_ref().map(Score::id), r#","newest_score_id":"#),
+            (newest.as_ref().map(Score::id), r#","history_span_secs":"#),
+            (span, "}"),
+        ];
+
+        for (value, suffix) in fields {
+            match value {
+                Some(n) => json.push_str(buf.format(n)),
+                None => json.push_str("null"),
+            }
+
+            json.push_str(suffix);
+        }
+
+        json
+    }
+
+    fn cursor_id(&self) -> Option<u64> {
+        Some(self.cursor_id.load(Relaxed)).filter(|&id| id > 0)
+    }
+
+    /// Sends the history starting after the resume cursors except for the scores
+    /// whose id is in the sorted `skip`.
+    ///
+    /// Runs in the background so that large histories neither hold up the
+    /// connection nor the runtime. Scores broadcasted meanwhile are sent
+    /// afterwards.
+    async fn replay_history(
+        self: Arc<Self>,
+        resume: Option<ResumeCursors>,
+        addr: Peer,
+        client: Arc<Client>,
+        skip: Vec<u64>,
+    ) {
+        let resume_id = resume.as_ref().map(ResumeCursors::oldest);
+        let start_id = resume_id.map_or(0, |id| id + 1);
+        let mut projections = Projections::default();
+        let mut sent = 0;
+        let history = self.history.lock().unwrap().snapshot();
+
+        // If the client's last score is no longer in the history, scores
+        // after it may have been evicted so the client should backfill
+        if let Some(oldest) = history
+            .first_id()
+            .filter(|&oldest| resume_id.is_some_and(|id| id < oldest))
+        {
+            let notice = format!(r#"{{"type":"resume_truncated","oldest":{oldest}}}"#);
+            client.send(Message::Text(notice.into()));
+        }
+
+        // Compressed clients receive the scores all at once after the loop
+        let mut ndjson = client.compressed_replay().then(Vec::new);
+
+        for score in history.range_from(start_id) {
+            if skip.binary_search(&score.id()).is_ok()
+                || resume.is_some_and(|resume| !resume.is_after(&score))
+            {
+                continue;
+            }
+
+            sent += 1;
+
+            match ndjson {
+                Some(ref mut ndjson) => {
+                    client.collect_replayed(&score, &mut projections, ndjson);
+                }
+                None => client.send_replayed(&score, &mut projections),
+            }
+
+            if sent % YIELD_EVERY == 0 {
+                if client.is_closed() {
+                    return debug!(%addr, "Stopped replaying to disconnected client");
+                }
+
+                tokio::task::yield_now().await;
+            }
+        }
+
+        if let Some(ndjson) = ndjson {
+            Self::send_replay_blob(&client, addr, ndjson).await;
+        }
+
+        let caught_up = client.finish_replay(&history, &mut projections);
+
+        info!(
+            %addr,
+            "Sent {sent} scores from the history and {caught_up} broadcasted meanwhile"
+        );
+    }
+
+    /// Sends the replayed scores as `{"type":"replay_blob",...}`, followed by
+    /// their zstd compressed NDJSON as a single binary message.
+    async fn send_replay_blob(client: &Client, addr: Peer, ndjson: Vec<u8>) {
+        if ndjson.is_empty() {
+            return;
+        }
+
+        let lines = ndjson.iter().filter(|&&byte| byte == b'\n').count();
+        let len = ndjson.len();
+
+        // Compressing megabytes would block the runtime for too long
+        let res = tokio::task::spawn_blocking(move || {
+            zstd::bulk::compress(&ndjson, zstd::DEFAULT_COMPRESSION_LEVEL)
+        })
+        .await;
+
+        let blob = match res {
+            Ok(Ok(blob)) => blob,
+            Ok(Err(err)) => return error!(?err, %addr, "Failed to compress the history"),
+            Err(err) => return error!(?err, %addr, "Compression panicked"),
+        };
+
+        debug!(%addr, scores = lines, len, compressed = blob.len(), "Compressed the history");
+
+        let header =
+            format!(r#"{{"type":"replay_blob","encoding":"zstd","scores":{lines},"bytes":{len}}}"#);
+
+        client.send(Message::Text(header.into()));
+        client.send(Message::Binary(blob.into()));
+    }
+
+    async fn process_rate_limited(&self, addr: Peer, outgoing: &mut Outgoing) {
+        warn!(%addr, "Disconnecting due to rate limits");
+
+        ErrorFrame::RATE_LIMITED.send(outgoing).await;
+        self.clients.pin().remove(&addr);
+    }
+
+    /// Skips the client's queue so that it learns why it's disconnected.
+    async fn process_lagging(&self, addr: Peer, lag: usize, outgoing: &mut Outgoing) {
+        let max_lag = self.max_client_lag.unwrap_or(0);
+        warn!(%addr, lag, max_lag, "Disconnecting due to lag");
+
+        let _ = outgoing.send(ErrorFrame::LAGGING.to_message()).await;
+
+        let close = Message::Close(Some(CloseFrame {
+            code: CloseCode::Policy,
+            reason: format!("Too far behind: {lag} queued messages exceed the limit of {max_lag}")
+                .into(),
+        }));
+
+        let _ = outgoing.send(close).await;
+        self.clients.pin().remove(&addr);
+    }
+
+    /// Closes the connection with the score id to resume from as reason.
+    fn idle_close_frame(&self, client: &Client) -> Message {
+        let id = client
+            .last_score_id()
+            .or_else(|| self.history.lock().unwrap().last_id());
+
+        let reason = match id {
+            Some(id) => format!(r#"{{"resume_score_id":{id}}}"#),
+            None => r#"{"resume_score_id":null}"#.to_owned(),
+        };
+
+        Message::Close(Some(CloseFrame {
+            code: CloseCode::Away,
+            reason: reason.into(),
+        }))
+    }
+
+    async fn process_disconnect(&self, outgoing: &mut Outgoing) {
+        info!("Processing disconnect...");
+
+        let id = self.history.lock().unwrap().last_id().unwrap_or(0);
+        let msg = Message::Text(itoa::Buffer::new().format(id).into());
+
+        if let Err(err) = outgoing.send(msg).await {
+            return warn!(?err, "Failed to send score id {id} on disconnect");
+        }
+
+        let close = Message::Close(Some(CloseFrame {
+            code: CloseCode::Normal,
+            reason: "".into(),
+        }));
+
+        let _: Result<_, _> = outgoing.send(close).await;
+    }
 }
@@ -0,0 +1,107 @@
+use std::path::PathBuf;
+
+use eyre::Result;
+
+/// Runs `scores-ws` in the background for machines without a supervisor.
+///
+/// Invoked through `scores-ws --daemon [--pid-file <path>] [--out-file <path>]`.
+pub struct Daemon {
+    pid_file: PathBuf,
+    /// Receives stdout and stderr, e.g. panics, since there is no terminal.
+    out_file: PathBuf,
+}
+
+impl Daemon {
+    pub const fn new(pid_file: PathBuf, out_file: PathBuf) -> Self {
+        Self { pid_file, out_file }
+    }
+
+    /// Detaches from the terminal and writes the pid file.
+    ///
+    /// Must be called before any threads are spawned, i.e. before the runtime
+    /// is built.
+    #[cfg(unix)]
+    pub fn detach(self) -> Result<PidFile> {
+        use std::{
+            fs::{File, OpenOptions},
+            io,
+            os::fd::AsRawFd,
+        };
+
+        use eyre::Context as _;
+
+        fn fork() -> Result<()> {
+            // SAFETY: The process is still single-threaded
+            match unsafe { libc::fork() } {
+                -1 => Err(io::Error::last_os_error()).context("Failed to fork"),
+                0 => Ok(()),
+                _ => std::process::exit(0),
+            }
+        }
+
+        if let Some(pid) = self.running_pid() {
+            bail!("scores-ws is already running with pid {pid}");
+        }
+
+        let null = File::open("/dev/null").context("Failed to open `/dev/null`")?;
+
+        let out = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.out_file)
+            .with_context(|| format!("Failed to open `{}`", self.out_file.display()))?;
+
+        fork()?;
+
+        // SAFETY: Only called in the forked child
+        if unsafe { libc::setsid() } == -1 {
+            return Err(io::Error::last_os_error()).context("Failed to create session");
+        }
+
+        // The session leader exits so the daemon can't reacquire a terminal
+        fork()?;
+
+        for (from, to) in [(&null, 0), (&out, 1), (&out, 2)] {
+            // SAFETY: Both file descriptors are valid
+            if unsafe { libc::dup2(from.as_raw_fd(), to) } == -1 {
+                return Err(io::Error::last_os_error()).context("Failed to redirect stdio");
+            }
+        }
+
+        std::fs::write(&self.pid_file, format!("{}\n", std::process::id()))
+            .with_context(|| format!("Failed to write `{}`", self.pid_file.display()))?;
+
+        Ok(PidFile(self.pid_file))
+    }
+
+    #[cfg(not(unix))]
+    pub fn detach(self) -> Result<PidFile> {
+        let _ = (self.pid_file, self.out_file);
+
+        bail!("`--daemon` is only supported on unix; on windows, install a service through `scores-ws service install` instead")
+    }
+
+    /// The pid of the pid file if its process is still alive.
+    #[cfg(unix)]
+    fn running_pid(&self) -> Option<i32> {
+        let pid = std::fs::read_to_string(&self.pid_file)
+            .ok()?
+            .trim()
+            .parse()
+            .ok()?;
+
+        // SAFETY: Signal 0 only checks whether the process exists
+        (unsafe { libc::kill(pid, 0) } == 0).then_some(pid)
+    }
+}
+
+/// Removed when dropped, i.e. once `scores-ws` shuts down.
+pub struct PidFile(PathBuf);
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.0) {
+            eprintln!("Failed to remove `{}`: {err}", self.0.display());
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use std::{
+    collections::BTreeSet,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use eyre::{Context as _, Result};
+
+use crate::{
+    config::Setup,
+    osu::{Score, Scores},
+};
+
+/// Rolling window of the ids of recently broadcasted scores so that scores
+/// aren't broadcasted again, e.g. when pages overlap or the cursor resets
+/// after the scores were trimmed from the history.
+///
+/// If a file is specified, the ids are persisted to it so that a restart does
+/// not broadcast the same scores again either. The file consists of
+/// little-endian `u64` ids.
+pub struct Dedup {
+    path: Option<PathBuf>,
+    capacity: usize,
+    ids: BTreeSet<u64>,
+}
+
+impl Dedup {
+    /// Returns `None` if neither `dedup_file` nor `dedup_window` is specified.
+    pub fn from_setup(setup: &Setup) -> Result<Option<Self>> {
+        let capacity = setup.dedup_window.unwrap_or(setup.history_length);
+
+        match (setup.dedup_file.as_ref(), setup.dedup_window) {
+            (Some(path), _) => Self::load(path.clone(), capacity).map(Some),
+            (None, Some(_)) => Ok(Some(Self::new(capacity))),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Keeps the ids only in memory.
+    pub const fn new(capacity: usize) -> Self {
+        Self {
+            path: None,
+            capacity,
+            ids: BTreeSet::new(),
+        }
+    }
+
+    /// Loads the ids from the file if it exists.
+    pub fn load(path: PathBuf, capacity: usize) -> Result<Self> {
+        let ids = match std::fs::read(&path) {
+            Ok(bytes) => bytes
+                .chunks_exact(8)
+                .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+                .collect(),
+            Err(err) if err.kind() == ErrorKind::NotFound => BTreeSet::new(),
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("Failed to read dedup file `{}`", path.display()))
+            }
+        };
+
+        info!(count = ids.len(), "Loaded ids of broadcasted scores");
+
+        Ok(Self {
+            path: Some(path),
+            capacity,
+            ids,
+        })
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Removes and returns scores from `start` onwards that were broadcasted
+    /// already and records the ids of all others.
+    pub fn remove_known(&mut self, scores: &mut Scores, start: &Score) -> Vec<Score> {
+        let mut known = Vec::new();
+
+        for score in scores.range(start..) {
+            if !self.ids.insert(score.id()) {
+                known.push(score.clone());
+            }
+        }
+
+        for score in &known {
+            scores.remove(score);
+        }
+
+        while self.ids.len() > self.capacity {
+            self.ids.pop_first();
+        }
+
+        known
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.ids.iter().flat_map(|id| id.to_le_bytes()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+
+    #[test]
+    fn remove_known() {
+        let path = std::env::temp_dir().join("scores-ws-dedup-test");
+        let mut dedup = Dedup {
+            path: Some(path.clone()),
+            capacity: 3,
+            ids: BTreeSet::from([1, 2, 3]),
+        };
+
+        let mut scores: Scores = (2..=5).map(|id| Score::new(id, Bytes::new())).collect();
+
+        let known = dedup.remove_known(&mut scores, &Score::only_id(0));
+        let known: Vec<_> = known.iter().map(Score::id).collect();
+        let remaining: Vec<_> = scores.iter().map(Score::id).collect();
+
+        assert_eq!(known, [2, 3]);
+        assert_eq!(remaining, [4, 5]);
+
+        std::fs::write(&path, dedup.to_bytes()).unwrap();
+        let loaded = Dedup::load(path.clone(), 3).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(loaded.ids, BTreeSet::from([3, 4, 5]));
+    }
+
+    #[test]
+    fn rolling_window() {
+        let mut dedup = Dedup::new(3);
+        let ids = |scores: &Scores| scores.iter().map(Score::id).collect::<Vec<_>>();
+
+        let mut scores: Scores = (1..=4).map(|id| Score::new(id, Bytes::new())).collect();
+        assert!(dedup
+            .remove_known(&mut scores, &Score::only_id(0))
+            .is_empty());
+
+        // Overlapping page after the history may have been trimmed
+        let mut scores: Scores = (1..=6).map(|id| Score::new(id, Bytes::new())).collect();
+        let known = dedup.remove_known(&mut scores, &Score::only_id(0));
+
+        assert_eq!(ids(&known.into_iter().collect()), [2, 3, 4]);
+        assert_eq!(ids(&scores), [1, 5, 6]);
+        assert!(dedup.path().is_none());
+    }
+}
@@ -0,0 +1,168 @@
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    client::{Client, Projections},
+    osu::Score,
+};
+
+/// Recently broadcasted scores indexed by the time they were broadcasted so
+/// that clients with a delay receive them once they're due.
+pub struct DelayQueue {
+    /// The longest delay of any listener; entries are kept for this long.
+    retention: Option<Duration>,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    entries: VecDeque<Entry>,
+    next_seq: u64,
+}
+
+struct Entry {
+    seq: u64,
+    broadcasted_at: Instant,
+    score: Score,
+}
+
+impl DelayQueue {
+    pub const fn new(retention: Option<Duration>) -> Self {
+        Self {
+            retention,
+            inner: Mutex::new(Inner {
+                entries: VecDeque::new(),
+                next_seq: 0,
+            }),
+        }
+    }
+
+    pub fn push<'a>(&self, scores: impl Iterator<Item = &'a Score>) {
+        if self.retention.is_none() {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut inner = self.inner.lock().unwrap();
+
+        for score in scores {
+            let seq = inner.next_seq;
+            inner.next_seq += 1;
+
+            inner.entries.push_back(Entry {
+                seq,
+                broadcasted_at: now,
+                score: score.clone(),
+            });
+        }
+    }
+
+    /// Returns the sequence number from which on the client should receive
+    /// scores, as well as the sorted ids of scores that are not yet due for
+    /// the client and must hence not be sent from the history.
+    pub fn not_due(&self, delay: Duration, now: Instant) -> (u64, Vec<u64>) {
+        let inner = self.inner.lock().unwrap();
+
+        let not_due = inner
+            .entries
+            .iter()
+            .skip_while(|entry| entry.broadcasted_at + delay <= now);
+
+        let next_seq = not_due
+            .clone()
+            .next()
+            .map_or(inner.next_seq, |entry| entry.seq);
+        let mut ids: Vec<_> = not_due.map(|entry| entry.score.id()).collect();
+        ids.sort_unstable();
+
+        (next_seq, ids)
+    }
+
+    /// Sends all scores that became due to the client.
+    pub fn deliver(&self, client: &Client, delay: Duration, now: Instant) {
+        let inner = self.inner.lock().unwrap();
+        let start_seq = client.next_seq();
+        let mut next_seq = start_seq;
+
+        let due = inner
+            .entries
+            .iter()
+            .skip_while(|entry| entry.seq < start_seq)
+            .take_while(|entry| entry.broadcasted_at + delay <= now);
+
+        let mut projections = Projections::default();
+
+        for entry in due {
+            client.send_score(&entry.score, &mut projections);
+            next_seq = entry.seq + 1;
+        }
+
+        client.set_next_seq(next_seq);
+    }
+
+    /// Removes scores that are due for all clients.
+    pub fn prune(&self, now: Instant) {
+        let Some(retention) = self.retention else {
+            return;
+        };
+
+        let mut inner = self.inner.lock().unwrap();
+
+        while inner
+            .entries
+            .front()
+            .is_some_and(|entry| entry.broadcasted_at + retention <= now)
+        {
+            inner.entries.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use tokio::sync::mpsc;
+
+    use crate::auth::Permissions;
+
+    use super::*;
+
+    #[test]
+    fn deliver_when_due() {
+        let delay = Duration::from_secs(10);
+        let queue = DelayQueue::new(Some(delay));
+
+        let scores = [
+            Score::new(2, Bytes::from_static(b"2")),
+            Score::new(1, Bytes::from_static(b"1")),
+        ];
+
+        queue.push(scores.iter());
+        let now = Instant::now();
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let client = Client::new(tx, Permissions::ALL, None, Some(delay));
+
+        let (next_seq, not_due) = queue.not_due(delay, now);
+        assert_eq!(next_seq, 0);
+        assert_eq!(not_due, [1, 2]);
+
+        queue.deliver(&client, delay, now);
+        assert!(rx.try_recv().is_err());
+
+        let later = now + delay;
+        queue.deliver(&client, delay, later);
+        assert_eq!(rx.try_recv().unwrap().into_data(), b"2".as_slice());
+        assert_eq!(rx.try_recv().unwrap().into_data(), b"1".as_slice());
+        assert!(rx.try_recv().is_err());
+        assert_eq!(client.next_seq(), 2);
+
+        queue.deliver(&client, delay, later);
+        assert!(rx.try_recv().is_err());
+
+        queue.prune(later);
+        assert_eq!(queue.not_due(delay, now), (2, Vec::new()));
+    }
+}
@@ -0,0 +1,389 @@
+//! End-to-end tests that drive the real fetch loop and websocket server
+//! against a local fake of the osu!api.
+
+use std::{
+    collections::VecDeque,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+use crate::{
+    config::{OsuConfig, Setup},
+    context::Context,
+    listener::{Peer, Stream},
+    osu::Osu,
+    sink::Sinks,
+};
+
+const TOKEN: &str = "e2e-token";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Serves `/oauth/token` and `/api/v2/scores` with scripted responses.
+struct FakeOsu {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Status codes and bodies of upcoming score responses. Once they're used
+    /// up, there are no new scores.
+    responses: VecDeque<(u16, String)>,
+    /// Paths including queries of authorized score requests.
+    requests: Vec<String>,
+    token_requests: usize,
+}
+
+impl FakeOsu {
+    async fn start(responses: impl IntoIterator<Item = (u16, String)>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let state = Arc::new(Mutex::new(State {
+            responses: responses.into_iter().collect(),
+            ..State::default()
+        }));
+
+        let state_ = Arc::clone(&state);
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(Self::handle(stream, Arc::clone(&state_)));
+            }
+        });
+
+        Self { addr, state }
+    }
+
+    /// Handles requests of a kept-alive connection until it's closed.
+    async fn handle(mut stream: TcpStream, state: Arc<Mutex<State>>) {
+        let mut buf = Vec::new();
+
+        loop {
+            let mut headers = [httparse::EMPTY_HEADER; 32];
+            let mut req = httparse::Request::new(&mut headers);
+
+            let header_len = match req.parse(&buf) {
+                Ok(httparse::Status::Complete(len)) => len,
+                Ok(httparse::Status::Partial) => {
+                    let mut chunk = [0; 4096];
+
+                    match stream.read(&mut chunk).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                    }
+
+                    continue;
+                }
+                Err(err) => panic!("Invalid request: {err}"),
+            };
+
+            let header = |name: &str| {
+                req.headers
+                    .iter()
+                    .find(|header| header.name.eq_ignore_ascii_case(name))
+                    .and_then(|header| std::str::from_utf8(header.value).ok())
+            };
+
+            let body_len: usize = header("content-length").map_or(0, |len| len.parse().unwrap());
+
+            if buf.len() < header_len + body_len {
+                let mut chunk = [0; 4096];
+
+                match stream.read(&mut chunk).await {
+                    Ok(0) | Err(_) => return,
+                    Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                }
+
+                continue;
+            }
+
+            let path = req.path.unwrap_or_default().to_owned();
+            let authorized = header("authorization") == Some(&format!("Bearer {TOKEN}"));
+            buf.drain(..header_len + body_len);
+
+            let (status, body) = {
+                let mut state = state.lock().unwrap();
+
+                if path == "/oauth/token" {
+                    state.token_requests += 1;
+                    let body = format!(r#"{{"expires_in":86400,"access_token":"{TOKEN}"}}"#);
+
+                    (200, body)
+                } else if !path.starts_with("/api/v2/scores") {
+                    (404, String::from("{}"))
+                } else if !authorized {
+                    (401, String::from(r#"{"authentication":"basic"}"#))
+                } else {
+                    state.requests.push(path);
+
+                    state
+                        .responses
+                        .pop_front()
+                        .unwrap_or_else(|| (200, scores(&[])))
+                }
+            };
+
+            let res = format!(
+                "HTTP/1.1 {status} Fake\r\ncontent-type: application/json\r\n\
+                content-length: {}\r\n\r\n{body}",
+                body.len()
+            );
+
+            if stream.write_all(res.as_bytes()).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    fn requests(&self) -> Vec<String> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    fn token_requests(&self) -> usize {
+        self.state.lock().unwrap().token_requests
+    }
+
+    /// Runs the fetch loop against the fake and a websocket server for it.
+    async fn serve(&self, cursor_id: Option<u64>) -> SocketAddr {
+        self.serve_with_retry(cursor_id, "").await
+    }
+
+    /// Same as [`FakeOsu::serve`] with additional options of `[osu.retry]`,
+    /// possibly followed by further subsections of `[osu]`.
+    async fn serve_with_retry(&self, cursor_id: Option<u64>, retry: &str) -> SocketAddr {
+        let setup: Setup =
+            toml::from_str("interval = 1\nbroadcast_missed_estimate = true").unwrap();
+        let config = format!(
+            "client_id = 1\nclient_secret = \"secret\"\n[retry]\ninitial_secs = 0\n{retry}"
+        );
+        let config: OsuConfig = toml::from_str(&config).unwrap();
+
+        let osu = Osu::local(config, &format!("http://{}", self.addr)).unwrap();
+        let ctx = Arc::new(Context::new(&setup, None, None, Sinks::default()));
+        let handle = ctx
+            .loops()
+            .register(Box::from("e2e"), Duration::from_secs(1));
+
+        tokio::spawn(Context::evaluate_state(Arc::clone(&ctx)));
+
+        tokio::spawn(Context::fetch_scores(
+            Arc::clone(&ctx),
+            handle,
+            Arc::new(osu),
+            setup.fetch_interval(),
+            cursor_id,
+            None,
+            None,
+        ));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, peer) = listener.accept().await.unwrap();
+                let conn = (Stream::Tcp(stream), Peer::Tcp(peer));
+                tokio::spawn(Context::handle_connection(Arc::clone(&ctx), conn, false));
+            }
+        });
+
+        addr
+    }
+}
+
+/// Body of a score response with minimal scores of the given ids.
+fn scores(ids: &[u64]) -> String {
+    let scores: Vec<_> = ids
+        .iter()
+        .map(|id| format!(r#"{{"id":{id},"user_id":2,"ruleset_id":0}}"#))
+        .collect();
+
+    format!(
+        r#"{{"scores":[{}],"cursor_string":"abc"}}"#,
+        scores.join(",")
+    )
+}
+
+async fn connect(addr: SocketAddr) -> Client {
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{addr}/"))
+        .await
+        .unwrap();
+
+    client.send(Message::text("connect")).await.unwrap();
+
+    client
+}
+
+/// Receives the next text frame, skipping scores.
+async fn receive_text(client: &mut Client) -> String {
+    let receive = async {
+        while let Some(msg) = client.next().await {
+            if let Message::Text(text) = msg.unwrap() {
+                return text.as_str().to_owned();
+            }
+        }
+
+        panic!("Connection closed");
+    };
+
+    tokio::time::timeout(TIMEOUT, receive).await.unwrap()
+}
+
+/// Receives scores until one with the given id arrives and returns all ids.
+async fn receive_until(client: &mut Client, last_id: u64) -> Vec<u64> {
+    let mut ids = Vec::new();
+
+    let receive = async {
+        while let Some(msg) = client.next().await {
+            let Message::Binary(bytes) = msg.unwrap() else {
+                continue;
+            };
+
+            let json = std::str::from_utf8(&bytes).unwrap();
+            let id = json["{\"id\":".len()..json.find(',').unwrap()]
+                .parse()
+                .unwrap();
+            ids.push(id);
+
+            if id == last_id {
+                break;
+            }
+        }
+    };
+
+    let res = tokio::time::timeout(TIMEOUT, receive).await;
+    assert!(
+        res.is_ok(),
+        "Timeout while waiting for score {last_id}; received {ids:?}"
+    );
+
+    ids
+}
+
+#[tokio::test]
+async fn fetch_and_broadcast() {
+    let fake = FakeOsu::start([(200, scores(&[2, 1])), (200, scores(&[3]))]).await;
+    let addr = fake.serve(None).await;
+    let mut client = connect(addr).await;
+
+    assert_eq!(receive_until(&mut client, 3).await, [1, 2, 3]);
+    assert_eq!(fake.token_requests(), 1);
+
+    let requests = fake.requests();
+    assert_eq!(requests[0], "/api/v2/scores");
+    assert_eq!(requests[1], "/api/v2/scores?cursor[id]=2");
+}
+
+#[tokio::test]
+async fn cursor_too_old() {
+    let too_old = (422, String::from(r#"{"error":"cursor is too old"}"#));
+    let fake = FakeOsu::start([too_old, (200, scores(&[7, 8]))]).await;
+    let addr = fake.serve(Some(5)).await;
+    let mut client = connect(addr).await;
+
+    assert_eq!(
+        receive_text(&mut client).await,
+        r#"{"type":"missed_estimate","after":5,"until":7,"count":1}"#
+    );
+    assert_eq!(receive_until(&mut client, 8).await, [7, 8]);
+
+    let requests = fake.requests();
+    assert_eq!(requests[0], "/api/v2/scores?cursor[id]=5");
+    assert_eq!(requests[1], "/api/v2/scores");
+}
+
+#[tokio::test]
+async fn catch_up() {
+    let page = |ids: std::ops::RangeInclusive<u64>| scores(&ids.collect::<Vec<_>>());
+    let responses = [
+        (200, page(1..=1000)),
+        (200, page(1001..=2000)),
+        (200, page(2001..=2003)),
+    ];
+    let fake = FakeOsu::start(responses).await;
+    let addr = fake
+        .serve_with_retry(Some(0), "[catch_up]\nrequests_per_minute = 60000")
+        .await;
+    let mut client = connect(addr).await;
+
+    assert_eq!(receive_until(&mut client, 2003).await.len(), 2003);
+
+    let requests = fake.requests();
+    assert_eq!(requests[0], "/api/v2/scores?cursor[id]=0");
+    assert_eq!(requests[1], "/api/v2/scores?cursor[id]=1000");
+    assert_eq!(requests[2], "/api/v2/scores?cursor[id]=2000");
+}
+
+#[tokio::test]
+async fn retry_after_rate_limit() {
+    let rate_limited = (429, String::from(r#"{"error":"too many requests"}"#));
+    let fake = FakeOsu::start([rate_limited, (200, scores(&[4]))]).await;
+    let addr = fake.serve(None).await;
+    let mut client = connect(addr).await;
+
+    assert_eq!(receive_until(&mut client, 4).await, [4]);
+
+    let requests = fake.requests();
+    assert_eq!(requests[0], "/api/v2/scores");
+    assert_eq!(requests[1], "/api/v2/scores");
+}
+
+#[tokio::test]
+async fn circuit_breaker() {
+    let failed = (500, String::from(r#"{"error":"internal"}"#));
+    let responses = [
+        (200, scores(&[1])),
+        failed.clone(),
+        failed,
+        (200, scores(&[2])),
+    ];
+    let fake = FakeOsu::start(responses).await;
+    let addr = fake
+        .serve_with_retry(None, "budget = 2\ncooldown_secs = 1")
+        .await;
+    let mut client = connect(addr).await;
+
+    assert_eq!(receive_until(&mut client, 1).await, [1]);
+    assert_eq!(receive_text(&mut client).await, r#"{"type":"degraded"}"#);
+    assert_eq!(receive_until(&mut client, 2).await, [2]);
+}
+
+#[tokio::test]
+async fn maintenance() {
+    let unavailable = (503, String::from("Service Unavailable"));
+    let responses = [
+        (200, scores(&[1])),
+        unavailable.clone(),
+        unavailable.clone(),
+        unavailable,
+        (200, scores(&[2])),
+    ];
+    let fake = FakeOsu::start(responses).await;
+    let addr = fake
+        .serve_with_retry(None, "maintenance_after = 2\nprobe_secs = 1")
+        .await;
+    let mut client = connect(addr).await;
+
+    assert_eq!(receive_until(&mut client, 1).await, [1]);
+    assert_eq!(
+        receive_text(&mut client).await,
+        r#"{"type":"status","state":"osu_down"}"#
+    );
+    assert_eq!(receive_until(&mut client, 2).await, [2]);
+    assert_eq!(
+        receive_text(&mut client).await,
+        r#"{"type":"status","state":"recovered"}"#
+    );
+}
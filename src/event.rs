@@ -1,55 +1,806 @@
-use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::collections::HashSet;
 
-use tokio_tungstenite::tungstenite::Message;
+use futures_util::{Sink, SinkExt};
+use tokio_tungstenite::tungstenite::{
+    protocol::{frame::coding::CloseCode, CloseFrame},
+    Message,
+};
 
+use crate::{
+    auth::Op,
+    client::{Fields, Partition, Rulesets, Sample},
+    filter::{Beatmaps, Condition},
+    mods::ModUpdate,
+    osu::{Score, RULESETS},
+};
+
+/// Latest version of the initial message's object form. The plain strings
+/// and score ids count as version 1.
+pub const PROTOCOL_VERSION: u64 = 2;
+
+#[derive(Copy, Clone)]
 pub enum Event {
-    Connect,
-    Resume { score_id: u64 },
+    /// `compressed` if the history should be replayed as a single zstd
+    /// compressed blob.
+    Connect {
+        compressed: bool,
+    },
+    Resume {
+        score_id: u64,
+        compressed: bool,
+    },
+    /// `{"v":2,"action":"resume","legacy_score_id":<id>,"ruleset":"<ruleset>"}`
+    ResumeLegacy {
+        ruleset_id: u8,
+        legacy_score_id: u64,
+        compressed: bool,
+    },
+    /// `{"resume":{"<ruleset>":<score_id>,...}}`
+    ResumeRulesets {
+        cursors: ResumeCursors,
+    },
+    Late,
+    UserActive,
+    Aggregates,
+    Anomalies,
+    /// `{"subscribe":"alerts","min_pp":<pp>}`
+    Alerts {
+        min_pp: f64,
+    },
 }
 
 impl Event {
+    pub const fn op(&self) -> Op {
+        match self {
+            Self::Connect { .. } => Op::Connect,
+            Self::Resume { .. } | Self::ResumeLegacy { .. } | Self::ResumeRulesets { .. } => {
+                Op::Resume
+            }
+            Self::Late => Op::Late,
+            Self::UserActive => Op::UserActive,
+            Self::Aggregates => Op::Aggregates,
+            Self::Anomalies => Op::Anomalies,
+            Self::Alerts { .. } => Op::Alerts,
+        }
+    }
+
+    /// Whether the client negotiated to receive the history as a single
+    /// compressed blob.
+    pub const fn compressed_replay(&self) -> bool {
+        match self {
+            Self::Connect { compressed }
+            | Self::Resume { compressed, .. }
+            | Self::ResumeLegacy { compressed, .. } => *compressed,
+            _ => false,
+        }
+    }
+
     fn parse_score_id(bytes: &[u8]) -> Option<u64> {
         bytes.iter().try_fold(0, |id, &byte| match byte {
             b'0'..=b'9' => Some(id * 10 + u64::from(byte & 0xF)),
             _ => None,
         })
     }
+
+    /// Parses the remainder `"alerts","min_pp":<pp>` of a subscribe object.
+    fn parse_alerts(value: &str) -> Option<f64> {
+        let min_pp = value
+            .strip_prefix(r#""alerts""#)?
+            .trim_start()
+            .strip_prefix(',')?
+            .trim_start()
+            .strip_prefix(r#""min_pp""#)?
+            .trim_start()
+            .strip_prefix(':')?;
+
+        Self::parse_min_pp(min_pp.trim())
+    }
+
+    fn parse_min_pp(value: &str) -> Option<f64> {
+        value
+            .parse()
+            .ok()
+            .filter(|min_pp: &f64| min_pp.is_finite() && min_pp.is_sign_positive())
+    }
+
+    /// Parses `{"v":<version>,"action":"<action>"}` whose action is named
+    /// after its [`Op`]. The action `"resume"` also requires
+    /// `"score_id":<id>`. Entries may be in any order.
+    ///
+    /// Instead of `"score_id"`, `"resume"` may specify
+    /// `"legacy_score_id":<id>` together with `"ruleset":"<ruleset>"`.
+    ///
+    /// The actions `"connect"` and `"resume"` may also specify
+    /// `"compress":"zstd"` to receive the history as a single compressed blob.
+    ///
+    /// Returns `None` if the bytes aren't of that form.
+    fn parse_versioned(bytes: &[u8]) -> Option<Result<Self, ErrorFrame>> {
+        let entries = std::str::from_utf8(bytes)
+            .ok()?
+            .trim()
+            .strip_prefix('{')?
+            .strip_suffix('}')?;
+
+        let mut version = None;
+        let mut action = None;
+        let mut score_id = None;
+        let mut min_pp = None;
+        let mut compress = None;
+        let mut legacy_score_id = None;
+        let mut ruleset = None;
+        let mut unknown = false;
+
+        for entry in entries.split(',') {
+            let (key, value) = entry.split_once(':')?;
+            let key = key.trim().strip_prefix('"')?.strip_suffix('"')?;
+            let value = value.trim();
+
+            match key {
+                "v" => version = Some(value.parse::<u64>().ok()?),
+                "action" => action = Some(value.strip_prefix('"')?.strip_suffix('"')?),
+                "score_id" => score_id = Some(value),
+                "min_pp" => min_pp = Some(value),
+                "compress" => compress = Some(value),
+                "legacy_score_id" => legacy_score_id = Some(value),
+                "ruleset" => ruleset = Some(value),
+                _ => unknown = true,
+            }
+        }
+
+        // Newer versions may add entries so the version is checked first
+        if !(1..=PROTOCOL_VERSION).contains(&version?) {
+            return Some(Err(ErrorFrame::UNSUPPORTED_VERSION));
+        }
+
+        let compressed = match compress {
+            None => false,
+            Some(r#""zstd""#) if matches!(action, Some("connect" | "resume")) => true,
+            Some(_) => return Some(Err(ErrorFrame::INVALID_INITIAL)),
+        };
+
+        let legacy = legacy_score_id.is_some() || ruleset.is_some();
+
+        let event = match (action, score_id) {
+            _ if unknown
+                || (min_pp.is_some() != (action == Some("alerts")))
+                || (legacy && (action != Some("resume") || score_id.is_some())) =>
+            {
+                return Some(Err(ErrorFrame::INVALID_INITIAL))
+            }
+            (Some("connect"), None) => Self::Connect { compressed },
+            (Some("resume"), Some(score_id)) if !score_id.is_empty() => {
+                match Self::parse_score_id(score_id.as_bytes()) {
+                    Some(score_id) => Self::Resume {
+                        score_id,
+                        compressed,
+                    },
+                    None => return Some(Err(ErrorFrame::INVALID_INITIAL)),
+                }
+            }
+            (Some("resume"), None) if legacy => {
+                let legacy_score_id = legacy_score_id
+                    .and_then(|id| Self::parse_score_id(id.as_bytes()))
+                    .filter(|&id| id > 0);
+
+                let ruleset_id = ruleset
+                    .and_then(|ruleset| ruleset.strip_prefix('"')?.strip_suffix('"'))
+                    .and_then(|name| RULESETS.iter().position(|&ruleset| ruleset == name))
+                    .and_then(|idx| u8::try_from(idx).ok());
+
+                match (legacy_score_id, ruleset_id) {
+                    (Some(legacy_score_id), Some(ruleset_id)) => Self::ResumeLegacy {
+                        ruleset_id,
+                        legacy_score_id,
+                        compressed,
+                    },
+                    _ => return Some(Err(ErrorFrame::INVALID_INITIAL)),
+                }
+            }
+            (Some("late"), None) => Self::Late,
+            (Some("user_active"), None) => Self::UserActive,
+            (Some("aggregates"), None) => Self::Aggregates,
+            (Some("anomalies"), None) => Self::Anomalies,
+            (Some("alerts"), None) => match min_pp.and_then(Self::parse_min_pp) {
+                Some(min_pp) => Self::Alerts { min_pp },
+                None => return Some(Err(ErrorFrame::INVALID_INITIAL)),
+            },
+            _ => return Some(Err(ErrorFrame::INVALID_INITIAL)),
+        };
+
+        Some(Ok(event))
+    }
 }
 
 impl TryFrom<Message> for Event {
-    type Error = EventError;
+    type Error = ErrorFrame;
 
     fn try_from(msg: Message) -> Result<Self, Self::Error> {
         let bytes: &[u8] = match msg {
             Message::Text(ref bytes) => bytes.as_bytes(),
             Message::Binary(ref bytes) => bytes,
-            _ => return Err(EventError::Variant),
+            _ => return Err(ErrorFrame::INITIAL_NOT_DATA),
         };
 
         if bytes == b"connect" {
-            Ok(Self::Connect)
+            Ok(Self::Connect { compressed: false })
+        } else if bytes == b"late" {
+            Ok(Self::Late)
+        } else if bytes == b"user_active" {
+            Ok(Self::UserActive)
+        } else if bytes == b"anomalies" {
+            Ok(Self::Anomalies)
         } else if let Some(score_id) = Self::parse_score_id(bytes) {
-            Ok(Self::Resume { score_id })
+            Ok(Self::Resume {
+                score_id,
+                compressed: false,
+            })
+        } else if let Some(res) = Self::parse_versioned(bytes) {
+            res
+        } else if let Some(("resume", value)) = Command::parse_object(bytes) {
+            ResumeCursors::parse(value)
+                .map(|cursors| Self::ResumeRulesets { cursors })
+                .ok_or(ErrorFrame::INVALID_INITIAL)
+        } else if let Some(("subscribe", value)) = Command::parse_object(bytes) {
+            match value {
+                r#""stats""# => Ok(Self::Aggregates),
+                _ => Self::parse_alerts(value)
+                    .map(|min_pp| Self::Alerts { min_pp })
+                    .ok_or(ErrorFrame::INVALID_INITIAL),
+            }
         } else {
-            Err(EventError::Bytes)
+            Err(ErrorFrame::INVALID_INITIAL)
         }
     }
 }
 
-#[allow(clippy::module_name_repetitions)]
-#[derive(Debug)]
-pub enum EventError {
-    Bytes,
-    Variant,
+/// Score ids after which a resuming client continues, one per ruleset.
+///
+/// Feeds that join one fetcher per ruleset don't progress evenly through the
+/// ids so a single id would skip scores of lagging rulesets.
+#[derive(Copy, Clone)]
+pub struct ResumeCursors([Option<u64>; RULESETS.len()]);
+
+impl ResumeCursors {
+    /// The same cursor for all rulesets.
+    pub const fn global(score_id: u64) -> Self {
+        Self([Some(score_id); RULESETS.len()])
+    }
+
+    /// Parses `{"<ruleset>":<score_id>,...}` with at least one ruleset.
+    fn parse(value: &str) -> Option<Self> {
+        let entries = value.strip_prefix('{')?.strip_suffix('}')?;
+        let mut cursors = [None; RULESETS.len()];
+
+        for entry in entries.split(',') {
+            let (name, score_id) = entry.split_once(':')?;
+            let name = name.trim().strip_prefix('"')?.strip_suffix('"')?;
+            let idx = RULESETS.iter().position(|&ruleset| ruleset == name)?;
+            let score_id = Event::parse_score_id(score_id.trim().as_bytes())?;
+
+            if cursors[idx].replace(score_id).is_some() {
+                return None;
+            }
+        }
+
+        Some(Self(cursors))
+    }
+
+    /// The smallest cursor; rulesets without a cursor resume from it too.
+    pub fn oldest(&self) -> u64 {
+        self.0.iter().flatten().copied().min().unwrap_or(0)
+    }
+
+    /// Whether the score comes after the cursor of its ruleset.
+    pub fn is_after(&self, score: &Score) -> bool {
+        let cursor = score
+            .ruleset_id()
+            .and_then(|id| *self.0.get(usize::from(id))?)
+            .unwrap_or_else(|| self.oldest());
+
+        score.id() > cursor
+    }
 }
 
-impl Display for EventError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
-        match self {
-            EventError::Bytes => {
-                f.write_str("message must be either `\"connect\"` \r a score id to resume from")
+/// Message sent by a client after the initial message.
+pub enum Command {
+    Disconnect,
+    Stats,
+    /// `"top"`; requests the top scores of the rolling window.
+    Top,
+    /// `"cursor"`; requests the newest score id in the history.
+    Cursor,
+    /// `{"fields":[...]}`; an empty list resets to all fields.
+    Fields(Fields),
+    /// `{"ack":<score_id>}`; only relevant in ack mode.
+    Ack(u64),
+    /// `{"partition":<index>,"of":<count>}`
+    Partition(Partition),
+    /// `{"filter":"<field>","in":[<values>]}`; an empty list removes the
+    /// condition on that field.
+    Filter(Condition),
+    /// `{"beatmaps":[<ids>]}` or `{"beatmapsets":[<ids>]}`; an empty list
+    /// removes the restriction.
+    Beatmaps(Beatmaps),
+    /// `{"mods_include":[...],"mods_exclude":[...]}` where either entry may
+    /// be omitted; an empty list removes the restriction.
+    Mods(ModUpdate),
+    /// `{"rulesets":[...]}`; an empty list resets to all rulesets.
+    Rulesets(Rulesets),
+    /// `{"sample":<ratio>}`; a ratio of 1 resets to all scores.
+    Sample(Sample),
+    /// `{"max_per_sec":<count>}`; 0 removes the limit.
+    MaxPerSec(u32),
+    /// `{"replay":{"from":<score_id>,"to":<score_id>}}`; both inclusive.
+    Replay {
+        from: u64,
+        to: u64,
+    },
+}
+
+impl Command {
+    pub fn parse(msg: &Message) -> Option<Self> {
+        let bytes: &[u8] = match msg {
+            Message::Text(bytes) => bytes.as_bytes(),
+            Message::Binary(bytes) => bytes,
+            _ => return None,
+        };
+
+        match bytes {
+            b"disconnect" => Some(Self::Disconnect),
+            b"stats" => Some(Self::Stats),
+            b"top" => Some(Self::Top),
+            b"cursor" => Some(Self::Cursor),
+            _ => {
+                let (key, value) = Self::parse_object(bytes)?;
+
+                match key {
+                    "fields" => Self::parse_fields(value).map(Self::Fields),
+                    "ack" => Event::parse_score_id(value.as_bytes()).map(Self::Ack),
+                    "partition" => Self::parse_partition(value).map(Self::Partition),
+                    "filter" => Self::parse_filter(value).map(Self::Filter),
+                    "rulesets" => Self::parse_fields(value)
+                        .and_then(|names| Rulesets::from_names(names.iter().map(AsRef::as_ref)))
+                        .map(Self::Rulesets),
+                    "beatmaps" => Self::parse_ids(value)
+                        .map(Beatmaps::Maps)
+                        .map(Self::Beatmaps),
+                    "beatmapsets" => Self::parse_ids(value)
+                        .map(Beatmaps::Sets)
+                        .map(Self::Beatmaps),
+                    "mods_include" | "mods_exclude" => Self::parse_mods(key, value).map(Self::Mods),
+                    "replay" => Self::parse_replay(value),
+                    "sample" => value.parse().ok().and_then(Sample::new).map(Self::Sample),
+                    "max_per_sec" => value.parse().ok().map(Self::MaxPerSec),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /// Parses an object with a single entry and returns its key and value.
+    fn parse_object(bytes: &[u8]) -> Option<(&str, &str)> {
+        let (key, value) = std::str::from_utf8(bytes)
+            .ok()?
+            .trim()
+            .strip_prefix('{')?
+            .strip_suffix('}')?
+            .split_once(':')?;
+
+        let key = key.trim().strip_prefix('"')?.strip_suffix('"')?;
+
+        Some((key, value.trim()))
+    }
+
+    /// Parses the remainder `<index>,"of":<count>` of a partition object.
+    fn parse_partition(value: &str) -> Option<Partition> {
+        let (index, count) = value.split_once(',')?;
+
+        let count = count
+            .trim()
+            .strip_prefix(r#""of""#)?
+            .trim_start()
+            .strip_prefix(':')?
+            .trim();
+
+        Partition::new(index.trim().parse().ok()?, count.parse().ok()?)
+    }
+
+    /// Parses the value `{"from":<score_id>,"to":<score_id>}` of a replay
+    /// object.
+    fn parse_replay(value: &str) -> Option<Self> {
+        let (from, to) = value
+            .strip_prefix('{')?
+            .strip_suffix('}')?
+            .split_once(',')?;
+
+        let parse = |entry: &str, key: &str| {
+            let id = entry
+                .trim()
+                .strip_prefix(key)?
+                .trim_start()
+                .strip_prefix(':')?
+                .trim();
+
+            Event::parse_score_id(id.as_bytes())
+        };
+
+        let from = parse(from, r#""from""#)?;
+        let to = parse(to, r#""to""#)?;
+
+        (from <= to).then_some(Self::Replay { from, to })
+    }
+
+    /// Parses the remainder `"<field>","in":[<values>]` of a filter object.
+    fn parse_filter(value: &str) -> Option<Condition> {
+        let (path, values) = value.split_once(',')?;
+        let path = path.trim().strip_prefix('"')?.strip_suffix('"')?;
+
+        let list = values
+            .trim()
+            .strip_prefix(r#""in""#)?
+            .trim_start()
+            .strip_prefix(':')?
+            .trim()
+            .strip_prefix('[')?
+            .strip_suffix(']')?
+            .trim();
+
+        if list.is_empty() {
+            return Condition::new(path, []);
+        }
+
+        Condition::new(path, list.split(','))
+    }
+
+    /// Parses the remainder `[...]` of a mods object and its optional second
+    /// entry.
+    fn parse_mods(key: &str, value: &str) -> Option<ModUpdate> {
+        let mut update = ModUpdate::default();
+        let mut entry = Some((key, value));
+
+        while let Some((key, value)) = entry {
+            let (list, rest) = value.trim().strip_prefix('[')?.split_once(']')?;
+            let acronyms = ModUpdate::parse_acronyms(list.split(','))?;
+
+            let slot = match key {
+                "mods_include" => &mut update.include,
+                "mods_exclude" => &mut update.exclude,
+                _ => return None,
+            };
+
+            if slot.replace(acronyms).is_some() {
+                return None;
             }
-            EventError::Variant => f.write_str("message must contain text data"),
+
+            let rest = rest.trim();
+
+            entry = if rest.is_empty() {
+                None
+            } else {
+                let (key, value) = rest.strip_prefix(',')?.split_once(':')?;
+
+                Some((key.trim().strip_prefix('"')?.strip_suffix('"')?, value))
+            };
+        }
+
+        Some(update)
+    }
+
+    fn parse_ids(value: &str) -> Option<HashSet<u64>> {
+        Beatmaps::parse_ids(value.strip_prefix('[')?.strip_suffix(']')?)
+    }
+
+    fn parse_fields(value: &str) -> Option<Fields> {
+        let list = value.strip_prefix('[')?.strip_suffix(']')?.trim();
+
+        if list.is_empty() {
+            return Some(Fields::default());
         }
+
+        list.split(',')
+            .map(|field| {
+                let field = field.trim().strip_prefix('"')?.strip_suffix('"')?;
+
+                (!field.contains(['"', '\\'])).then(|| Box::from(field))
+            })
+            .collect()
+    }
+}
+
+/// Error that is sent to clients as JSON containing a stable code to branch on.
+#[derive(Copy, Clone)]
+pub struct ErrorFrame {
+    code: &'static str,
+    message: &'static str,
+    /// Code of the close frame that follows the error.
+    close_code: CloseCode,
+}
+
+impl ErrorFrame {
+    /// There is no standard close code for timeouts so it's modeled after
+    /// HTTP's 408.
+    const TIMEOUT: CloseCode = CloseCode::Library(4408);
+
+    pub const AUTH_REQUIRED: Self = Self {
+        code: "AUTH_REQUIRED",
+        message: "a key is required to connect",
+        close_code: CloseCode::Policy,
+    };
+
+    pub const INVALID_KEY: Self = Self {
+        code: "INVALID_KEY",
+        message: "the given key is unknown",
+        close_code: CloseCode::Policy,
+    };
+
+    pub const PERMISSION_DENIED: Self = Self {
+        code: "PERMISSION_DENIED",
+        message: "missing permission for this operation",
+        close_code: CloseCode::Policy,
+    };
+
+    pub const INVALID_INITIAL: Self = Self {
+        code: "INVALID_INITIAL",
+        message: "message must be either `\"connect\"`, `\"late\"`, `\"user_active\"`, \
+            `\"anomalies\"`, `{\"subscribe\":\"stats\"}`, \
+            `{\"subscribe\":\"alerts\",\"min_pp\":<pp>}`, a score id to resume from, \
+            `{\"resume\":{\"<ruleset>\":<score_id>}}`, or an object \
+            `{\"v\":2,\"action\":\"...\"}`",
+        close_code: CloseCode::Policy,
+    };
+
+    pub const UNSUPPORTED_VERSION: Self = Self {
+        code: "UNSUPPORTED_VERSION",
+        message: "the initial message's `v` is not a supported protocol version",
+        close_code: CloseCode::Policy,
+    };
+
+    pub const LEGACY_IDS_DISABLED: Self = Self {
+        code: "LEGACY_IDS_DISABLED",
+        message: "resuming by legacy score id is not enabled",
+        close_code: CloseCode::Policy,
+    };
+
+    pub const INITIAL_NOT_DATA: Self = Self {
+        code: "INVALID_INITIAL",
+        message: "message must contain text data",
+        close_code: CloseCode::Unsupported,
+    };
+
+    pub const INITIAL_TIMEOUT: Self = Self {
+        code: "INITIAL_TIMEOUT",
+        message: "no initial message was sent within 5 seconds",
+        close_code: Self::TIMEOUT,
+    };
+
+    pub const SESSION_EXPIRED: Self = Self {
+        code: "SESSION_EXPIRED",
+        message: "the session is unknown or expired; resume through a score id instead",
+        close_code: CloseCode::Policy,
+    };
+
+    pub const RATE_LIMITED: Self = Self {
+        code: "RATE_LIMITED",
+        message: "too many messages",
+        close_code: CloseCode::Policy,
+    };
+
+    pub const LAGGING: Self = Self {
+        code: "LAGGING",
+        message: "too many messages are queued; the connection can't keep up",
+        close_code: CloseCode::Policy,
+    };
+
+    #[cfg(feature = "grpc")]
+    pub const fn message(self) -> &'static str {
+        self.message
+    }
+
+    pub fn to_message(self) -> Message {
+        let Self {
+            code,
+            message,
+            close_code: _,
+        } = self;
+        let message = message.replace('"', r#"\""#);
+        let json = format!(r#"{{"type":"error","code":"{code}","message":"{message}"}}"#);
+
+        Message::Text(json.into())
+    }
+
+    /// Close frame with the error's code as reason.
+    pub fn to_close(self) -> Message {
+        Message::Close(Some(CloseFrame {
+            code: self.close_code,
+            reason: self.code.into(),
+        }))
+    }
+
+    /// Sends the error, followed by its close frame.
+    pub async fn send<S: Sink<Message> + Unpin>(self, sink: &mut S) {
+        let _: Result<_, _> = sink.send(self.to_message()).await;
+        let _: Result<_, _> = sink.send(self.to_close()).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+
+    #[test]
+    fn versioned_initial() {
+        let parse = |text: &'static str| Event::try_from(Message::Text(text.into()));
+
+        assert!(matches!(
+            parse("connect"),
+            Ok(Event::Connect { compressed: false })
+        ));
+        assert!(matches!(
+            parse("123"),
+            Ok(Event::Resume {
+                score_id: 123,
+                compressed: false
+            })
+        ));
+        assert!(matches!(
+            parse(r#"{"subscribe":"stats"}"#),
+            Ok(Event::Aggregates)
+        ));
+
+        assert!(matches!(
+            parse(r#"{"v":2,"action":"connect"}"#),
+            Ok(Event::Connect { compressed: false })
+        ));
+        assert!(matches!(
+            parse(r#"{ "action" : "resume" , "score_id" : 123 , "v" : 2 }"#),
+            Ok(Event::Resume {
+                score_id: 123,
+                compressed: false
+            })
+        ));
+        assert!(matches!(
+            parse(r#"{"v":2,"action":"connect","compress":"zstd"}"#),
+            Ok(Event::Connect { compressed: true })
+        ));
+        assert!(matches!(
+            parse(r#"{"v":2,"compress":"zstd","action":"resume","score_id":5}"#),
+            Ok(Event::Resume {
+                score_id: 5,
+                compressed: true
+            })
+        ));
+        assert!(matches!(
+            parse(r#"{"v":2,"action":"aggregates"}"#),
+            Ok(Event::Aggregates)
+        ));
+        assert!(matches!(parse("anomalies"), Ok(Event::Anomalies)));
+        assert!(matches!(
+            parse(r#"{"subscribe":"alerts", "min_pp": 700}"#),
+            Ok(Event::Alerts { min_pp }) if (min_pp - 700.0).abs() < f64::EPSILON
+        ));
+        assert!(matches!(
+            parse(r#"{"v":2,"action":"alerts","min_pp":650.5}"#),
+            Ok(Event::Alerts { min_pp }) if (min_pp - 650.5).abs() < f64::EPSILON
+        ));
+        assert!(parse(r#"{"subscribe":"alerts"}"#).is_err());
+        assert!(parse(r#"{"resume":{"osu":1,"osu":2}}"#).is_err());
+        assert!(parse(r#"{"resume":{"catch":1}}"#).is_err());
+        assert!(parse(r#"{"resume":{}}"#).is_err());
+        assert!(parse(r#"{"subscribe":"alerts","min_pp":-1}"#).is_err());
+        assert!(parse(r#"{"v":2,"action":"connect","min_pp":1}"#).is_err());
+        assert!(parse(r#"{"v":2,"action":"connect","compress":"gzip"}"#).is_err());
+        assert!(parse(r#"{"v":2,"action":"late","compress":"zstd"}"#).is_err());
+
+        assert!(matches!(
+            parse(r#"{"v":2,"action":"resume","ruleset":"mania","legacy_score_id":42}"#),
+            Ok(Event::ResumeLegacy {
+                ruleset_id: 3,
+                legacy_score_id: 42,
+                compressed: false
+            })
+        ));
+        assert!(parse(r#"{"v":2,"action":"resume","legacy_score_id":42}"#).is_err());
+        assert!(parse(r#"{"v":2,"action":"resume","ruleset":"osu"}"#).is_err());
+        assert!(parse(r#"{"v":2,"action":"resume","ruleset":"std","legacy_score_id":1}"#).is_err());
+        assert!(parse(
+            r#"{"v":2,"action":"resume","score_id":1,"ruleset":"osu","legacy_score_id":1}"#
+        )
+        .is_err());
+        assert!(
+            parse(r#"{"v":2,"action":"connect","ruleset":"osu","legacy_score_id":1}"#).is_err()
+        );
+
+        let code = |text| parse(text).err().map(|err| err.code);
+        assert_eq!(
+            code(r#"{"v":3,"action":"connect","new":1}"#),
+            Some("UNSUPPORTED_VERSION")
+        );
+        assert_eq!(
+            code(r#"{"v":2,"action":"resume"}"#),
+            Some("INVALID_INITIAL")
+        );
+        assert_eq!(
+            code(r#"{"v":2,"action":"connect","new":1}"#),
+            Some("INVALID_INITIAL")
+        );
+        assert_eq!(code(r#"{"action":"connect"}"#), Some("INVALID_INITIAL"));
+    }
+
+    #[test]
+    fn resume_cursors() {
+        let Ok(Event::ResumeRulesets { cursors }) = Event::try_from(Message::Text(
+            r#"{"resume":{ "osu" : 10 ,"mania":5}}"#.into(),
+        )) else {
+            panic!("expected resume cursors");
+        };
+
+        let score = |id: u64, ruleset_id: u8| {
+            let json = format!(r#"{{"id":{id},"ruleset_id":{ruleset_id}}}"#);
+
+            Score::new(id, Bytes::from(json))
+        };
+
+        assert_eq!(cursors.oldest(), 5);
+        assert!(!cursors.is_after(&score(8, 0)));
+        assert!(cursors.is_after(&score(11, 0)));
+        assert!(cursors.is_after(&score(6, 3)));
+        assert!(cursors.is_after(&score(6, 1)));
+        assert!(!cursors.is_after(&score(5, 2)));
+    }
+
+    #[test]
+    fn replay() {
+        let parse = |text: &'static str| Command::parse(&Message::Text(text.into()));
+
+        assert!(matches!(
+            parse(r#"{"replay":{"from":1,"to":5}}"#),
+            Some(Command::Replay { from: 1, to: 5 })
+        ));
+        assert!(matches!(
+            parse(r#"{ "replay" : { "from" : 3 , "to" : 3 } }"#),
+            Some(Command::Replay { from: 3, to: 3 })
+        ));
+        assert!(parse(r#"{"replay":{"from":5,"to":1}}"#).is_none());
+        assert!(parse(r#"{"replay":{"to":5,"from":1}}"#).is_none());
+    }
+
+    #[test]
+    fn sampling() {
+        let parse = |text: &'static str| Command::parse(&Message::Text(text.into()));
+
+        assert!(matches!(
+            parse(r#"{"sample":0.1}"#),
+            Some(Command::Sample(_))
+        ));
+        assert!(matches!(parse(r#"{"sample":1}"#), Some(Command::Sample(_))));
+        assert!(parse(r#"{"sample":0}"#).is_none());
+        assert!(parse(r#"{"sample":1.5}"#).is_none());
+        assert!(matches!(
+            parse(r#"{"max_per_sec":50}"#),
+            Some(Command::MaxPerSec(50))
+        ));
+        assert!(parse(r#"{"max_per_sec":-1}"#).is_none());
+    }
+
+    #[test]
+    fn mods() {
+        let parse = |text: &'static str| match Command::parse(&Message::Text(text.into())) {
+            Some(Command::Mods(update)) => Some((update.include, update.exclude)),
+            _ => None,
+        };
+
+        let (include, exclude) =
+            parse(r#"{"mods_include":["HD","DT"], "mods_exclude" : ["RX"]}"#).unwrap();
+        assert_eq!(include.unwrap().len(), 2);
+        assert_eq!(exclude.unwrap().len(), 1);
+
+        assert!(matches!(
+            parse(r#"{"mods_exclude":[]}"#),
+            Some((None, Some(exclude))) if exclude.is_empty()
+        ));
+        assert!(parse(r#"{"mods_include":["HD"],"mods_include":["DT"]}"#).is_none());
+        assert!(parse(r#"{"mods_include":["HD"],"rulesets":["osu"]}"#).is_none());
     }
 }
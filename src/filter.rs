@@ -0,0 +1,240 @@
+use std::collections::HashSet;
+
+use crate::{
+    json,
+    mods::{ModFilter, ModUpdate},
+    osu::Score,
+};
+
+/// Upper bound for the amount of conditions so that clients cannot make
+/// broadcasting arbitrarily expensive.
+const MAX_CONDITIONS: usize = 8;
+
+/// Upper bound for the amount of beatmap or beatmapset ids, plenty for a
+/// mappool.
+const MAX_BEATMAP_IDS: usize = 1000;
+
+/// Conditions on score fields that must all hold for a score to be sent.
+#[derive(Default)]
+pub struct Filter {
+    conditions: Vec<Condition>,
+    /// Only scores on these beatmaps match unless it's empty.
+    beatmap_ids: HashSet<u64>,
+    /// Only scores on beatmaps of these beatmapsets match unless it's empty.
+    beatmapset_ids: HashSet<u64>,
+    mods: ModFilter,
+}
+
+impl Filter {
+    pub fn matches(&self, score: &Score) -> bool {
+        let contains = |ids: &HashSet<u64>, id: Option<u64>| {
+            ids.is_empty() || id.is_some_and(|id| ids.contains(&id))
+        };
+
+        contains(&self.beatmap_ids, score.beatmap_id())
+            && contains(&self.beatmapset_ids, score.beatmapset_id())
+            && self
+                .conditions
+                .iter()
+                .all(|condition| condition.matches(score.bytes()))
+            && self.mods.matches(score.bytes())
+    }
+
+    pub fn update_mods(&mut self, update: ModUpdate) {
+        self.mods.update(update);
+    }
+
+    /// Replaces the ids of the same kind; no ids remove the restriction.
+    pub fn set_beatmaps(&mut self, beatmaps: Beatmaps) {
+        match beatmaps {
+            Beatmaps::Maps(ids) => self.beatmap_ids = ids,
+            Beatmaps::Sets(ids) => self.beatmapset_ids = ids,
+        }
+    }
+
+    /// Replaces the condition on the same field. A condition without values
+    /// removes the one on its field instead.
+    pub fn set(&mut self, condition: Condition) {
+        let existing = self
+            .conditions
+            .iter()
+            .position(|existing| existing.path == condition.path);
+
+        match existing {
+            Some(idx) if condition.values.is_empty() => {
+                self.conditions.swap_remove(idx);
+            }
+            Some(idx) => self.conditions[idx] = condition,
+            None if condition.values.is_empty() => {}
+            None if self.conditions.len() >= MAX_CONDITIONS => {}
+            None => self.conditions.push(condition),
+        }
+    }
+}
+
+/// Beatmap or beatmapset ids of which a score's must be one, e.g. those of a
+/// tournament's mappool.
+pub enum Beatmaps {
+    Maps(HashSet<u64>),
+    Sets(HashSet<u64>),
+}
+
+impl Beatmaps {
+    /// Parses comma-separated ids such as `123,456`.
+    ///
+    /// Returns `None` if an id is invalid or there are too many.
+    pub fn parse_ids(list: &str) -> Option<HashSet<u64>> {
+        let ids: HashSet<u64> = list
+            .split(',')
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(|id| id.parse().ok())
+            .collect::<Option<_>>()?;
+
+        (ids.len() <= MAX_BEATMAP_IDS).then_some(ids)
+    }
+}
+
+/// A field, possibly nested like `user.country_code`, and the values it may
+/// have.
+pub struct Condition {
+    path: Box<str>,
+    /// Key of each segment of the path.
+    keys: Box<[Box<[u8]>]>,
+    /// Raw JSON values such as `"DE"` or `true`.
+    values: Box<[Box<[u8]>]>,
+}
+
+impl Condition {
+    /// Returns `None` if the path is empty or a value is not a JSON string,
+    /// number, boolean, or null.
+    pub fn new<'a>(path: &str, values: impl IntoIterator<Item = &'a str>) -> Option<Self> {
+        let keys = path
+            .split('.')
+            .map(|key| {
+                let valid = !key.is_empty() && !key.contains(['"', '\\']);
+
+                valid.then(|| Box::from(key.as_bytes()))
+            })
+            .collect::<Option<_>>()?;
+
+        let values = values
+            .into_iter()
+            .map(|value| {
+                let value = value.trim();
+
+                is_scalar(value).then(|| Box::from(value.as_bytes()))
+            })
+            .collect::<Option<_>>()?;
+
+        Some(Self {
+            path: Box::from(path),
+            keys,
+            values,
+        })
+    }
+
+    /// Parses a query parameter's comma-separated values which don't need to
+    /// be quoted, e.g. `DE,FR`.
+    pub fn from_query(path: &str, list: &str) -> Option<Self> {
+        let values: Vec<_> = list
+            .split(',')
+            .filter(|value| !value.is_empty())
+            .map(|value| {
+                if is_scalar(value) {
+                    value.to_owned()
+                } else {
+                    format!(r#""{value}""#)
+                }
+            })
+            .collect();
+
+        Self::new(path, values.iter().map(String::as_str))
+    }
+
+    fn matches(&self, bytes: &[u8]) -> bool {
+        field(bytes, &self.keys).is_some_and(|value| self.values.iter().any(|v| **v == *value))
+    }
+}
+
+/// The raw value of the field at the end of the given keys, each nested
+/// within the object of the previous one.
+fn field<'a>(bytes: &'a [u8], keys: &[Box<[u8]>]) -> Option<&'a [u8]> {
+    json::value(bytes, json::find_path(bytes, keys)?)
+}
+
+fn is_scalar(value: &str) -> bool {
+    match value {
+        "true" | "false" | "null" => true,
+        _ if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') => {
+            !value[1..value.len() - 1].contains(['"', '\\'])
+        }
+        _ => value.parse::<f64>().is_ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+
+    #[test]
+    fn conditions() {
+        let score = Score::new(
+            1,
+            Bytes::from_static(
+                br#"{"id":1,"passed":true,"rank":"S","pp":12.5,"user":{"country_code":"DE","id":2}}"#,
+            ),
+        );
+
+        let mut filter = Filter::default();
+        assert!(filter.matches(&score));
+
+        filter.set(Condition::from_query("user.country_code", "FR,DE").unwrap());
+        filter.set(Condition::new("passed", ["true"]).unwrap());
+        assert!(filter.matches(&score));
+
+        filter.set(Condition::new("rank", [r#""X""#, r#""SH""#]).unwrap());
+        assert!(!filter.matches(&score));
+
+        filter.set(Condition::new("rank", []).unwrap());
+        filter.set(Condition::new("pp", ["12.5"]).unwrap());
+        assert!(filter.matches(&score));
+
+        filter.set(Condition::new("user.username", ["null"]).unwrap());
+        assert!(!filter.matches(&score));
+
+        assert!(Condition::new("rank", ["S"]).is_none());
+        assert!(Condition::new("user.", [r#""DE""#]).is_none());
+    }
+
+    #[test]
+    fn beatmaps() {
+        let score = Score::new(
+            1,
+            Bytes::from_static(
+                br#"{"id":1,"beatmap_id":75,"beatmap":{"beatmapset_id":1,"id":75}}"#,
+            ),
+        );
+
+        let mut filter = Filter::default();
+
+        let ids = Beatmaps::parse_ids("129891, 75").unwrap();
+        filter.set_beatmaps(Beatmaps::Maps(ids));
+        assert!(filter.matches(&score));
+
+        filter.set_beatmaps(Beatmaps::Sets(Beatmaps::parse_ids("2").unwrap()));
+        assert!(!filter.matches(&score));
+
+        filter.set_beatmaps(Beatmaps::Sets(Beatmaps::parse_ids("").unwrap()));
+        assert!(filter.matches(&score));
+
+        let only_id = Score::new(2, Bytes::from_static(br#"{"id":2}"#));
+        assert!(!filter.matches(&only_id));
+
+        assert!(Beatmaps::parse_ids("1,x").is_none());
+        let many: Vec<_> = (0..=MAX_BEATMAP_IDS).map(|id| id.to_string()).collect();
+        assert!(Beatmaps::parse_ids(&many.join(",")).is_none());
+    }
+}
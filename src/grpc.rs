@@ -0,0 +1,268 @@
+use std::{
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering::Relaxed},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures_util::{stream, Stream};
+use tokio::{
+    net::TcpListener,
+    sync::{mpsc, oneshot},
+};
+use tokio_tungstenite::tungstenite::Message;
+use tonic::{
+    transport::{server::TcpIncoming, Server},
+    Request, Response, Status,
+};
+
+use crate::{
+    auth::{Op, Permissions},
+    client::{Client, Fields},
+    context::Context,
+    event::{ErrorFrame, ResumeCursors},
+    limiter::ConnectionPermit,
+    listener::Peer,
+};
+
+use self::proto::{
+    scores_server::{Scores, ScoresServer},
+    Score, SubscribeRequest,
+};
+
+#[allow(clippy::pedantic, clippy::missing_const_for_fn)]
+mod proto {
+    tonic::include_proto!("scores_ws");
+
+    pub const FILE_DESCRIPTOR_SET: &[u8] =
+        tonic::include_file_descriptor_set!("scores_ws_descriptor");
+}
+
+/// Amount of scores that may be on their way to a gRPC client.
+const STREAM_BUFFER: usize = 16;
+
+type ScoreStream = Pin<Box<dyn Stream<Item = Result<Score, Status>> + Send>>;
+
+/// Runs a gRPC server providing the service defined in `proto/scores.proto`
+/// as well as server reflection.
+///
+/// Its streaming method mirrors the websocket: clients receive the history
+/// starting from an optional score id, followed by all new scores.
+pub async fn run(ctx: Arc<Context>, listener: TcpListener, delay: Option<Duration>) {
+    let reflection = match tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(proto::FILE_DESCRIPTOR_SET)
+        .build_v1()
+    {
+        Ok(reflection) => reflection,
+        Err(err) => return error!(?err, "Failed to build gRPC reflection service"),
+    };
+
+    let incoming = match TcpIncoming::from_listener(listener, true, None) {
+        Ok(incoming) => incoming,
+        Err(err) => return error!(?err, "Failed to accept gRPC connections"),
+    };
+
+    let service = Service {
+        ctx,
+        delay,
+        next_id: AtomicU64::new(0),
+    };
+
+    let res = Server::builder()
+        .add_service(ScoresServer::new(service))
+        .add_service(reflection)
+        .serve_with_incoming(incoming)
+        .await;
+
+    if let Err(err) = res {
+        error!(?err, "gRPC server failed");
+    }
+}
+
+struct Service {
+    ctx: Arc<Context>,
+    delay: Option<Duration>,
+    /// Distinguishes streams of the same connection.
+    next_id: AtomicU64,
+}
+
+#[tonic::async_trait]
+impl Scores for Service {
+    type SubscribeScoresStream = ScoreStream;
+
+    async fn subscribe_scores(
+        &self,
+        request: Request<SubscribeRequest>,
+    ) -> Result<Response<ScoreStream>, Status> {
+        let addr = request
+            .remote_addr()
+            .unwrap_or(SocketAddr::from(([0, 0, 0, 0], 0)));
+
+        let peer = Peer::Grpc {
+            addr,
+            id: self.next_id.fetch_add(1, Relaxed) + 1,
+        };
+
+        let key = bearer_key(&request);
+        let (admitted_tx, admitted_rx) = oneshot::channel();
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+
+        // The task holds on to the connection permit for as long as the
+        // client is subscribed
+        tokio::spawn(subscribe(
+            Arc::clone(&self.ctx),
+            peer,
+            key,
+            request.into_inner(),
+            self.delay,
+            admitted_tx,
+            tx,
+        ));
+
+        admitted_rx
+            .await
+            .map_err(|_| Status::internal("failed to subscribe"))??;
+
+        let scores = stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|score| (Ok(score), rx))
+        });
+
+        Ok(Response::new(Box::pin(scores)))
+    }
+}
+
+/// Registers the client if it's admitted and forwards its scores until it
+/// disconnects.
+async fn subscribe(
+    ctx: Arc<Context>,
+    addr: Peer,
+    key: Option<Box<str>>,
+    request: SubscribeRequest,
+    delay: Option<Duration>,
+    admitted: oneshot::Sender<Result<(), Status>>,
+    tx: mpsc::Sender<Score>,
+) {
+    let op = match request.resume_score_id {
+        Some(_) => Op::Resume,
+        None => Op::Connect,
+    };
+
+    let (_permit, permissions) = match admit(&ctx, addr, key.as_deref(), op) {
+        Ok(admission) => {
+            let _ = admitted.send(Ok(()));
+
+            admission
+        }
+        Err(status) => {
+            let _ = admitted.send(Err(status));
+
+            return;
+        }
+    };
+
+    let (client_tx, mut rx) = mpsc::unbounded_channel();
+
+    let fields: Fields = request.fields.into_iter().map(Box::from).collect();
+    let fields = (!fields.is_empty()).then_some(fields);
+
+    let client = Arc::new(Client::new(client_tx, permissions, fields, delay));
+
+    info!(resume_score_id = request.resume_score_id, %addr, "gRPC subscribe");
+    ctx.add_client(
+        addr,
+        &client,
+        request.resume_score_id.map(ResumeCursors::global),
+    );
+
+    loop {
+        let msg = tokio::select! {
+            msg = rx.recv() => msg,
+            () = tx.closed() => break,
+        };
+
+        let Some(msg) = msg else { break };
+        client.dequeued(&msg);
+
+        // Only scores are forwarded; other messages are websocket specific
+        let Message::Binary(json) = msg else { continue };
+
+        let score = Score {
+            json: String::from_utf8_lossy(&json).into_owned(),
+        };
+
+        if tx.send(score).await.is_err() {
+            break;
+        }
+    }
+
+    info!("{addr} disconnected");
+    ctx.remove_client(addr);
+}
+
+// Boxing the status wouldn't gain anything since it's returned right away
+#[allow(clippy::result_large_err)]
+fn admit(
+    ctx: &Context,
+    addr: Peer,
+    key: Option<&str>,
+    op: Op,
+) -> Result<(ConnectionPermit, Permissions), Status> {
+    if !ctx.state().phase().accepts_connections() {
+        return Err(Status::unavailable("draining"));
+    }
+
+    let Some(permit) = ctx.limiter().connect(addr.ip()) else {
+        warn!(%addr, "Rejecting gRPC stream due to rate limits");
+
+        return Err(Status::resource_exhausted("rate limited"));
+    };
+
+    if ctx.limiter().exceeds_max_clients() {
+        warn!(%addr, "Rejecting gRPC stream because the maximum amount of clients is reached");
+
+        return Err(Status::resource_exhausted("too many clients"));
+    }
+
+    let permissions = match ctx.auth().permissions(key) {
+        Ok(permissions) => permissions,
+        Err(err) => {
+            info!(%addr, "Rejecting gRPC stream due to invalid authorization");
+
+            return Err(Status::unauthenticated(err.message()));
+        }
+    };
+
+    if !permissions.allows(op) {
+        let message = ErrorFrame::PERMISSION_DENIED.message();
+
+        return Err(Status::permission_denied(message));
+    }
+
+    Ok((permit, permissions))
+}
+
+/// The key of the metadata `authorization: Bearer {key}`.
+fn bearer_key<T>(request: &Request<T>) -> Option<Box<str>> {
+    let authorization = request.metadata().get("authorization")?.to_str().ok()?;
+
+    authorization.strip_prefix("Bearer ").map(Box::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bearer() {
+        let mut request = Request::new(SubscribeRequest::default());
+        assert_eq!(bearer_key(&request), None);
+
+        request
+            .metadata_mut()
+            .insert("authorization", "Bearer abc".parse().unwrap());
+        assert_eq!(bearer_key(&request).as_deref(), Some("abc"));
+    }
+}
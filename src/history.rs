@@ -0,0 +1,433 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+};
+
+use bytes::Bytes;
+use tracing::warn;
+
+use crate::osu::Score;
+
+/// Amount of scores after which a new segment is started.
+const SEGMENT_LEN: usize = 1024;
+
+type Segment = Arc<VecDeque<Score>>;
+
+/// The most recent scores, ordered by id.
+///
+/// Scores arrive nearly sorted so they're kept in ring buffers in which
+/// inserting almost always appends and range queries binary search their
+/// start. Compared to a `BTreeSet` this avoids node overhead and pointer
+/// chasing when replaying large histories.
+///
+/// The ring buffers are split into reference-counted segments so that
+/// replaying can iterate over a [`Snapshot`] without holding a lock. Segments
+/// are only copied if they're modified while a snapshot still references them
+/// which, since scores are appended, mostly affects the last segment.
+///
+/// If compressed, each score's JSON is stored lz4 compressed and only
+/// decompressed when it's read, e.g. while replaying.
+///
+/// If legacy score ids are mapped, the ids of scores are also kept by their
+/// ruleset and legacy score id so that clients can resume by the latter.
+pub struct History {
+    segments: VecDeque<Segment>,
+    len: usize,
+    capacity: usize,
+    /// Total size of all scores' JSON as stored, i.e. after compression.
+    bytes: usize,
+    /// Scores are evicted while their total size exceeds this if specified.
+    max_bytes: Option<usize>,
+    compressed: bool,
+    /// Score ids by ruleset id and legacy score id if mapped. Entries of
+    /// evicted scores are only purged once they pile up.
+    legacy_ids: Option<HashMap<(u8, u64), u64>>,
+}
+
+impl History {
+    pub const fn new(capacity: usize, max_bytes: Option<usize>) -> Self {
+        Self {
+            segments: VecDeque::new(),
+            len: 0,
+            capacity,
+            bytes: 0,
+            max_bytes,
+            compressed: false,
+            legacy_ids: None,
+        }
+    }
+
+    /// Stores scores compressed which trades CPU for a larger history within
+    /// the same byte budget.
+    #[must_use]
+    pub const fn with_compression(mut self, compressed: bool) -> Self {
+        self.compressed = compressed;
+
+        self
+    }
+
+    /// Maps the legacy score ids of scores to their ids.
+    #[must_use]
+    pub fn with_legacy_ids(mut self, legacy_ids: bool) -> Self {
+        self.legacy_ids = legacy_ids.then(HashMap::new);
+
+        self
+    }
+
+    pub const fn maps_legacy_ids(&self) -> bool {
+        self.legacy_ids.is_some()
+    }
+
+    /// The id of the score in the history with the given ruleset and legacy
+    /// score id; `None` if it's not in the history or ids aren't mapped.
+    pub fn legacy_to_id(&self, ruleset_id: u8, legacy_score_id: u64) -> Option<u64> {
+        let id = *self
+            .legacy_ids
+            .as_ref()?
+            .get(&(ruleset_id, legacy_score_id))?;
+        let oldest = self.segments.front()?.front()?.id();
+
+        (id >= oldest).then_some(id)
+    }
+
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    pub const fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn first(&self) -> Option<Score> {
+        self.segments
+            .front()?
+            .front()
+            .and_then(|score| read(score, self.compressed))
+    }
+
+    pub fn last(&self) -> Option<Score> {
+        self.segments
+            .back()?
+            .back()
+            .and_then(|score| read(score, self.compressed))
+    }
+
+    /// The id of the newest score without decompressing it.
+    pub fn last_id(&self) -> Option<u64> {
+        self.segments.back()?.back().map(Score::id)
+    }
+
+    /// Adds all scores to the history and evicts the oldest ones if it
+    /// exceeds its capacity or byte budget.
+    pub fn extend(&mut self, scores: impl IntoIterator<Item = Score>) {
+        for score in scores {
+            self.insert(score);
+        }
+
+        while self.len > self.capacity
+            || self
+                .max_bytes
+                .is_some_and(|max_bytes| self.bytes > max_bytes)
+        {
+            if !self.evict_oldest() {
+                break;
+            }
+        }
+
+        self.purge_legacy_ids();
+    }
+
+    /// Evicts the oldest scores until their total size is at most
+    /// `max_bytes` and returns how many were evicted.
+    pub fn trim_bytes(&mut self, max_bytes: usize) -> usize {
+        let len = self.len;
+
+        while self.bytes > max_bytes && self.evict_oldest() {}
+
+        self.purge_legacy_ids();
+
+        len - self.len
+    }
+
+    /// Forgets the legacy score ids of evicted scores once there are more
+    /// than a segment's worth of them.
+    fn purge_legacy_ids(&mut self) {
+        let Some(ref mut legacy_ids) = self.legacy_ids else {
+            return;
+        };
+
+        if legacy_ids.len() <= self.len + SEGMENT_LEN {
+            return;
+        }
+
+        let oldest = self
+            .segments
+            .front()
+            .and_then(|segment| segment.front())
+            .map_or(u64::MAX, Score::id);
+
+        legacy_ids.retain(|_, id| *id >= oldest);
+    }
+
+    /// Returns `false` if the history is empty.
+    fn evict_oldest(&mut self) -> bool {
+        let Some(front) = self.segments.front_mut() else {
+            return false;
+        };
+
+        if let Some(evicted) = Arc::make_mut(front).pop_front() {
+            self.len -= 1;
+            self.bytes -= evicted.bytes().len();
+        }
+
+        if front.is_empty() {
+            self.segments.pop_front();
+        }
+
+        true
+    }
+
+    /// Inserts the score unless one with the same id is already present.
+    fn insert(&mut self, mut score: Score) {
+        if let Some(ref mut legacy_ids) = self.legacy_ids {
+            if let Some((ruleset_id, legacy_score_id)) =
+                score.ruleset_id().zip(score.legacy_score_id())
+            {
+                legacy_ids.insert((ruleset_id, legacy_score_id), score.id());
+            }
+        }
+
+        if self.compressed {
+            let compressed = lz4_flex::compress_prepend_size(score.bytes());
+            score = score.with_bytes(Bytes::from(compressed));
+        }
+
+        let score_len = score.bytes().len();
+
+        if self.last_id().is_none_or(|last| last < score.id()) {
+            match self.segments.back_mut() {
+                Some(segment) if segment.len() < SEGMENT_LEN => {
+                    Arc::make_mut(segment).push_back(score);
+                }
+                _ => self.segments.push_back(Arc::new(VecDeque::from([score]))),
+            }
+        } else {
+            // The first segment whose last score is not older
+            let segment_idx = self.segments.partition_point(|segment| {
+                segment.back().is_some_and(|last| last.id() < score.id())
+            });
+
+            let segment = &mut self.segments[segment_idx];
+            let idx = segment.partition_point(|found| found.id() < score.id());
+
+            if segment
+                .get(idx)
+                .is_some_and(|found| found.id() == score.id())
+            {
+                return;
+            }
+
+            Arc::make_mut(segment).insert(idx, score);
+        }
+
+        self.len += 1;
+        self.bytes += score_len;
+    }
+
+    /// Shares the current scores for iterating without holding a lock.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            segments: self.segments.iter().map(Arc::clone).collect(),
+            compressed: self.compressed,
+        }
+    }
+}
+
+/// The scores of a [`History`] at the time the snapshot was taken.
+pub struct Snapshot {
+    segments: Box<[Segment]>,
+    compressed: bool,
+}
+
+impl Snapshot {
+    pub fn first_id(&self) -> Option<u64> {
+        self.segments.first()?.front().map(Score::id)
+    }
+
+    pub fn contains(&self, id: u64) -> bool {
+        self.stored_from(id)
+            .next()
+            .is_some_and(|score| score.id() == id)
+    }
+
+    /// Iterates over all scores whose id is at least `start_id`.
+    ///
+    /// Compressed scores are decompressed one at a time while iterating.
+    pub fn range_from(&self, start_id: u64) -> impl Iterator<Item = Score> + '_ {
+        self.stored_from(start_id)
+            .filter_map(|score| read(score, self.compressed))
+    }
+
+    /// Iterates over the scores as they're stored, i.e. possibly compressed.
+    fn stored_from(&self, start_id: u64) -> impl Iterator<Item = &Score> {
+        let segment_idx = self
+            .segments
+            .partition_point(|segment| segment.back().is_some_and(|last| last.id() < start_id));
+
+        let mut segments = self.segments[segment_idx..].iter();
+
+        let first = segments.next().map(|segment| {
+            let idx = segment.partition_point(|score| score.id() < start_id);
+
+            segment.range(idx..)
+        });
+
+        first
+            .into_iter()
+            .flatten()
+            .chain(segments.flat_map(|segment| segment.iter()))
+    }
+}
+
+/// The score with its original JSON.
+///
+/// Cloning only increments the reference count of uncompressed scores.
+fn read(score: &Score, compressed: bool) -> Option<Score> {
+    if !compressed {
+        return Some(score.clone());
+    }
+
+    match lz4_flex::decompress_size_prepended(score.bytes()) {
+        Ok(bytes) => Some(score.with_bytes(Bytes::from(bytes))),
+        Err(err) => {
+            warn!(
+                ?err,
+                id = score.id(),
+                "Failed to decompress score of the history"
+            );
+
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::osu::Scores;
+
+    fn ids(history: &History) -> Vec<u64> {
+        history
+            .snapshot()
+            .range_from(0)
+            .map(|score| score.id())
+            .collect()
+    }
+
+    #[test]
+    fn insert_and_trim() {
+        let mut history = History::new(4, None);
+
+        let scores: Scores = [3, 1, 5].map(|id| Score::new(id, Bytes::new())).into();
+        history.extend(scores);
+        assert_eq!(ids(&history), [1, 3, 5]);
+
+        history.extend([4, 3, 6].map(|id| Score::new(id, Bytes::new())));
+        assert_eq!(ids(&history), [3, 4, 5, 6]);
+
+        let range: Vec<_> = history
+            .snapshot()
+            .range_from(5)
+            .map(|score| score.id())
+            .collect();
+        assert_eq!(range, [5, 6]);
+        assert_eq!(history.snapshot().range_from(7).count(), 0);
+    }
+
+    #[test]
+    fn segments() {
+        let len = SEGMENT_LEN as u64;
+        let mut history = History::new(3 * SEGMENT_LEN, None);
+        history.extend((0..2 * len).map(|id| Score::only_id(id * 2)));
+
+        let snapshot = history.snapshot();
+
+        // Fills the gaps in both segments and starts a third one
+        history.extend([1, 2 * len + 1, 4 * len].map(Score::only_id));
+        assert_eq!(history.len(), 2 * SEGMENT_LEN + 3);
+
+        let range: Vec<_> = history
+            .snapshot()
+            .range_from(2 * len - 2)
+            .map(|score| score.id())
+            .collect();
+        assert_eq!(range[..4], [2 * len - 2, 2 * len, 2 * len + 1, 2 * len + 2]);
+        assert_eq!(range.last(), Some(&(4 * len)));
+
+        // The snapshot is unaffected
+        assert_eq!(snapshot.range_from(0).count(), 2 * SEGMENT_LEN);
+        assert_eq!(
+            snapshot.range_from(1).next().map(|score| score.id()),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn byte_budget() {
+        let mut history = History::new(10, Some(10));
+        let score = |id| Score::new(id, Bytes::from_static(b"1234"));
+
+        history.extend([1, 2].map(score));
+        assert_eq!(history.bytes(), 8);
+
+        history.extend([3, 2].map(score));
+        assert_eq!(ids(&history), [2, 3]);
+        assert_eq!(history.bytes(), 8);
+
+        assert_eq!(history.trim_bytes(5), 1);
+        assert_eq!(ids(&history), [3]);
+    }
+
+    #[test]
+    fn compression() {
+        let json = Bytes::from(r#"{"id":1,"mods":[],"user":{"country_code":"DE"}}"#.repeat(20));
+        let mut history = History::new(10, None).with_compression(true);
+
+        history.extend([2, 1].map(|id| Score::new(id, json.clone())));
+        assert_eq!(ids(&history), [1, 2]);
+        assert!(history.bytes() < json.len());
+
+        let snapshot = history.snapshot();
+        assert!(snapshot.contains(2));
+        assert!(snapshot.range_from(0).all(|score| *score.bytes() == json));
+        assert_eq!(
+            history.last().map(|score| score.bytes().clone()),
+            Some(json)
+        );
+    }
+
+    #[test]
+    fn legacy_ids() {
+        let score = |id: u64, ruleset_id: u8, legacy_score_id: u64| {
+            let json = format!(
+                r#"{{"id":{id},"ruleset_id":{ruleset_id},"legacy_score_id":{legacy_score_id}}}"#
+            );
+
+            Score::new(id, Bytes::from(json))
+        };
+
+        let mut history = History::new(2, None)
+            .with_compression(true)
+            .with_legacy_ids(true);
+
+        history.extend([score(1, 0, 10), score(2, 3, 10), score(3, 0, 0)]);
+        assert_eq!(history.legacy_to_id(3, 10), Some(2));
+        assert_eq!(history.legacy_to_id(1, 10), None);
+        assert_eq!(history.legacy_to_id(0, 0), None);
+
+        // Evicted
+        assert_eq!(history.legacy_to_id(0, 10), None);
+    }
+}
@@ -0,0 +1,69 @@
+use bytes::Bytes;
+use eyre::{Context as _, Result};
+use http_body_util::{BodyExt, Full};
+use hyper::{Request, StatusCode};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::{
+    client::legacy::{connect::HttpConnector, Builder, Client},
+    rt::TokioExecutor,
+};
+use rustls::crypto::CryptoProvider;
+
+pub type Body = Full<Bytes>;
+
+/// Client for sinks that send scores to other services.
+///
+/// Unlike the osu! client, it supports HTTP/1.1 and plain HTTP since
+/// self-hosted services commonly don't offer anything else.
+pub struct HttpClient {
+    client: Client<HttpsConnector<HttpConnector>, Body>,
+}
+
+impl HttpClient {
+    pub fn new() -> Result<Self> {
+        let https = HttpsConnectorBuilder::new()
+            .with_provider_and_webpki_roots(crypto_provider())
+            .context("Failed to configure https connector")?
+            .https_or_http()
+            .enable_all_versions()
+            .build();
+
+        let client = Builder::new(TokioExecutor::new()).build(https);
+
+        Ok(Self { client })
+    }
+
+    /// Sends the request and collects the response body.
+    pub async fn send(&self, req: Request<Body>) -> Result<(Bytes, StatusCode)> {
+        let response = self
+            .client
+            .request(req)
+            .await
+            .context("Failed to send request")?;
+
+        let (parts, incoming) = response.into_parts();
+
+        let bytes = incoming
+            .collect()
+            .await
+            .context("Failed to collect bytes")?
+            .to_bytes();
+
+        Ok((bytes, parts.status))
+    }
+}
+
+/// The crypto provider of the enabled `ring` or `aws` feature.
+pub fn crypto_provider() -> CryptoProvider {
+    #[cfg(feature = "ring")]
+    let crypto_provider = rustls::crypto::ring::default_provider();
+    #[cfg(all(feature = "aws", not(feature = "ring")))]
+    let crypto_provider = rustls::crypto::aws_lc_rs::default_provider();
+    #[cfg(not(any(feature = "ring", feature = "aws")))]
+    let crypto_provider = CryptoProvider::get_default()
+        .expect("No default crypto provider installed or configured via crate features")
+        .as_ref()
+        .clone();
+
+    crypto_provider
+}
@@ -0,0 +1,327 @@
+//! Minimal navigation through JSON bytes without parsing them.
+//!
+//! Fields are looked up by walking the entries of objects so that neither
+//! nested fields nor occurrences within strings are mistaken for the
+//! requested one. Strings and nested values are skipped as a whole.
+
+use std::ops::Range;
+
+pub enum Entry {
+    /// Index after the colon of the entry.
+    Found(usize),
+    Missing,
+}
+
+/// Index after the colon of the top-level entry with the given key.
+///
+/// Returns `None` if there is no such entry or the object is malformed. The
+/// key must not contain quotes or backslashes.
+pub fn find_field(bytes: &[u8], key: &[u8]) -> Option<usize> {
+    find_path(bytes, &[key])
+}
+
+/// Index after the colon of the entry at the end of the given keys, each
+/// nested within the object of the previous one.
+pub fn find_path<K: AsRef<[u8]>>(bytes: &[u8], keys: &[K]) -> Option<usize> {
+    keys.iter()
+        .try_fold(0, |i, key| match find_entry(bytes, i, key.as_ref())? {
+            Entry::Found(i) => Some(i),
+            Entry::Missing => None,
+        })
+}
+
+/// The top-level field with the given key if it's an unsigned integer.
+pub fn find_u64_field(bytes: &[u8], key: &[u8]) -> Option<u64> {
+    u64_value(bytes, find_field(bytes, key)?)
+}
+
+/// The top-level field with the given key if it's a number.
+pub fn find_f64_field(bytes: &[u8], key: &[u8]) -> Option<f64> {
+    let value = value(bytes, find_field(bytes, key)?)?;
+
+    std::str::from_utf8(value).ok()?.parse().ok()
+}
+
+/// Content of the top-level field with the given key if it's a string,
+/// without its quotes and still escaped.
+pub fn find_str_field<'a>(bytes: &'a [u8], key: &[u8]) -> Option<&'a [u8]> {
+    string(bytes, find_field(bytes, key)?)
+}
+
+/// The top-level field with the given key if it's a boolean.
+pub fn find_bool_field(bytes: &[u8], key: &[u8]) -> Option<bool> {
+    let value = &bytes[skip_ws(bytes, find_field(bytes, key)?)..];
+
+    if value.starts_with(b"true") {
+        Some(true)
+    } else if value.starts_with(b"false") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// The value at or after `i` if it's an unsigned integer.
+pub fn u64_value(bytes: &[u8], i: usize) -> Option<u64> {
+    let start = skip_ws(bytes, i);
+    let digits = bytes
+        .get(start..)?
+        .iter()
+        .take_while(|byte| byte.is_ascii_digit())
+        .count();
+
+    // Fractions and exponents are not integers
+    if digits == 0 || matches!(bytes.get(start + digits), Some(b'.' | b'e' | b'E')) {
+        return None;
+    }
+
+    bytes[start..start + digits]
+        .iter()
+        .try_fold(0_u64, |n, byte| {
+            n.checked_mul(10)?.checked_add(u64::from(byte & 0xF))
+        })
+}
+
+/// Walks the entries of the object starting at `start` and looks for the
+/// entry with the given key.
+///
+/// Returns `None` if the object is malformed.
+pub fn find_entry(bytes: &[u8], start: usize, key: &[u8]) -> Option<Entry> {
+    let mut i = expect(bytes, start, b'{')?;
+
+    if bytes.get(skip_ws(bytes, i)) == Some(&b'}') {
+        return Some(Entry::Missing);
+    }
+
+    loop {
+        let key_start = skip_ws(bytes, i);
+
+        if bytes.get(key_start) != Some(&b'"') {
+            return None;
+        }
+
+        let key_end = string_end(bytes, key_start)?;
+        i = expect(bytes, key_end, b':')?;
+
+        if &bytes[key_start + 1..key_end - 1] == key {
+            return Some(Entry::Found(i));
+        }
+
+        i = skip_ws(bytes, value_end(bytes, i)?);
+
+        match bytes.get(i)? {
+            b',' => i += 1,
+            b'}' => return Some(Entry::Missing),
+            _ => return None,
+        }
+    }
+}
+
+/// Keys of the entries of the object starting at `start`, without quotes,
+/// and the range of each entry from its key to the end of its value.
+///
+/// Returns `None` if the object is malformed.
+pub fn entries(bytes: &[u8], start: usize) -> Option<Vec<(&[u8], Range<usize>)>> {
+    let mut i = expect(bytes, start, b'{')?;
+    let mut entries = Vec::new();
+
+    if bytes.get(skip_ws(bytes, i)) == Some(&b'}') {
+        return Some(entries);
+    }
+
+    loop {
+        let key_start = skip_ws(bytes, i);
+
+        if bytes.get(key_start) != Some(&b'"') {
+            return None;
+        }
+
+        let key_end = string_end(bytes, key_start)?;
+        let value_end = value_end(bytes, expect(bytes, key_end, b':')?)?;
+        let end = key_start + bytes[key_start..value_end].trim_ascii_end().len();
+        entries.push((&bytes[key_start + 1..key_end - 1], key_start..end));
+        i = skip_ws(bytes, value_end);
+
+        match bytes.get(i)? {
+            b',' => i += 1,
+            b'}' => return Some(entries),
+            _ => return None,
+        }
+    }
+}
+
+/// Start indices of the elements of the array at or after `i`.
+///
+/// Returns `None` if the array is malformed.
+pub fn elements(bytes: &[u8], i: usize) -> Option<Vec<usize>> {
+    let mut i = expect(bytes, i, b'[')?;
+    let mut elements = Vec::new();
+
+    if bytes.get(skip_ws(bytes, i)) == Some(&b']') {
+        return Some(elements);
+    }
+
+    loop {
+        i = skip_ws(bytes, i);
+        elements.push(i);
+        i = skip_ws(bytes, value_end(bytes, i)?);
+
+        match bytes.get(i)? {
+            b',' => i += 1,
+            b']' => return Some(elements),
+            _ => return None,
+        }
+    }
+}
+
+/// Content of the string at or after `i` without its quotes; `None` if it's
+/// not a string.
+pub fn string(bytes: &[u8], i: usize) -> Option<&[u8]> {
+    let i = skip_ws(bytes, i);
+
+    if bytes.get(i) != Some(&b'"') {
+        return None;
+    }
+
+    Some(&bytes[i + 1..string_end(bytes, i)? - 1])
+}
+
+/// Raw bytes of the value at or after `i`, e.g. `123` or `"DE"`.
+pub fn value(bytes: &[u8], i: usize) -> Option<&[u8]> {
+    let i = skip_ws(bytes, i);
+
+    Some(bytes[i..value_end(bytes, i)?].trim_ascii_end())
+}
+
+/// Returns the index after the expected byte which may follow whitespace.
+pub fn expect(bytes: &[u8], i: usize, expected: u8) -> Option<usize> {
+    let i = skip_ws(bytes, i);
+
+    (bytes.get(i) == Some(&expected)).then_some(i + 1)
+}
+
+pub fn skip_ws(bytes: &[u8], i: usize) -> usize {
+    i + bytes
+        .get(i..)
+        .map_or(0, |rest| rest.len() - rest.trim_ascii_start().len())
+}
+
+/// Returns the index after the closing quote of the string starting at `i`.
+pub fn string_end(bytes: &[u8], i: usize) -> Option<usize> {
+    let mut i = i + 1;
+
+    loop {
+        i += memchr::memchr2(b'"', b'\\', bytes.get(i..)?)?;
+
+        if bytes[i] == b'"' {
+            return Some(i + 1);
+        }
+
+        // Skip the escaped character; `\uXXXX` contains no quotes anyway
+        i += 2;
+    }
+}
+
+/// Returns the index after the value starting at or after `i`.
+pub fn value_end(bytes: &[u8], i: usize) -> Option<usize> {
+    let mut i = skip_ws(bytes, i);
+
+    match bytes.get(i)? {
+        b'"' => string_end(bytes, i),
+        b'{' | b'[' => {
+            let mut depth = 0_usize;
+
+            loop {
+                match bytes.get(i)? {
+                    b'"' => {
+                        i = string_end(bytes, i)?;
+
+                        continue;
+                    }
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => {
+                        depth -= 1;
+
+                        if depth == 0 {
+                            return Some(i + 1);
+                        }
+                    }
+                    _ => {}
+                }
+
+                i += 1;
+            }
+        }
+        _ => {
+            let len = bytes[i..]
+                .iter()
+                .take_while(|&&byte| !matches!(byte, b',' | b'}' | b']'))
+                .count();
+
+            Some(i + len)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn navigate() {
+        let bytes = br#"{"a":{"b":"}"},"list":[1, "x\"y" ,{"c":[]}],"n":12 }"#;
+
+        let Some(Entry::Found(list)) = find_entry(bytes, 0, b"list") else {
+            panic!("missing list");
+        };
+
+        let elements = elements(bytes, list).unwrap();
+        assert_eq!(elements.len(), 3);
+        assert_eq!(value(bytes, elements[0]), Some(b"1".as_slice()));
+        assert_eq!(string(bytes, elements[1]), Some(br#"x\"y"#.as_slice()));
+        assert!(string(bytes, elements[2]).is_none());
+
+        let Some(Entry::Found(n)) = find_entry(bytes, 0, b"n") else {
+            panic!("missing n");
+        };
+
+        assert_eq!(value(bytes, n), Some(b"12".as_slice()));
+        assert!(matches!(find_entry(bytes, 0, b"b"), Some(Entry::Missing)));
+        assert!(find_entry(br#"{"a":1"#, 0, b"b").is_none());
+    }
+
+    #[test]
+    fn find_fields() {
+        let bytes = br#"{"note":"\"id\":7","user":{"id" : 2,"active":true},"id":3,"pp":1.5,"x\\":{"n":"a\"b"}}"#;
+
+        assert_eq!(find_u64_field(bytes, b"id"), Some(3));
+        assert_eq!(find_u64_field(bytes, b"pp"), None);
+        assert!(find_f64_field(bytes, b"pp").is_some_and(|pp| (pp - 1.5).abs() < f64::EPSILON));
+        assert_eq!(find_u64_field(bytes, b"note"), None);
+        assert_eq!(find_u64_field(bytes, b"missing"), None);
+        assert_eq!(find_bool_field(bytes, b"active"), None);
+        assert_eq!(find_bool_field(bytes, b"id"), None);
+        assert_eq!(
+            find_str_field(bytes, b"note"),
+            Some(br#"\"id\":7"#.as_slice())
+        );
+        assert_eq!(find_str_field(bytes, b"n"), None);
+
+        let active = find_path(bytes, &[b"user".as_slice(), b"active"]).unwrap();
+        assert_eq!(value(bytes, active), Some(b"true".as_slice()));
+        let n = find_path(bytes, &[br"x\\".as_slice(), b"n"]).unwrap();
+        assert_eq!(string(bytes, n), Some(br#"a\"b"#.as_slice()));
+
+        let keys: Vec<_> = entries(bytes, 0)
+            .unwrap()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys.len(), 5);
+        assert_eq!(keys[1], b"user");
+        assert_eq!(
+            find_u64_field(br#"{"id":99999999999999999999}"#, b"id"),
+            None
+        );
+    }
+}
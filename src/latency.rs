@@ -0,0 +1,132 @@
+use std::sync::Mutex;
+
+use crate::osu::Score;
+
+/// Delay between the `ended_at` of scores and when they were broadcasted,
+/// i.e. how far the feed lags behind.
+///
+/// Since `ended_at` only has a precision of seconds, so do the latencies.
+pub struct Latency {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// Broadcasted scores that have an `ended_at`.
+    count: u64,
+    sum_ms: u64,
+    /// Of the most recently broadcasted scores.
+    last: Option<Summary>,
+}
+
+#[derive(Copy, Clone)]
+pub struct Summary {
+    pub median_ms: u64,
+    pub max_ms: u64,
+}
+
+impl Latency {
+    pub const fn new() -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                count: 0,
+                sum_ms: 0,
+                last: None,
+            }),
+        }
+    }
+
+    /// Records the latencies of scores that are broadcasted at `now_ms` and
+    /// summarizes them; `None` if none of them have an `ended_at`.
+    pub fn track<'a>(
+        &self,
+        scores: impl Iterator<Item = &'a Score>,
+        now_ms: u64,
+    ) -> Option<Summary> {
+        let mut latencies: Vec<u64> = scores
+            .filter_map(Score::ended_at)
+            .map(|ended_at| now_ms.saturating_sub(ended_at * 1000))
+            .collect();
+
+        if latencies.is_empty() {
+            return None;
+        }
+
+        let mid = latencies.len() / 2;
+        let (_, &mut median_ms, _) = latencies.select_nth_unstable(mid);
+        let max_ms = latencies.iter().copied().max().unwrap_or(median_ms);
+
+        let summary = Summary { median_ms, max_ms };
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.count += latencies.len() as u64;
+        inner.sum_ms += latencies.iter().sum::<u64>();
+        inner.last = Some(summary);
+
+        Some(summary)
+    }
+
+    /// Latencies as JSON with the average across all scores and the median
+    /// and maximum of the most recently broadcasted ones.
+    pub fn to_json(&self) -> String {
+        let inner = self.inner.lock().unwrap();
+        let avg_ms = inner.sum_ms.checked_div(inner.count);
+
+        let mut buf = itoa::Buffer::new();
+        let mut json = String::from(r#"{"avg_ms":"#);
+
+        let fields = [
+            (avg_ms, r#","last_median_ms":"#),
+            (inner.last.map(|last| last.median_ms), r#","last_max_ms":"#),
+            (inner.last.map(|last| last.max_ms), "}"),
+        ];
+
+        for (value, suffix) in fields {
+            match value {
+                Some(n) => json.push_str(buf.format(n)),
+                None => json.push_str("null"),
+            }
+
+            json.push_str(suffix);
+        }
+
+        json
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+
+    fn score(id: u64, second: u64) -> Score {
+        let json = format!(r#"{{"id":{id},"ended_at":"2025-01-09T12:00:{second:02}Z"}}"#);
+
+        Score::new(id, Bytes::from(json))
+    }
+
+    #[test]
+    fn summarize() {
+        // 2025-01-09T12:01:00Z
+        const NOW_MS: u64 = 1_736_424_060_000;
+
+        let latency = Latency::new();
+        assert_eq!(
+            latency.to_json(),
+            r#"{"avg_ms":null,"last_median_ms":null,"last_max_ms":null}"#
+        );
+
+        let scores = [score(1, 0), score(2, 30), score(3, 50)];
+        let summary = latency.track(scores.iter(), NOW_MS).unwrap();
+        assert_eq!((summary.median_ms, summary.max_ms), (30_000, 60_000));
+
+        assert!(latency.track([Score::only_id(4)].iter(), NOW_MS).is_none());
+        latency.track([score(5, 0)].iter(), NOW_MS + 30_000);
+
+        assert_eq!(
+            latency.to_json(),
+            r#"{"avg_ms":47500,"last_median_ms":90000,"last_max_ms":90000}"#
+        );
+    }
+}
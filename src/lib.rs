@@ -0,0 +1,863 @@
+//! Fetches all osu! scores from the api and sends them through websockets.
+//!
+//! ## Usage
+//!
+//! 1. Download the [latest release]
+//!
+//! 2. Input your client id and secret for the osu!api in `config.toml` and modify
+//!    the rest of the config to your liking.
+//!
+//! 3. Run `scores-ws`
+//!
+//! 4. Connect to `scores-ws` via websocket at `ws://{ip addr of your config}:{port of your config}`
+//!    and listen for scores. Check out the [examples] folder for some examples.
+//!
+//! ## How it works
+//!
+//! `scores-ws` uses your osu!api client id & secret to fetch from the [scores endpoint].
+//! Cursor management, score deduplication, rate limiting, and everything else is
+//! handled automatically!
+//!
+//! Trackers that only care about a few hundred users can list their ids in the
+//! `[osu.users]` section instead. `scores-ws` then polls each user's recent scores in
+//! turn, spread out to stay within `requests_per_minute`, and broadcasts new scores
+//! the same way.
+//!
+//! If consumers need up-to-date info about a score's user, uncomment the
+//! `[osu.user_info]` section. Each score then gets a field
+//! `"user_info":{"username":"peppy","country_code":"AU","global_rank":123}` from the
+//! osu!api's users endpoint with the global rank in the score's ruleset. Users are
+//! cached for `ttl_secs` and requested in batches of 50; scores of users that
+//! couldn't be requested are sent without the field.
+//!
+//! Scores on loved or qualified beatmaps come without pp. If `scores-ws` is
+//! compiled with the `pp` feature, the `[pp]` section can name a command, e.g. a
+//! small wrapper around [rosu-pp], that calculates them. It receives each score as
+//! a line of JSON on stdin and answers each with a line containing the pp or
+//! `null`. Scores are sent without pp if the command takes longer than
+//! `timeout_secs`.
+//!
+//! Private servers that mirror the scores endpoint can be fetched from by pointing
+//! `osu.api_url` and `osu.token_url` to them.
+//!
+//! Instead of fetching scores itself, `scores-ws` can also join the feeds of other
+//! `scores-ws` instances, e.g. one per ruleset or region, by listing them as
+//! `[[upstreams]]`. It connects to each as a websocket client, resumes from the last
+//! received score after reconnecting, and serves the merged and deduplicated scores
+//! to its own clients.
+//!
+//! To connect to the websocket, `scores-ws` requires you to send an initial message.
+//! That message may either contain
+//! - the string `"connect"` in which case it'll start off sending you all scores it
+//!   has fetched so far (in its history).
+//! - a score id in which case it'll send you all scores from that score id onwards.
+//! - an object like `{"resume":{"osu":123,"mania":120}}` with a score id per ruleset
+//!   in which case each ruleset's scores are sent from its own score id onwards.
+//!   Useful for feeds joined from one fetcher per ruleset whose ids don't progress
+//!   evenly. Rulesets without a score id are sent from the smallest one.
+//! - the string `"late"` in which case you'll only receive scores that were filtered
+//!   out by the `max_score_age` config option (requires `forward_late_scores`).
+//! - the string `"user_active"` in which case you won't receive scores but JSON text
+//!   messages like `{"event":"user_active","user_id":2,"last_score_id":123}`, at most
+//!   one per user within `user_active_window_secs`.
+//! - the string `"anomalies"` in which case you won't receive scores but JSON text
+//!   messages like
+//!   `{"type":"anomaly","reason":"score_rate","user_id":2,"score_id":123,"scores_per_minute":15}`
+//!   or `{"type":"anomaly","reason":"pp_jump","user_id":2,"score_id":123,"pp":950.2,"previous_max_pp":412.7}`
+//!   for users that submit implausibly many scores within a minute or whose pp
+//!   jumps far beyond their previous best (requires `[setup.anomalies]`).
+//! - the JSON object `{"subscribe":"stats"}` in which case you won't receive scores
+//!   but a JSON text message every minute that rolls up the past minute's scores:
+//!   their count per ruleset, the amount of unique users, the pp distribution, and
+//!   the score with the most pp.
+//! - the JSON object `{"subscribe":"alerts","min_pp":700}` in which case you'll only
+//!   receive scores with at least that much pp, e.g. for a bot announcing big plays.
+//!   Once a user's score on a beatmap was sent, their further scores on that beatmap
+//!   are skipped for `alert_window_secs`.
+//!
+//! The initial message may also be a versioned JSON object such as
+//! `{"v":2,"action":"connect"}` so that future protocol changes don't break
+//! existing clients. The action is one of `"connect"`, `"late"`, `"user_active"`,
+//! `"aggregates"`, `"anomalies"`, `"alerts"` together with `"min_pp":700`, or `"resume"`
+//! together with `"score_id":123`. Versions that `scores-ws` doesn't support are
+//! rejected with the error code `UNSUPPORTED_VERSION`.
+//!
+//! When resuming from far back, add `"compress":"zstd"` to a versioned `"connect"`
+//! or `"resume"` message to receive the history at once instead of score by score:
+//! `{"type":"replay_blob","encoding":"zstd","scores":1234,"bytes":5678}`, followed
+//! by a single binary message with the zstd compressed scores, one per line. `bytes`
+//! is the size after decompressing. Scores broadcasted meanwhile follow as usual.
+//!
+//! If `legacy_score_ids` is configured, you can also resume by the legacy score id
+//! of a score, which is only unique per ruleset, through
+//! `{"v":2,"action":"resume","legacy_score_id":123,"ruleset":"osu"}`. If that score
+//! is not in the history, you first receive `{"type":"resume_truncated",...}`
+//! followed by the entire history. Without `legacy_score_ids`, the connection is
+//! closed with the error code `LEGACY_IDS_DISABLED`.
+//!
+//! If you connect with the query parameter `hello`, e.g. `ws://127.0.0.1:7727/?hello`,
+//! you'll first receive a JSON text message containing the server's phase, the oldest
+//! and newest score id in the history, as well as the seconds between their
+//! `ended_at` timestamps. This helps deciding which initial message to send:
+//! `{"type":"hello","phase":"serving","oldest_score_id":123,"newest_score_id":456,"history_span_secs":789}`
+//!
+//! Errors are sent as JSON text messages with a stable `code` to branch on,
+//! e.g. `{"type":"error","code":"INVALID_INITIAL","message":"..."}`. Codes
+//! include `INVALID_INITIAL`, `INITIAL_TIMEOUT`, `AUTH_REQUIRED`,
+//! `INVALID_KEY`, `UNSUPPORTED_VERSION`, `PERMISSION_DENIED`,
+//! `LEGACY_IDS_DISABLED`, `SESSION_EXPIRED`, `RATE_LIMITED`, and `LAGGING`
+//! after which the connection is closed. The close frame repeats the error's
+//! code as reason, except for `LAGGING` whose reason states how many messages
+//! were queued, and uses the close code 1008 (policy violation), 1003 if the
+//! initial message isn't text, or 4408 for `INITIAL_TIMEOUT`. When `scores-ws`
+//! shuts down, connections are closed with the close code 1001 (going away).
+//!
+//! If you resume from a score id that is older than the oldest score in the history,
+//! scores in between may be missing. In that case you'll first receive
+//! `{"type":"resume_truncated","oldest":123}` so you can backfill up to that id, then
+//! the entire history.
+//!
+//! At any point you can send the string `"disconnect"` to the websocket. This will
+//! make the websocket respond with a score id and close the connection. This score
+//! id may be used later on to resume from with a new websocket connection.
+//! Alternatively, you can just use a score id from a score you recently received
+//! from the websocket and ignore this disconnect-message hassle.
+//!
+//! You can also send the string `"stats"` at any point to receive a JSON text
+//! message containing the amount of scores sent to you so far, the amount of
+//! messages still queued up for you, the most messages that were queued up at
+//! once, the seconds since you connected, and the current cursor id of `scores-ws`:
+//! `{"sent":1234,"lag":0,"max_lag":12,"uptime_secs":567,"cursor_id":890}`
+//!
+//! You can also send the string `"cursor"` at any point to receive the id of the
+//! newest score in the history without closing the connection, e.g. to checkpoint
+//! periodically: `{"type":"cursor","newest_score_id":890}`
+//!
+//! Send the string `"top"` to receive the `top_scores` scores with the most pp whose
+//! `ended_at` lies within the past `top_scores_window_secs`, e.g. the scores of the
+//! day, as `{"type":"top","window_secs":86400,"scores":[...]}`. It requires the same
+//! permission as `"stats"` and is also available through the admin API's `GET /top`.
+//!
+//! If `checkpoint_interval_secs` is configured, you'll also periodically receive
+//! `{"type":"checkpoint","id":123}` with the id of the last score that was sent to
+//! you, so you can store it as your resume point without tracking every score's id
+//! yourself. It's only sent when the id changed.
+//!
+//! If `osu.retry.budget` is configured and fetching is paused after too many
+//! failed requests, you receive `{"type":"degraded"}`. With
+//! `broadcast_missed_estimate`, you receive
+//! `{"type":"missed_estimate","after":1,"until":9,"count":7}` when scores between
+//! those ids were likely missed because fetching fell too far behind.
+//!
+//! When the osu!api keeps responding with 503, e.g. during maintenance, `scores-ws`
+//! only probes it every `osu.retry.probe_secs` instead of retrying with a backoff
+//! and you receive `{"type":"status","state":"osu_down"}`, followed by
+//! `{"type":"status","state":"recovered"}` once it answers again.
+//!
+//! If `memory_soft_limit_bytes` is configured and the history together with the
+//! messages queued up for clients exceeds it, the history is trimmed and you receive
+//! `{"type":"warning","reason":"memory","bytes":1234,"limit":1000}`.
+//!
+//! To receive a range of the history again without reconnecting, e.g. after your
+//! own processing failed, send `{"replay":{"from":123,"to":456}}` with both ids
+//! being inclusive. The scores are filtered just like the others and are followed by
+//! `{"type":"replay_done","from":123,"to":456,"sent":78}`. If `from` is no longer in
+//! the history, you first receive `{"type":"replay_truncated","oldest":234}`.
+//! Replaying requires the `resume` permission and doesn't affect which scores you
+//! receive when resuming later on.
+//!
+//! Since scores are rather large, you can limit which of their top-level fields are
+//! sent to you by connecting with a comma-separated list in the query parameter
+//! `fields`, e.g. `ws://127.0.0.1:7727/?fields=id,user_id,pp,beatmap`, or by sending
+//! `{"fields":["id","user_id","pp","beatmap"]}` at any point. Sending an empty list
+//! restores all fields.
+//!
+//! To spread scores across multiple consumers, each of them can connect with the
+//! query parameters `partition` and `of`, e.g. `ws://127.0.0.1:7727/?partition=2&of=8`,
+//! or send `{"partition":2,"of":8}` at any point. Scores are assigned to partitions
+//! by hashing their user id so each consumer receives a disjoint share and all
+//! scores of a user go to the same consumer.
+//!
+//! To only receive scores whose fields have certain values, connect with query
+//! parameters `filter.<field>` and a comma-separated list of values, e.g.
+//! `ws://127.0.0.1:7727/?filter.user.country_code=DE,FR&filter.rank=S,SS&filter.passed=true`,
+//! or send `{"filter":"user.country_code","in":["DE","FR"]}` at any point. Nested fields
+//! are separated by dots. A score is sent if every filtered field has one of its listed
+//! values; sending an empty list removes the filter on that field. The query parameter
+//! `passed` is short for `filter.passed=true`.
+//!
+//! To watch only the scores on a mappool, connect with a comma-separated list of ids in
+//! the query parameter `beatmaps` or `beatmapsets`, e.g.
+//! `ws://127.0.0.1:7727/?beatmaps=129891,75`, or send `{"beatmaps":[129891,75]}` or
+//! `{"beatmapsets":[39804]}` at any point. Up to 1000 ids are allowed for each and
+//! sending an empty list removes the restriction.
+//!
+//! To filter by mods, connect with comma-separated acronyms in the query parameters
+//! `mods_include` and `mods_exclude`, e.g. `ws://127.0.0.1:7727/?mods_include=HD,DT&mods_exclude=RX`,
+//! or send `{"mods_include":["HD","DT"],"mods_exclude":["RX"]}` at any point; either
+//! entry may be omitted. A score is sent if its `mods` contain all included mods and
+//! none of the excluded ones. Acronyms are case-insensitive and sending an empty list
+//! removes that restriction.
+//!
+//! Consumers that don't need every score, e.g. for analytics, can connect with the
+//! query parameter `sample` to only receive a share of scores, e.g.
+//! `ws://127.0.0.1:7727/?sample=0.1` for about every tenth score, or with `max_per_sec`
+//! to receive at most that many scores per second. Both can also be sent at any point
+//! as `{"sample":0.1}` and `{"max_per_sec":50}`; `{"sample":1}` and `{"max_per_sec":0}`
+//! go back to all scores. Sampling is based on the score id so every sampling
+//! consumer receives the same scores.
+//!
+//! If `scores-ws` fetches scores of all rulesets, consumers interested in only some of
+//! them can connect with a comma-separated list in the query parameter `rulesets`, e.g.
+//! `ws://127.0.0.1:7727/?rulesets=taiko,mania`, or send `{"rulesets":["taiko","mania"]}`
+//! at any point. Each score is tagged with its ruleset through its `ruleset_id` field;
+//! `0` for osu, `1` for taiko, `2` for fruits, and `3` for mania. Sending an empty list
+//! restores all rulesets.
+//!
+//! Scores are usually sent in increasing order of their id but that's not
+//! guaranteed, e.g. when the osu!api returns ids interleaved across pages. If you
+//! rely on ordering, e.g. to resume from ranges, connect with the query parameter
+//! `ordered`, e.g. `ws://127.0.0.1:7727/?ordered`. Scores are then held back for
+//! `reorder_window_ms` to be sorted and you'll never receive a score whose id is
+//! smaller than the one you received before.
+//!
+//! To measure latency or deduplicate downstream, connect with the query parameter
+//! `meta`, e.g. `ws://127.0.0.1:7727/?meta`. Each score is then wrapped with
+//! metadata: when `scores-ws` received it as unix timestamp in milliseconds, a
+//! sequence number that increases with each score sent to you, its ruleset, and
+//! whether it's replayed from the history or live:
+//! `{"meta":{"fetched_at":1736426096789,"seq":1,"ruleset":"osu","origin":"live"},"score":{...}}`
+//!
+//! If you connect with the query parameter `idle_minutes`, e.g.
+//! `ws://127.0.0.1:7727/?idle_minutes=30`, the connection is closed after you sent
+//! nothing for that many minutes; pings count as well. The reason of the close frame
+//! contains the score id to resume from: `{"resume_score_id":123}`
+//!
+//! For at-least-once delivery, connect with the query parameter `ack` and a name of
+//! your choice, e.g. `ws://127.0.0.1:7727/?ack=my-consumer`, and send
+//! `{"ack":<score_id>}` once you processed a score. When you connect again with the
+//! same name and send `"connect"`, you'll receive the history starting with the
+//! first score you didn't acknowledge; scores may be acknowledged in any order.
+//! Names are scoped to the key you connected with so that clients with other keys
+//! can't move your cursor.
+//!
+//! If `session_grace_secs` is configured and you connect with the query parameter
+//! `session`, e.g. `ws://127.0.0.1:7727/?session`, you'll first receive a token:
+//! `{"type":"session","token":"...","grace_secs":60,"resumed":false}`. Should your
+//! connection drop, connect again within the grace period with
+//! `ws://127.0.0.1:7727/?session={token}` and without an initial message to continue
+//! exactly where you left off, including all messages queued for you meanwhile and
+//! your fields, filters, and partition. Unknown or expired tokens are answered with
+//! the error code `SESSION_EXPIRED` in which case you can resume through a score id.
+//!
+//! To identify your consumer, connect with the query parameter `client_name`, e.g.
+//! `ws://127.0.0.1:7727/?client_name=my-app`. Multiple connections with the same
+//! name are logged and, if `unique_client_names` is enabled, the older connection
+//! is closed with the close code 1008 so that an accidental second instance doesn't
+//! double your traffic.
+//!
+//! Since `scores-ws` runs separately, it allows you to have downtime on your actual
+//! app without missing any scores; at least assuming there won't be more scores than the
+//! configured history length during the downtime.
+//!
+//! On unix machines without a supervisor like systemd, `scores-ws --daemon` runs it in
+//! the background. Its pid is written to `scores-ws.pid` and its stdout and stderr are
+//! appended to `scores-ws.out`; use `--pid-file <path>` and `--out-file <path>` to
+//! choose other files. Stop it through `kill $(cat scores-ws.pid)` to shut down
+//! gracefully. The working directory stays the same so `config.toml` is still found.
+//!
+//! On Windows, `scores-ws service install [--name <name>]` instead registers it as a
+//! service that starts with the machine, provided it's compiled with the
+//! `windows-service` feature. Run it from the directory that contains `config.toml`.
+//! Since services have no terminal, configure `[logging]` with a `directory` to keep
+//! its logs. `scores-ws service uninstall` stops and removes the service again.
+//!
+//! Besides serving, the binary bundles some operational tooling as subcommands; run
+//! `scores-ws help` for an overview. `scores-ws serve` is the default so running
+//! `scores-ws` without a command works as before. `scores-ws replay <file>` hands the
+//! scores of an NDJSON file, e.g. one written by the `[ndjson]` sink, to the configured
+//! sinks; use `--sink <name>` to only hand them to some of them.
+//!
+//! To catch misconfigurations before deploying, `scores-ws check-config` checks
+//! `config.toml` without starting. Besides invalid values, it reports an interval that
+//! would exceed the osu!api's rate limits and a history that wouldn't fit into memory.
+//! With `--credentials`, it also requests a token to check your client id and secret.
+//! It exits with a non-zero code if any check fails.
+//!
+//! Scores that the configured sinks, e.g. `[postgres]` or `[discord]`, failed to
+//! deliver are written to the file of the `[dead_letter]` section. Once the sink is
+//! available again, `scores-ws redeliver` hands them to it; scores that fail again stay
+//! in the file.
+//!
+//! If `scores-ws` is compiled with the `tui` feature, run `scores-ws --tui` to watch
+//! a live dashboard in your terminal instead of the logs. It shows the state of each
+//! fetch loop, scores per second, connected clients with their lag, the history
+//! length, and the most recent warnings and errors. Press `q` or ctrl-c to shut down.
+//!
+//! To get started with a consumer of your own, run `scores-ws scaffold rust` or
+//! `scores-ws scaffold python` next to your `config.toml`. This generates a project
+//! that stores the id of the last processed score to resume from after reconnects or
+//! restarts, as well as a compose file that runs it alongside `scores-ws`. Use
+//! `--out <dir>` to choose the directory and `--fields <a,b,...>` to only receive
+//! those fields.
+//!
+//! Rust consumers can use the [`scores-ws-client`] crate of this repository instead
+//! of implementing the handshake themselves. It provides the scores as a stream and
+//! reconnects and resumes from the last received score automatically:
+//! `ScoresWsClient::connect("ws://127.0.0.1:7727").resume(123).fields(["pp"])`
+//! With its `serde` feature, scores can be deserialized via `score.parse::<T>()`
+//! such as into the provided `scores_ws_client::model::OsuScore`.
+//!
+//! [latest release]: https://github.com/MaxOhn/scores-ws/releases/latest
+//! [`scores-ws-client`]: https://github.com/MaxOhn/scores-ws/tree/main/scores-ws-client
+//! [examples]: https://github.com/MaxOhn/scores-ws/tree/main/examples
+//! [scores endpoint]: https://osu.ppy.sh/docs/index.html#scores
+//! [rosu-pp]: https://github.com/MaxOhn/rosu-pp
+
+#![warn(clippy::pedantic, clippy::missing_const_for_fn)]
+
+#[macro_use]
+extern crate eyre;
+
+#[macro_use]
+extern crate tracing;
+
+use std::{future::Future, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+
+use clap::Parser as _;
+use eyre::{Context as _, Result};
+use osu::Osu;
+use tokio::{net::TcpListener, runtime::Runtime, sync::mpsc, task::JoinSet};
+use tracing::Instrument;
+
+use crate::{
+    cli::{Cli, Command},
+    config::{
+        ClickHouseConfig, Config, DeadLetterConfig, DiscordConfig, MqttConfig, NdjsonConfig,
+        OsuConfig, PostgresConfig, PpConfig, RedisConfig, RedisMode, Role, Setup, UpstreamConfig,
+    },
+    context::Context,
+    daemon::Daemon,
+    dedup::Dedup,
+    listener::Listener,
+    redis::ScoreStream,
+    sink::{ClickHouse, DeadLetters, Discord, Mqtt, Ndjson, Sinks},
+    state::Phase,
+    upstream::Upstream,
+    user_info::UserInfo,
+};
+
+mod ack;
+mod activity;
+mod admin;
+mod aggregate;
+mod alert;
+mod anomaly;
+#[cfg(feature = "archive")]
+mod archive;
+mod auth;
+#[cfg(any(test, feature = "bench"))]
+#[doc(hidden)]
+pub mod bench;
+#[cfg(feature = "chaos")]
+mod chaos;
+mod check;
+mod cli;
+mod client;
+mod config;
+mod context;
+mod daemon;
+mod dedup;
+mod delay;
+#[cfg(test)]
+mod e2e;
+mod event;
+mod filter;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod history;
+mod http;
+mod json;
+mod latency;
+mod limiter;
+mod listener;
+mod logging;
+mod loops;
+mod mods;
+mod osu;
+#[cfg_attr(not(feature = "pp"), allow(dead_code))]
+mod pp;
+mod redis;
+mod reorder;
+mod scaffold;
+#[cfg(all(windows, feature = "windows-service"))]
+mod service;
+mod session;
+mod sink;
+mod state;
+mod top;
+#[cfg(feature = "tui")]
+mod tui;
+mod upstream;
+mod user_info;
+mod validate;
+
+/// Runs the subcommand given through the command line arguments.
+///
+/// # Errors
+///
+/// Returns an error if the arguments are invalid or the subcommand failed.
+pub fn main() -> Result<()> {
+    match Cli::parse().into_command() {
+        Command::Serve(args) => {
+            let tui = args.tui;
+
+            #[cfg(not(feature = "tui"))]
+            if tui {
+                bail!("`scores-ws --tui` requires the `tui` feature");
+            }
+
+            // Detaching forks so it must happen before the runtime spawns threads
+            let _pid_file = args.daemon().map(Daemon::detach).transpose()?;
+
+            runtime()?.block_on(run(tui, std::future::pending()))
+        }
+        Command::CheckConfig { credentials } => check::run(credentials),
+        Command::Replay { file, sinks } => runtime()?.block_on(replay(file, sinks)),
+        Command::Redeliver => runtime()?.block_on(redeliver()),
+        Command::Scaffold {
+            language,
+            out,
+            fields,
+        } => scaffold::run(language, &out, fields.as_deref()),
+        #[cfg(all(windows, feature = "windows-service"))]
+        Command::Service { action, name, dir } => service::run(action, name, dir),
+        #[cfg(not(all(windows, feature = "windows-service")))]
+        Command::Service { .. } => {
+            bail!("`scores-ws service` requires windows and the `windows-service` feature")
+        }
+    }
+}
+
+fn runtime() -> Result<Runtime> {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .context("Failed to build runtime")
+}
+
+/// Hands the scores of an NDJSON file, e.g. one of the `[ndjson]` sink, to
+/// the configured sinks.
+async fn replay(file: PathBuf, names: Vec<Box<str>>) -> Result<()> {
+    let Config {
+        setup,
+        discord,
+        postgres,
+        clickhouse,
+        mqtt,
+        ndjson,
+        dead_letter,
+        ..
+    } = Config::parse()?;
+
+    logging::init(&setup.log, setup.logging.as_ref(), false)?;
+
+    let content =
+        std::fs::read(&file).with_context(|| format!("Failed to read `{}`", file.display()))?;
+
+    let scores: Vec<_> = sink::read_scores(&content)
+        .with_context(|| format!("Failed to parse `{}`", file.display()))?
+        .into_iter()
+        .collect();
+
+    let sinks = spawn_sinks(dead_letter, discord, postgres, clickhouse, mqtt, ndjson)?;
+
+    info!(count = scores.len(), "Replaying scores");
+    sinks.replay(&names, &scores).await?;
+    sinks.shutdown().await;
+
+    Ok(())
+}
+
+fn spawn_pp(ctx: &Context, pp: Option<PpConfig>) {
+    let Some(pp) = pp else {
+        return;
+    };
+
+    #[cfg(feature = "pp")]
+    {
+        let timeout = Duration::from_secs(pp.timeout_secs);
+        ctx.set_pp_hook(pp::PpHook::spawn(pp::Command::new(pp), timeout));
+    }
+
+    #[cfg(not(feature = "pp"))]
+    {
+        let _ = (ctx, pp);
+        warn!("Ignoring section `[pp]` because the `pp` feature is not enabled");
+    }
+}
+
+/// Hands the scores of the dead-letter file to their sinks again.
+async fn redeliver() -> Result<()> {
+    let Config {
+        setup,
+        discord,
+        postgres,
+        clickhouse,
+        mqtt,
+        ndjson,
+        dead_letter,
+        ..
+    } = Config::parse()?;
+
+    logging::init(&setup.log, setup.logging.as_ref(), false)?;
+
+    let Some(dead_letter) = dead_letter else {
+        bail!("Missing section `[dead_letter]` in `config.toml`");
+    };
+    let sinks = spawn_sinks(
+        Some(dead_letter),
+        discord,
+        postgres,
+        clickhouse,
+        mqtt,
+        ndjson,
+    )?;
+
+    sink::redeliver(sinks).await
+}
+
+async fn run(tui: bool, stop: impl Future<Output = ()>) -> Result<()> {
+    let Config {
+        setup,
+        osu,
+        admin,
+        redis,
+        auth,
+        grpc,
+        discord,
+        postgres,
+        clickhouse,
+        mqtt,
+        ndjson,
+        archive,
+        dead_letter,
+        pp,
+        upstreams,
+    } = Config::parse()?;
+
+    logging::init(&setup.log, setup.logging.as_ref(), tui)?;
+
+    let max_broadcast_delay = grpc
+        .as_ref()
+        .and_then(|grpc| grpc.broadcast_delay_secs)
+        .max(setup.broadcast_delay_secs)
+        .map(Duration::from_secs);
+
+    if let Some(archive) = archive {
+        #[cfg(feature = "archive")]
+        archive::init(archive).context("Failed to initialize archive")?;
+
+        #[cfg(not(feature = "archive"))]
+        {
+            let _ = archive;
+            warn!("Ignoring section `[archive]` because the `archive` feature is not enabled");
+        }
+    }
+
+    let sinks = spawn_sinks(dead_letter, discord, postgres, clickhouse, mqtt, ndjson)?;
+    let ctx = Arc::new(Context::new(&setup, auth, max_broadcast_delay, sinks));
+    spawn_pp(&ctx, pp);
+
+    if max_broadcast_delay.is_some() {
+        tokio::spawn(Context::deliver_delayed(Arc::clone(&ctx)));
+    }
+
+    let listeners = match setup.role {
+        Role::Fetcher => Vec::new(),
+        Role::Server | Role::Both => Listener::bind_all(&setup).await?,
+    };
+
+    if let Some(admin) = admin {
+        let addr = SocketAddr::new(admin.ip_addr, admin.port);
+
+        let listener = TcpListener::bind(addr)
+            .await
+            .context("Failed to bind admin listener")?;
+
+        info!("Admin API listening on {addr}...");
+        tokio::spawn(admin::run(
+            Arc::clone(&ctx),
+            listener,
+            admin.health_max_intervals,
+        ));
+    }
+
+    if let Some(grpc) = grpc {
+        #[cfg(feature = "grpc")]
+        {
+            let addr = SocketAddr::new(grpc.ip_addr, grpc.port);
+
+            let listener = TcpListener::bind(addr)
+                .await
+                .context("Failed to bind gRPC listener")?;
+
+            info!("gRPC server listening on {addr}...");
+            let delay = grpc.broadcast_delay_secs.map(Duration::from_secs);
+            tokio::spawn(grpc::run(Arc::clone(&ctx), listener, delay));
+        }
+
+        #[cfg(not(feature = "grpc"))]
+        {
+            let _ = grpc;
+            warn!("Ignoring section `[grpc]` because the `grpc` feature is not enabled");
+        }
+    }
+
+    let dedup = Dedup::from_setup(&setup)?;
+    spawn_sources(&ctx, &setup, osu, redis, upstreams, dedup)?;
+
+    ctx.state().transition(Phase::Warmup);
+    tokio::spawn(Context::evaluate_state(Arc::clone(&ctx)));
+    tokio::spawn(Context::emit_aggregates(Arc::clone(&ctx)));
+    tokio::spawn(Context::deliver_ordered(Arc::clone(&ctx)));
+
+    if let Some(secs) = setup.checkpoint_interval_secs.filter(|&secs| secs > 0) {
+        let period = Duration::from_secs(secs);
+        tokio::spawn(Context::emit_checkpoints(Arc::clone(&ctx), period));
+    }
+
+    #[cfg(feature = "tui")]
+    let dashboard = tui
+        .then(|| tui::Dashboard::spawn(Arc::clone(&ctx)))
+        .transpose()
+        .context("Failed to set up the terminal")?;
+
+    let quit = async {
+        #[cfg(feature = "tui")]
+        if let Some(ref dashboard) = dashboard {
+            return dashboard.quit_requested().await;
+        }
+
+        stop.await;
+    };
+
+    serve(&ctx, listeners, quit).await;
+
+    #[cfg(feature = "tui")]
+    drop(dashboard);
+
+    #[cfg(feature = "archive")]
+    archive::shutdown().await;
+
+    Ok(())
+}
+
+/// Creates the configured sinks and spawns a task for each of them.
+fn spawn_sinks(
+    dead_letter: Option<DeadLetterConfig>,
+    discord: Vec<DiscordConfig>,
+    postgres: Option<PostgresConfig>,
+    clickhouse: Option<ClickHouseConfig>,
+    mqtt: Option<MqttConfig>,
+    ndjson: Option<NdjsonConfig>,
+) -> Result<Sinks> {
+    let mut sinks = Sinks::new(dead_letter.map(DeadLetters::new));
+
+    for config in discord {
+        let sink = Discord::new(config).context("Failed to create Discord sink")?;
+        sinks.spawn(sink);
+    }
+
+    if let Some(clickhouse) = clickhouse {
+        let sink = ClickHouse::new(clickhouse).context("Failed to create ClickHouse sink")?;
+        sinks.spawn(sink);
+    }
+
+    if let Some(mqtt) = mqtt {
+        sinks.spawn(Mqtt::new(mqtt));
+    }
+
+    if let Some(ndjson) = ndjson {
+        sinks.spawn(Ndjson::new(ndjson));
+    }
+
+    if let Some(postgres) = postgres {
+        #[cfg(feature = "postgres")]
+        sinks.spawn(sink::Postgres::new(postgres));
+
+        #[cfg(not(feature = "postgres"))]
+        {
+            let _ = postgres;
+            warn!("Ignoring section `[postgres]` because the `postgres` feature is not enabled");
+        }
+    }
+
+    Ok(sinks)
+}
+
+/// Spawns the loop that fetches scores from the osu!api.
+fn spawn_fetch(
+    ctx: &Arc<Context>,
+    setup: &Setup,
+    mut osu: OsuConfig,
+    stream: Option<ScoreStream>,
+    dedup: Option<Dedup>,
+) -> Result<()> {
+    let interval = Duration::from_secs(setup.interval);
+    let handle = ctx.loops().register(osu.label(), interval);
+    let span = info_span!("loop", label = handle.label());
+    let users = osu.users.take();
+    let user_info = osu.user_info.take();
+    let osu = Osu::new(osu).context("Failed to create osu! client")?;
+    let osu = Arc::new(osu);
+
+    if let Some(config) = user_info {
+        ctx.set_user_info(UserInfo::new(Arc::clone(&osu), &config));
+    }
+
+    let fut = Osu::refresh_token(Arc::clone(&osu), Arc::clone(&handle));
+    tokio::spawn(fut.instrument(span.clone()));
+
+    if let Some(users) = users {
+        let fut = Context::poll_users(
+            Arc::clone(ctx),
+            handle,
+            osu,
+            users,
+            setup.fetch_interval(),
+            stream,
+            dedup,
+        );
+
+        tokio::spawn(fut.instrument(span));
+    } else {
+        let fut = Context::fetch_scores(
+            Arc::clone(ctx),
+            handle,
+            osu,
+            setup.fetch_interval(),
+            setup.resume_score_id,
+            stream,
+            dedup,
+        );
+
+        tokio::spawn(fut.instrument(span));
+    }
+
+    Ok(())
+}
+
+/// Spawns the loops that supply scores, i.e. consuming from redis, joining
+/// upstreams, or fetching from the osu!api.
+fn spawn_sources(
+    ctx: &Arc<Context>,
+    setup: &Setup,
+    osu: Option<OsuConfig>,
+    redis: Option<RedisConfig>,
+    upstreams: Vec<UpstreamConfig>,
+    dedup: Option<Dedup>,
+) -> Result<()> {
+    let stream = redis.map(|config| (config.mode(setup.role), ScoreStream::new(config)));
+
+    if let Some((RedisMode::Consume, stream)) = stream {
+        // `XREAD` blocks for up to five seconds
+        let interval = Duration::from_secs(5);
+        let handle = ctx.loops().register(stream.label().into(), interval);
+        let span = info_span!("loop", label = handle.label());
+        let fut = Context::consume_scores(Arc::clone(ctx), handle, stream, dedup);
+        tokio::spawn(fut.instrument(span));
+    } else if !upstreams.is_empty() {
+        let stream = stream.map(|(_, stream)| stream);
+        spawn_upstreams(ctx, setup, upstreams, stream, dedup);
+    } else {
+        // Only optional when consuming from redis or upstreams
+        let osu = osu.expect("missing osu config");
+        let stream = stream.map(|(_, stream)| stream);
+        spawn_fetch(ctx, setup, osu, stream, dedup)?;
+    }
+
+    Ok(())
+}
+
+fn spawn_upstreams(
+    ctx: &Arc<Context>,
+    setup: &Setup,
+    upstreams: Vec<UpstreamConfig>,
+    stream: Option<ScoreStream>,
+    dedup: Option<Dedup>,
+) {
+    let (tx, rx) = mpsc::channel(upstreams.len() * 4);
+
+    for config in upstreams {
+        let upstream = Upstream::new(config);
+        let handle = ctx
+            .loops()
+            .register(upstream.label().into(), Duration::from_secs(setup.interval));
+        let span = info_span!("loop", label = handle.label());
+        tokio::spawn(upstream.run(handle, tx.clone()).instrument(span));
+    }
+
+    // Upstreams overlap whenever they reconnect so dedup is never optional
+    let dedup = dedup.unwrap_or_else(|| Dedup::new(setup.history_length));
+    tokio::spawn(Context::join_upstreams(Arc::clone(ctx), rx, stream, dedup));
+}
+
+/// Waits for ctrl-c or, on unix, `SIGTERM` which is what e.g. `kill` sends to
+/// a daemon.
+async fn shutdown_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+
+        tokio::select! {
+            res = tokio::signal::ctrl_c() => res,
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
+
+/// Accepts connections until a listener fails, a shutdown signal is
+/// received, or `quit` resolves, then drains clients.
+async fn serve(ctx: &Arc<Context>, listeners: Vec<Listener>, quit: impl Future<Output = ()>) {
+    let shutdown = async {
+        if let Err(err) = shutdown_signal().await {
+            error!(?err, "Failed to listen for shutdown signal");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    let mut accepting = JoinSet::new();
+
+    for listener in listeners {
+        let ctx = Arc::clone(ctx);
+
+        accepting.spawn(async move {
+            while let Ok(conn) = listener.accept().await {
+                let fut = Context::handle_connection(Arc::clone(&ctx), conn, listener.auth());
+                tokio::spawn(fut);
+            }
+        });
+    }
+
+    tokio::select! {
+        // Fetchers have no listeners in which case only the signal counts
+        Some(_) = accepting.join_next() => {}
+        () = shutdown => {}
+        () = quit => {}
+    }
+
+    accepting.abort_all();
+
+    ctx.state().transition(Phase::Draining);
+    ctx.close_clients();
+
+    // Give clients a moment to receive the close frame
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    ctx.state().transition(Phase::Stopped);
+}
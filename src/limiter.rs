@@ -0,0 +1,234 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering::Relaxed},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use crate::config::Setup;
+
+/// Limits the amount of connections and incoming messages per ip address as
+/// well as the total amount of connections.
+pub struct RateLimiter {
+    max_connections: Option<usize>,
+    max_clients: Option<usize>,
+    /// Connections across all ip addresses, including unix sockets.
+    clients: AtomicUsize,
+    /// Messages per second and burst size
+    message_rate: Option<(f64, f64)>,
+    ips: Mutex<HashMap<IpAddr, IpState>>,
+}
+
+impl RateLimiter {
+    pub fn new(setup: &Setup) -> Self {
+        Self {
+            max_connections: setup.max_connections_per_ip,
+            max_clients: setup.max_clients,
+            clients: AtomicUsize::new(0),
+            message_rate: setup
+                .messages_per_second
+                .map(|rate| (rate, f64::from(setup.message_burst))),
+            ips: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Registers a new connection unless the ip address already reached its
+    /// limit. Connecting counts as a message as well so that reconnect spam
+    /// is limited too.
+    ///
+    /// The connection is unregistered when the returned permit is dropped.
+    /// Connections without ip address, i.e. through a unix socket, are not
+    /// limited.
+    pub fn connect(self: &Arc<Self>, ip: Option<IpAddr>) -> Option<ConnectionPermit> {
+        let Some(ip) = ip else {
+            self.clients.fetch_add(1, Relaxed);
+
+            return Some(ConnectionPermit {
+                limiter: Arc::clone(self),
+                ip: None,
+            });
+        };
+
+        let mut ips = self.ips.lock().unwrap();
+        let burst = self.message_rate.map_or(0.0, |(_, burst)| burst);
+        let state = ips.entry(ip).or_insert_with(|| IpState::new(burst));
+
+        if self
+            .max_connections
+            .is_some_and(|max| state.connections >= max)
+        {
+            return None;
+        }
+
+        if let Some((rate, burst)) = self.message_rate {
+            if !state.bucket.try_take(rate, burst, Instant::now()) {
+                return None;
+            }
+        }
+
+        state.connections += 1;
+        self.clients.fetch_add(1, Relaxed);
+
+        Some(ConnectionPermit {
+            limiter: Arc::clone(self),
+            ip: Some(ip),
+        })
+    }
+
+    /// Whether more connections are permitted than `setup.max_clients`.
+    pub fn exceeds_max_clients(&self) -> bool {
+        self.max_clients
+            .is_some_and(|max| self.clients.load(Relaxed) > max)
+    }
+
+    /// Whether the ip address may send another message.
+    pub fn message(&self, ip: Option<IpAddr>) -> bool {
+        let (Some(ip), Some((rate, burst))) = (ip, self.message_rate) else {
+            return true;
+        };
+
+        self.ips
+            .lock()
+            .unwrap()
+            .get_mut(&ip)
+            .is_none_or(|state| state.bucket.try_take(rate, burst, Instant::now()))
+    }
+
+    fn disconnect(&self, ip: IpAddr) {
+        let mut ips = self.ips.lock().unwrap();
+
+        let Some(state) = ips.get_mut(&ip) else {
+            return;
+        };
+
+        state.connections -= 1;
+
+        if state.connections > 0 {
+            return;
+        }
+
+        // Only forget about the ip once its bucket is full again; otherwise
+        // reconnecting would reset its tokens.
+        let is_full = self.message_rate.is_none_or(|(rate, burst)| {
+            state.bucket.refill(rate, burst, Instant::now());
+
+            state.bucket.tokens >= burst
+        });
+
+        if is_full {
+            ips.remove(&ip);
+        }
+    }
+}
+
+/// Owns the limiter so that it can outlive the connection, e.g. while its
+/// session is parked.
+pub struct ConnectionPermit {
+    limiter: Arc<RateLimiter>,
+    ip: Option<IpAddr>,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.limiter.clients.fetch_sub(1, Relaxed);
+
+        if let Some(ip) = self.ip {
+            self.limiter.disconnect(ip);
+        }
+    }
+}
+
+struct IpState {
+    connections: usize,
+    bucket: TokenBucket,
+}
+
+impl IpState {
+    fn new(burst: f64) -> Self {
+        Self {
+            connections: 0,
+            bucket: TokenBucket::full(burst),
+        }
+    }
+}
+
+pub struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn full(burst: f64) -> Self {
+        Self {
+            tokens: burst,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, rate: f64, burst: f64, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens = burst.min(self.tokens + elapsed.as_secs_f64() * rate);
+        self.last_refill = now;
+    }
+
+    pub fn try_take(&mut self, rate: f64, burst: f64, now: Instant) -> bool {
+        self.refill(rate, burst, now);
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+
+        self.tokens -= 1.0;
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn token_bucket() {
+        let start = Instant::now();
+
+        let mut bucket = TokenBucket {
+            tokens: 2.0,
+            last_refill: start,
+        };
+
+        assert!(bucket.try_take(1.0, 2.0, start));
+        assert!(bucket.try_take(1.0, 2.0, start));
+        assert!(!bucket.try_take(1.0, 2.0, start));
+
+        let later = start + Duration::from_millis(1500);
+        assert!(bucket.try_take(1.0, 2.0, later));
+        assert!(!bucket.try_take(1.0, 2.0, later));
+
+        let much_later = later + Duration::from_secs(10);
+        assert!(bucket.try_take(1.0, 2.0, much_later));
+        assert!(bucket.try_take(1.0, 2.0, much_later));
+        assert!(!bucket.try_take(1.0, 2.0, much_later));
+    }
+
+    #[test]
+    fn max_clients() {
+        let setup: Setup = toml::from_str("max_clients = 1").unwrap();
+        let limiter = Arc::new(RateLimiter::new(&setup));
+
+        let first = limiter.connect(None).unwrap();
+        assert!(!limiter.exceeds_max_clients());
+
+        let second = limiter.connect("127.0.0.1".parse().ok()).unwrap();
+        assert!(limiter.exceeds_max_clients());
+
+        drop(first);
+        assert!(!limiter.exceeds_max_clients());
+        drop(second);
+    }
+}
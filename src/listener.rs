@@ -0,0 +1,246 @@
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    io::Result as IoResult,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+#[cfg(unix)]
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+
+use eyre::{Context as _, Result};
+use socket2::{Domain, Protocol, Type};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::config::{IpAddrs, Setup};
+
+/// Unix sockets peers of all listeners are numbered through this counter.
+#[cfg(unix)]
+static NEXT_UNIX_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Accepts websocket connections either through TCP or a unix domain socket.
+pub struct Listener {
+    socket: Socket,
+    /// Whether clients are subject to the `[auth]` section.
+    auth: bool,
+}
+
+enum Socket {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+}
+
+impl Listener {
+    /// Binds the listeners of `setup` as well as all of `setup.listeners`.
+    pub async fn bind_all(setup: &Setup) -> Result<Vec<Self>> {
+        let mut listeners = Vec::with_capacity(1 + setup.listeners.len());
+
+        let path = setup.unix_path();
+        Self::bind_each(&setup.ip_addr, setup.port, path, true, &mut listeners).await?;
+
+        for config in &setup.listeners {
+            let port = config.port.unwrap_or_default();
+            let path = config.unix_path();
+            Self::bind_each(&config.ip_addr, port, path, config.auth, &mut listeners).await?;
+        }
+
+        Ok(listeners)
+    }
+
+    /// Binds to the unix socket `path` if specified or to each address
+    /// otherwise.
+    async fn bind_each(
+        ip_addrs: &IpAddrs,
+        port: u16,
+        path: Option<&str>,
+        auth: bool,
+        listeners: &mut Vec<Self>,
+    ) -> Result<()> {
+        if path.is_some() {
+            let addr = SocketAddr::new(ip_addrs.first(), port);
+            listeners.push(Self::bind(addr, path, auth, false).await?);
+
+            return Ok(());
+        }
+
+        // IPv6 sockets usually accept IPv4 connections too which would
+        // conflict with binding IPv4 addresses separately
+        let v6_only = ip_addrs.is_multiple();
+
+        for ip in ip_addrs.iter() {
+            let addr = SocketAddr::new(ip, port);
+            listeners.push(Self::bind(addr, None, auth, v6_only).await?);
+        }
+
+        Ok(())
+    }
+
+    /// Binds to the unix socket `path` if specified or to `addr` otherwise.
+    async fn bind(addr: SocketAddr, path: Option<&str>, auth: bool, v6_only: bool) -> Result<Self> {
+        let posture = if auth { "" } else { " without auth" };
+
+        if let Some(path) = path {
+            #[cfg(unix)]
+            {
+                // A leftover socket file from a previous run would make
+                // binding fail
+                if std::fs::metadata(path).is_ok() {
+                    std::fs::remove_file(path)
+                        .with_context(|| format!("Failed to remove old socket `{path}`"))?;
+                }
+
+                let listener = tokio::net::UnixListener::bind(path)
+                    .with_context(|| format!("Failed to bind unix socket `{path}`"))?;
+
+                info!("Listening on unix:{path}{posture}...");
+
+                return Ok(Self {
+                    socket: Socket::Unix(listener),
+                    auth,
+                });
+            }
+
+            #[cfg(not(unix))]
+            bail!("Unix domain sockets are not supported on this platform: `{path}`");
+        }
+
+        let listener = if v6_only && addr.is_ipv6() {
+            bind_v6_only(addr)
+        } else {
+            TcpListener::bind(addr).await
+        };
+
+        let listener = listener.with_context(|| format!("Failed to bind {addr}"))?;
+
+        info!("Listening on {addr}{posture}...");
+
+        Ok(Self {
+            socket: Socket::Tcp(listener),
+            auth,
+        })
+    }
+
+    pub const fn auth(&self) -> bool {
+        self.auth
+    }
+
+    pub async fn accept(&self) -> IoResult<(Stream, Peer)> {
+        match self.socket {
+            Socket::Tcp(ref listener) => {
+                let (stream, addr) = listener.accept().await?;
+
+                Ok((Stream::Tcp(stream), Peer::Tcp(addr)))
+            }
+            #[cfg(unix)]
+            Socket::Unix(ref listener) => {
+                let (stream, _) = listener.accept().await?;
+                let id = NEXT_UNIX_ID.fetch_add(1, Relaxed);
+
+                Ok((Stream::Unix(stream), Peer::Unix(id)))
+            }
+        }
+    }
+}
+
+/// Binds a TCP listener that only accepts IPv6 connections.
+fn bind_v6_only(addr: SocketAddr) -> IoResult<TcpListener> {
+    let socket = socket2::Socket::new(Domain::IPV6, Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_only_v6(true)?;
+
+    // Same as `TcpListener::bind`
+    #[cfg(not(windows))]
+    socket.set_reuse_address(true)?;
+
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    socket.set_nonblocking(true)?;
+
+    TcpListener::from_std(socket.into())
+}
+
+/// Identifies a connected client.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Peer {
+    Tcp(SocketAddr),
+    /// Unix sockets peers are generally unnamed so we number them instead.
+    Unix(u64),
+    /// A stream within a gRPC connection.
+    #[cfg(feature = "grpc")]
+    Grpc {
+        addr: SocketAddr,
+        id: u64,
+    },
+}
+
+impl Peer {
+    pub const fn ip(self) -> Option<IpAddr> {
+        match self {
+            Self::Tcp(addr) => Some(addr.ip()),
+            Self::Unix(_) => None,
+            #[cfg(feature = "grpc")]
+            Self::Grpc { addr, .. } => Some(addr.ip()),
+        }
+    }
+}
+
+impl Display for Peer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Tcp(addr) => Display::fmt(addr, f),
+            Self::Unix(id) => write!(f, "unix#{id}"),
+            #[cfg(feature = "grpc")]
+            Self::Grpc { addr, id } => write!(f, "{addr}/grpc#{id}"),
+        }
+    }
+}
+
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixStream),
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<IoResult<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            Self::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<IoResult<usize>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            Self::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            Self::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(unix)]
+            Self::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
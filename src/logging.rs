@@ -0,0 +1,141 @@
+use std::{
+    fs,
+    path::Path,
+    sync::{Mutex, OnceLock},
+};
+
+use eyre::{Context as _, Result};
+use file_rotate::{
+    compression::Compression, suffix::AppendCount, ContentLimit, FileRotate, TimeFrequency,
+};
+use tracing_subscriber::{
+    layer::SubscriberExt, reload::Handle, util::SubscriberInitExt, EnvFilter, Layer, Registry,
+};
+
+use crate::config::{LogFormat, LoggingConfig, Rotation};
+
+pub const LEVELS: [&str; 6] = ["info", "warn", "error", "debug", "trace", "off"];
+
+/// Allows changing the level after initialization.
+static LEVEL: OnceLock<LevelHandle> = OnceLock::new();
+
+struct LevelHandle {
+    handle: Handle<EnvFilter, Registry>,
+    current: Mutex<&'static str>,
+}
+
+/// The current log level.
+pub fn level() -> Option<&'static str> {
+    LEVEL.get().map(|level| *level.current.lock().unwrap())
+}
+
+/// Replaces the log level without restarting.
+pub fn set_level(level: &str) -> Result<(), String> {
+    let Some(level) = LEVELS.into_iter().find(|&allowed| allowed == level) else {
+        return Err(format!(
+            "Unexpected level `{level}`; must be one of {}",
+            LEVELS.join(", ")
+        ));
+    };
+
+    let handle = LEVEL.get().ok_or("Logging is not initialized")?;
+
+    handle
+        .handle
+        .reload(filter(level))
+        .map_err(|err| format!("Failed to reload log level: {err}"))?;
+
+    *handle.current.lock().unwrap() = level;
+    info!("Changed log level to {level}");
+
+    Ok(())
+}
+
+fn filter(level: &str) -> EnvFilter {
+    EnvFilter::new(format!("scores_ws={level},off"))
+}
+
+/// Sets up logging to stdout and, if configured, to rotating files.
+///
+/// With `tui`, stdout is left to the dashboard which shows recent warnings and
+/// errors instead.
+pub fn init(level: &str, config: Option<&LoggingConfig>, tui: bool) -> Result<()> {
+    type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+    let (filter, handle) = tracing_subscriber::reload::Layer::new(filter(level));
+    let level = LEVELS
+        .into_iter()
+        .find(|&allowed| allowed == level)
+        .unwrap_or("info");
+
+    let _ = LEVEL.set(LevelHandle {
+        handle,
+        current: Mutex::new(level),
+    });
+
+    let mut layers: Vec<BoxedLayer> = Vec::new();
+
+    let format = config.map_or(LogFormat::Text, |config| config.format);
+
+    if tui {
+        #[cfg(feature = "tui")]
+        layers.push(crate::tui::RecentErrors.boxed());
+    } else if config.is_none_or(|config| config.stdout) {
+        let layer = tracing_subscriber::fmt::layer();
+
+        layers.push(match format {
+            LogFormat::Text => layer.boxed(),
+            LogFormat::Json => layer.json().boxed(),
+        });
+    }
+
+    let file_config = config.and_then(|config| Some((config.directory.as_deref()?, config)));
+
+    if let Some((directory, config)) = file_config {
+        let file = rolling_file(directory, config)?;
+
+        let layer = tracing_subscriber::fmt::layer()
+            .with_ansi(false)
+            .with_writer(Mutex::new(file));
+
+        layers.push(match format {
+            LogFormat::Text => layer.boxed(),
+            LogFormat::Json => layer.json().boxed(),
+        });
+    }
+
+    tracing_subscriber::registry()
+        .with(layers.with_filter(filter))
+        .init();
+
+    Ok(())
+}
+
+/// Writes logs to `{directory}/scores-ws.log` and rotates previous files to
+/// `scores-ws.log.1`, `scores-ws.log.2`, ... up to `max_files`.
+fn rolling_file(directory: &Path, config: &LoggingConfig) -> Result<FileRotate<AppendCount>> {
+    fs::create_dir_all(directory)
+        .with_context(|| format!("Failed to create log directory `{}`", directory.display()))?;
+
+    let limit = match config.rotation {
+        Rotation::Never => ContentLimit::None,
+        Rotation::Daily => ContentLimit::Time(TimeFrequency::Daily),
+        Rotation::Size => {
+            let max_bytes = config.max_file_mb * 1024 * 1024;
+
+            // Rotates after a line exceeds the size so lines aren't split
+            ContentLimit::BytesSurpassed(usize::try_from(max_bytes).unwrap_or(usize::MAX))
+        }
+    };
+
+    let file = FileRotate::new(
+        directory.join("scores-ws.log"),
+        AppendCount::new(config.max_files),
+        limit,
+        Compression::None,
+        #[cfg(unix)]
+        None,
+    );
+
+    Ok(file)
+}
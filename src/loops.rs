@@ -0,0 +1,353 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering::Relaxed},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::sync::watch;
+
+/// All loops that supply scores, identified by their label.
+pub struct Loops {
+    handles: Mutex<Vec<Arc<LoopHandle>>>,
+}
+
+impl Loops {
+    pub const fn new() -> Self {
+        Self {
+            handles: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// `interval` is the expected time between successful iterations.
+    pub fn register(&self, label: Box<str>, interval: Duration) -> Arc<LoopHandle> {
+        let handle = Arc::new(LoopHandle {
+            label,
+            running: watch::Sender::new(true),
+            interval,
+            health: Health::new(),
+        });
+
+        self.handles.lock().unwrap().push(Arc::clone(&handle));
+
+        handle
+    }
+
+    pub fn get(&self, label: &str) -> Option<Arc<LoopHandle>> {
+        self.handles
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|handle| handle.label.as_ref() == label)
+            .map(Arc::clone)
+    }
+
+    /// Whether all running loops succeeded within `max_intervals` many of
+    /// their intervals.
+    pub fn is_healthy(&self, max_intervals: u32) -> bool {
+        let now = unix_now();
+
+        self.handles.lock().unwrap().iter().all(|handle| {
+            let max_age = (handle.interval * max_intervals).as_secs();
+
+            !handle.is_running() || now.saturating_sub(handle.health.last_success()) <= max_age
+        })
+    }
+
+    /// Whether any running loop is currently backing off.
+    pub fn any_backing_off(&self) -> bool {
+        self.handles
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|handle| handle.is_running() && handle.health.backoff_secs.load(Relaxed) > 0)
+    }
+
+    /// Whether any running loop paused fetching because its retry budget
+    /// was exhausted.
+    pub fn any_circuit_open(&self) -> bool {
+        self.handles
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|handle| handle.is_running() && handle.health.circuit_open.load(Relaxed))
+    }
+
+    /// Whether any running loop only probes the osu!api because it's down.
+    pub fn any_osu_down(&self) -> bool {
+        self.handles
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|handle| handle.is_running() && handle.health.is_osu_down())
+    }
+
+    /// Whether all running loops succeeded at least once.
+    pub fn all_succeeded(&self) -> bool {
+        self.handles
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|handle| !handle.is_running() || handle.health.last_success.load(Relaxed) > 0)
+    }
+
+    pub fn handles(&self) -> Vec<Arc<LoopHandle>> {
+        self.handles.lock().unwrap().clone()
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("[");
+
+        for (i, handle) in self.handles.lock().unwrap().iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+
+            json.push_str(&handle.to_json());
+        }
+
+        json.push(']');
+
+        json
+    }
+}
+
+/// Identity and run state of a single loop.
+pub struct LoopHandle {
+    /// Validated to only contain alphanumeric characters, `-`, and `_`.
+    label: Box<str>,
+    running: watch::Sender<bool>,
+    interval: Duration,
+    health: Health,
+}
+
+impl LoopHandle {
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    pub const fn health(&self) -> &Health {
+        &self.health
+    }
+
+    pub fn is_running(&self) -> bool {
+        *self.running.borrow()
+    }
+
+    /// Starts or stops the loop; returns whether the state changed.
+    pub fn set_running(&self, running: bool) -> bool {
+        let changed = self.running.send_replace(running) != running;
+
+        if changed {
+            let action = if running { "Started" } else { "Stopped" };
+            info!(label = self.label(), "{action} loop");
+        }
+
+        changed
+    }
+
+    /// Waits until the loop is started again if it's currently stopped.
+    ///
+    /// Returns whether it had to wait.
+    pub async fn wait_until_running(&self) -> bool {
+        if self.is_running() {
+            return false;
+        }
+
+        let mut rx = self.running.subscribe();
+
+        // The sender lives in `self` so the channel cannot be closed
+        let _ = rx.wait_for(|&running| running).await;
+
+        true
+    }
+
+    /// Short description of what the loop is currently doing.
+    pub fn state(&self) -> &'static str {
+        let health = &self.health;
+
+        if !self.is_running() {
+            "stopped"
+        } else if health.standby.load(Relaxed) {
+            "standby"
+        } else if health.is_osu_down() {
+            "osu down"
+        } else if health.circuit_open.load(Relaxed) {
+            "paused"
+        } else if health.backoff_secs.load(Relaxed) > 0 {
+            "backing off"
+        } else if health.last_success.load(Relaxed) == 0 {
+            "starting"
+        } else {
+            "ok"
+        }
+    }
+
+    /// `None` if there was no success yet.
+    pub fn last_success_secs_ago(&self) -> Option<u64> {
+        match self.health.last_success.load(Relaxed) {
+            0 => None,
+            last_success => Some(unix_now().saturating_sub(last_success)),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let health = &self.health;
+
+        let token_valid = match health.token.load(Relaxed) {
+            Health::TOKEN_VALID => "true",
+            Health::TOKEN_INVALID => "false",
+            _ => "null",
+        };
+
+        let mut json = format!(
+            r#"{{"label":"{}","running":{},"token_valid":{token_valid},"last_success_secs_ago":"#,
+            self.label,
+            self.is_running(),
+        );
+
+        let mut buf = itoa::Buffer::new();
+
+        match health.last_success.load(Relaxed) {
+            0 => json.push_str("null"),
+            last_success => {
+                json.push_str(buf.format(unix_now().saturating_sub(last_success)));
+            }
+        }
+
+        json.push_str(r#","backoff_secs":"#);
+
+        match health.backoff_secs.load(Relaxed) {
+            0 => json.push_str("null"),
+            backoff => json.push_str(buf.format(backoff)),
+        }
+
+        json.push_str(r#","circuit_open":"#);
+        json.push_str(if health.circuit_open.load(Relaxed) {
+            "true"
+        } else {
+            "false"
+        });
+        json.push_str(r#","circuit_trips":"#);
+        json.push_str(buf.format(health.circuit_trips.load(Relaxed)));
+        json.push_str(r#","missed_scores":"#);
+        json.push_str(buf.format(health.missed_scores.load(Relaxed)));
+        json.push_str(r#","standby":"#);
+        json.push_str(if health.standby.load(Relaxed) {
+            "true"
+        } else {
+            "false"
+        });
+        json.push_str(r#","osu_down":"#);
+        json.push_str(if health.is_osu_down() {
+            "true"
+        } else {
+            "false"
+        });
+        json.push('}');
+
+        json
+    }
+}
+
+/// Reported through the admin API's `GET /health`.
+pub struct Health {
+    started_at: u64,
+    /// Unix timestamp in seconds; `0` if there was none yet.
+    last_success: AtomicU64,
+    /// `0` if the loop is currently not backing off.
+    backoff_secs: AtomicU64,
+    token: AtomicU8,
+    /// Whether fetching is paused after too many failures.
+    circuit_open: AtomicBool,
+    /// How often fetching was paused so far.
+    circuit_trips: AtomicU64,
+    /// Estimated total of scores that were missed due to expired cursors.
+    missed_scores: AtomicU64,
+    /// Whether another instance holds the lease on fetching.
+    standby: AtomicBool,
+    /// Whether the osu!api keeps responding with 503, e.g. for maintenance.
+    osu_down: AtomicBool,
+}
+
+impl Health {
+    const TOKEN_UNKNOWN: u8 = 0;
+    const TOKEN_VALID: u8 = 1;
+    const TOKEN_INVALID: u8 = 2;
+
+    pub fn new() -> Self {
+        Self {
+            started_at: unix_now(),
+            last_success: AtomicU64::new(0),
+            backoff_secs: AtomicU64::new(0),
+            token: AtomicU8::new(Self::TOKEN_UNKNOWN),
+            circuit_open: AtomicBool::new(false),
+            circuit_trips: AtomicU64::new(0),
+            missed_scores: AtomicU64::new(0),
+            standby: AtomicBool::new(false),
+            osu_down: AtomicBool::new(false),
+        }
+    }
+
+    pub fn success(&self) {
+        self.last_success.store(unix_now(), Relaxed);
+        self.backoff_secs.store(0, Relaxed);
+    }
+
+    pub fn backoff(&self, secs: u64) {
+        self.backoff_secs.store(secs, Relaxed);
+    }
+
+    /// Fetching is paused for `secs` seconds.
+    pub fn open_circuit(&self, secs: u64) {
+        self.circuit_open.store(true, Relaxed);
+        self.circuit_trips.fetch_add(1, Relaxed);
+        self.backoff(secs);
+    }
+
+    pub fn close_circuit(&self) {
+        self.circuit_open.store(false, Relaxed);
+    }
+
+    pub fn missed_scores(&self, count: u64) {
+        self.missed_scores.fetch_add(count, Relaxed);
+    }
+
+    pub fn standby(&self, standby: bool) {
+        self.standby.store(standby, Relaxed);
+    }
+
+    /// Returns whether the state changed.
+    pub fn osu_down(&self, down: bool) -> bool {
+        self.osu_down.swap(down, Relaxed) != down
+    }
+
+    pub fn is_osu_down(&self) -> bool {
+        self.osu_down.load(Relaxed)
+    }
+
+    pub fn token_valid(&self, valid: bool) {
+        let token = if valid {
+            Self::TOKEN_VALID
+        } else {
+            Self::TOKEN_INVALID
+        };
+
+        self.token.store(token, Relaxed);
+    }
+
+    /// Last success or, if there was none yet, when the loop started.
+    fn last_success(&self) -> u64 {
+        match self.last_success.load(Relaxed) {
+            0 => self.started_at,
+            last_success => last_success,
+        }
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
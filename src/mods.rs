@@ -0,0 +1,144 @@
+use crate::json::{self, Entry};
+
+/// Upper bound for the amount of mods a client may include or exclude.
+const MAX_MODS: usize = 32;
+
+/// Mods of which a score must contain all and mods of which it must contain
+/// none to be sent.
+#[derive(Default)]
+pub struct ModFilter {
+    include: Box<[Box<str>]>,
+    exclude: Box<[Box<str>]>,
+}
+
+impl ModFilter {
+    pub fn matches(&self, bytes: &[u8]) -> bool {
+        if self.include.is_empty() && self.exclude.is_empty() {
+            return true;
+        }
+
+        let Some(mods) = acronyms(bytes) else {
+            return false;
+        };
+
+        let contains = |acronym: &str| mods.contains(&acronym.as_bytes());
+
+        self.include.iter().all(|acronym| contains(acronym))
+            && !self.exclude.iter().any(|acronym| contains(acronym))
+    }
+
+    /// Replaces the given lists; lists that are `None` stay as they are.
+    pub fn update(&mut self, update: ModUpdate) {
+        if let Some(include) = update.include {
+            self.include = include;
+        }
+
+        if let Some(exclude) = update.exclude {
+            self.exclude = exclude;
+        }
+    }
+}
+
+/// Changes to a [`ModFilter`] through `mods_include` and `mods_exclude`.
+#[derive(Default)]
+pub struct ModUpdate {
+    pub include: Option<Box<[Box<str>]>>,
+    pub exclude: Option<Box<[Box<str>]>>,
+}
+
+impl ModUpdate {
+    /// Parses acronyms such as `HD` which may be quoted and are uppercased.
+    ///
+    /// Returns `None` if an acronym is invalid or there are too many.
+    pub fn parse_acronyms<'a>(list: impl IntoIterator<Item = &'a str>) -> Option<Box<[Box<str>]>> {
+        let acronyms: Box<[Box<str>]> = list
+            .into_iter()
+            .map(str::trim)
+            .filter(|acronym| !acronym.is_empty())
+            .map(|acronym| {
+                let acronym = acronym
+                    .strip_prefix('"')
+                    .and_then(|acronym| acronym.strip_suffix('"'))
+                    .unwrap_or(acronym);
+
+                let valid = (1..=4).contains(&acronym.len())
+                    && acronym.bytes().all(|byte| byte.is_ascii_alphanumeric());
+
+                valid.then(|| acronym.to_ascii_uppercase().into_boxed_str())
+            })
+            .collect::<Option<_>>()?;
+
+        (acronyms.len() <= MAX_MODS).then_some(acronyms)
+    }
+}
+
+/// Acronyms of the score's top-level `mods` array whose elements are either
+/// objects like `{"acronym":"DT","settings":{...}}` or, in the legacy format,
+/// strings like `"DT"`.
+///
+/// A score without `mods` has none. Returns `None` if the JSON is malformed.
+pub fn acronyms(bytes: &[u8]) -> Option<Vec<&[u8]>> {
+    let Entry::Found(start) = json::find_entry(bytes, 0, b"mods")? else {
+        return Some(Vec::new());
+    };
+
+    let mut acronyms = Vec::new();
+
+    for i in json::elements(bytes, start)? {
+        match bytes[i] {
+            b'"' => acronyms.push(json::string(bytes, i)?),
+            b'{' => {
+                if let Entry::Found(start) = json::find_entry(bytes, i, b"acronym")? {
+                    acronyms.extend(json::string(bytes, start));
+                }
+            }
+            _ => return None,
+        }
+    }
+
+    Some(acronyms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let score = br#"{"id":1,"user":{"mods":["XX"]},"mods":[{"acronym":"HD"}, {"settings":{"acronym":"no","speed_change":1.5},"acronym":"DT"}],"pp":1}"#;
+        assert_eq!(acronyms(score).unwrap(), [b"HD", b"DT"]);
+
+        let legacy = br#"{ "mods" : [ "HD" , "HR" ] }"#;
+        assert_eq!(acronyms(legacy).unwrap(), [b"HD", b"HR"]);
+
+        assert!(acronyms(br#"{"id":1,"mods":[]}"#).unwrap().is_empty());
+        assert!(acronyms(br#"{"id":1}"#).unwrap().is_empty());
+        assert!(acronyms(br#"{"id":1,"mods":[{"acronym":"HD"}"#).is_none());
+    }
+
+    #[test]
+    fn filter() {
+        let hddt = br#"{"mods":[{"acronym":"HD"},{"acronym":"DT"}]}"#;
+        let hdrx = br#"{"mods":[{"acronym":"HD"},{"acronym":"RX"}]}"#;
+
+        let mut filter = ModFilter::default();
+        assert!(filter.matches(hdrx));
+
+        filter.update(ModUpdate {
+            include: ModUpdate::parse_acronyms(["hd", "\"DT\""]),
+            exclude: ModUpdate::parse_acronyms(["RX"]),
+        });
+        assert!(filter.matches(hddt));
+        assert!(!filter.matches(hdrx));
+
+        filter.update(ModUpdate {
+            include: ModUpdate::parse_acronyms(["HD"]),
+            exclude: None,
+        });
+        assert!(!filter.matches(hdrx));
+        assert!(!filter.matches(b"{}"));
+
+        assert!(ModUpdate::parse_acronyms(["H\"D"]).is_none());
+        assert!(ModUpdate::parse_acronyms(["DOUBLETIME"]).is_none());
+    }
+}
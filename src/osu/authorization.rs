@@ -1,53 +1,91 @@
-use std::sync::atomic::{AtomicPtr, Ordering::SeqCst};
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 
 use eyre::{Context, ContextCompat, Result};
+use tokio::{sync::watch, time::Instant};
 
 pub struct Authorization {
-    // We use an atomic pointer to allow mutation through immutable reference.
-    // Since atomic pointers only support thin pointers, we need to keep the
-    // str boxed which means double indirection but that's fine.
-    ptr: AtomicPtr<Box<str>>,
+    // Refreshed in the background while fetches may be in flight so the
+    // header is shared instead of borrowed.
+    header: RwLock<Arc<str>>,
+    /// When the current token expires; `None` if there is no token yet.
+    expires_at: watch::Sender<Option<Instant>>,
 }
 
 impl Authorization {
-    pub fn as_str(&self) -> &str {
-        let ptr = self.ptr.load(SeqCst);
+    pub fn header(&self) -> Arc<str> {
+        Arc::clone(&self.header.read().unwrap())
+    }
 
-        unsafe { (*ptr).as_ref() }
+    pub fn subscribe_expiry(&self) -> watch::Receiver<Option<Instant>> {
+        self.expires_at.subscribe()
     }
 
     pub fn parse(&self, bytes: &[u8]) -> Result<()> {
         const KEY: &[u8] = br#""access_token":"#;
 
         let idx = memchr::memmem::find(bytes, KEY).context("missing `\"access_token\"`")?;
-        let bytes = &bytes[idx + KEY.len()..];
-        let mut iter = memchr::memchr_iter(b'"', bytes);
+        let token_bytes = &bytes[idx + KEY.len()..];
+        let mut iter = memchr::memchr_iter(b'"', token_bytes);
         let (start, end) = iter.next().zip(iter.next()).context("missing quotes")?;
 
-        let token = std::str::from_utf8(&bytes[start + 1..end])
+        let token = std::str::from_utf8(&token_bytes[start + 1..end])
             .context("access token is not valid utf-8")?;
 
-        let authorization = format!("Bearer {token}");
-        let ptr = Box::into_raw(Box::new(authorization.into_boxed_str()));
+        *self.header.write().unwrap() = format!("Bearer {token}").into();
 
-        let old = self.ptr.swap(ptr, SeqCst);
-        unsafe { old.drop_in_place() };
+        let expires_at = Self::parse_expires_in(bytes).map(|secs| Instant::now() + secs);
+        self.expires_at.send_replace(expires_at);
 
         Ok(())
     }
+
+    fn parse_expires_in(bytes: &[u8]) -> Option<Duration> {
+        const KEY: &[u8] = br#""expires_in":"#;
+
+        let idx = memchr::memmem::find(bytes, KEY)?;
+
+        let secs = bytes[idx + KEY.len()..]
+            .iter()
+            .skip_while(|byte| byte.is_ascii_whitespace())
+            .take_while(|byte| byte.is_ascii_digit())
+            .try_fold(0_u64, |secs, byte| {
+                secs.checked_mul(10)?.checked_add(u64::from(byte - b'0'))
+            })?;
+
+        Some(Duration::from_secs(secs))
+    }
 }
 
 impl Default for Authorization {
     fn default() -> Self {
         Self {
-            ptr: AtomicPtr::new(Box::into_raw(Box::default())),
+            header: RwLock::new(Arc::from("")),
+            expires_at: watch::Sender::new(None),
         }
     }
 }
 
-impl Drop for Authorization {
-    fn drop(&mut self) {
-        let ptr = self.ptr.load(SeqCst);
-        unsafe { ptr.drop_in_place() };
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let authorization = Authorization::default();
+        let bytes = br#"{"token_type":"Bearer","expires_in": 86400,"access_token":"abc"}"#;
+        authorization.parse(bytes).unwrap();
+
+        assert_eq!(authorization.header().as_ref(), "Bearer abc");
+
+        let expires_in = authorization
+            .subscribe_expiry()
+            .borrow()
+            .unwrap()
+            .duration_since(Instant::now());
+
+        assert!(expires_in > Duration::from_secs(86_000));
     }
 }
@@ -1,7 +1,16 @@
-use std::{borrow::Cow, cmp, time::Duration};
+use std::{
+    cmp,
+    collections::hash_map::RandomState,
+    error::Error as StdError,
+    fmt::{Debug, Display, Formatter, Result as FmtResult},
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    sync::Arc,
+    time::Duration,
+};
 
 use bytes::Bytes;
-use eyre::{Context as _, Result};
+use eyre::{Context as _, Report, Result};
 use http_body_util::{BodyExt, Full};
 use hyper::{
     header::{ACCEPT, AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE, USER_AGENT},
@@ -10,56 +19,105 @@ use hyper::{
 use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
 use hyper_util::{
     client::legacy::{connect::HttpConnector, Builder, Client},
-    rt::TokioExecutor,
+    rt::{TokioExecutor, TokioTimer},
 };
 use memchr::memmem;
+use tracing::instrument;
 
-use crate::config::OsuConfig;
+use crate::{
+    config::{CatchUpConfig, HttpVersion, OsuConfig, RetryConfig},
+    loops::{Health, LoopHandle},
+};
 
-use super::{authorization::Authorization, Scores, ScoresDeserializer};
+use super::{authorization::Authorization, ScoreSource, Scores, ScoresDeserializer};
 
+/// Version from which on scores are in their current format.
+const API_VERSION: &str = "20220705";
 const MY_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
 const APPLICATION_JSON: &str = "application/json";
 const APPLICATION_URL_ENCODED: &str = "application/x-www-form-urlencoded";
 
+/// How long before its expiry a token is refreshed.
+const REFRESH_MARGIN: Duration = Duration::from_mins(5);
+
 type Body = Full<Bytes>;
 
 pub struct Osu {
     config: OsuConfig,
     authorization: Authorization,
     client: Client<HttpsConnector<HttpConnector>, Body>,
+    api_url: Box<str>,
+    token_url: Box<str>,
+    user_agent: Box<str>,
 }
 
 impl Osu {
     pub fn new(config: OsuConfig) -> Result<Self> {
-        #[cfg(feature = "ring")]
-        let crypto_provider = rustls::crypto::ring::default_provider();
-        #[cfg(all(feature = "aws", not(feature = "ring")))]
-        let crypto_provider = rustls::crypto::aws_lc_rs::default_provider();
-        #[cfg(not(any(feature = "ring", feature = "aws")))]
-        let crypto_provider = rustls::crypto::CryptoProvider::get_default()
-            .expect("No default crypto provider installed or configured via crate features")
-            .clone();
-
         let https = HttpsConnectorBuilder::new()
-            .with_provider_and_webpki_roots(crypto_provider)
+            .with_provider_and_webpki_roots(crate::http::crypto_provider())
             .context("Failed to configure https connector")?
-            .https_only()
-            .enable_http2()
-            .build();
+            .https_only();
+
+        let https = match config.http_version {
+            HttpVersion::Http2 => https.enable_http2().build(),
+            HttpVersion::Http1 => https.enable_http1().build(),
+            HttpVersion::Auto => https.enable_all_versions().build(),
+        };
+
+        let http = &config.http;
+        let mut builder = Builder::new(TokioExecutor::new());
+
+        builder
+            .timer(TokioTimer::new())
+            .pool_timer(TokioTimer::new())
+            .http2_only(matches!(config.http_version, HttpVersion::Http2))
+            .pool_idle_timeout(Duration::from_secs(http.idle_timeout_secs))
+            .http2_keep_alive_interval(http.keep_alive_interval_secs.map(Duration::from_secs))
+            .http2_keep_alive_timeout(Duration::from_secs(http.keep_alive_timeout_secs))
+            // Fetches are seconds apart so connections are idle most of the time
+            .http2_keep_alive_while_idle(true);
+
+        if let Some(max_idle) = http.max_idle_connections {
+            builder.pool_max_idle_per_host(max_idle);
+        }
 
-        let client = Builder::new(TokioExecutor::new())
-            .http2_only(true)
-            .build(https);
+        let client = builder.build(https);
 
         Ok(Self {
+            user_agent: user_agent(&config),
+            api_url: Box::from(config.api_url.trim_end_matches('/')),
+            token_url: config.token_url.clone(),
             config,
             client,
             authorization: Authorization::default(),
         })
     }
 
+    /// Client for a fake osu!api that's served through plain HTTP/1.1 at
+    /// `base_url`, e.g. `http://127.0.0.1:1234`.
+    #[cfg(test)]
+    pub fn local(config: OsuConfig, base_url: &str) -> Result<Self> {
+        let http = HttpsConnectorBuilder::new()
+            .with_provider_and_webpki_roots(crate::http::crypto_provider())
+            .context("Failed to configure http connector")?
+            .https_or_http()
+            .enable_http1()
+            .build();
+
+        Ok(Self {
+            user_agent: user_agent(&config),
+            config,
+            client: Builder::new(TokioExecutor::new()).build(http),
+            authorization: Authorization::default(),
+            api_url: format!("{base_url}/api/v2").into_boxed_str(),
+            token_url: format!("{base_url}/oauth/token").into_boxed_str(),
+        })
+    }
+
     async fn fetch_response(&self, req: Request<Body>) -> Result<(Bytes, StatusCode)> {
+        #[cfg(feature = "chaos")]
+        crate::chaos::CHAOS.before_request().await?;
+
         let response = self
             .client
             .request(req)
@@ -74,18 +132,32 @@ impl Osu {
             .context("Failed to collect bytes")?
             .to_bytes();
 
+        #[cfg(feature = "chaos")]
+        let bytes = crate::chaos::CHAOS.response_body(bytes);
+
         Ok((bytes, parts.status))
     }
 
-    async fn reauthorize(&self) -> Result<()> {
-        const URL: &str = "https://osu.ppy.sh/oauth/token";
-
+    async fn reauthorize(&self, health: &Health) -> Result<()> {
         info!("Re-authorizing...");
 
         let OsuConfig {
             client_id,
             client_secret,
             ruleset: _,
+            label: _,
+            retry: _,
+            users: _,
+            user_info: _,
+            concurrent_pages: _,
+            id_threshold: _,
+            page_delay_ms: _,
+            passed_only: _,
+            http_version: _,
+            http: _,
+            api_url: _,
+            token_url: _,
+            user_agent_suffix: _,
         } = &self.config;
 
         let body = format!(
@@ -93,8 +165,8 @@ impl Osu {
             &grant_type=client_credentials&scope=public"
         );
 
-        let req = Request::post(URL)
-            .header(USER_AGENT, MY_USER_AGENT)
+        let req = Request::post(self.token_url.as_ref())
+            .header(USER_AGENT, self.user_agent.as_ref())
             .header(ACCEPT, APPLICATION_JSON)
             .header(CONTENT_TYPE, APPLICATION_URL_ENCODED)
             .header(CONTENT_LENGTH, body.len())
@@ -107,11 +179,18 @@ impl Osu {
             .context("Failed to fetch response")?;
 
         match status_code {
-            StatusCode::OK => self
-                .authorization
-                .parse(&bytes)
-                .context("Failed to parse authorization"),
+            StatusCode::OK => {
+                self.authorization
+                    .parse(&bytes)
+                    .context("Failed to parse authorization")?;
+
+                health.token_valid(true);
+
+                Ok(())
+            }
             StatusCode::UNAUTHORIZED => {
+                health.token_valid(false);
+
                 bail!(
                     "Received 401 error while authorizing, make sure your \
                     client id and secret are valid: {bytes:?}"
@@ -127,106 +206,402 @@ impl Osu {
         }
     }
 
-    pub async fn fetch_scores(&self, scores: &mut Scores, cursor_id: Option<u64>) -> FetchResult {
-        const URL: &str = "https://osu.ppy.sh/api/v2/scores";
+    /// Fetches the users of the given ids, at most 50, as
+    /// `{"users":[...]}` including each user's `statistics_rulesets`.
+    pub async fn fetch_users(&self, user_ids: &[u64]) -> Result<Bytes> {
+        let mut url = format!("{}/users", self.api_url);
 
-        async fn fetch_inner(
-            osu: &Osu,
-            scores: &mut Scores,
-            just_authorized: bool,
-            cursor_id: Option<u64>,
-        ) -> Result<FetchResult> {
-            let mut url = Cow::Borrowed(URL);
-
-            if let Some(ruleset) = osu.config.ruleset.as_deref() {
-                let url = url.to_mut();
-                url.push_str("?ruleset=");
-                url.push_str(ruleset);
-            }
-
-            if let Some(cursor_id) = cursor_id {
-                let is_without_query = matches!(url, Cow::Borrowed(_));
-                let url = url.to_mut();
-
-                if is_without_query {
-                    url.push('?');
-                } else {
-                    url.push('&');
-                }
+        for (i, user_id) in user_ids.iter().enumerate() {
+            url.push_str(if i == 0 { "?ids[]=" } else { "&ids[]=" });
+            url.push_str(itoa::Buffer::new().format(*user_id));
+        }
 
-                url.push_str("cursor[id]=");
-                url.push_str(itoa::Buffer::new().format(cursor_id));
-            }
+        let timeout = Duration::from_secs(self.config.retry.timeout_secs);
 
-            let req = Request::get(url.as_ref())
-                .header(USER_AGENT, MY_USER_AGENT)
-                // doesn't seem to affect the response data format
-                // .header("x-api-version", 0_usize)
+        for just_authorized in [false, true] {
+            let req = Request::get(&url)
+                .header(USER_AGENT, self.user_agent.as_ref())
                 .header(ACCEPT, APPLICATION_JSON)
-                .header(AUTHORIZATION, osu.authorization.as_str())
+                .header(AUTHORIZATION, &*self.authorization.header())
                 .header(CONTENT_LENGTH, 0_usize)
                 .body(Full::default())
                 .context("Failed to create request")?;
 
-            let (bytes, status_code) = osu
-                .fetch_response(req)
+            let (bytes, status_code) = tokio::time::timeout(timeout, self.fetch_response(req))
                 .await
+                .context("Timeout while awaiting users")?
                 .context("Failed to fetch response")?;
 
             match status_code {
-                StatusCode::OK => {
-                    ScoresDeserializer::new(bytes).deserialize(scores)?;
+                StatusCode::OK => return Ok(bytes),
+                StatusCode::UNAUTHORIZED if !just_authorized => {
+                    self.reauthorize(&Health::new())
+                        .await
+                        .context("Failed to re-authorize")?;
+                }
+                _ => bail!("Status code: {status_code}, Response: {bytes:?}"),
+            }
+        }
+
+        bail!("Received 401 error after authorizing")
+    }
+
+    /// Requests a token to check whether the client id and secret are valid.
+    pub async fn check_credentials(&self) -> Result<()> {
+        self.reauthorize(&Health::new()).await
+    }
+
+    /// Refreshes the token shortly before it expires so that fetches don't
+    /// run into a 401 first.
+    pub async fn refresh_token(osu: Arc<Self>, handle: Arc<LoopHandle>) {
+        let mut expires_at = osu.authorization.subscribe_expiry();
+
+        loop {
+            // The sender lives in `osu` so the channel cannot be closed
+            let Some(at) = *expires_at.borrow_and_update() else {
+                let _ = expires_at.changed().await;
+
+                continue;
+            };
 
-                    Ok(FetchResult::Ok)
+            let refresh_at = at.checked_sub(REFRESH_MARGIN).unwrap_or(at);
+
+            tokio::select! {
+                () = tokio::time::sleep_until(refresh_at) => {
+                    if let Err(err) = osu.reauthorize(handle.health()).await {
+                        error!(?err, "Failed to refresh token");
+                        tokio::time::sleep(Duration::from_secs(30)).await;
+                    }
                 }
-                StatusCode::UNAUTHORIZED => {
-                    if just_authorized {
-                        bail!("Received 401 error after authorizing: {bytes:?}");
+                _ = expires_at.changed() => {}
+            }
+        }
+    }
+
+    #[instrument(level = "debug", name = "fetch", skip(self, scores, health))]
+    async fn fetch_scores(
+        &self,
+        scores: &mut Scores,
+        cursor_id: Option<u64>,
+        health: &Health,
+    ) -> FetchResult {
+        let mut url = format!("{}/scores", self.api_url);
+
+        if let Some(ruleset) = self.config.ruleset.as_deref() {
+            url.push_str("?ruleset=");
+            url.push_str(ruleset);
+        }
+
+        if let Some(cursor_id) = cursor_id {
+            url.push(if self.config.ruleset.is_some() {
+                '&'
+            } else {
+                '?'
+            });
+            url.push_str("cursor[id]=");
+            url.push_str(itoa::Buffer::new().format(cursor_id));
+        }
+
+        info!(?cursor_id, "Fetching scores...");
+
+        let res = self
+            .fetch_with_retry(&url, Endpoint::Scores, scores, health)
+            .await;
+
+        if let FetchResult::CursorTooOld = res {
+            if let Some(cursor_id) = cursor_id {
+                warn!("Score id {cursor_id} too old to fetch from");
+            } else {
+                debug!("\"cursor too old\" without a cursor id");
+            }
+        }
+
+        res
+    }
+
+    /// Fetches the most recent scores of a user.
+    #[instrument(level = "debug", name = "fetch", skip(self, scores, health))]
+    pub async fn fetch_user_scores(
+        &self,
+        user_id: u32,
+        scores: &mut Scores,
+        health: &Health,
+    ) -> FetchResult {
+        let mut url = format!("{}/users/{user_id}/scores/recent?limit=100", self.api_url);
+
+        if let Some(ruleset) = self.config.ruleset.as_deref() {
+            url.push_str("&mode=");
+            url.push_str(ruleset);
+        }
+
+        debug!(user_id, "Fetching user scores...");
+
+        self.fetch_with_retry(&url, Endpoint::UserScores, scores, health)
+            .await
+    }
+
+    async fn fetch_with_retry(
+        &self,
+        url: &str,
+        endpoint: Endpoint,
+        scores: &mut Scores,
+        health: &Health,
+    ) -> FetchResult {
+        let retry = &self.config.retry;
+        let timeout = Duration::from_secs(retry.timeout_secs);
+        let mut backoff = Backoff::new(retry);
+        let mut failures = 0;
+        let mut unavailable = 0;
+
+        loop {
+            let fetch_fut = fetch_inner(self, url, endpoint, scores, false, health);
+
+            let is_unavailable = match tokio::time::timeout(timeout, fetch_fut).await {
+                Ok(Ok(res)) => {
+                    if health.osu_down(false) {
+                        info!("osu!api recovered, fetching at the regular interval again");
                     }
 
-                    osu.reauthorize().await.context("Failed to re-authorize")?;
+                    health.success();
 
-                    return Box::pin(fetch_inner(osu, scores, true, cursor_id)).await;
+                    return res;
                 }
-                StatusCode::UNPROCESSABLE_ENTITY
-                    if memmem::rfind(&bytes, br#""error":"cursor is too old""#).is_some() =>
-                {
-                    if let Some(cursor_id) = cursor_id {
-                        warn!("Score id {cursor_id} too old to fetch from");
-                    } else {
-                        debug!("\"cursor too old\" without a cursor id");
-                    }
+                // Errors were already logged when the osu!api went down
+                Ok(Err(err)) if health.is_osu_down() => {
+                    debug!(?err, "osu!api still down");
 
-                    Ok(FetchResult::CursorTooOld)
+                    err.is::<ServiceUnavailable>()
                 }
-                StatusCode::TOO_MANY_REQUESTS => {
-                    bail!("Received 429 error, try reducing your interval: {bytes:?}")
+                Ok(Err(err)) => {
+                    error!(?err, "Failed to fetch scores");
+
+                    err.is::<ServiceUnavailable>()
                 }
-                StatusCode::SERVICE_UNAVAILABLE => {
-                    bail!("Received 503 error, osu! servers likely temporarily down: {bytes:?}")
+                Err(_) => {
+                    error!("Timeout while awaiting scores");
+
+                    false
                 }
-                _ => bail!("Status code: {status_code}, Response: {bytes:?}"),
+            };
+
+            unavailable = if is_unavailable { unavailable + 1 } else { 0 };
+
+            if unavailable >= retry.maintenance_after {
+                if health.osu_down(true) {
+                    warn!(
+                        "osu!api keeps responding with 503, likely maintenance; probing every {}s...",
+                        retry.probe_secs
+                    );
+                }
+
+                health.backoff(retry.probe_secs);
+                tokio::time::sleep(Duration::from_secs(retry.probe_secs)).await;
+
+                continue;
+            }
+
+            failures += 1;
+
+            if retry.budget.is_some_and(|budget| failures >= budget) {
+                error!(
+                    failures,
+                    "Retry budget exhausted, pausing fetches for {}s...", retry.cooldown_secs
+                );
+
+                health.open_circuit(retry.cooldown_secs);
+                tokio::time::sleep(Duration::from_secs(retry.cooldown_secs)).await;
+                health.close_circuit();
+
+                failures = 0;
+                backoff = Backoff::new(retry);
+
+                continue;
             }
+
+            let delay = backoff.next();
+            info!("Retrying in {delay:.1?}...");
+            health.backoff(cmp::max(1, delay.as_secs()));
+            tokio::time::sleep(delay).await;
         }
+    }
+}
 
-        info!(?cursor_id, "Fetching scores...");
+/// Fetches scores once; re-authorizes on 401 and tries again.
+async fn fetch_inner(
+    osu: &Osu,
+    url: &str,
+    endpoint: Endpoint,
+    scores: &mut Scores,
+    just_authorized: bool,
+    health: &Health,
+) -> Result<FetchResult> {
+    let mut req = Request::get(url)
+        .header(USER_AGENT, osu.user_agent.as_ref())
+        .header(ACCEPT, APPLICATION_JSON)
+        .header(AUTHORIZATION, &*osu.authorization.header())
+        .header(CONTENT_LENGTH, 0_usize);
+
+    // Doesn't seem to affect the response data format of `/scores`
+    // but older versions of user scores use legacy score ids
+    if let Endpoint::UserScores = endpoint {
+        req = req.header("x-api-version", API_VERSION);
+    }
 
-        let mut backoff = 2;
+    let req = req
+        .body(Full::default())
+        .context("Failed to create request")?;
 
-        loop {
-            let fetch_fut = fetch_inner(self, scores, false, cursor_id);
+    let (bytes, status_code) = osu
+        .fetch_response(req)
+        .await
+        .context("Failed to fetch response")?;
 
-            match tokio::time::timeout(Duration::from_secs(10), fetch_fut).await {
-                Ok(Ok(res)) => return res,
-                Ok(Err(err)) => error!(?err, "Failed to fetch scores"),
-                Err(_) => error!("Timeout while awaiting scores"),
+    match status_code {
+        StatusCode::OK => {
+            #[cfg(feature = "archive")]
+            crate::archive::push(&bytes);
+
+            {
+                let _span = debug_span!("parse", bytes = bytes.len()).entered();
+                let deserializer = ScoresDeserializer::new(bytes);
+
+                match endpoint {
+                    Endpoint::Scores => deserializer.deserialize(scores)?,
+                    Endpoint::UserScores => deserializer.deserialize_array(scores)?,
+                }
             }
 
-            info!("Retrying in {backoff}s...");
-            tokio::time::sleep(Duration::from_secs(backoff)).await;
-            backoff = cmp::min(120, backoff * 2);
+            health.token_valid(true);
+
+            Ok(FetchResult::Ok)
+        }
+        StatusCode::UNAUTHORIZED => {
+            health.token_valid(false);
+
+            if just_authorized {
+                bail!("Received 401 error after authorizing: {bytes:?}");
+            }
+
+            osu.reauthorize(health)
+                .await
+                .context("Failed to re-authorize")?;
+
+            return Box::pin(fetch_inner(osu, url, endpoint, scores, true, health)).await;
+        }
+        StatusCode::NOT_FOUND if matches!(endpoint, Endpoint::UserScores) => {
+            Ok(FetchResult::NotFound)
+        }
+        StatusCode::UNPROCESSABLE_ENTITY
+            if memmem::rfind(&bytes, br#""error":"cursor is too old""#).is_some() =>
+        {
+            Ok(FetchResult::CursorTooOld)
         }
+        StatusCode::TOO_MANY_REQUESTS => {
+            bail!("Received 429 error, try reducing your interval: {bytes:?}")
+        }
+        StatusCode::SERVICE_UNAVAILABLE => Err(Report::new(ServiceUnavailable(bytes))),
+        _ => bail!("Status code: {status_code}, Response: {bytes:?}"),
+    }
+}
+
+impl ScoreSource for Osu {
+    fn fetch(
+        &self,
+        scores: &mut Scores,
+        cursor_id: Option<u64>,
+        health: &Health,
+    ) -> impl Future<Output = FetchResult> + Send {
+        self.fetch_scores(scores, cursor_id, health)
+    }
+
+    fn concurrent_pages(&self) -> usize {
+        self.config.concurrent_pages
     }
+
+    fn id_threshold(&self) -> u64 {
+        self.config.id_threshold
+    }
+
+    fn page_delay(&self) -> Duration {
+        Duration::from_millis(self.config.page_delay_ms)
+    }
+
+    fn catch_up(&self) -> Option<&CatchUpConfig> {
+        Some(&self.config.catch_up)
+    }
+
+    fn passed_only(&self) -> bool {
+        self.config.passed_only
+    }
+}
+
+/// e.g. `scores-ws/1.0.3 (contact@example.com)`
+fn user_agent(config: &OsuConfig) -> Box<str> {
+    match config.user_agent_suffix {
+        Some(ref suffix) => format!("{MY_USER_AGENT} ({suffix})").into_boxed_str(),
+        None => Box::from(MY_USER_AGENT),
+    }
+}
+
+/// Response with status code 503, e.g. during maintenance.
+struct ServiceUnavailable(Bytes);
+
+impl Debug for ServiceUnavailable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{self}: {:?}", self.0)
+    }
+}
+
+impl Display for ServiceUnavailable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str("Received 503 error, osu! servers likely temporarily down")
+    }
+}
+
+impl StdError for ServiceUnavailable {}
+
+#[derive(Copy, Clone)]
+enum Endpoint {
+    Scores,
+    UserScores,
+}
+
+/// Delays between retries as configured through `[osu.retry]`.
+struct Backoff<'a> {
+    config: &'a RetryConfig,
+    current: Duration,
+}
+
+impl<'a> Backoff<'a> {
+    const fn new(config: &'a RetryConfig) -> Self {
+        Self {
+            config,
+            current: Duration::from_secs(config.initial_secs),
+        }
+    }
+
+    fn next(&mut self) -> Duration {
+        let delay = self
+            .current
+            .mul_f64(1.0 - self.config.jitter * random_fraction());
+        let max = Duration::from_secs(self.config.max_secs);
+        let next = self.current.as_secs_f64() * self.config.multiplier;
+        self.current = Duration::try_from_secs_f64(next).map_or(max, |next| cmp::min(max, next));
+
+        delay
+    }
+}
+
+/// Returns a random value in `[0, 1)`; good enough for jitter.
+fn random_fraction() -> f64 {
+    // Each `RandomState` is seeded differently
+    let bits = RandomState::new().build_hasher().finish() >> 11;
+
+    // 53 bits fit into the mantissa
+    #[allow(clippy::cast_precision_loss)]
+    let fraction = bits as f64 / (1_u64 << 53) as f64;
+
+    fraction
 }
 
 #[derive(Default)]
@@ -234,4 +609,6 @@ pub enum FetchResult {
     #[default]
     Ok,
     CursorTooOld,
+    /// The requested user does not exist or is restricted.
+    NotFound,
 }
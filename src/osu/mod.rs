@@ -1,8 +1,10 @@
 mod authorization;
 mod client;
 mod scores;
+mod source;
 
 pub use self::{
     client::{FetchResult, Osu},
-    scores::{Deserializer as ScoresDeserializer, Score, Scores},
+    scores::{unix_millis, Deserializer as ScoresDeserializer, Score, Scores, RULESETS},
+    source::ScoreSource,
 };
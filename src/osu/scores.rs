@@ -1,12 +1,24 @@
 use bytes::Bytes;
 use eyre::{Context as _, ContextCompat, Result};
 use memchr::memmem;
-use tokio_tungstenite::tungstenite::Message;
 
-use std::{cmp::Ordering, collections::BTreeSet, ops::ControlFlow};
+use std::{
+    cmp::Ordering,
+    collections::BTreeSet,
+    ops::ControlFlow,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::json;
+
+#[cfg(feature = "simd")]
+mod simd;
 
 pub type Scores = BTreeSet<Score>;
 
+/// Names of rulesets, indexed by their id.
+pub const RULESETS: [&str; 4] = ["osu", "taiko", "fruits", "mania"];
+
 /// Deserializes the osu!api response.
 ///
 /// The format is expected to be of the following form:
@@ -44,84 +56,77 @@ impl Deserializer {
             .with_context(|| format!("Failed to deserialize scores; Bytes:\n{:?}", self.bytes))
     }
 
+    /// Deserializes a response that consists of only the array of scores
+    /// such as the one of user scores.
+    pub fn deserialize_array(mut self, scores: &mut Scores) -> Result<()> {
+        self.deserialize_scores(scores)
+            .with_context(|| format!("Failed to deserialize scores; Bytes:\n{:?}", self.bytes))
+    }
+
     fn deserialize_scores(&mut self, scores: &mut Scores) -> Result<()> {
         let start = Self::skip_whitespace_until(&self.bytes[self.idx..], |byte| byte == b'[')
             .context("Failed to skip until opening bracket")?;
 
         self.idx += start + 1;
+        let fetched_at = unix_millis();
+
+        loop {
+            let start = Self::skip_whitespace_until(&self.bytes[self.idx..], |byte| {
+                matches!(byte, b'{' | b']')
+            })
+            .context("Expected opening brace or closing bracket")?;
+
+            self.idx += start;
 
-        match self.bytes[self.idx] {
-            b'{' => {}
-            b']' => {
+            if self.bytes[self.idx] == b']' {
                 self.idx += 1;
 
                 return Ok(());
             }
-            _ => bail!("Expected opening brace or closing bracket"),
-        }
-
-        let mut parentheses = memchr::memchr2_iter(b'{', b'}', &self.bytes[self.idx..]);
-
-        // The first opening brace is already handled. We don't want to skip it
-        // via index offset because all future iterator items would be affected
-        // by that offset too which would make things more complicated.
-        parentheses.next();
-
-        let mut init = 0;
-        let mut prev_depth = 1;
-        let mut prev_idx = init;
-        let mut id = None;
-
-        for i in parentheses {
-            let curr_depth = match self.bytes[self.idx + i] {
-                b'{' => prev_depth + 1,
-                b'}' => prev_depth - 1,
-                _ => unreachable!(),
-            };
 
-            if id.is_none() && prev_depth == 1 {
-                const ID: &[u8] = br#""id":"#;
+            let start = self.idx;
 
-                let slice = &self.bytes[self.idx + prev_idx..self.idx + i];
+            #[cfg(not(feature = "simd"))]
+            let id = self.skip_object()?;
+            #[cfg(feature = "simd")]
+            let id = self.skip_object_simd()?;
+            let bytes = self.bytes.slice(start..self.idx);
 
-                if let Some(id_idx) = memmem::find(slice, ID) {
-                    let n = Self::peek_u64(&slice[id_idx + ID.len()..])
-                        .context("Failed to peek u64")?;
+            let id = id.with_context(|| format!("Missing id within bytes {bytes:?}"))?;
+            scores.insert(Score {
+                bytes,
+                id,
+                fetched_at,
+            });
 
-                    id = Some(n);
-                }
-            }
-
-            match curr_depth {
-                1 => {
-                    if prev_depth == 0 {
-                        init = i;
-                    }
-
-                    prev_idx = i;
-                }
-                0 => {
-                    let bytes = self.bytes.slice(self.idx + init..=self.idx + i);
+            let next = Self::skip_whitespace_until(&self.bytes[self.idx..], |byte| {
+                matches!(byte, b',' | b']')
+            })
+            .context("Expected comma or closing bracket")?;
 
-                    let id = id
-                        .take()
-                        .with_context(|| format!("Missing id within bytes {bytes:?}"))?;
+            self.idx += next;
 
-                    scores.insert(Score { bytes, id });
+            if self.bytes[self.idx] == b']' {
+                self.idx += 1;
 
-                    match self.bytes[self.idx + i + 1] {
-                        b',' => {}
-                        b']' => break,
-                        _ => bail!("Expected comma or closing bracket"),
-                    }
-                }
-                _ => {}
+                return Ok(());
             }
 
-            prev_depth = curr_depth;
+            self.idx += 1;
         }
+    }
 
-        Ok(())
+    /// Advances past the object at the current index and returns the value of
+    /// its top-level `id` field.
+    ///
+    /// Braces and quotes within strings, including escaped ones, are skipped
+    /// so that arbitrary user-provided strings can't mis-split objects.
+    #[cfg_attr(feature = "simd", allow(dead_code))]
+    fn skip_object(&mut self) -> Result<Option<u64>> {
+        let start = self.idx;
+        self.idx = json::value_end(&self.bytes, start).context("Unterminated object")?;
+
+        Ok(json::find_u64_field(&self.bytes[start..self.idx], b"id"))
     }
 
     fn skip_whitespace_until(bytes: &[u8], until: fn(u8) -> bool) -> Result<usize> {
@@ -129,39 +134,48 @@ impl Deserializer {
             .iter()
             .enumerate()
             .try_fold((), |(), (idx, &byte)| match byte {
-                b' ' => ControlFlow::Continue(()),
+                _ if byte.is_ascii_whitespace() => ControlFlow::Continue(()),
                 _ if until(byte) => ControlFlow::Break(Ok(idx)),
                 _ => ControlFlow::Break(Err(eyre!("Unexpected character `{}`", byte as char))),
             })
             .break_value()
             .context("`until` condition never met")?
     }
-
-    fn peek_u64(bytes: &[u8]) -> Result<u64> {
-        let start = Self::skip_whitespace_until(bytes, |byte| byte.is_ascii_digit())
-            .context("Failed to skip until digit")?;
-
-        let n = bytes[start..]
-            .iter()
-            .copied()
-            .take_while(u8::is_ascii_digit)
-            .fold(0, |n, byte| n * 10 + u64::from(byte & 0xF));
-
-        Ok(n)
-    }
 }
 
+#[derive(Clone)]
 #[cfg_attr(test, derive(Debug))]
 pub struct Score {
     bytes: Bytes,
     pub id: u64,
+    /// Unix timestamp in milliseconds of when the score was received; `0` if
+    /// unknown.
+    fetched_at: u64,
 }
 
 impl Score {
+    pub fn new(id: u64, bytes: Bytes) -> Self {
+        Self {
+            bytes,
+            id,
+            fetched_at: unix_millis(),
+        }
+    }
+
+    /// The same score with different bytes, e.g. with fields added.
+    pub const fn with_bytes(&self, bytes: Bytes) -> Self {
+        Self {
+            bytes,
+            id: self.id,
+            fetched_at: self.fetched_at,
+        }
+    }
+
     pub const fn only_id(id: u64) -> Self {
         Self {
             bytes: Bytes::new(),
             id,
+            fetched_at: 0,
         }
     }
 
@@ -169,11 +183,125 @@ impl Score {
         self.id
     }
 
-    pub fn as_message(&self) -> Message {
-        Message::Binary(self.bytes.clone())
+    pub const fn fetched_at(&self) -> u64 {
+        self.fetched_at
+    }
+
+    pub const fn bytes(&self) -> &Bytes {
+        &self.bytes
+    }
+
+    /// Unix timestamp in seconds of the score's top-level `ended_at` field.
+    pub fn ended_at(&self) -> Option<u64> {
+        json::find_str_field(&self.bytes, b"ended_at").and_then(parse_timestamp)
+    }
+
+    /// The score's top-level `user_id` field.
+    pub fn user_id(&self) -> Option<u64> {
+        json::find_u64_field(&self.bytes, b"user_id")
+    }
+
+    /// The score's top-level `beatmap_id` field.
+    pub fn beatmap_id(&self) -> Option<u64> {
+        json::find_u64_field(&self.bytes, b"beatmap_id")
+    }
+
+    /// The `beatmapset_id` field of the score's `beatmap`.
+    pub fn beatmapset_id(&self) -> Option<u64> {
+        let start = json::find_path(&self.bytes, &[b"beatmap".as_slice(), b"beatmapset_id"])?;
+
+        json::u64_value(&self.bytes, start)
+    }
+
+    /// The score's top-level `ruleset_id` field.
+    pub fn ruleset_id(&self) -> Option<u8> {
+        json::find_u64_field(&self.bytes, b"ruleset_id").and_then(|id| u8::try_from(id).ok())
+    }
+
+    /// The score's top-level `legacy_score_id` field; `None` if it's `null` or `0`.
+    ///
+    /// Legacy score ids are only unique per ruleset.
+    pub fn legacy_score_id(&self) -> Option<u64> {
+        json::find_u64_field(&self.bytes, b"legacy_score_id").filter(|&id| id > 0)
+    }
+
+    /// Whether the score's top-level `passed` field is `true`.
+    pub fn passed(&self) -> bool {
+        json::find_bool_field(&self.bytes, b"passed") == Some(true)
+    }
+
+    /// The score's top-level `pp` field; `None` if it's `null`.
+    pub fn pp(&self) -> Option<f64> {
+        json::find_f64_field(&self.bytes, b"pp")
+    }
+
+    /// Creates the score's JSON object containing only the given top-level
+    /// fields.
+    pub fn project(&self, fields: &[Box<str>]) -> Bytes {
+        let mut projected = Vec::with_capacity(self.bytes.len() / 4);
+        projected.push(b'{');
+
+        let entries = json::entries(&self.bytes, 0).unwrap_or_default();
+
+        for (key, entry) in entries {
+            if fields.iter().any(|field| field.as_bytes() == key) {
+                if projected.len() > 1 {
+                    projected.push(b',');
+                }
+
+                projected.extend_from_slice(&self.bytes[entry]);
+            }
+        }
+
+        projected.push(b'}');
+
+        Bytes::from(projected)
     }
 }
 
+/// Parses the leading `YYYY-MM-DDTHH:MM:SS` of an ISO 8601 timestamp into
+/// unix seconds. The osu!api always uses UTC so the offset is ignored.
+fn parse_timestamp(bytes: &[u8]) -> Option<u64> {
+    fn num(bytes: &[u8]) -> Option<u64> {
+        bytes.iter().try_fold(0, |n, &byte| {
+            byte.is_ascii_digit()
+                .then(|| n * 10 + u64::from(byte & 0xF))
+        })
+    }
+
+    let [y0, y1, y2, y3, b'-', m0, m1, b'-', d0, d1, b'T', h0, h1, b':', min0, min1, b':', s0, s1, ..] =
+        *bytes
+    else {
+        return None;
+    };
+
+    let year = num(&[y0, y1, y2, y3])?;
+    let month = num(&[m0, m1])?;
+    let day = num(&[d0, d1])?;
+
+    // Days since 1970-01-01; see <https://howardhinnant.github.io/date_algorithms.html#days_from_civil>
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let month_offset = (month + 9) % 12;
+    let day_of_year = (153 * month_offset + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = (era * 146_097 + day_of_era).checked_sub(719_468)?;
+
+    let secs = num(&[h0, h1])? * 3600 + num(&[min0, min1])? * 60 + num(&[s0, s1])?;
+
+    Some(days * 86_400 + secs)
+}
+
+/// Current unix timestamp in milliseconds.
+pub fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| {
+            duration.as_secs() * 1000 + u64::from(duration.subsec_millis())
+        })
+}
+
 impl PartialEq for Score {
     fn eq(&self, other: &Self) -> bool {
         self.id == other.id
@@ -227,4 +355,160 @@ mod tests {
         );
         assert!(iter.next().is_none());
     }
+
+    #[test]
+    fn deserialize_array() {
+        let mut scores = Scores::new();
+
+        Deserializer::new(br#"[{"id": 2, "user": {"id": 3}}, {"id": 1}]"#.as_slice().into())
+            .deserialize_array(&mut scores)
+            .unwrap();
+
+        let ids: Vec<_> = scores.iter().map(Score::id).collect();
+        assert_eq!(ids, [1, 2]);
+
+        Deserializer::new(b"[]".as_slice().into())
+            .deserialize_array(&mut scores)
+            .unwrap();
+    }
+
+    #[test]
+    fn deserialize_strings() {
+        let mut scores = Scores::new();
+
+        let bytes = br#"{"scores":[
+            {"user":{"username":"{x}}"},"id":1},
+            {"name":"a\"}{\"","id" : 2,"tag":"\u007b\\"},
+            {"text":"\"id\":9","id":3}
+        ]}"#;
+
+        Deserializer::new(bytes.as_slice().into())
+            .deserialize(&mut scores)
+            .unwrap();
+
+        let mut iter = scores.iter();
+
+        assert_eq!(
+            iter.next().unwrap(),
+            (br#"{"user":{"username":"{x}}"},"id":1}"#.as_slice(), 1)
+        );
+        assert_eq!(
+            iter.next().unwrap(),
+            (
+                br#"{"name":"a\"}{\"","id" : 2,"tag":"\u007b\\"}"#.as_slice(),
+                2
+            )
+        );
+        assert_eq!(
+            iter.next().unwrap(),
+            (br#"{"text":"\"id\":9","id":3}"#.as_slice(), 3)
+        );
+        assert!(iter.next().is_none());
+    }
+
+    /// Truncated and randomly mutated payloads must not cause panics and
+    /// only yield whole objects.
+    #[test]
+    fn fuzz() {
+        const ALPHABET: &[u8] = b"{}[]\",:\\ \n0123456789idu";
+
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+
+        let mut rand = |n: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+
+            usize::try_from(state).unwrap() % n
+        };
+
+        let check = |bytes: Vec<u8>| {
+            let mut scores = Scores::new();
+
+            if Deserializer::new(bytes.into())
+                .deserialize(&mut scores)
+                .is_ok()
+            {
+                for score in &scores {
+                    assert!(score.bytes.starts_with(b"{") && score.bytes.ends_with(b"}"));
+                }
+            }
+        };
+
+        for len in 0..SCORES.len() {
+            check(SCORES[..len].to_vec());
+        }
+
+        for _ in 0..10_000 {
+            let mut bytes = SCORES.to_vec();
+
+            for _ in 0..=rand(4) {
+                let idx = rand(bytes.len());
+                let byte = ALPHABET[rand(ALPHABET.len())];
+
+                match rand(3) {
+                    0 => bytes[idx] = byte,
+                    1 => {
+                        bytes.remove(idx);
+                    }
+                    _ => bytes.insert(idx, byte),
+                }
+            }
+
+            check(bytes);
+        }
+    }
+
+    #[test]
+    fn ended_at() {
+        let score = Score {
+            bytes: br#"{"id":1,"ended_at":"2025-01-09T12:34:56Z","user":{"id":2}}"#
+                .as_slice()
+                .into(),
+            id: 1,
+            fetched_at: 0,
+        };
+
+        assert_eq!(score.ended_at(), Some(1_736_426_096));
+        assert_eq!(Score::only_id(1).ended_at(), None);
+    }
+
+    #[test]
+    fn passed() {
+        let score = |bytes: &'static [u8]| Score::new(1, Bytes::from_static(bytes));
+
+        assert!(score(br#"{"id":1,"passed":true,"pp":1.0}"#).passed());
+        assert!(score(br#"{"id":1,"passed": true}"#).passed());
+        assert!(!score(br#"{"id":1,"passed":false}"#).passed());
+        assert!(!score(br#"{"id":1,"name":"\"passed\":true","passed":false}"#).passed());
+        assert!(score(br#"{"id":1,"pp" : 12.5}"#)
+            .pp()
+            .is_some_and(|pp| (pp - 12.5).abs() < f64::EPSILON));
+        assert!(!Score::only_id(1).passed());
+    }
+
+    #[test]
+    fn project() {
+        let score = Score {
+            bytes: br#"{"id": 1, "pp":null,"user":{"id":2,"pp":3}, "mods":[{"acronym":"HD"}], "name":"a\",\"pp\":\"}" , "user_id":2 }"#
+                .as_slice()
+                .into(),
+            id: 1,
+            fetched_at: 0,
+        };
+
+        let fields = [Box::from("id"), Box::from("pp"), Box::from("mods")];
+        assert_eq!(
+            score.project(&fields),
+            br#"{"id": 1,"pp":null,"mods":[{"acronym":"HD"}]}"#.as_slice()
+        );
+
+        let fields = [Box::from("user_id"), Box::from("name")];
+        assert_eq!(
+            score.project(&fields),
+            br#"{"name":"a\",\"pp\":\"}","user_id":2}"#.as_slice()
+        );
+
+        assert_eq!(score.project(&[]), b"{}".as_slice());
+    }
 }
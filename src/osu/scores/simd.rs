@@ -0,0 +1,269 @@
+//! Splits scores by scanning 64 bytes at a time.
+//!
+//! Instead of stopping at every quote and brace like the scalar path, each
+//! block is turned into bitmasks from which escaped characters and string
+//! contents are masked out as described in "Parsing Gigabytes of JSON per
+//! Second" by Langdale and Lemire. Only braces outside of strings and the
+//! starts of strings remain to be visited individually.
+
+use bytes::Bytes;
+use eyre::{ContextCompat, Result};
+
+use crate::json;
+
+use super::Deserializer;
+
+const BLOCK: usize = 64;
+const ODD_BITS: u64 = 0xAAAA_AAAA_AAAA_AAAA;
+
+/// Positions of characters within a block, one bit per byte.
+struct Masks {
+    quote: u64,
+    backslash: u64,
+    open: u64,
+    close: u64,
+}
+
+impl Deserializer {
+    /// Same as [`Deserializer::skip_object`] but scanning whole blocks.
+    pub(super) fn skip_object_simd(&mut self) -> Result<Option<u64>> {
+        let bytes = &self.bytes[..];
+        let mut depth = 0_usize;
+        let mut id_idx = None;
+        let mut pos = self.idx;
+
+        // Whether the block's first character is escaped
+        let mut next_is_escaped = 0;
+        // All ones if the block starts within a string
+        let mut in_string_carry = 0;
+
+        loop {
+            let rest = bytes.get(pos..).filter(|rest| !rest.is_empty());
+            let rest = rest.context("Unterminated object")?;
+
+            let mut block = [b' '; BLOCK];
+            let len = rest.len().min(BLOCK);
+            block[..len].copy_from_slice(&rest[..len]);
+
+            let masks = masks(&block);
+            let escaped = escaped(masks.backslash, &mut next_is_escaped);
+            let quote = masks.quote & !escaped;
+
+            // Bits of opening quotes and string contents
+            let in_string = prefix_xor(quote) ^ in_string_carry;
+            in_string_carry = 0_u64.wrapping_sub(in_string >> 63);
+
+            let open = masks.open & !in_string;
+            let close = masks.close & !in_string;
+            let string_starts = quote & in_string;
+
+            let mut events = open | close | string_starts;
+
+            while events != 0 {
+                let bit = events & events.wrapping_neg();
+                events ^= bit;
+                let i = pos + bit.trailing_zeros() as usize;
+
+                if open & bit != 0 {
+                    depth += 1;
+                } else if close & bit != 0 {
+                    depth -= 1;
+
+                    if depth == 0 {
+                        self.idx = i + 1;
+
+                        return Ok(id_idx.and_then(|idx| json::u64_value(bytes, idx)));
+                    }
+                } else if depth == 1 && id_idx.is_none() {
+                    id_idx = id_value_idx(bytes, i);
+                }
+            }
+
+            pos += BLOCK;
+        }
+    }
+}
+
+impl Deserializer {
+    /// Splits all objects of `bytes` that are separated by single commas
+    /// through either path and returns the end index and id of each.
+    #[cfg(any(test, feature = "bench"))]
+    pub fn split_objects(bytes: Bytes, simd: bool) -> Result<Vec<(usize, Option<u64>)>> {
+        let len = bytes.len();
+        let mut deserializer = Self::new(bytes);
+        let mut objects = Vec::new();
+
+        while deserializer.idx < len {
+            let id = if simd {
+                deserializer.skip_object_simd()?
+            } else {
+                deserializer.skip_object()?
+            };
+
+            objects.push((deserializer.idx, id));
+            deserializer.idx += 1;
+        }
+
+        Ok(objects)
+    }
+}
+
+/// Index after the colon if the string starting at `quote_idx` is the key
+/// `"id"`.
+fn id_value_idx(bytes: &[u8], quote_idx: usize) -> Option<usize> {
+    bytes.get(quote_idx..)?.strip_prefix(br#""id""#)?;
+
+    json::expect(bytes, quote_idx + 4, b':')
+}
+
+/// Characters that are escaped by a backslash; see
+/// <https://github.com/simdjson/simdjson/blob/master/include/simdjson/generic/json_character_block.h>
+fn escaped(backslash: u64, next_is_escaped: &mut u64) -> u64 {
+    if backslash == 0 {
+        return std::mem::take(next_is_escaped);
+    }
+
+    let potential_escape = backslash & !*next_is_escaped;
+    let maybe_escaped = potential_escape << 1;
+    let escape_and_terminal_code =
+        ((maybe_escaped | ODD_BITS).wrapping_sub(potential_escape)) ^ ODD_BITS;
+    let escaped = escape_and_terminal_code ^ (backslash | *next_is_escaped);
+    let escape = escape_and_terminal_code & backslash;
+    *next_is_escaped = escape >> 63;
+
+    escaped
+}
+
+/// Each bit becomes the xor of itself and all lower bits so that the bits
+/// between a pair of quotes are set.
+const fn prefix_xor(mut bits: u64) -> u64 {
+    bits ^= bits << 1;
+    bits ^= bits << 2;
+    bits ^= bits << 4;
+    bits ^= bits << 8;
+    bits ^= bits << 16;
+    bits ^= bits << 32;
+
+    bits
+}
+
+#[cfg(target_arch = "x86_64")]
+fn masks(block: &[u8; BLOCK]) -> Masks {
+    use std::arch::x86_64::{
+        __m128i, _mm_cmpeq_epi8, _mm_loadu_si128, _mm_movemask_epi8, _mm_set1_epi8,
+    };
+
+    // SAFETY: SSE2 is part of the x86_64 baseline and the unaligned loads
+    // stay within the block
+    unsafe {
+        let chunks: [__m128i; 4] =
+            std::array::from_fn(|i| _mm_loadu_si128(block.as_ptr().add(i * 16).cast()));
+
+        let eq = |byte: u8| {
+            let needle = _mm_set1_epi8(byte.cast_signed());
+
+            chunks.iter().enumerate().fold(0, |mask, (i, &chunk)| {
+                let bits = _mm_movemask_epi8(_mm_cmpeq_epi8(chunk, needle)).cast_unsigned();
+
+                mask | u64::from(bits) << (i * 16)
+            })
+        };
+
+        Masks {
+            quote: eq(b'"'),
+            backslash: eq(b'\\'),
+            open: eq(b'{'),
+            close: eq(b'}'),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn masks(block: &[u8; BLOCK]) -> Masks {
+    let eq = |byte: u8| {
+        block
+            .iter()
+            .enumerate()
+            .fold(0, |mask, (i, &b)| mask | u64::from(b == byte) << i)
+    };
+
+    Masks {
+        quote: eq(b'"'),
+        backslash: eq(b'\\'),
+        open: eq(b'{'),
+        close: eq(b'}'),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::score;
+
+    fn assert_matches_scalar(scores: &[String]) {
+        let bytes = Bytes::from(scores.join(","));
+        let simd = Deserializer::split_objects(bytes.clone(), true).unwrap();
+
+        assert_eq!(simd, Deserializer::split_objects(bytes, false).unwrap());
+        assert_eq!(simd.len(), scores.len());
+        assert!(simd.iter().all(|(_, id)| id.is_some()));
+    }
+
+    #[test]
+    fn matches_scalar() {
+        let mut state = 0x9e37_79b9_7f4a_7c15_u64;
+
+        let mut rand = |n: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+
+            usize::try_from(state).unwrap() % n
+        };
+
+        // Names with escapes, braces, and quotes at every offset of a block
+        let names: Vec<String> = (0..500)
+            .map(|_| {
+                const PARTS: &[&str] = &[r#"\""#, r"\\", "{", "}", r"{", "a", r#"\"id\":"#];
+
+                (0..rand(80)).map(|_| PARTS[rand(PARTS.len())]).collect()
+            })
+            .collect();
+
+        let scores: Vec<_> = names
+            .iter()
+            .enumerate()
+            .map(|(id, name)| score(id as u64, name))
+            .collect();
+
+        assert_matches_scalar(&scores);
+    }
+
+    #[test]
+    fn block_edges() {
+        // Escaped quotes and backslashes, and braces within strings
+        const PATTERNS: &[&str] = &[
+            r#"\""#,
+            r"\\",
+            r#"\\\""#,
+            r"\\\\",
+            r#"\"}"#,
+            r#"{\"id\":1}"#,
+            r"}}}",
+        ];
+
+        // Shifts each pattern across the edge between two blocks
+        let scores: Vec<_> = PATTERNS
+            .iter()
+            .flat_map(|pattern| (0..2 * BLOCK).map(move |padding| (pattern, padding)))
+            .enumerate()
+            .map(|(id, (pattern, padding))| {
+                let name = format!("{}{pattern}", "a".repeat(padding));
+
+                score(id as u64, &name)
+            })
+            .collect();
+
+        assert_matches_scalar(&scores);
+    }
+}
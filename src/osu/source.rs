@@ -0,0 +1,144 @@
+use std::{future::Future, time::Duration};
+
+use crate::{config::CatchUpConfig, loops::Health};
+
+use super::{FetchResult, Scores};
+
+/// Where [`Context::fetch_scores`] gets its scores from.
+///
+/// Sources handle retries themselves so that fetching only returns once
+/// scores were received or the cursor turned out to be too old.
+///
+/// [`Context::fetch_scores`]: crate::context::Context::fetch_scores
+pub trait ScoreSource: Send + Sync + 'static {
+    /// Fetches the most recent scores, starting at `cursor_id` if specified.
+    fn fetch(
+        &self,
+        scores: &mut Scores,
+        cursor_id: Option<u64>,
+        health: &Health,
+    ) -> impl Future<Output = FetchResult> + Send;
+
+    /// Amount of pages that [`ScoreSource::fetch_pages`] fetches at once.
+    fn concurrent_pages(&self) -> usize {
+        1
+    }
+
+    /// Minimum id range that a page must cover for the next page to be
+    /// fetched right away; also the step between concurrently fetched pages.
+    fn id_threshold(&self) -> u64 {
+        900
+    }
+
+    /// Delay before the next pages are fetched.
+    fn page_delay(&self) -> Duration {
+        Duration::from_secs(1)
+    }
+
+    /// Pacing of the catch-up phase on startup; no catch-up phase if `None`.
+    fn catch_up(&self) -> Option<&CatchUpConfig> {
+        None
+    }
+
+    /// Whether failed scores are dropped before they're forwarded.
+    fn passed_only(&self) -> bool {
+        false
+    }
+
+    /// Fetches up to [`ScoreSource::concurrent_pages`] pages at once whose
+    /// cursors are `step` ids apart, starting at `cursor_id`.
+    ///
+    /// Pages are only kept as long as each one reaches the next one's cursor
+    /// so that there are no gaps before the newest kept score.
+    fn fetch_pages(
+        &self,
+        scores: &mut Scores,
+        cursor_id: u64,
+        step: u64,
+        health: &Health,
+    ) -> impl Future<Output = FetchResult> + Send {
+        async move {
+            let fetches = (0..self.concurrent_pages() as u64).map(|i| async move {
+                let cursor_id = cursor_id + i * step;
+                let mut page = Scores::new();
+                let res = self.fetch(&mut page, Some(cursor_id), health).await;
+
+                (cursor_id, page, res)
+            });
+
+            let mut covered = cursor_id;
+
+            for (cursor_id, mut page, res) in futures_util::future::join_all(fetches).await {
+                if cursor_id > covered {
+                    debug!(cursor_id, covered, "Discarding pages after a gap");
+
+                    break;
+                }
+
+                if let FetchResult::CursorTooOld = res {
+                    return res;
+                }
+
+                let Some(last) = page.last() else { break };
+
+                covered = covered.max(last.id());
+                scores.append(&mut page);
+            }
+
+            FetchResult::Ok
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{loops::Loops, osu::Score};
+
+    use super::*;
+
+    /// Serves the three ids after the cursor unless they're missing.
+    struct Sparse {
+        missing: &'static [u64],
+    }
+
+    impl ScoreSource for Sparse {
+        async fn fetch(
+            &self,
+            scores: &mut Scores,
+            cursor_id: Option<u64>,
+            _: &Health,
+        ) -> FetchResult {
+            let start = cursor_id.unwrap_or(0) + 1;
+
+            let ids = (start..start + 3).filter(|id| !self.missing.contains(id));
+            scores.extend(ids.map(Score::only_id));
+
+            FetchResult::Ok
+        }
+
+        fn concurrent_pages(&self) -> usize {
+            3
+        }
+    }
+
+    #[tokio::test]
+    async fn discard_pages_after_gap() {
+        let loops = Loops::new();
+        let handle = loops.register(Box::from("test"), Duration::from_secs(1));
+        let ids = |scores: &Scores| scores.iter().map(Score::id).collect::<Vec<_>>();
+
+        let mut scores = Scores::new();
+        let source = Sparse { missing: &[] };
+        source.fetch_pages(&mut scores, 0, 3, handle.health()).await;
+        assert_eq!(ids(&scores), (1..=9).collect::<Vec<_>>());
+
+        // The second page doesn't reach the third page's cursor so scores in
+        // between could be missing
+        let mut scores = Scores::new();
+        let source = Sparse { missing: &[6] };
+        source.fetch_pages(&mut scores, 0, 3, handle.health()).await;
+        assert_eq!(ids(&scores), [1, 2, 3, 4, 5]);
+    }
+}
@@ -0,0 +1,133 @@
+use std::process::Stdio;
+
+use eyre::{ContextCompat, Result, WrapErr};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    process::{Child, ChildStdin, ChildStdout},
+};
+
+use super::PpCalculator;
+use crate::{config::PpConfig, osu::Score};
+
+/// Calculates pp through a long-running external command, e.g. a small
+/// wrapper around rosu-pp.
+///
+/// Each score is written as a single line of JSON to the command's stdin and
+/// for each score the command writes a line to stdout that's either the pp or
+/// `null`. The command is restarted if it fails.
+pub struct Command {
+    config: PpConfig,
+    name: String,
+    process: Option<Process>,
+}
+
+struct Process {
+    _child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+}
+
+impl Command {
+    pub fn new(config: PpConfig) -> Self {
+        let name = config.command.display().to_string();
+
+        Self {
+            config,
+            name,
+            process: None,
+        }
+    }
+
+    fn spawn(&self) -> Result<Process> {
+        let mut child = tokio::process::Command::new(&self.config.command)
+            .args(&self.config.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed to spawn `{}`", self.name))?;
+
+        let stdin = child.stdin.take().context("missing stdin")?;
+        let stdout = child.stdout.take().context("missing stdout")?;
+
+        Ok(Process {
+            _child: child,
+            stdin,
+            stdout: BufReader::new(stdout).lines(),
+        })
+    }
+}
+
+impl Process {
+    async fn calculate(&mut self, scores: &[Score]) -> Result<Vec<Option<f64>>> {
+        let mut input = Vec::new();
+
+        for score in scores {
+            input.extend_from_slice(score.bytes());
+            input.push(b'\n');
+        }
+
+        self.stdin
+            .write_all(&input)
+            .await
+            .context("Failed to write scores")?;
+        self.stdin.flush().await.context("Failed to flush scores")?;
+
+        let mut pps = Vec::with_capacity(scores.len());
+
+        for _ in scores {
+            let line = self
+                .stdout
+                .next_line()
+                .await
+                .context("Failed to read pp")?
+                .context("command exited")?;
+
+            pps.push(parse_pp(&line)?);
+        }
+
+        Ok(pps)
+    }
+}
+
+impl PpCalculator for Command {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn calculate(&mut self, scores: &[Score]) -> Result<Vec<Option<f64>>> {
+        let mut process = match self.process.take() {
+            Some(process) => process,
+            None => self.spawn()?,
+        };
+
+        let pps = process.calculate(scores).await?;
+
+        // Only keep the process if it's in a known state
+        self.process = Some(process);
+
+        Ok(pps)
+    }
+}
+
+fn parse_pp(line: &str) -> Result<Option<f64>> {
+    match line.trim() {
+        "null" | "" => Ok(None),
+        pp => pp
+            .parse()
+            .map(Some)
+            .with_context(|| format!("Invalid pp `{pp}`")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_pp("123.45\n").unwrap(), Some(123.45));
+        assert_eq!(parse_pp("null").unwrap(), None);
+        assert!(parse_pp("lots").is_err());
+    }
+}
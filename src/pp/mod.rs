@@ -0,0 +1,179 @@
+use std::{future::Future, time::Duration};
+
+use bytes::Bytes;
+use eyre::Result;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    json::{self, Entry},
+    osu::{Score, Scores},
+};
+
+#[cfg(feature = "pp")]
+pub use self::command::Command;
+
+#[cfg(feature = "pp")]
+mod command;
+
+/// Amount of batches that may wait for the calculator before scores are
+/// broadcasted without pp.
+const QUEUE_LEN: usize = 4;
+
+/// Calculates pp locally, e.g. through rosu-pp, for scores that the osu!api
+/// sends without, such as scores on loved or qualified beatmaps.
+pub trait PpCalculator: Send + 'static {
+    fn name(&self) -> &str;
+
+    /// Returns the pp of each score in the same order; `None` for scores that
+    /// can't be calculated.
+    fn calculate(
+        &mut self,
+        scores: &[Score],
+    ) -> impl Future<Output = Result<Vec<Option<f64>>>> + Send;
+}
+
+type Request = (Box<[Score]>, oneshot::Sender<Vec<Option<f64>>>);
+
+/// Runs a [`PpCalculator`] on its own task and fills in the pp of scores
+/// before they're broadcasted.
+pub struct PpHook {
+    tx: mpsc::Sender<Request>,
+    timeout: Duration,
+}
+
+impl PpHook {
+    /// Scores are broadcasted without pp if the calculator takes longer than
+    /// `timeout`.
+    pub fn spawn(calculator: impl PpCalculator, timeout: Duration) -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_LEN);
+        info!(calculator = calculator.name(), "Calculating missing pp");
+        tokio::spawn(run(calculator, rx));
+
+        Self { tx, timeout }
+    }
+
+    /// Fills in the pp of all scores from `start` onwards whose pp are
+    /// `null`.
+    pub async fn fill(&self, scores: &mut Scores, start: &Score) {
+        let missing: Box<[Score]> = scores
+            .range(start..)
+            .filter(|score| score.pp().is_none())
+            .cloned()
+            .collect();
+
+        if missing.is_empty() {
+            return;
+        }
+
+        let (tx, rx) = oneshot::channel();
+
+        if self.tx.try_send((missing.clone(), tx)).is_err() {
+            return warn!(count = missing.len(), "pp calculator is lagging behind");
+        }
+
+        let pps = match tokio::time::timeout(self.timeout, rx).await {
+            Ok(Ok(pps)) => pps,
+            Ok(Err(_)) => return,
+            Err(_) => return warn!(count = missing.len(), "Timeout while calculating pp"),
+        };
+
+        for (score, pp) in missing.iter().zip(pps) {
+            if let Some(score) = pp.and_then(|pp| set_pp(score, pp)) {
+                scores.replace(score);
+            }
+        }
+    }
+}
+
+async fn run(mut calculator: impl PpCalculator, mut rx: mpsc::Receiver<Request>) {
+    while let Some((scores, tx)) = rx.recv().await {
+        match calculator.calculate(&scores).await {
+            Ok(pps) if pps.len() == scores.len() => {
+                let _ = tx.send(pps);
+            }
+            Ok(pps) => warn!(
+                calculator = calculator.name(),
+                expected = scores.len(),
+                received = pps.len(),
+                "pp calculator returned the wrong amount of values"
+            ),
+            Err(err) => warn!(
+                ?err,
+                calculator = calculator.name(),
+                "Failed to calculate pp"
+            ),
+        }
+    }
+}
+
+/// Replaces the score's top-level `pp`, or adds it if it's missing.
+fn set_pp(score: &Score, pp: f64) -> Option<Score> {
+    if !pp.is_finite() {
+        return None;
+    }
+
+    let bytes = score.bytes();
+
+    let (start, end) = match json::find_entry(bytes, 0, b"pp")? {
+        Entry::Found(i) => {
+            let start = json::skip_ws(bytes, i);
+
+            (start, json::value_end(bytes, start)?)
+        }
+        Entry::Missing => {
+            let end = bytes.trim_ascii_end().len().checked_sub(1)?;
+
+            (end, end)
+        }
+    };
+
+    let mut value = format_pp(pp);
+
+    if start == end {
+        value.insert_str(0, r#","pp":"#);
+    }
+
+    let mut replaced = Vec::with_capacity(bytes.len() + value.len());
+    replaced.extend_from_slice(&bytes[..start]);
+    replaced.extend_from_slice(value.as_bytes());
+    replaced.extend_from_slice(&bytes[end..]);
+
+    Some(score.with_bytes(Bytes::from(replaced)))
+}
+
+/// Formats pp like the osu!api with at most three decimals.
+fn format_pp(pp: f64) -> String {
+    let mut value = format!("{pp:.3}");
+    let trimmed = value.trim_end_matches('0').trim_end_matches('.').len();
+    value.truncate(trimmed);
+
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fill_in() {
+        let score = Score::new(
+            1,
+            Bytes::from_static(br#"{"id":1,"pp":null,"user":{"pp":2}}"#),
+        );
+        let filled = set_pp(&score, 123.456_78).unwrap();
+        assert_eq!(
+            filled.bytes().as_ref(),
+            br#"{"id":1,"pp":123.457,"user":{"pp":2}}"#
+        );
+        assert_eq!(filled.pp(), Some(123.457));
+
+        let score = Score::new(2, Bytes::from_static(br#"{"id":2,"user":{"pp":2}} "#));
+        let filled = set_pp(&score, 50.0).unwrap();
+        assert_eq!(
+            filled.bytes().as_ref(),
+            br#"{"id":2,"user":{"pp":2},"pp":50} "#
+        );
+
+        assert!(set_pp(&score, f64::NAN).is_none());
+    }
+}
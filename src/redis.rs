@@ -0,0 +1,392 @@
+use std::{
+    cmp,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use eyre::{Context as _, ContextCompat, Result};
+use redis::{
+    aio::{ConnectionManager, ConnectionManagerConfig},
+    streams::{StreamMaxlen, StreamReadOptions, StreamReadReply},
+    AsyncCommands, Client, ConnectionInfo, ExistenceCheck, IntoConnectionInfo, Script, SetExpiry,
+    SetOptions,
+};
+
+use crate::{
+    config::RedisConfig,
+    loops::Health,
+    osu::{Score, Scores},
+};
+
+/// Renews the lease only if this instance still holds it.
+const RENEW_LEASE: &str = "if redis.call('GET', KEYS[1]) == ARGV[1] then \
+    return redis.call('PEXPIRE', KEYS[1], ARGV[2]) else return 0 end";
+
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
+/// Must exceed [`READ_BLOCK_MS`] so that blocking reads don't time out.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Maximum amount of entries per read.
+const READ_COUNT: usize = 1000;
+/// How long a read waits for new entries.
+const READ_BLOCK_MS: usize = 5000;
+
+/// A redis stream through which scores are shared between multiple instances.
+///
+/// Each stream entry consists of the fields `id` and `score`, the latter
+/// containing the score's JSON bytes.
+///
+/// With `redis.lease_secs`, publishing instances coordinate through the key
+/// `{stream}:lease` so that only one of them fetches at a time. The holder of
+/// the lease stores its cursor in `{stream}:cursor` for the next holder to
+/// continue from.
+pub struct ScoreStream {
+    config: RedisConfig,
+    /// Reconnects on its own once it's connected.
+    conn: Option<ConnectionManager>,
+    /// Id of the last stream entry that was read.
+    last_entry_id: String,
+    /// Identifies this instance as holder of the lease.
+    instance_id: String,
+    holds_lease: bool,
+    renew_lease: Script,
+}
+
+/// Outcome of [`ScoreStream::lease`].
+pub enum Lease {
+    /// The lease was just acquired; fetching continues from the stored cursor
+    /// if there is one.
+    Acquired {
+        cursor_id: Option<u64>,
+    },
+    Renewed,
+    /// Another instance holds the lease.
+    Standby,
+}
+
+impl ScoreStream {
+    pub fn new(config: RedisConfig) -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.subsec_nanos());
+
+        Self {
+            config,
+            conn: None,
+            last_entry_id: "0-0".to_owned(),
+            instance_id: format!("{}-{nanos}", std::process::id()),
+            holds_lease: false,
+            renew_lease: Script::new(RENEW_LEASE),
+        }
+    }
+
+    pub const fn has_lease(&self) -> bool {
+        self.config.lease_secs.is_some()
+    }
+
+    fn key(&self, suffix: &str) -> String {
+        format!("{}:{suffix}", self.config.stream)
+    }
+
+    /// Acquires or renews the lease on fetching.
+    ///
+    /// If redis can't be reached, the previous state is kept so that fetching
+    /// neither stops nor starts on every hiccup.
+    pub async fn lease(&mut self) -> Lease {
+        match self.try_lease().await {
+            Ok(lease) => lease,
+            Err(err) => {
+                error!(?err, "Failed to update the lease");
+
+                if self.holds_lease {
+                    Lease::Renewed
+                } else {
+                    Lease::Standby
+                }
+            }
+        }
+    }
+
+    async fn try_lease(&mut self) -> Result<Lease> {
+        let lease_ms = self.config.lease_secs.unwrap_or(0) * 1000;
+        let key = self.key("lease");
+        let cursor_key = self.key("cursor");
+        let instance_id = self.instance_id.clone();
+        let holds_lease = self.holds_lease;
+        let renew_lease = self.renew_lease.clone();
+        let conn = self.connection().await?;
+
+        if holds_lease {
+            // Loads the script if redis doesn't know it yet
+            let renewed: i64 = renew_lease
+                .key(&key)
+                .arg(&instance_id)
+                .arg(lease_ms)
+                .invoke_async(conn)
+                .await
+                .context("Failed to renew the lease")?;
+
+            if renewed == 1 {
+                return Ok(Lease::Renewed);
+            }
+
+            warn!("Lost the lease on fetching to another instance");
+            self.holds_lease = false;
+
+            return Ok(Lease::Standby);
+        }
+
+        let options = SetOptions::default()
+            .conditional_set(ExistenceCheck::NX)
+            .with_expiration(SetExpiry::PX(lease_ms));
+
+        // `nil` if the key exists already
+        let acquired: Option<String> = conn
+            .set_options(&key, &instance_id, options)
+            .await
+            .context("Failed to acquire the lease")?;
+
+        if acquired.is_none() {
+            return Ok(Lease::Standby);
+        }
+
+        let cursor_id: Option<u64> = conn
+            .get(cursor_key)
+            .await
+            .context("Failed to get the cursor")?;
+
+        info!(cursor_id, "Acquired the lease on fetching");
+        self.holds_lease = true;
+
+        Ok(Lease::Acquired { cursor_id })
+    }
+
+    /// Stores the cursor for whichever instance holds the lease next.
+    pub async fn store_cursor(&mut self, cursor_id: u64) {
+        let key = self.key("cursor");
+
+        let res = match self.connection().await {
+            Ok(conn) => conn
+                .set::<_, _, ()>(key, cursor_id)
+                .await
+                .map_err(eyre::Report::from),
+            Err(err) => Err(err),
+        };
+
+        if let Err(err) = res {
+            warn!(?err, "Failed to store the cursor in redis");
+        }
+    }
+
+    pub fn label(&self) -> &str {
+        &self.config.label
+    }
+
+    async fn connection(&mut self) -> Result<&mut ConnectionManager> {
+        if self.conn.is_none() {
+            let client =
+                Client::open(connection_info(&self.config)?).context("Invalid redis config")?;
+
+            let config = ConnectionManagerConfig::new()
+                .set_connection_timeout(CONNECTION_TIMEOUT)
+                .set_response_timeout(RESPONSE_TIMEOUT);
+
+            let conn = ConnectionManager::new_with_config(client, config)
+                .await
+                .context("Failed to connect to redis")?;
+
+            info!(addr = self.config.addr.as_ref(), "Connected to redis");
+            self.conn = Some(conn);
+        }
+
+        Ok(self.conn.as_mut().unwrap())
+    }
+
+    /// Adds scores to the stream.
+    ///
+    /// Errors are logged but otherwise ignored so that fetching continues.
+    pub async fn publish<'a>(&mut self, scores: impl Iterator<Item = &'a Score>) {
+        let max_len = StreamMaxlen::Approx(self.config.max_len);
+        let mut pipe = redis::pipe();
+        let mut count = 0;
+
+        for score in scores {
+            let mut id = itoa::Buffer::new();
+            let fields: [(&str, &[u8]); 2] = [
+                ("id", id.format(score.id).as_bytes()),
+                ("score", score.bytes()),
+            ];
+
+            pipe.xadd_maxlen(&*self.config.stream, max_len, "*", &fields)
+                .ignore();
+            count += 1;
+        }
+
+        if count == 0 {
+            return;
+        }
+
+        let res = match self.connection().await {
+            Ok(conn) => pipe
+                .query_async::<()>(conn)
+                .await
+                .map_err(eyre::Report::from),
+            Err(err) => Err(err),
+        };
+
+        match res {
+            Ok(()) => debug!(count, "Published scores to redis"),
+            Err(err) => error!(?err, "Failed to publish scores to redis"),
+        }
+    }
+
+    /// Waits for new stream entries and inserts their scores.
+    ///
+    /// On failure, it retries with a backoff until it succeeds.
+    pub async fn read(&mut self, scores: &mut Scores, health: &Health) {
+        let options = StreamReadOptions::default()
+            .count(READ_COUNT)
+            .block(READ_BLOCK_MS);
+
+        let mut backoff = 2;
+
+        loop {
+            let stream = self.config.stream.clone();
+            let last_entry_id = self.last_entry_id.clone();
+
+            let res = match self.connection().await {
+                Ok(conn) => conn
+                    .xread_options(&[&*stream], &[&last_entry_id], &options)
+                    .await
+                    .map_err(eyre::Report::from),
+                Err(err) => Err(err),
+            };
+
+            match res.and_then(|reply| Self::parse_entries(reply, scores)) {
+                Ok(Some(last_entry_id)) => {
+                    self.last_entry_id = last_entry_id;
+                    health.success();
+
+                    return;
+                }
+                // Timed out without new entries
+                Ok(None) => {
+                    backoff = 2;
+                    health.success();
+                }
+                Err(err) => {
+                    error!(?err, "Failed to read scores from redis");
+
+                    info!("Retrying in {backoff}s...");
+                    health.backoff(backoff);
+                    tokio::time::sleep(Duration::from_secs(backoff)).await;
+                    backoff = cmp::min(120, backoff * 2);
+                }
+            }
+        }
+    }
+
+    /// Inserts the scores of an `XREAD` reply and returns the last entry id.
+    ///
+    /// The reply is `None` if the read timed out.
+    fn parse_entries(
+        reply: Option<StreamReadReply>,
+        scores: &mut Scores,
+    ) -> Result<Option<String>> {
+        let Some(reply) = reply else {
+            return Ok(None);
+        };
+
+        let mut last_entry_id = None;
+
+        for entry in reply.keys.into_iter().flat_map(|stream| stream.ids) {
+            let id: u64 = entry
+                .get("id")
+                .with_context(|| format!("Missing id in entry {}", entry.id))?;
+
+            let bytes: Vec<u8> = entry
+                .get("score")
+                .with_context(|| format!("Missing score in entry {}", entry.id))?;
+
+            scores.insert(Score::new(id, Bytes::from(bytes)));
+            last_entry_id = Some(entry.id);
+        }
+
+        Ok(last_entry_id)
+    }
+}
+
+fn connection_info(config: &RedisConfig) -> Result<ConnectionInfo> {
+    let scheme = if config.tls { "rediss" } else { "redis" };
+
+    let mut info = format!("{scheme}://{}", config.addr)
+        .into_connection_info()
+        .context("`redis.addr` must be of the form `host:port`")?;
+
+    info.redis.password = config.password.as_deref().map(str::to_owned);
+
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use redis::{
+        streams::{StreamId, StreamKey},
+        ConnectionAddr, Value,
+    };
+
+    use super::*;
+
+    #[test]
+    fn info_from_config() {
+        let config: RedisConfig = toml::from_str(
+            r#"
+            addr = "redis.local:6380"
+            password = "abc"
+            tls = true
+            "#,
+        )
+        .unwrap();
+
+        let info = connection_info(&config).unwrap();
+        assert_eq!(info.redis.password.as_deref(), Some("abc"));
+        assert!(matches!(
+            info.addr,
+            ConnectionAddr::TcpTls { ref host, port: 6380, .. } if host == "redis.local"
+        ));
+
+        let config: RedisConfig = toml::from_str(r#"addr = "127.0.0.1:6379""#).unwrap();
+        let info = connection_info(&config).unwrap();
+        assert_eq!(info.redis.password, None);
+        assert!(matches!(info.addr, ConnectionAddr::Tcp(_, 6379)));
+    }
+
+    #[test]
+    fn parse_entries() {
+        let entry = |entry_id: &str, id: &str, score: &[u8]| StreamId {
+            id: entry_id.to_owned(),
+            map: HashMap::from([
+                ("id".to_owned(), Value::BulkString(id.as_bytes().to_vec())),
+                ("score".to_owned(), Value::BulkString(score.to_vec())),
+            ]),
+        };
+
+        let reply = StreamReadReply {
+            keys: vec![StreamKey {
+                key: "scores-ws".to_owned(),
+                ids: vec![
+                    entry("1-0", "1", b"{\"id\":1}"),
+                    entry("2-0", "2", b"{\"id\":2}"),
+                ],
+            }],
+        };
+
+        let mut scores = Scores::new();
+        let last_entry_id = ScoreStream::parse_entries(Some(reply), &mut scores).unwrap();
+        assert_eq!(last_entry_id.as_deref(), Some("2-0"));
+        assert_eq!(scores.len(), 2);
+
+        assert_eq!(ScoreStream::parse_entries(None, &mut scores).unwrap(), None);
+    }
+}
@@ -0,0 +1,102 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::osu::Score;
+
+/// Holds back broadcasted scores for a short window so that clients with the
+/// `ordered` option receive them sorted by id, even if they were fetched out
+/// of order.
+pub struct ReorderBuffer {
+    window: Duration,
+    entries: Mutex<Vec<Entry>>,
+}
+
+struct Entry {
+    received_at: Instant,
+    score: Score,
+}
+
+impl ReorderBuffer {
+    pub const fn new(window: Duration) -> Self {
+        Self {
+            window,
+            entries: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn push<'a>(&self, scores: impl Iterator<Item = &'a Score>) {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+
+        entries.extend(scores.map(|score| Entry {
+            received_at: now,
+            score: score.clone(),
+        }));
+    }
+
+    /// Removes the scores that were held back for the whole window, sorted by
+    /// id.
+    ///
+    /// Scores with a smaller id than a released one are released as well
+    /// since they could no longer be sent in order afterwards.
+    pub fn release(&self, now: Instant) -> Vec<Score> {
+        let mut entries = self.entries.lock().unwrap();
+
+        let max_due_id = entries
+            .iter()
+            .filter(|entry| entry.received_at + self.window <= now)
+            .map(|entry| entry.score.id())
+            .max();
+
+        let Some(max_due_id) = max_due_id else {
+            return Vec::new();
+        };
+
+        let mut released = Vec::new();
+
+        entries.retain(|entry| {
+            let release = entry.score.id() <= max_due_id;
+
+            if release {
+                released.push(entry.score.clone());
+            }
+
+            !release
+        });
+
+        released.sort_unstable_by_key(Score::id);
+
+        released
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn release_sorted() {
+        let window = Duration::from_secs(2);
+        let buffer = ReorderBuffer::new(window);
+
+        buffer.push([3, 1].map(Score::only_id).iter());
+        let now = Instant::now();
+        assert!(buffer.release(now).is_empty());
+
+        buffer
+            .entries
+            .lock()
+            .unwrap()
+            .extend([2, 5].map(|id| Entry {
+                received_at: now + window / 2,
+                score: Score::only_id(id),
+            }));
+
+        let ids = |scores: Vec<Score>| scores.iter().map(Score::id).collect::<Vec<_>>();
+        assert_eq!(ids(buffer.release(now + window)), [1, 2, 3]);
+        assert!(buffer.release(now + window).is_empty());
+        assert_eq!(ids(buffer.release(now + window * 2)), [5]);
+    }
+}
@@ -0,0 +1,137 @@
+use std::{fs, net::IpAddr, path::Path};
+
+use clap::ValueEnum;
+use eyre::{Context as _, Result};
+
+use crate::{
+    auth::Op,
+    config::{AuthConfig, Config},
+};
+
+const COMPOSE: &str = include_str!("../templates/scaffold/docker-compose.yml");
+
+const PYTHON: &[(&str, &str)] = &[
+    (
+        "consumer.py",
+        include_str!("../templates/scaffold/python/consumer.py"),
+    ),
+    (
+        "requirements.txt",
+        include_str!("../templates/scaffold/python/requirements.txt"),
+    ),
+    (
+        "Dockerfile",
+        include_str!("../templates/scaffold/python/Dockerfile"),
+    ),
+];
+
+// Not named `Cargo.toml` since cargo would skip the directory when packaging
+const RUST: &[(&str, &str)] = &[
+    (
+        "Cargo.toml",
+        include_str!("../templates/scaffold/rust/Cargo.toml.in"),
+    ),
+    (
+        "src/main.rs",
+        include_str!("../templates/scaffold/rust/main.rs"),
+    ),
+    (
+        "Dockerfile",
+        include_str!("../templates/scaffold/rust/Dockerfile"),
+    ),
+];
+
+/// Language of the generated consumer.
+#[derive(Copy, Clone, ValueEnum)]
+pub enum Language {
+    Rust,
+    Python,
+}
+
+/// Generates a consumer project and a compose file based on `config.toml`.
+///
+/// Invoked through `scores-ws scaffold <rust|python> [--out <dir>] [--fields <a,b,...>]`.
+pub fn run(language: Language, out: &Path, fields: Option<&str>) -> Result<()> {
+    let files = match language {
+        Language::Rust => RUST,
+        Language::Python => PYTHON,
+    };
+
+    // Resuming relies on score ids
+    if fields.is_some_and(|fields| !fields.split(',').any(|field| field == "id")) {
+        bail!("`--fields` must include `id`");
+    }
+
+    if out.exists() {
+        bail!("`{}` already exists", out.display());
+    }
+
+    let Config { setup, auth, .. } = Config::parse()?;
+
+    if setup.listen.is_some() {
+        bail!("Scaffolding requires `scores-ws` to listen on a port instead of `listen`");
+    }
+
+    let query = fields.map_or_else(String::new, |fields| format!("?fields={fields}"));
+
+    let host = match setup.ip_addr.first() {
+        ip if ip.is_unspecified() => "127.0.0.1".to_owned(),
+        IpAddr::V6(ip) => format!("[{ip}]"),
+        IpAddr::V4(ip) => ip.to_string(),
+    };
+
+    let url = format!("ws://{host}:{}/{query}", setup.port);
+    let compose_url = format!("ws://scores-ws:{}/{query}", setup.port);
+
+    let key_env = if requires_key(auth.as_ref()) {
+        "      SCORES_WS_KEY: \"${SCORES_WS_KEY}\"\n"
+    } else {
+        ""
+    };
+
+    let consumer_dir = out.join("consumer");
+
+    for (path, template) in files {
+        let path = consumer_dir.join(path);
+        let content = template.replace("{{url}}", &url);
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create `{}`", dir.display()))?;
+        }
+
+        fs::write(&path, content)
+            .with_context(|| format!("Failed to write `{}`", path.display()))?;
+    }
+
+    let compose = COMPOSE
+        .replace("{{compose_url}}", &compose_url)
+        .replace("{{key_env}}", key_env);
+
+    let compose_path = out.join("docker-compose.yml");
+
+    fs::write(&compose_path, compose)
+        .with_context(|| format!("Failed to write `{}`", compose_path.display()))?;
+
+    println!("Generated consumer project in `{}`", out.display());
+    println!("Copy the `scores-ws` binary and `config.toml` into it, then run `docker compose up`");
+
+    if !setup.ip_addr.iter().any(|ip| ip.is_unspecified()) {
+        println!(
+            "Note: set `ip_addr = \"0.0.0.0\"` in `config.toml` to be reachable within compose"
+        );
+    }
+
+    if !key_env.is_empty() {
+        println!("Note: connecting requires a key; provide it through `SCORES_WS_KEY`");
+    }
+
+    Ok(())
+}
+
+/// Whether clients need a key to send `"connect"`.
+fn requires_key(auth: Option<&AuthConfig>) -> bool {
+    auth.is_some_and(|auth| {
+        auth.require_key || !auth.anonymous.iter().any(|op| matches!(op, Op::Connect))
+    })
+}
@@ -0,0 +1,194 @@
+use std::{ffi::OsString, path::PathBuf, sync::OnceLock, time::Duration};
+
+use eyre::{Context as _, ContextCompat, Result};
+use tokio::sync::oneshot;
+use windows_service::{
+    define_windows_service,
+    service::{
+        ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+        ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+    },
+    service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle},
+    service_dispatcher,
+    service_manager::{ServiceManager, ServiceManagerAccess},
+};
+
+use crate::cli::ServiceAction;
+
+/// Name of the service that the service manager started.
+static NAME: OnceLock<String> = OnceLock::new();
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// Registers `scores-ws` as a Windows service for machines without another
+/// supervisor.
+///
+/// Invoked through `scores-ws service <install|uninstall> [--name <name>]`.
+/// The service manager itself runs `scores-ws service run`.
+pub fn run(action: ServiceAction, name: String, dir: Option<PathBuf>) -> Result<()> {
+    match action {
+        ServiceAction::Install => install(&name),
+        ServiceAction::Uninstall => uninstall(&name),
+        ServiceAction::Run => {
+            // Services start in the system directory so `install` passes
+            // on the directory that contains `config.toml`
+            if let Some(dir) = dir {
+                std::env::set_current_dir(&dir).with_context(|| {
+                    format!("Failed to change directory to `{}`", dir.display())
+                })?;
+            }
+
+            let _ = NAME.set(name.clone());
+
+            // Blocks until the service stopped
+            service_dispatcher::start(name, ffi_service_main)
+                .context("Failed to start service; `scores-ws service run` is only meant for the service manager")
+        }
+    }
+}
+
+fn install(name: &str) -> Result<()> {
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )
+    .context("Failed to connect to the service manager")?;
+
+    let executable = std::env::current_exe().context("Failed to get executable path")?;
+    let dir = std::env::current_dir().context("Failed to get working directory")?;
+
+    let info = ServiceInfo {
+        name: OsString::from(name),
+        display_name: OsString::from(name),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: executable,
+        launch_arguments: vec![
+            OsString::from("service"),
+            OsString::from("run"),
+            OsString::from("--name"),
+            OsString::from(name),
+            OsString::from("--dir"),
+            dir.into_os_string(),
+        ],
+        dependencies: Vec::new(),
+        account_name: None,
+        account_password: None,
+    };
+
+    let service = manager
+        .create_service(&info, ServiceAccess::CHANGE_CONFIG)
+        .context("Failed to create service")?;
+
+    service
+        .set_description("Fetches osu! scores and forwards them through websockets")
+        .context("Failed to set service description")?;
+
+    println!("Installed service `{name}`; start it through `sc start {name}`");
+
+    Ok(())
+}
+
+fn uninstall(name: &str) -> Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+        .context("Failed to connect to the service manager")?;
+
+    let access = ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE;
+
+    let service = manager
+        .open_service(name, access)
+        .with_context(|| format!("Failed to open service `{name}`"))?;
+
+    let status = service
+        .query_status()
+        .context("Failed to query service status")?;
+
+    if status.current_state != ServiceState::Stopped {
+        service.stop().context("Failed to stop service")?;
+    }
+
+    service.delete().context("Failed to delete service")?;
+    println!("Uninstalled service `{name}`");
+
+    Ok(())
+}
+
+fn service_main(_: Vec<OsString>) {
+    // There is no terminal so errors only show up in the log files of
+    // `[logging]`, if logging was initialized
+    if let Err(err) = serve() {
+        error!(?err, "Service failed");
+    }
+}
+
+fn serve() -> Result<()> {
+    let name = NAME.get().context("Missing service name")?;
+    let (stop_tx, stop_rx) = oneshot::channel();
+    let mut stop_tx = Some(stop_tx);
+
+    let handler = move |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            if let Some(tx) = stop_tx.take() {
+                let _ = tx.send(());
+            }
+
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    };
+
+    let handle = service_control_handler::register(name, handler)
+        .context("Failed to register service control handler")?;
+
+    let accept = ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN;
+    set_status(
+        &handle,
+        ServiceState::Running,
+        accept,
+        ServiceExitCode::Win32(0),
+    )?;
+
+    let stop = async {
+        let _ = stop_rx.await;
+    };
+
+    let res = crate::runtime().and_then(|runtime| runtime.block_on(crate::run(false, stop)));
+
+    let exit_code = if res.is_ok() {
+        ServiceExitCode::Win32(0)
+    } else {
+        ServiceExitCode::ServiceSpecific(1)
+    };
+
+    set_status(
+        &handle,
+        ServiceState::Stopped,
+        ServiceControlAccept::empty(),
+        exit_code,
+    )?;
+
+    res
+}
+
+fn set_status(
+    handle: &ServiceStatusHandle,
+    current_state: ServiceState,
+    controls_accepted: ServiceControlAccept,
+    exit_code: ServiceExitCode,
+) -> Result<()> {
+    let status = ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state,
+        controls_accepted,
+        exit_code,
+        checkpoint: 0,
+        wait_hint: Duration::ZERO,
+        process_id: None,
+    };
+
+    handle
+        .set_service_status(status)
+        .context("Failed to set service status")
+}
@@ -0,0 +1,135 @@
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering::Relaxed},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use crate::{
+    ack::CursorName,
+    client::{Client, Receiver},
+    limiter::ConnectionPermit,
+    listener::Peer,
+};
+
+/// Clients whose connection dropped, kept around for a grace period so that
+/// they can reconnect with their session token and continue with their queue.
+pub struct Sessions {
+    grace: Duration,
+    parked: Mutex<HashMap<Box<str>, Parked>>,
+    next_epoch: AtomicU64,
+}
+
+/// A client that is still registered and queueing messages while it's
+/// disconnected.
+pub struct Parked {
+    pub addr: Peer,
+    pub client: Arc<Client>,
+    pub rx: Receiver,
+    pub ack: Option<CursorName>,
+    pub idle_timeout: Option<Duration>,
+    /// Keeps counting towards the connection limits until the session
+    /// expires or is resumed.
+    _permit: ConnectionPermit,
+    /// Distinguishes repeated parkings of the same session.
+    epoch: u64,
+}
+
+impl Parked {
+    pub const fn new(
+        addr: Peer,
+        client: Arc<Client>,
+        rx: Receiver,
+        ack: Option<CursorName>,
+        idle_timeout: Option<Duration>,
+        permit: ConnectionPermit,
+    ) -> Self {
+        Self {
+            addr,
+            client,
+            rx,
+            ack,
+            idle_timeout,
+            _permit: permit,
+            epoch: 0,
+        }
+    }
+}
+
+impl Sessions {
+    pub fn new(grace: Duration) -> Self {
+        Self {
+            grace,
+            parked: Mutex::new(HashMap::new()),
+            next_epoch: AtomicU64::new(0),
+        }
+    }
+
+    pub const fn grace(&self) -> Duration {
+        self.grace
+    }
+
+    /// Generates a token of 32 hex digits from the operating system's
+    /// secure random number generator so tokens can't be guessed.
+    ///
+    /// Returns `None` if the generator failed.
+    pub fn new_token() -> Option<Box<str>> {
+        let mut bytes = [0; 16];
+
+        if let Err(err) = getrandom::getrandom(&mut bytes) {
+            warn!(%err, "Failed to generate a session token");
+
+            return None;
+        }
+
+        let mut token = String::with_capacity(32);
+
+        for byte in bytes {
+            let _ = write!(token, "{byte:02x}");
+        }
+
+        Some(token.into_boxed_str())
+    }
+
+    /// Stores the client and returns the epoch to pass to [`Sessions::expire`].
+    pub fn park(&self, token: Box<str>, mut parked: Parked) -> u64 {
+        let epoch = self.next_epoch.fetch_add(1, Relaxed);
+        parked.epoch = epoch;
+        self.parked.lock().unwrap().insert(token, parked);
+
+        epoch
+    }
+
+    pub fn resume(&self, token: &str) -> Option<Parked> {
+        self.parked.lock().unwrap().remove(token)
+    }
+
+    /// Removes the client unless it has been resumed since it was parked.
+    pub fn expire(&self, token: &str, epoch: u64) -> Option<Parked> {
+        let mut parked = self.parked.lock().unwrap();
+
+        if parked.get(token)?.epoch != epoch {
+            return None;
+        }
+
+        parked.remove(token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens() {
+        let a = Sessions::new_token().unwrap();
+        let b = Sessions::new_token().unwrap();
+
+        assert_eq!(a.len(), 32);
+        assert!(a.bytes().all(|byte| byte.is_ascii_hexdigit()));
+        assert_ne!(a, b);
+    }
+}
@@ -0,0 +1,324 @@
+use std::{borrow::Cow, fmt::Write, time::Duration};
+
+use bytes::Bytes;
+use eyre::{Context as _, ContextCompat, Result};
+use http_body_util::Full;
+use hyper::{header::CONTENT_LENGTH, Request};
+use serde::Serialize;
+
+use crate::{config::ClickHouseConfig, http::HttpClient, osu::Score};
+
+use super::{excess_rows, Sink};
+
+/// Inserts scores into a `ClickHouse` table through its HTTP interface.
+///
+/// Scores are buffered and inserted once the batch is full or the flush
+/// interval passed since `ClickHouse` prefers few large inserts. Failed
+/// inserts are retried with the next flush until `max_buffered` is reached.
+///
+/// If the table is larger than `max_size_mb`, its oldest scores are deleted
+/// before it is compacted.
+pub struct ClickHouse {
+    config: ClickHouseConfig,
+    client: HttpClient,
+    /// Rows in the `JSONEachRow` format.
+    rows: String,
+    len: usize,
+    table_created: bool,
+}
+
+impl ClickHouse {
+    pub fn new(config: ClickHouseConfig) -> Result<Self> {
+        Ok(Self {
+            config,
+            client: HttpClient::new()?,
+            rows: String::new(),
+            len: 0,
+            table_created: false,
+        })
+    }
+
+    /// Returns the response body on success.
+    async fn query(&self, query: &str, body: String) -> Result<Bytes> {
+        let mut url = format!("{}/?query=", self.config.url.trim_end_matches('/'));
+        percent_encode(&mut url, query);
+
+        let mut req = Request::post(url)
+            .header(CONTENT_LENGTH, body.len())
+            .header("X-ClickHouse-User", self.config.user.as_ref())
+            .header("X-ClickHouse-Database", self.config.database.as_ref());
+
+        if let Some(ref password) = self.config.password {
+            req = req.header("X-ClickHouse-Key", password.as_ref());
+        }
+
+        let req = req
+            .body(Full::from(body))
+            .context("Failed to create ClickHouse request")?;
+
+        let (bytes, status) = self.client.send(req).await?;
+
+        if !status.is_success() {
+            bail!(
+                "ClickHouse responded with {status}: {}",
+                String::from_utf8_lossy(&bytes).trim_end()
+            );
+        }
+
+        Ok(bytes)
+    }
+
+    /// Runs a query that returns a single number.
+    async fn query_u64(&self, query: &str) -> Result<Option<u64>> {
+        let bytes = self.query(query, String::new()).await?;
+
+        let value = std::str::from_utf8(&bytes)
+            .ok()
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(str::parse)
+            .transpose()
+            .ok()
+            .context("Unexpected ClickHouse response")?;
+
+        Ok(value)
+    }
+
+    /// Size of the table's active parts in bytes.
+    async fn size(&self) -> Result<u64> {
+        let query = format!(
+            "SELECT sum(bytes_on_disk) FROM system.parts \
+            WHERE active AND database = currentDatabase() AND table = '{}'",
+            self.config.table
+        );
+
+        let size = self
+            .query_u64(&query)
+            .await
+            .context("Failed to get table size")?;
+
+        Ok(size.unwrap_or(0))
+    }
+
+    /// Deletes the oldest scores while the table is too large and merges
+    /// its parts to free their space.
+    async fn enforce_max_size(&self, max_size: u64) -> Result<()> {
+        let table = &self.config.table;
+        let before = self.size().await?;
+
+        if before <= max_size {
+            return Ok(());
+        }
+
+        let count = self
+            .query_u64(&format!("SELECT count() FROM {table}"))
+            .await
+            .context("Failed to count scores")?
+            .unwrap_or(0);
+
+        let deleted = excess_rows(count, before, max_size);
+        let cutoff = format!("SELECT id FROM {table} ORDER BY id LIMIT 1 OFFSET {deleted}");
+
+        let Some(cutoff) = self.query_u64(&cutoff).await? else {
+            return Ok(());
+        };
+
+        self.query(
+            &format!("DELETE FROM {table} WHERE id < {cutoff}"),
+            String::new(),
+        )
+        .await
+        .context("Failed to delete scores")?;
+
+        // Deleted rows are only masked until their parts are merged
+        self.query(&format!("OPTIMIZE TABLE {table} FINAL"), String::new())
+            .await
+            .context("Failed to optimize table")?;
+
+        let after = self.size().await?;
+
+        info!(
+            deleted,
+            size_mb = after / 1024 / 1024,
+            reclaimed_mb = before.saturating_sub(after) / 1024 / 1024,
+            "Compacted ClickHouse table"
+        );
+
+        Ok(())
+    }
+
+    /// Inserts all buffered rows and clears them on success.
+    async fn insert(&mut self) -> Result<()> {
+        if !self.table_created {
+            self.query(&create_table(&self.config.table), String::new())
+                .await
+                .context("Failed to create table")?;
+
+            self.table_created = true;
+        }
+
+        let query = format!("INSERT INTO {} FORMAT JSONEachRow", self.config.table);
+
+        // The rows stay buffered until the insert succeeded; duplicates of a
+        // partially applied insert are merged away by `ReplacingMergeTree`
+        self.query(&query, self.rows.clone()).await?;
+        debug!(count = self.len, "Inserted scores into ClickHouse");
+
+        self.rows.clear();
+        self.len = 0;
+
+        Ok(())
+    }
+}
+
+impl Sink for ClickHouse {
+    fn name(&self) -> &'static str {
+        "clickhouse"
+    }
+
+    async fn write(&mut self, scores: &[Score]) -> Result<()> {
+        for score in scores {
+            write_row(&mut self.rows, score);
+        }
+
+        self.len += scores.len();
+
+        if self.len >= self.config.batch_size {
+            self.flush().await?;
+        }
+
+        Ok(())
+    }
+
+    fn flush_interval(&self) -> Option<Duration> {
+        Some(Duration::from_secs(self.config.flush_interval_secs))
+    }
+
+    fn buffers(&self) -> bool {
+        true
+    }
+
+    fn buffered(&self) -> usize {
+        self.len
+    }
+
+    /// Only with `max_size_mb` since parts are compacted by background
+    /// merges anyway.
+    fn maintenance_interval(&self) -> Option<Duration> {
+        self.config
+            .max_size_mb
+            .map(|_| Duration::from_secs(self.config.maintenance_interval_secs))
+    }
+
+    async fn maintain(&mut self) -> Result<()> {
+        match self.config.max_size_mb {
+            Some(max_size_mb) => self.enforce_max_size(max_size_mb * 1024 * 1024).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        if self.len == 0 {
+            return Ok(());
+        }
+
+        let res = self.insert().await;
+
+        if res.is_err() && self.len >= self.config.max_buffered {
+            warn!(
+                count = self.len,
+                "ClickHouse buffer reached `max_buffered`; dropping scores"
+            );
+
+            self.rows.clear();
+            self.len = 0;
+        }
+
+        res
+    }
+}
+
+fn create_table(table: &str) -> String {
+    format!(
+        "CREATE TABLE IF NOT EXISTS {table} (\
+            id UInt64, \
+            user_id UInt32, \
+            ruleset_id UInt8, \
+            pp Nullable(Float64), \
+            ended_at DateTime, \
+            data String\
+        ) \
+        ENGINE = ReplacingMergeTree \
+        PARTITION BY toYYYYMM(ended_at) \
+        ORDER BY id"
+    )
+}
+
+/// A line of `JSONEachRow`.
+#[derive(Serialize)]
+struct Row<'a> {
+    id: u64,
+    user_id: u64,
+    ruleset_id: u8,
+    pp: Option<f64>,
+    ended_at: u64,
+    data: Cow<'a, str>,
+}
+
+/// e.g. `{"id":1,"user_id":2,"ruleset_id":0,"pp":null,"ended_at":0,"data":"{\"id\":1}"}`
+fn write_row(rows: &mut String, score: &Score) {
+    let row = Row {
+        id: score.id(),
+        user_id: score.user_id().unwrap_or(0),
+        ruleset_id: score.ruleset_id().unwrap_or(0),
+        pp: score.pp(),
+        ended_at: score.ended_at().unwrap_or(0),
+        data: String::from_utf8_lossy(score.bytes()),
+    };
+
+    // Serializing plain numbers and strings can't fail
+    if let Ok(json) = serde_json::to_string(&row) {
+        rows.push_str(&json);
+        rows.push('\n');
+    }
+}
+
+fn percent_encode(out: &mut String, s: &str) {
+    for byte in s.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~') {
+            out.push(char::from(byte));
+        } else {
+            let _ = write!(out, "%{byte:02X}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+
+    #[test]
+    fn rows() {
+        let score = Score::new(
+            1,
+            Bytes::from_static(
+                br#"{"id":1,"user_id":2,"pp":null,"ended_at":"2025-01-09T12:34:56Z"}"#,
+            ),
+        );
+
+        let mut rows = String::new();
+        write_row(&mut rows, &score);
+
+        assert_eq!(
+            rows,
+            r#"{"id":1,"user_id":2,"ruleset_id":0,"pp":null,"ended_at":1736426096,"data":"{\"id\":1,\"user_id\":2,\"pp\":null,\"ended_at\":\"2025-01-09T12:34:56Z\"}"}
+"#
+        );
+
+        let mut url = String::new();
+        percent_encode(&mut url, "INSERT INTO scores FORMAT JSONEachRow");
+        assert_eq!(url, "INSERT%20INTO%20scores%20FORMAT%20JSONEachRow");
+    }
+}
@@ -0,0 +1,177 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+use bytes::Bytes;
+use eyre::{Context as _, Result};
+
+use crate::{config::DeadLetterConfig, osu::Score};
+
+use super::Sinks;
+
+/// Scores that sinks failed to deliver, appended as lines of
+/// `{"sink":"<name>","score_id":<id>,"score":<score>}` so that they can be
+/// redelivered through `scores-ws redeliver`.
+pub struct DeadLetters {
+    path: PathBuf,
+    pub retries: u32,
+    pub retry_delay: Duration,
+}
+
+impl DeadLetters {
+    pub fn new(config: DeadLetterConfig) -> Self {
+        Self {
+            path: config.path,
+            retries: config.retries,
+            retry_delay: Duration::from_secs(config.retry_delay_secs),
+        }
+    }
+
+    /// Appends the scores; failures are only logged since there's no other
+    /// place left for them.
+    pub fn append<'a>(&self, sink: &str, scores: impl IntoIterator<Item = &'a Score>) {
+        let mut buf = Vec::new();
+        let mut count = 0;
+
+        for score in scores {
+            write_line(&mut buf, sink, score);
+            count += 1;
+        }
+
+        if count == 0 {
+            return;
+        }
+
+        let res = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(&buf));
+
+        match res {
+            Ok(()) => warn!(sink, count, "Wrote undelivered scores to dead-letter file"),
+            Err(err) => error!(
+                ?err,
+                sink,
+                count,
+                path = %self.path.display(),
+                "Failed to write dead-letter file; scores are lost"
+            ),
+        }
+    }
+}
+
+fn write_line(buf: &mut Vec<u8>, sink: &str, score: &Score) {
+    buf.extend_from_slice(br#"{"sink":""#);
+    buf.extend_from_slice(sink.as_bytes());
+    buf.extend_from_slice(br#"","score_id":"#);
+    buf.extend_from_slice(itoa::Buffer::new().format(score.id()).as_bytes());
+    buf.extend_from_slice(br#","score":"#);
+    buf.extend_from_slice(score.bytes());
+    buf.extend_from_slice(b"}\n");
+}
+
+/// Parses a line of the dead-letter file into the sink's name and the score.
+fn parse_line(line: &str) -> Option<(&str, Score)> {
+    let (sink, rest) = line
+        .strip_prefix(r#"{"sink":""#)?
+        .split_once(r#"","score_id":"#)?;
+    let (id, score) = rest.split_once(r#","score":"#)?;
+    let score = score.strip_suffix('}')?;
+
+    Some((
+        sink,
+        Score::new(id.parse().ok()?, Bytes::from(score.to_owned())),
+    ))
+}
+
+/// Hands the scores of the dead-letter file to the sinks of the same name.
+///
+/// The file is moved aside first so that a running `scores-ws` may keep
+/// appending to it meanwhile. Scores that fail again, or whose sink isn't
+/// configured anymore, are appended to the dead-letter file once more.
+pub async fn redeliver(sinks: Sinks) -> Result<()> {
+    let Some(dead_letters) = sinks.dead_letters.clone() else {
+        bail!("Missing dead-letter file");
+    };
+
+    let path = &dead_letters.path;
+    let mut moved = path.clone().into_os_string();
+    moved.push(".redelivering");
+    let moved = PathBuf::from(moved);
+
+    // A previous redelivery may have been interrupted
+    if fs::metadata(&moved).is_err() {
+        match fs::rename(path, &moved) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                info!("Dead-letter file `{}` doesn't exist", path.display());
+
+                return Ok(());
+            }
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to move `{}`", path.display()))
+            }
+        }
+    }
+
+    let content = fs::read_to_string(&moved)
+        .with_context(|| format!("Failed to read `{}`", moved.display()))?;
+
+    let mut batches: Vec<(&str, Vec<Score>)> = Vec::new();
+
+    for (i, line) in content.lines().enumerate() {
+        let Some((sink, score)) = parse_line(line) else {
+            bail!("Invalid line {} in `{}`", i + 1, moved.display());
+        };
+
+        match batches.iter_mut().find(|(name, _)| *name == sink) {
+            Some((_, scores)) => scores.push(score),
+            None => batches.push((sink, vec![score])),
+        }
+    }
+
+    for (sink, scores) in batches {
+        if sinks.redeliver(sink, &scores).await {
+            info!(sink, count = scores.len(), "Redelivering scores");
+        } else {
+            warn!(sink, "Sink is not configured; keeping its scores");
+            dead_letters.append(sink, &scores);
+        }
+    }
+
+    // Waits for all sinks so that failures are written before the file is
+    // gone
+    sinks.shutdown().await;
+
+    fs::remove_file(&moved).with_context(|| format!("Failed to remove `{}`", moved.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines() {
+        let score = Score::new(1, Bytes::from_static(br#"{"id":1,"pp":null}"#));
+
+        let mut buf = Vec::new();
+        write_line(&mut buf, "clickhouse", &score);
+
+        let line = std::str::from_utf8(&buf).unwrap();
+        assert_eq!(
+            line,
+            "{\"sink\":\"clickhouse\",\"score_id\":1,\"score\":{\"id\":1,\"pp\":null}}\n"
+        );
+
+        let (sink, parsed) = parse_line(line.trim_end()).unwrap();
+        assert_eq!(sink, "clickhouse");
+        assert_eq!(parsed.id(), 1);
+        assert_eq!(parsed.bytes(), score.bytes());
+
+        assert!(parse_line(r#"{"sink":"discord","score_id":x,"score":{}}"#).is_none());
+    }
+}
@@ -0,0 +1,180 @@
+use std::{fmt::Write, time::Duration};
+
+use eyre::{Context as _, ContextCompat, Result};
+use http_body_util::Full;
+use hyper::{
+    header::{CONTENT_LENGTH, CONTENT_TYPE},
+    Request, StatusCode,
+};
+
+use crate::{
+    client::Rulesets,
+    config::DiscordConfig,
+    http::HttpClient,
+    json,
+    osu::{Score, RULESETS},
+};
+
+use super::Sink;
+
+/// Discord rejects messages with more characters.
+const MAX_CONTENT_LEN: usize = 2000;
+
+/// Posts messages to a Discord webhook for scores that match its rule.
+pub struct Discord {
+    webhook_url: Box<str>,
+    min_pp: Option<f64>,
+    user_ids: Box<[u64]>,
+    rulesets: Rulesets,
+    client: HttpClient,
+    /// Amount of scores of the last write whose messages were posted.
+    delivered: usize,
+}
+
+impl Discord {
+    pub fn new(config: DiscordConfig) -> Result<Self> {
+        let rulesets = Rulesets::from_names(config.rulesets.iter().map(AsRef::as_ref))
+            .context("Invalid ruleset")?;
+
+        Ok(Self {
+            webhook_url: config.webhook_url,
+            min_pp: config.min_pp,
+            user_ids: config.user_ids,
+            rulesets,
+            client: HttpClient::new()?,
+            delivered: 0,
+        })
+    }
+
+    fn matches(&self, score: &Score) -> bool {
+        self.rulesets.contains(score)
+            && self
+                .min_pp
+                .is_none_or(|min_pp| score.pp().is_some_and(|pp| pp >= min_pp))
+            && (self.user_ids.is_empty()
+                || score
+                    .user_id()
+                    .is_some_and(|user_id| self.user_ids.contains(&user_id)))
+    }
+
+    /// Posts the content, retrying once if Discord asks to slow down.
+    async fn post(&self, content: &str) -> Result<()> {
+        for _ in 0..2 {
+            // `content` consists of numbers and links only so nothing to escape
+            let body = format!(r#"{{"content":"{}"}}"#, content.replace('\n', r"\n"));
+
+            let req = Request::post(self.webhook_url.as_ref())
+                .header(CONTENT_TYPE, "application/json")
+                .header(CONTENT_LENGTH, body.len())
+                .body(Full::from(body))
+                .context("Failed to create webhook request")?;
+
+            let (bytes, status) = self.client.send(req).await?;
+
+            match status {
+                status if status.is_success() => return Ok(()),
+                StatusCode::TOO_MANY_REQUESTS => {
+                    let retry_after = retry_after(&bytes).unwrap_or(1.0).clamp(0.0, 60.0);
+                    tokio::time::sleep(Duration::from_secs_f64(retry_after)).await;
+                }
+                status => bail!("Webhook responded with {status}: {bytes:?}"),
+            }
+        }
+
+        bail!("Webhook is rate limited")
+    }
+}
+
+impl Sink for Discord {
+    fn name(&self) -> &'static str {
+        "discord"
+    }
+
+    async fn write(&mut self, scores: &[Score]) -> Result<()> {
+        self.delivered = 0;
+        let mut content = String::new();
+
+        for (i, score) in scores.iter().enumerate() {
+            if !self.matches(score) {
+                continue;
+            }
+
+            let line = line(score);
+
+            if content.len() + line.len() > MAX_CONTENT_LEN {
+                self.post(&content).await?;
+                content.clear();
+
+                // Retries must not post the same message again
+                self.delivered = i;
+            }
+
+            content.push_str(&line);
+        }
+
+        if !content.is_empty() {
+            self.post(&content).await?;
+        }
+
+        self.delivered = scores.len();
+
+        Ok(())
+    }
+
+    fn delivered(&self) -> usize {
+        self.delivered
+    }
+}
+
+/// Seconds to wait according to the `retry_after` field of a rate limited
+/// response.
+fn retry_after(bytes: &[u8]) -> Option<f64> {
+    json::find_f64_field(bytes, b"retry_after")
+}
+
+/// e.g. `**123.45pp** osu score by <https://osu.ppy.sh/users/2>: https://osu.ppy.sh/scores/1`
+fn line(score: &Score) -> String {
+    let mut line = String::with_capacity(128);
+
+    if let Some(pp) = score.pp() {
+        let _ = write!(line, "**{pp:.2}pp** ");
+    }
+
+    let ruleset = score
+        .ruleset_id()
+        .and_then(|id| RULESETS.get(usize::from(id)))
+        .unwrap_or(&"unknown");
+
+    let _ = write!(line, "{ruleset} score");
+
+    if let Some(user_id) = score.user_id() {
+        let _ = write!(line, " by <https://osu.ppy.sh/users/{user_id}>");
+    }
+
+    let _ = writeln!(line, ": https://osu.ppy.sh/scores/{}", score.id());
+
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+
+    #[test]
+    fn lines() {
+        let score = Score::new(
+            1,
+            Bytes::from_static(br#"{"id":1,"pp":123.456,"ruleset_id":3,"user_id":2}"#),
+        );
+
+        assert_eq!(
+            line(&score),
+            "**123.46pp** mania score by <https://osu.ppy.sh/users/2>: https://osu.ppy.sh/scores/1\n"
+        );
+
+        let body = br#"{"message":"You are being rate limited.","retry_after":0.5,"global":false}"#;
+        assert_eq!(retry_after(body), Some(0.5));
+    }
+}
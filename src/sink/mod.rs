@@ -0,0 +1,478 @@
+use std::{future::Future, sync::Arc, time::Duration};
+
+use eyre::Result;
+use tokio::{
+    sync::mpsc::{self, error::TrySendError},
+    task::JoinHandle,
+    time::{Instant, Interval, MissedTickBehavior},
+};
+
+use crate::osu::Score;
+
+#[cfg(feature = "postgres")]
+pub use self::postgres::Postgres;
+pub use self::{
+    clickhouse::ClickHouse,
+    dead_letter::{redeliver, DeadLetters},
+    discord::Discord,
+    mqtt::Mqtt,
+    ndjson::{read_scores, Ndjson},
+};
+
+mod clickhouse;
+mod dead_letter;
+mod discord;
+mod mqtt;
+mod ndjson;
+#[cfg(feature = "postgres")]
+mod postgres;
+
+/// Amount of batches that a sink may lag behind before batches are dropped.
+const QUEUE_LEN: usize = 64;
+
+/// Destination that receives broadcasted scores besides websocket clients.
+pub trait Sink: Send + 'static {
+    fn name(&self) -> &str;
+
+    /// Handles a batch of scores in the order they were broadcasted.
+    fn write(&mut self, scores: &[Score]) -> impl Future<Output = Result<()>> + Send;
+
+    /// Interval in which [`Sink::flush`] is called for sinks that buffer
+    /// scores.
+    fn flush_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Whether written scores are only delivered once flushed.
+    fn buffers(&self) -> bool {
+        false
+    }
+
+    /// Amount of written scores that are buffered but neither flushed nor
+    /// dropped yet.
+    fn buffered(&self) -> usize {
+        0
+    }
+
+    /// Amount of leading scores of the last failed [`Sink::write`] that were
+    /// delivered nonetheless so that retries skip them.
+    fn delivered(&self) -> usize {
+        0
+    }
+
+    fn flush(&mut self) -> impl Future<Output = Result<()>> + Send {
+        async { Ok(()) }
+    }
+
+    /// Interval in which [`Sink::maintain`] is called for sinks that store
+    /// scores.
+    fn maintenance_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Deletes the oldest scores to stay within the configured size and
+    /// reclaims unused space.
+    fn maintain(&mut self) -> impl Future<Output = Result<()>> + Send {
+        async { Ok(()) }
+    }
+}
+
+/// Hands broadcasted scores to each sink's own task so that slow sinks don't
+/// hold up broadcasting.
+#[derive(Default)]
+pub struct Sinks {
+    senders: Vec<Sender>,
+    /// Receives scores that couldn't be delivered if specified.
+    dead_letters: Option<Arc<DeadLetters>>,
+}
+
+struct Sender {
+    name: Box<str>,
+    tx: mpsc::Sender<Box<[Score]>>,
+    task: JoinHandle<()>,
+}
+
+impl Sinks {
+    pub fn new(dead_letters: Option<DeadLetters>) -> Self {
+        Self {
+            senders: Vec::new(),
+            dead_letters: dead_letters.map(Arc::new),
+        }
+    }
+
+    pub fn spawn(&mut self, sink: impl Sink) {
+        let (tx, rx) = mpsc::channel(QUEUE_LEN);
+        let name: Box<str> = Box::from(sink.name());
+        info!(sink = name.as_ref(), "Forwarding scores to sink");
+        let task = tokio::spawn(run(sink, rx, self.dead_letters.clone()));
+        self.senders.push(Sender { name, tx, task });
+    }
+
+    pub fn send<'a>(&self, scores: impl Iterator<Item = &'a Score>) {
+        if self.senders.is_empty() {
+            return;
+        }
+
+        let batch: Box<[Score]> = scores.cloned().collect();
+
+        if batch.is_empty() {
+            return;
+        }
+
+        for sender in &self.senders {
+            if let Err(TrySendError::Full(_)) = sender.tx.try_send(batch.clone()) {
+                warn!(
+                    sink = sender.name.as_ref(),
+                    count = batch.len(),
+                    "Sink is lagging behind; dropping scores"
+                );
+
+                if let Some(ref dead_letters) = self.dead_letters {
+                    dead_letters.append(&sender.name, &batch);
+                }
+            }
+        }
+    }
+
+    /// Hands the scores to all sinks of the given name without dropping
+    /// any; returns `false` if there is no such sink.
+    pub async fn redeliver(&self, name: &str, scores: &[Score]) -> bool {
+        self.send_all(|sink| sink == name, scores).await
+    }
+
+    /// Hands the scores to the sinks of the given names, or to all sinks if
+    /// there are none, without dropping any.
+    pub async fn replay(&self, names: &[Box<str>], scores: &[Score]) -> Result<()> {
+        if let Some(name) = names
+            .iter()
+            .find(|name| !self.senders.iter().any(|sender| sender.name == **name))
+        {
+            bail!("Sink `{name}` is not configured");
+        }
+
+        let matches = |sink: &str| names.is_empty() || names.iter().any(|name| **name == *sink);
+
+        if !self.send_all(matches, scores).await {
+            bail!("No sink is configured");
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether any sink matched.
+    async fn send_all(&self, matches: impl Fn(&str) -> bool, scores: &[Score]) -> bool {
+        let mut found = false;
+
+        for sender in self.senders.iter().filter(|sender| matches(&sender.name)) {
+            found = true;
+
+            for batch in scores.chunks(1000) {
+                if sender.tx.send(Box::from(batch)).await.is_err() {
+                    break;
+                }
+            }
+        }
+
+        found
+    }
+
+    /// Waits until all sinks handled their queued scores and flushed.
+    pub async fn shutdown(self) {
+        for sender in self.senders {
+            drop(sender.tx);
+
+            if let Err(err) = sender.task.await {
+                warn!(?err, sink = sender.name.as_ref(), "Sink task failed");
+            }
+        }
+    }
+}
+
+async fn run(
+    mut sink: impl Sink,
+    mut rx: mpsc::Receiver<Box<[Score]>>,
+    dead_letters: Option<Arc<DeadLetters>>,
+) {
+    let mut interval = sink.flush_interval().map(delayed_interval);
+    let mut maintenance = sink.maintenance_interval().map(delayed_interval);
+
+    let mut delivery = Delivery {
+        dead_letters,
+        pending: Vec::new(),
+    };
+
+    loop {
+        tokio::select! {
+            scores = rx.recv() => match scores {
+                Some(scores) => delivery.write(&mut sink, &scores).await,
+                None => break,
+            },
+            () = tick(interval.as_mut()) => delivery.flush(&mut sink).await,
+            () = tick(maintenance.as_mut()) => {
+                if let Err(err) = sink.maintain().await {
+                    warn!(?err, sink = sink.name(), "Failed to maintain sink");
+                }
+            }
+        }
+    }
+
+    delivery.flush(&mut sink).await;
+}
+
+/// Retries failed writes and hands scores that still couldn't be delivered
+/// to the dead-letter file.
+struct Delivery {
+    dead_letters: Option<Arc<DeadLetters>>,
+    /// Scores that a buffering sink didn't flush yet; only tracked with
+    /// dead letters.
+    pending: Vec<Score>,
+}
+
+impl Delivery {
+    async fn write(&mut self, sink: &mut impl Sink, scores: &[Score]) {
+        let Some(ref dead_letters) = self.dead_letters else {
+            if let Err(err) = sink.write(scores).await {
+                warn!(?err, sink = sink.name(), "Failed to write scores");
+            }
+
+            return;
+        };
+
+        // Buffering sinks retry failed flushes themselves so only the
+        // scores they dropped are dead-lettered.
+        if sink.buffers() {
+            self.pending.extend_from_slice(scores);
+
+            if let Err(err) = sink.write(scores).await {
+                warn!(?err, sink = sink.name(), "Failed to write scores");
+                self.dead_letter_dropped(sink, dead_letters);
+            }
+
+            // The sink may have flushed while writing so only its remaining
+            // buffer is still pending.
+            self.trim_pending(sink);
+
+            return;
+        }
+
+        let mut attempt = 0;
+        let mut scores = scores;
+
+        while let Err(err) = sink.write(scores).await {
+            scores = &scores[sink.delivered().min(scores.len())..];
+
+            if attempt == dead_letters.retries {
+                warn!(?err, sink = sink.name(), "Failed to write scores");
+                dead_letters.append(sink.name(), scores);
+
+                return;
+            }
+
+            attempt += 1;
+            warn!(
+                ?err,
+                sink = sink.name(),
+                attempt,
+                "Failed to write scores; retrying"
+            );
+            tokio::time::sleep(dead_letters.retry_delay).await;
+        }
+    }
+
+    async fn flush(&mut self, sink: &mut impl Sink) {
+        if let Err(err) = sink.flush().await {
+            warn!(?err, sink = sink.name(), "Failed to flush scores");
+
+            if let Some(ref dead_letters) = self.dead_letters {
+                self.dead_letter_dropped(sink, dead_letters);
+            }
+        }
+
+        self.trim_pending(sink);
+    }
+
+    /// Dead-letters the pending scores that the sink no longer buffers after
+    /// a failure.
+    fn dead_letter_dropped(&self, sink: &impl Sink, dead_letters: &DeadLetters) {
+        let dropped = self.pending.len().saturating_sub(sink.buffered());
+
+        if dropped > 0 {
+            dead_letters.append(sink.name(), &self.pending[..dropped]);
+        }
+    }
+
+    /// Keeps only the pending scores that the sink still buffers.
+    fn trim_pending(&mut self, sink: &impl Sink) {
+        let done = self.pending.len().saturating_sub(sink.buffered());
+        self.pending.drain(..done);
+    }
+}
+
+/// Interval whose first tick is after one period.
+fn delayed_interval(period: Duration) -> Interval {
+    let mut interval = tokio::time::interval_at(Instant::now() + period, period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    interval
+}
+
+/// Amount of the oldest rows to delete so that a table of `size` bytes
+/// shrinks to `max_size` bytes, assuming all rows take up the same space.
+fn excess_rows(rows: u64, size: u64, max_size: u64) -> u64 {
+    if size <= max_size {
+        return 0;
+    }
+
+    let excess = (u128::from(rows) * u128::from(size - max_size)).div_ceil(u128::from(size));
+
+    u64::try_from(excess).unwrap_or(rows)
+}
+
+/// Sinks without interval never tick.
+async fn tick(interval: Option<&mut Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::config::DeadLetterConfig;
+
+    use super::*;
+
+    /// Flushes once two scores are buffered unless it fails.
+    #[derive(Default)]
+    struct Batching {
+        buffered: usize,
+        fail: bool,
+    }
+
+    impl Sink for Batching {
+        fn name(&self) -> &'static str {
+            "batching"
+        }
+
+        async fn write(&mut self, scores: &[Score]) -> Result<()> {
+            for _ in scores {
+                self.buffered += 1;
+
+                if self.buffered >= 2 {
+                    if self.fail {
+                        bail!("failed");
+                    }
+
+                    self.buffered = 0;
+                }
+            }
+
+            Ok(())
+        }
+
+        fn buffers(&self) -> bool {
+            true
+        }
+
+        fn buffered(&self) -> usize {
+            self.buffered
+        }
+    }
+
+    /// Delivers one score per write and fails every other write.
+    #[derive(Default)]
+    struct Partial {
+        received: Vec<u64>,
+        delivered: usize,
+    }
+
+    impl Sink for Partial {
+        fn name(&self) -> &'static str {
+            "partial"
+        }
+
+        async fn write(&mut self, scores: &[Score]) -> Result<()> {
+            self.delivered = 0;
+
+            for score in scores {
+                if self.delivered == 1 {
+                    bail!("failed");
+                }
+
+                self.received.push(score.id());
+                self.delivered += 1;
+            }
+
+            Ok(())
+        }
+
+        fn delivered(&self) -> usize {
+            self.delivered
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_undelivered_scores() {
+        let dead_letters = DeadLetters::new(DeadLetterConfig {
+            path: "./dead_letters_test.ndjson".into(),
+            retries: 2,
+            retry_delay_secs: 0,
+        });
+
+        let mut delivery = Delivery {
+            dead_letters: Some(Arc::new(dead_letters)),
+            pending: Vec::new(),
+        };
+
+        let mut sink = Partial::default();
+        let score = |id| Score::new(id, Bytes::from_static(b"{}"));
+
+        delivery
+            .write(&mut sink, &[score(1), score(2), score(3)])
+            .await;
+
+        assert_eq!(sink.received, [1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn trims_pending_on_inner_flush() {
+        let dead_letters = DeadLetters::new(DeadLetterConfig {
+            path: "./dead_letters_test.ndjson".into(),
+            retries: 0,
+            retry_delay_secs: 0,
+        });
+
+        let mut delivery = Delivery {
+            dead_letters: Some(Arc::new(dead_letters)),
+            pending: Vec::new(),
+        };
+
+        let mut sink = Batching::default();
+        let score = |id| Score::new(id, Bytes::from_static(b"{}"));
+
+        delivery.write(&mut sink, &[score(1)]).await;
+        assert_eq!(delivery.pending.len(), 1);
+
+        delivery.write(&mut sink, &[score(2), score(3)]).await;
+        assert_eq!(delivery.pending.len(), 1);
+        assert_eq!(delivery.pending[0].id(), 3);
+
+        // Scores that the sink keeps for retrying stay pending
+        sink.fail = true;
+        delivery.write(&mut sink, &[score(4)]).await;
+        assert_eq!(delivery.pending.len(), 2);
+    }
+
+    #[test]
+    fn excess() {
+        assert_eq!(excess_rows(1000, 100, 200), 0);
+        assert_eq!(excess_rows(1000, 200, 200), 0);
+        assert_eq!(excess_rows(1000, 400, 300), 250);
+        assert_eq!(excess_rows(3, 300, 200), 1);
+        assert_eq!(excess_rows(u64::MAX, u64::MAX, 0), u64::MAX);
+    }
+}
@@ -0,0 +1,165 @@
+use std::time::Duration;
+
+use eyre::{Context as _, ContextCompat, Result};
+use rumqttc::{
+    AsyncClient, ConnectionError, Event, EventLoop, MqttOptions, Packet, QoS, Transport,
+};
+
+use crate::{
+    config::MqttConfig,
+    osu::{Score, RULESETS},
+};
+
+use super::Sink;
+
+/// Amount of publishes that may queue up for the event loop.
+const REQUEST_CAP: usize = 1024;
+/// Publishing a batch fails if the event loop doesn't catch up within this
+/// time, e.g. while the broker is unreachable.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Publishes scores to an MQTT broker with `QoS` 0.
+///
+/// Each score is published to `{prefix}/{ruleset}` and, if enabled, to
+/// `{prefix}/users/{user_id}`.
+pub struct Mqtt {
+    config: MqttConfig,
+    client: Option<AsyncClient>,
+}
+
+impl Mqtt {
+    pub const fn new(config: MqttConfig) -> Self {
+        Self {
+            config,
+            client: None,
+        }
+    }
+
+    /// Creates the client on first use and spawns its event loop which
+    /// connects, keeps the connection alive, and reconnects.
+    fn client(&mut self) -> Result<&AsyncClient> {
+        if self.client.is_none() {
+            let (client, eventloop) = AsyncClient::new(options(&self.config)?, REQUEST_CAP);
+            tokio::spawn(poll(eventloop, self.config.addr.clone()));
+            self.client = Some(client);
+        }
+
+        Ok(self.client.as_ref().unwrap())
+    }
+
+    async fn publish(&mut self, scores: &[Score]) -> Result<()> {
+        let prefix = self.config.topic_prefix.clone();
+        let user_topics = self.config.user_topics;
+        let client = self.client()?;
+
+        for score in scores {
+            let ruleset = score
+                .ruleset_id()
+                .and_then(|id| RULESETS.get(usize::from(id)))
+                .unwrap_or(&"unknown");
+
+            client
+                .publish_bytes(
+                    format!("{prefix}/{ruleset}"),
+                    QoS::AtMostOnce,
+                    false,
+                    score.bytes().clone(),
+                )
+                .await
+                .context("MQTT event loop stopped")?;
+
+            if let Some(user_id) = score.user_id().filter(|_| user_topics) {
+                client
+                    .publish_bytes(
+                        format!("{prefix}/users/{user_id}"),
+                        QoS::AtMostOnce,
+                        false,
+                        score.bytes().clone(),
+                    )
+                    .await
+                    .context("MQTT event loop stopped")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Sink for Mqtt {
+    fn name(&self) -> &'static str {
+        "mqtt"
+    }
+
+    async fn write(&mut self, scores: &[Score]) -> Result<()> {
+        tokio::time::timeout(WRITE_TIMEOUT, self.publish(scores))
+            .await
+            .context("Timed out publishing to MQTT broker")?
+    }
+}
+
+fn options(config: &MqttConfig) -> Result<MqttOptions> {
+    let (host, port) = config
+        .addr
+        .rsplit_once(':')
+        .and_then(|(host, port)| Some((host, port.parse::<u16>().ok()?)))
+        .context("`mqtt.addr` must be of the form `host:port`")?;
+
+    let mut options = MqttOptions::new(config.client_id.as_ref(), host, port);
+    options
+        .set_keep_alive(Duration::from_secs(u64::from(config.keep_alive_secs)))
+        .set_clean_session(true);
+
+    if let Some(ref username) = config.username {
+        let password = config.password.as_deref().unwrap_or_default();
+        options.set_credentials(username.as_ref(), password);
+    }
+
+    if config.tls {
+        options.set_transport(Transport::tls_with_default_config());
+    }
+
+    Ok(options)
+}
+
+/// Drives the connection until the client is dropped.
+async fn poll(mut eventloop: EventLoop, addr: Box<str>) {
+    loop {
+        match eventloop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                info!(addr = addr.as_ref(), "Connected to MQTT broker");
+            }
+            Ok(_) => {}
+            Err(ConnectionError::RequestsDone) => break,
+            Err(err) => {
+                warn!(?err, addr = addr.as_ref(), "MQTT connection failed");
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_from_config() {
+        let config: MqttConfig = toml::from_str(
+            r#"
+            addr = "broker.local:8883"
+            username = "user"
+            "#,
+        )
+        .unwrap();
+
+        let mqtt_options = options(&config).unwrap();
+        assert_eq!(
+            mqtt_options.broker_address(),
+            ("broker.local".to_owned(), 8883)
+        );
+
+        let config: MqttConfig = toml::from_str(r#"addr = "broker.local""#).unwrap();
+        assert!(options(&config).is_err());
+    }
+}
@@ -0,0 +1,234 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use eyre::{Context as _, Result};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::{self, AsyncWriteExt},
+};
+
+use crate::{
+    config::{NdjsonConfig, Rotation},
+    osu::{Score, Scores, ScoresDeserializer},
+};
+
+use super::Sink;
+
+/// Appends each score as a line of JSON to stdout or to a file.
+///
+/// Files are rotated to `{path}.1`, `{path}.2`, ... up to `max_files`, just
+/// like log files.
+pub struct Ndjson {
+    config: NdjsonConfig,
+    file: Option<OpenFile>,
+    buf: Vec<u8>,
+}
+
+struct OpenFile {
+    file: File,
+    size: u64,
+    /// Days since the unix epoch on which the file was opened.
+    day: u64,
+}
+
+impl Ndjson {
+    pub const fn new(config: NdjsonConfig) -> Self {
+        Self {
+            config,
+            file: None,
+            buf: Vec::new(),
+        }
+    }
+
+    fn is_stdout(&self) -> bool {
+        self.config.path == Path::new("-")
+    }
+
+    async fn write_file(&mut self) -> Result<()> {
+        let file = match self.file {
+            Some(ref mut file) if !self.config.should_rotate(file, self.buf.len()) => file,
+            Some(_) => {
+                // Keep writing to the current file rather than losing scores
+                if let Err(err) = self.rotate().await {
+                    warn!(?err, "Failed to rotate NDJSON file");
+                }
+
+                self.file.as_mut().unwrap()
+            }
+            None => self.file.insert(open(&self.config.path).await?),
+        };
+
+        file.file
+            .write_all(&self.buf)
+            .await
+            .context("Failed to write NDJSON file")?;
+
+        file.file
+            .flush()
+            .await
+            .context("Failed to flush NDJSON file")?;
+
+        file.size += self.buf.len() as u64;
+
+        Ok(())
+    }
+
+    async fn rotate(&mut self) -> Result<()> {
+        let path = |n: usize| match n {
+            0 => self.config.path.clone(),
+            n => {
+                let mut path = self.config.path.clone().into_os_string();
+                path.push(format!(".{n}"));
+
+                PathBuf::from(path)
+            }
+        };
+
+        if self.config.max_files == 0 {
+            fs::remove_file(path(0)).await?;
+        } else {
+            for n in (0..self.config.max_files).rev() {
+                if fs::try_exists(path(n)).await? {
+                    fs::rename(path(n), path(n + 1)).await?;
+                }
+            }
+        }
+
+        self.file = Some(open(&path(0)).await?);
+
+        Ok(())
+    }
+}
+
+impl Sink for Ndjson {
+    fn name(&self) -> &'static str {
+        "ndjson"
+    }
+
+    async fn write(&mut self, scores: &[Score]) -> Result<()> {
+        self.buf.clear();
+
+        for score in scores {
+            self.buf.extend_from_slice(score.bytes());
+            self.buf.push(b'\n');
+        }
+
+        if self.is_stdout() {
+            let mut stdout = io::stdout();
+            stdout.write_all(&self.buf).await?;
+
+            return stdout.flush().await.context("Failed to write to stdout");
+        }
+
+        self.write_file().await
+    }
+}
+
+impl NdjsonConfig {
+    fn should_rotate(&self, file: &OpenFile, len: usize) -> bool {
+        match self.rotation {
+            Rotation::Never => false,
+            Rotation::Daily => file.day != today(),
+            Rotation::Size => {
+                file.size > 0 && file.size + len as u64 > self.max_file_mb * 1024 * 1024
+            }
+        }
+    }
+}
+
+async fn open(path: &Path) -> Result<OpenFile> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .with_context(|| format!("Failed to open NDJSON file `{}`", path.display()))?;
+
+    let size = file.metadata().await.map_or(0, |metadata| metadata.len());
+
+    Ok(OpenFile {
+        file,
+        size,
+        day: today(),
+    })
+}
+
+/// Days since the unix epoch.
+fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() / 86_400)
+}
+
+/// Parses the lines of a file as written by [`Ndjson`], e.g. to replay them.
+pub fn read_scores(content: &[u8]) -> Result<Scores> {
+    let mut array = Vec::with_capacity(content.len() + 2);
+    array.push(b'[');
+
+    let lines = content
+        .split(|&byte| byte == b'\n')
+        .map(<[u8]>::trim_ascii)
+        .filter(|line| !line.is_empty());
+
+    for (i, line) in lines.enumerate() {
+        if i > 0 {
+            array.push(b',');
+        }
+
+        array.extend_from_slice(line);
+    }
+
+    array.push(b']');
+
+    let mut scores = Scores::new();
+    ScoresDeserializer::new(Bytes::from(array)).deserialize_array(&mut scores)?;
+
+    Ok(scores)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotate() {
+        let dir = std::env::temp_dir().join("scores-ws-ndjson-test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut sink = Ndjson::new(NdjsonConfig {
+            path: dir.join("scores.ndjson"),
+            rotation: Rotation::Size,
+            max_file_mb: 0,
+            max_files: 1,
+        });
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        for id in 1..=3 {
+            let score = Score::new(id, Bytes::from(format!(r#"{{"id":{id}}}"#)));
+            runtime.block_on(sink.write(&[score])).unwrap();
+        }
+
+        let read = |name| std::fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("scores.ndjson"), "{\"id\":3}\n");
+        assert_eq!(read("scores.ndjson.1"), "{\"id\":2}\n");
+        assert!(!dir.join("scores.ndjson.2").exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn read() {
+        let scores = read_scores(b"{\"id\":2,\"user\":{\"id\":3}}\n\n{\"id\":1}\n").unwrap();
+        let ids: Vec<_> = scores.iter().map(Score::id).collect();
+        assert_eq!(ids, [1, 2]);
+
+        assert!(read_scores(b"{\"user_id\":3}\n").is_err());
+    }
+}
@@ -0,0 +1,340 @@
+use std::{future::Future, sync::Arc, time::Duration};
+
+use eyre::{Context as _, ContextCompat, Result};
+use rustls::{ClientConfig, RootCertStore};
+use tokio_postgres::{Client, NoTls, Statement};
+use tokio_postgres_rustls::MakeRustlsConnect;
+
+use crate::{config::PostgresConfig, osu::Score};
+
+use super::{excess_rows, Sink};
+
+/// Inserts scores into a table with the score's JSON as `data` and a few
+/// indexed columns to query by.
+///
+/// Scores that are already in the table are skipped. The table is vacuumed
+/// regularly and, if it's larger than `max_size_mb`, its oldest scores are
+/// deleted before it is compacted.
+pub struct Postgres {
+    config: PostgresConfig,
+    conn: Option<Connection>,
+}
+
+impl Postgres {
+    pub const fn new(config: PostgresConfig) -> Self {
+        Self { config, conn: None }
+    }
+
+    async fn connection(&mut self) -> Result<&Connection> {
+        if self.conn.is_none() {
+            let conn = Connection::connect(&self.config).await?;
+            info!(addr = self.config.addr.as_ref(), "Connected to postgres");
+            self.conn = Some(conn);
+        }
+
+        Ok(self.conn.as_ref().unwrap())
+    }
+
+    async fn vacuum(&mut self) -> Result<()> {
+        let max_size = self.config.max_size_mb.map(|mb| mb * 1024 * 1024);
+        let conn = self.connection().await?;
+        let before = conn.size().await?;
+
+        let deleted = match max_size {
+            Some(max_size) if before > max_size => {
+                let rows = excess_rows(conn.count().await?, before, max_size);
+
+                conn.delete_oldest(rows).await?
+            }
+            _ => 0,
+        };
+
+        // Only a full vacuum gives space back but it locks and rewrites the
+        // whole table so it's reserved for after deleting
+        let vacuum = if deleted > 0 {
+            "VACUUM FULL"
+        } else {
+            "VACUUM (ANALYZE)"
+        };
+
+        conn.client
+            .batch_execute(&format!("{vacuum} {}", conn.table))
+            .await
+            .context("Failed to vacuum table")?;
+
+        let after = conn.size().await?;
+
+        info!(
+            deleted,
+            size_mb = after / 1024 / 1024,
+            reclaimed_mb = before.saturating_sub(after) / 1024 / 1024,
+            "Vacuumed postgres table"
+        );
+
+        Ok(())
+    }
+}
+
+impl Sink for Postgres {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    async fn write(&mut self, scores: &[Score]) -> Result<()> {
+        let batch_size = self.config.batch_size;
+
+        for batch in scores.chunks(batch_size) {
+            let res = match self.connection().await {
+                Ok(conn) => conn.insert(batch).await,
+                Err(err) => Err(err),
+            };
+
+            if let Err(err) = res {
+                self.conn = None;
+
+                return Err(err);
+            }
+        }
+
+        debug!(count = scores.len(), "Inserted scores into postgres");
+
+        Ok(())
+    }
+
+    fn maintenance_interval(&self) -> Option<Duration> {
+        Some(Duration::from_secs(self.config.maintenance_interval_secs))
+    }
+
+    async fn maintain(&mut self) -> Result<()> {
+        let res = self.vacuum().await;
+
+        if res.is_err() {
+            self.conn = None;
+        }
+
+        res
+    }
+}
+
+struct Connection {
+    client: Client,
+    table: Box<str>,
+    insert: Statement,
+}
+
+impl Connection {
+    async fn connect(config: &PostgresConfig) -> Result<Self> {
+        let (host, port) = config
+            .addr
+            .rsplit_once(':')
+            .and_then(|(host, port)| Some((host, port.parse::<u16>().ok()?)))
+            .context("`postgres.addr` must be of the form `host:port`")?;
+
+        let mut pg_config = tokio_postgres::Config::new();
+
+        pg_config
+            .host(host)
+            .port(port)
+            .user(config.user.as_ref())
+            .dbname(config.database.as_ref());
+
+        if let Some(ref password) = config.password {
+            pg_config.password(password.as_ref());
+        }
+
+        // The connection performs the actual communication and needs to be
+        // polled separately
+        let client = if config.tls {
+            let tls = MakeRustlsConnect::new(tls_config()?);
+            let (client, conn) = pg_config
+                .connect(tls)
+                .await
+                .context("Failed to connect to postgres")?;
+            tokio::spawn(drive(conn));
+
+            client
+        } else {
+            let (client, conn) = pg_config
+                .connect(NoTls)
+                .await
+                .context("Failed to connect to postgres")?;
+            tokio::spawn(drive(conn));
+
+            client
+        };
+
+        client
+            .batch_execute(&create_table(&config.table))
+            .await
+            .context("Failed to create table")?;
+
+        let insert = client
+            .prepare(&insert(&config.table))
+            .await
+            .context("Failed to prepare insert")?;
+
+        Ok(Self {
+            client,
+            table: Box::from(identifier(&config.table)),
+            insert,
+        })
+    }
+
+    async fn insert(&self, scores: &[Score]) -> Result<()> {
+        #[allow(clippy::cast_possible_wrap)]
+        let ids: Vec<i64> = scores.iter().map(|score| score.id() as i64).collect();
+
+        #[allow(clippy::cast_possible_wrap)]
+        let user_ids: Vec<Option<i64>> = scores
+            .iter()
+            .map(|score| score.user_id().map(|id| id as i64))
+            .collect();
+
+        let ruleset_ids: Vec<Option<i16>> = scores
+            .iter()
+            .map(|score| score.ruleset_id().map(i16::from))
+            .collect();
+
+        #[allow(clippy::cast_precision_loss)]
+        let ended_at: Vec<Option<f64>> = scores
+            .iter()
+            .map(|score| score.ended_at().map(|secs| secs as f64))
+            .collect();
+
+        let data: Vec<String> = scores
+            .iter()
+            .map(|score| String::from_utf8_lossy(score.bytes()).into_owned())
+            .collect();
+
+        self.client
+            .execute(
+                &self.insert,
+                &[&ids, &user_ids, &ruleset_ids, &ended_at, &data],
+            )
+            .await
+            .context("Failed to insert scores")?;
+
+        Ok(())
+    }
+
+    /// Size of the table including its indexes in bytes.
+    async fn size(&self) -> Result<u64> {
+        let row = self
+            .client
+            .query_one(
+                "SELECT pg_total_relation_size($1::TEXT::REGCLASS)",
+                &[&self.table.as_ref()],
+            )
+            .await
+            .context("Failed to get table size")?;
+
+        Ok(row.get::<_, i64>(0).unsigned_abs())
+    }
+
+    async fn count(&self) -> Result<u64> {
+        let row = self
+            .client
+            .query_one(&format!("SELECT COUNT(*) FROM {}", self.table), &[])
+            .await
+            .context("Failed to count scores")?;
+
+        Ok(row.get::<_, i64>(0).unsigned_abs())
+    }
+
+    /// Deletes the scores with the lowest ids and returns their amount.
+    async fn delete_oldest(&self, count: u64) -> Result<u64> {
+        let query = format!(
+            "DELETE FROM {table} WHERE id IN (SELECT id FROM {table} ORDER BY id LIMIT $1)",
+            table = self.table
+        );
+
+        let count = i64::try_from(count).unwrap_or(i64::MAX);
+
+        self.client
+            .execute(&query, &[&count])
+            .await
+            .context("Failed to delete scores")
+    }
+}
+
+/// Logs the reason once the connection closes; the sink reconnects on its
+/// next write.
+async fn drive<F>(conn: F)
+where
+    F: Future<Output = Result<(), tokio_postgres::Error>>,
+{
+    if let Err(err) = conn.await {
+        warn!(?err, "Postgres connection closed");
+    }
+}
+
+fn tls_config() -> Result<ClientConfig> {
+    let roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+
+    let config = ClientConfig::builder_with_provider(Arc::new(crate::http::crypto_provider()))
+        .with_safe_default_protocol_versions()
+        .context("Failed to configure tls")?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    Ok(config)
+}
+
+/// Quotes the name so that it's used as is.
+fn identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn create_table(table: &str) -> String {
+    let index = |column: &str| {
+        format!(
+            "CREATE INDEX IF NOT EXISTS {} ON {} ({column});",
+            identifier(&format!("{table}_{column}_idx")),
+            identifier(table),
+        )
+    };
+
+    format!(
+        "CREATE TABLE IF NOT EXISTS {} (\
+            id BIGINT PRIMARY KEY, \
+            user_id BIGINT, \
+            ruleset_id SMALLINT, \
+            ended_at TIMESTAMPTZ, \
+            data JSONB NOT NULL\
+        );{}{}{}",
+        identifier(table),
+        index("user_id"),
+        index("ruleset_id"),
+        index("ended_at"),
+    )
+}
+
+/// A single statement that inserts all scores, passed as one array per column.
+fn insert(table: &str) -> String {
+    format!(
+        "INSERT INTO {} (id, user_id, ruleset_id, ended_at, data) \
+        SELECT id, user_id, ruleset_id, to_timestamp(ended_at), data::JSONB \
+        FROM UNNEST($1::BIGINT[], $2::BIGINT[], $3::SMALLINT[], $4::FLOAT8[], $5::TEXT[]) \
+        AS t (id, user_id, ruleset_id, ended_at, data) \
+        ON CONFLICT (id) DO NOTHING",
+        identifier(table)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoted_table() {
+        assert_eq!(identifier("scores"), "\"scores\"");
+        assert_eq!(identifier("a\"b"), "\"a\"\"b\"");
+
+        assert!(insert("my-scores").starts_with("INSERT INTO \"my-scores\" "));
+        assert!(create_table("my-scores").contains(
+            "CREATE INDEX IF NOT EXISTS \"my-scores_user_id_idx\" ON \"my-scores\" (user_id);"
+        ));
+    }
+}
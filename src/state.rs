@@ -0,0 +1,142 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use tokio::sync::watch;
+
+use crate::loops::Loops;
+
+/// Lifecycle phase of the server.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// Listeners and loops are being set up
+    Starting,
+    /// Loops are running but not all of them succeeded yet
+    Warmup,
+    Serving,
+    /// A running loop is currently backing off after failures
+    Degraded,
+    /// New connections are rejected; either shutting down or on request
+    Draining,
+    Stopped,
+}
+
+impl Phase {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Starting => "starting",
+            Self::Warmup => "warmup",
+            Self::Serving => "serving",
+            Self::Degraded => "degraded",
+            Self::Draining => "draining",
+            Self::Stopped => "stopped",
+        }
+    }
+
+    /// Whether the server should receive traffic.
+    pub const fn is_ready(self) -> bool {
+        matches!(self, Self::Serving | Self::Degraded)
+    }
+
+    /// Whether new connections are accepted.
+    pub const fn accepts_connections(self) -> bool {
+        !matches!(self, Self::Draining | Self::Stopped)
+    }
+
+    const fn can_transition(self, to: Self) -> bool {
+        match (self, to) {
+            (Self::Starting, Self::Warmup)
+            | (Self::Warmup | Self::Degraded, Self::Serving)
+            | (Self::Warmup | Self::Serving, Self::Degraded)
+            | (Self::Draining, Self::Warmup | Self::Stopped) => true,
+            (Self::Stopped, _) => false,
+            (_, to) => matches!(to, Self::Draining),
+        }
+    }
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Holds the current [`Phase`] so that all components agree on it.
+pub struct ServerState {
+    phase: watch::Sender<Phase>,
+}
+
+impl ServerState {
+    pub fn new() -> Self {
+        Self {
+            phase: watch::Sender::new(Phase::Starting),
+        }
+    }
+
+    pub fn phase(&self) -> Phase {
+        *self.phase.borrow()
+    }
+
+    /// Moves to the given phase if the transition is valid; returns whether
+    /// it was.
+    pub fn transition(&self, to: Phase) -> bool {
+        let mut from = to;
+
+        let changed = self.phase.send_if_modified(|phase| {
+            from = *phase;
+
+            if *phase == to || !phase.can_transition(to) {
+                return false;
+            }
+
+            *phase = to;
+
+            true
+        });
+
+        if changed {
+            info!(%from, %to, "Server phase changed");
+        }
+
+        changed
+    }
+
+    /// Moves between warmup, serving, and degraded based on the health of
+    /// running loops.
+    pub fn evaluate(&self, loops: &Loops) {
+        let phase = self.phase();
+
+        if !matches!(phase, Phase::Warmup | Phase::Serving | Phase::Degraded) {
+            return;
+        }
+
+        if loops.any_backing_off() {
+            self.transition(Phase::Degraded);
+        } else if loops.all_succeeded() {
+            self.transition(Phase::Serving);
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        format!(r#"{{"phase":"{}"}}"#, self.phase())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transitions() {
+        let state = ServerState::new();
+
+        assert!(!state.transition(Phase::Serving));
+        assert!(state.transition(Phase::Warmup));
+        assert!(state.transition(Phase::Degraded));
+        assert!(!state.transition(Phase::Warmup));
+        assert!(state.transition(Phase::Serving));
+        assert!(state.transition(Phase::Draining));
+        assert!(!state.transition(Phase::Serving));
+        assert!(state.transition(Phase::Stopped));
+        assert!(!state.transition(Phase::Draining));
+        assert_eq!(state.phase(), Phase::Stopped);
+    }
+}
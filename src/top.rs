@@ -0,0 +1,177 @@
+use std::sync::Mutex;
+
+use crate::osu::Score;
+
+/// The scores with the most pp whose `ended_at` lies within a rolling window,
+/// e.g. the scores of the day.
+pub struct TopScores {
+    size: usize,
+    window_secs: u64,
+    /// Candidates sorted by pp in descending order.
+    ///
+    /// Besides the current top scores, this keeps scores that will move up
+    /// once better but older scores leave the window.
+    candidates: Mutex<Vec<Candidate>>,
+}
+
+struct Candidate {
+    pp: f64,
+    /// Unix timestamp in seconds of the score's `ended_at`.
+    ended_at: u64,
+    score: Score,
+}
+
+impl TopScores {
+    pub const fn new(size: usize, window_secs: u64) -> Self {
+        Self {
+            size,
+            window_secs,
+            candidates: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn track<'a>(&self, scores: impl Iterator<Item = &'a Score>, now: u64) {
+        if self.size == 0 {
+            return;
+        }
+
+        let mut candidates = self.candidates.lock().unwrap();
+        let mut added = false;
+
+        for score in scores {
+            let (Some(pp), Some(ended_at)) = (score.pp(), score.ended_at()) else {
+                continue;
+            };
+
+            if ended_at + self.window_secs <= now {
+                continue;
+            }
+
+            let idx = candidates.partition_point(|candidate| candidate.pp >= pp);
+
+            // Scores below enough better scores that stay in the window for
+            // longer can never move up
+            let better_and_newer = candidates[..idx]
+                .iter()
+                .filter(|candidate| candidate.ended_at >= ended_at)
+                .count();
+
+            if better_and_newer >= self.size {
+                continue;
+            }
+
+            let candidate = Candidate {
+                pp,
+                ended_at,
+                score: score.clone(),
+            };
+
+            candidates.insert(idx, candidate);
+            added = true;
+        }
+
+        if added {
+            self.prune(&mut candidates, now);
+        }
+    }
+
+    /// Removes candidates that left the window or can never move up.
+    fn prune(&self, candidates: &mut Vec<Candidate>, now: u64) {
+        let mut kept: Vec<u64> = Vec::with_capacity(candidates.len());
+
+        candidates.retain(|candidate| {
+            if candidate.ended_at + self.window_secs <= now {
+                return false;
+            }
+
+            let better_and_newer = kept
+                .iter()
+                .filter(|&&ended_at| ended_at >= candidate.ended_at)
+                .count();
+
+            let keep = better_and_newer < self.size;
+
+            if keep {
+                kept.push(candidate.ended_at);
+            }
+
+            keep
+        });
+    }
+
+    /// The current top scores as
+    /// `{"type":"top","window_secs":<secs>,"scores":[<score>,...]}`.
+    pub fn to_json(&self, now: u64) -> String {
+        let candidates = self.candidates.lock().unwrap();
+
+        let mut json = format!(
+            r#"{{"type":"top","window_secs":{},"scores":["#,
+            self.window_secs
+        );
+
+        let top = candidates
+            .iter()
+            .filter(|candidate| candidate.ended_at + self.window_secs > now)
+            .take(self.size);
+
+        for (i, candidate) in top.enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+
+            json.push_str(&String::from_utf8_lossy(candidate.score.bytes()));
+        }
+
+        json.push_str("]}");
+
+        json
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+
+    fn score(id: u64, minute: u64, pp: f64) -> Score {
+        let json = format!(r#"{{"id":{id},"pp":{pp},"ended_at":"2025-01-09T12:{minute:02}:00Z"}}"#);
+
+        Score::new(id, Bytes::from(json))
+    }
+
+    #[test]
+    fn rolling() {
+        // 2025-01-09T12:00:00Z
+        const NOON: u64 = 1_736_424_000;
+
+        let top = TopScores::new(2, 600);
+
+        let scores = [
+            score(1, 0, 500.0),
+            score(2, 1, 400.0),
+            score(3, 2, 100.0),
+            score(4, 3, 300.0),
+            score(5, 4, 200.0),
+        ];
+
+        top.track(scores.iter(), NOON + 300);
+
+        let ids = |json: String| {
+            json.match_indices(r#"{"id":"#)
+                .map(|(idx, _)| json.as_bytes()[idx + 6] - b'0')
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(ids(top.to_json(NOON + 300)), [1, 2]);
+
+        // Score 3 is below two better and newer scores so it's gone for good
+        assert_eq!(top.candidates.lock().unwrap().len(), 4);
+
+        // Scores 1 and 2 leave the window
+        assert_eq!(ids(top.to_json(NOON + 61 + 600)), [4, 5]);
+
+        top.track([score(6, 0, 900.0)].iter(), NOON + 700);
+        assert_eq!(ids(top.to_json(NOON + 700)), [4, 5]);
+    }
+}
@@ -0,0 +1,270 @@
+use std::{
+    collections::VecDeque,
+    fmt::{Debug, Write as _},
+    io,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crossterm::event::{Event as TermEvent, EventStream, KeyCode, KeyEvent, KeyModifiers};
+use futures_util::StreamExt;
+use ratatui::{
+    layout::{Constraint, Layout},
+    style::{Style, Stylize},
+    text::Line,
+    widgets::{Block, List, Paragraph, Row, Table},
+    DefaultTerminal, Frame,
+};
+use tokio::{sync::Notify, task::JoinHandle};
+use tracing::{
+    field::{Field, Visit},
+    Event, Level, Subscriber,
+};
+use tracing_subscriber::{layer::Context as LayerContext, Layer};
+
+use crate::{context::Context, loops::unix_now};
+
+/// Amount of warnings and errors shown on the dashboard.
+const RECENT_ERRORS: usize = 8;
+
+static ERRORS: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// Redraws a status dashboard every second and whenever the terminal is
+/// resized until `q` or `ctrl+c` is pressed.
+///
+/// The terminal is restored once dropped or when panicking.
+pub struct Dashboard {
+    task: JoinHandle<()>,
+    quit: Arc<Notify>,
+}
+
+impl Dashboard {
+    pub fn spawn(ctx: Arc<Context>) -> io::Result<Self> {
+        // Also installs a panic hook that restores the terminal
+        let terminal = ratatui::try_init()?;
+        let quit = Arc::new(Notify::new());
+        let task = tokio::spawn(draw(ctx, terminal, Arc::clone(&quit)));
+
+        Ok(Self { task, quit })
+    }
+
+    /// Resolves once the user asked to quit.
+    pub async fn quit_requested(&self) {
+        self.quit.notified().await;
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        self.task.abort();
+        ratatui::restore();
+    }
+}
+
+async fn draw(ctx: Arc<Context>, mut terminal: DefaultTerminal, quit: Arc<Notify>) {
+    let started_at = Instant::now();
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    let mut events = EventStream::new();
+    let mut last_broadcasted = ctx.broadcasted();
+    let mut per_sec = 0;
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let broadcasted = ctx.broadcasted();
+                per_sec = broadcasted - last_broadcasted;
+                last_broadcasted = broadcasted;
+            }
+            event = events.next() => match event {
+                Some(Ok(TermEvent::Key(key))) if is_quit(key) => {
+                    // Raw mode swallows the signal of ctrl+c
+                    quit.notify_one();
+
+                    return;
+                }
+                // Redrawing adjusts to the new size
+                Some(Ok(TermEvent::Resize(..))) => {}
+                Some(Ok(_)) => continue,
+                Some(Err(err)) => return warn!(?err, "Failed to read terminal events"),
+                None => return,
+            },
+        }
+
+        let res = terminal.draw(|frame| render(frame, &ctx, started_at.elapsed(), per_sec));
+
+        if let Err(err) = res {
+            return warn!(?err, "Failed to draw dashboard");
+        }
+    }
+}
+
+fn is_quit(key: KeyEvent) -> bool {
+    match key.code {
+        KeyCode::Char('q') => true,
+        KeyCode::Char('c') => key.modifiers.contains(KeyModifiers::CONTROL),
+        _ => false,
+    }
+}
+
+fn render(frame: &mut Frame<'_>, ctx: &Context, uptime: Duration, per_sec: u64) {
+    let secs = uptime.as_secs();
+    let (history_len, history_bytes) = ctx.history_size();
+    let (_, queued_bytes) = ctx.memory_usage();
+    let handles = ctx.loops().handles();
+
+    let header = Paragraph::new(vec![
+        Line::from(format!(
+            "scores-ws {} | {} | up {}h {:02}m {:02}s | {per_sec} scores/s",
+            env!("CARGO_PKG_VERSION"),
+            ctx.state().phase(),
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60,
+        )),
+        Line::from(format!(
+            "history: {history_len} scores ({} KiB) | client queues: {} KiB | press q to quit",
+            history_bytes / 1024,
+            queued_bytes / 1024
+        )),
+    ]);
+
+    let loops = handles.iter().map(|handle| {
+        let last_success = match handle.last_success_secs_ago() {
+            Some(secs) => format!("{secs}s ago"),
+            None => "never".to_owned(),
+        };
+
+        Row::new([
+            handle.label().to_owned(),
+            handle.state().to_owned(),
+            last_success,
+        ])
+    });
+
+    let loops = Table::new(
+        loops,
+        [
+            Constraint::Length(24),
+            Constraint::Length(12),
+            Constraint::Fill(1),
+        ],
+    )
+    .header(Row::new(["label", "state", "last success"]).style(Style::new().bold()))
+    .block(Block::bordered().title("Fetch"));
+
+    let mut clients = Vec::new();
+
+    ctx.for_each_client(|addr, client| {
+        clients.push(Row::new([
+            addr.to_string(),
+            client.name().unwrap_or("-").to_owned(),
+            client.sent_scores().to_string(),
+            client.lag().to_string(),
+            client.max_lag().to_string(),
+        ]));
+    });
+
+    let clients = Table::new(
+        clients,
+        [
+            Constraint::Length(24),
+            Constraint::Length(16),
+            Constraint::Length(10),
+            Constraint::Length(8),
+            Constraint::Length(8),
+        ],
+    )
+    .header(Row::new(["addr", "name", "sent", "lag", "max lag"]).style(Style::new().bold()))
+    .block(Block::bordered().title(format!("Clients ({})", ctx.client_count())));
+
+    let errors = List::new(ERRORS.lock().unwrap().iter().cloned())
+        .block(Block::bordered().title("Recent errors"));
+
+    // Loop amounts are tiny
+    #[allow(clippy::cast_possible_truncation)]
+    let [header_area, loops_area, clients_area, errors_area] = Layout::vertical([
+        Constraint::Length(2),
+        Constraint::Length(handles.len() as u16 + 3),
+        Constraint::Min(4),
+        Constraint::Length(RECENT_ERRORS as u16 + 2),
+    ])
+    .areas(frame.area());
+
+    frame.render_widget(header, header_area);
+    frame.render_widget(loops, loops_area);
+    frame.render_widget(clients, clients_area);
+    frame.render_widget(errors, errors_area);
+}
+
+/// Keeps the most recent warnings and errors for the dashboard since logs
+/// aren't printed to stdout while it's shown.
+pub struct RecentErrors;
+
+impl<S: Subscriber> Layer<S> for RecentErrors {
+    fn on_event(&self, event: &Event<'_>, _: LayerContext<'_, S>) {
+        let level = *event.metadata().level();
+
+        if level > Level::WARN {
+            return;
+        }
+
+        let secs = unix_now();
+        let mut line = format!(
+            "{:02}:{:02}:{:02} {level:<5} ",
+            secs / 3600 % 24,
+            secs / 60 % 60,
+            secs % 60
+        );
+
+        event.record(&mut MessageVisitor(&mut line));
+
+        let mut errors = ERRORS.lock().unwrap();
+
+        if errors.len() == RECENT_ERRORS {
+            errors.pop_front();
+        }
+
+        errors.push_back(line);
+    }
+}
+
+/// Writes the message followed by all other fields, each on a single line.
+struct MessageVisitor<'a>(&'a mut String);
+
+impl Visit for MessageVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        let value = format!("{value:?}");
+        let value = value.lines().next().unwrap_or_default();
+
+        if field.name() == "message" {
+            self.0.push_str(value);
+        } else {
+            let _ = write!(self.0, " {}={value}", field.name());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+
+    #[test]
+    fn recent_errors() {
+        let subscriber = tracing_subscriber::registry().with(RecentErrors);
+
+        tracing::subscriber::with_default(subscriber, || {
+            info!("ignored");
+
+            for i in 0..=RECENT_ERRORS {
+                warn!(i, "Failed");
+            }
+        });
+
+        let errors = ERRORS.lock().unwrap();
+        assert_eq!(errors.len(), RECENT_ERRORS);
+        assert!(errors[0].ends_with("WARN  Failed i=1"));
+        assert!(errors.iter().all(|error| !error.contains("ignored")));
+    }
+}
@@ -0,0 +1,180 @@
+use std::{cmp, sync::Arc, time::Duration};
+
+use bytes::Bytes;
+use eyre::{ContextCompat, Result, WrapErr};
+use futures_util::{FutureExt, SinkExt, StreamExt};
+use tokio::{net::TcpStream, sync::mpsc};
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+use crate::{
+    config::UpstreamConfig,
+    json::{self, Entry},
+    loops::{Health, LoopHandle},
+    osu::{Score, Scores},
+};
+
+/// Upper bound for the amount of scores that are read at once before they're
+/// handed on.
+const MAX_BATCH: usize = 1000;
+
+type Connection = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Another `scores-ws` instance whose scores are joined into this one's.
+pub struct Upstream {
+    config: UpstreamConfig,
+    conn: Option<Connection>,
+    /// Id of the newest received score so that a reconnect resumes from it.
+    last_id: Option<u64>,
+}
+
+impl Upstream {
+    pub const fn new(config: UpstreamConfig) -> Self {
+        Self {
+            config,
+            conn: None,
+            last_id: None,
+        }
+    }
+
+    pub fn label(&self) -> &str {
+        &self.config.label
+    }
+
+    /// Reads scores and hands them on until the receiver is gone.
+    pub async fn run(mut self, handle: Arc<LoopHandle>, tx: mpsc::Sender<Scores>) {
+        info!(
+            url = self.config.url.as_ref(),
+            "Joining scores of upstream..."
+        );
+
+        loop {
+            handle.wait_until_running().await;
+
+            let mut scores = Scores::new();
+            self.read(&mut scores, handle.health()).await;
+
+            if scores.is_empty() {
+                continue;
+            }
+
+            if tx.send(scores).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Waits for the next scores and inserts them along with all that are
+    /// ready right away.
+    ///
+    /// On failure, it reconnects with a backoff until it succeeds.
+    async fn read(&mut self, scores: &mut Scores, health: &Health) {
+        let mut backoff = 2;
+
+        loop {
+            match self.try_read(scores).await {
+                Ok(()) => {
+                    health.success();
+
+                    return;
+                }
+                Err(err) => {
+                    error!(?err, label = self.label(), "Failed to read from upstream");
+                    self.conn = None;
+
+                    info!("Reconnecting in {backoff}s...");
+                    health.backoff(backoff);
+                    tokio::time::sleep(Duration::from_secs(backoff)).await;
+                    backoff = cmp::min(120, backoff * 2);
+                }
+            }
+        }
+    }
+
+    async fn try_read(&mut self, scores: &mut Scores) -> Result<()> {
+        let conn = self.connection().await?;
+
+        let msg = conn.next().await.context("connection closed")?;
+        let mut last_id = Self::insert(msg?, scores)?;
+
+        while scores.len() < MAX_BATCH {
+            let Some(next) = conn.next().now_or_never() else {
+                break;
+            };
+
+            let msg = next.context("connection closed")?;
+            last_id = last_id.max(Self::insert(msg?, scores)?);
+        }
+
+        self.last_id = self.last_id.max(last_id);
+
+        Ok(())
+    }
+
+    /// Inserts the message's score, if any, and returns its id.
+    fn insert(msg: Message, scores: &mut Scores) -> Result<Option<u64>> {
+        match msg {
+            // Scores are binary; everything else are events for clients
+            Message::Binary(bytes) => {
+                let score = parse_score(bytes).context("Invalid score")?;
+                let id = score.id();
+                scores.insert(score);
+
+                Ok(Some(id))
+            }
+            Message::Close(frame) => Err(eyre!("upstream closed connection: {frame:?}")),
+            _ => Ok(None),
+        }
+    }
+
+    async fn connection(&mut self) -> Result<&mut Connection> {
+        if self.conn.is_none() {
+            let (mut conn, _) = tokio_tungstenite::connect_async(self.config.url.as_ref())
+                .await
+                .context("Failed to connect")?;
+
+            // Resumes after the last score or starts with the upstream's
+            // history
+            let initial = match self.last_id {
+                Some(id) => id.to_string(),
+                None => "connect".to_owned(),
+            };
+
+            conn.send(Message::text(initial))
+                .await
+                .context("Failed to send initial message")?;
+
+            info!(url = self.config.url.as_ref(), "Connected to upstream");
+            self.conn = Some(conn);
+        }
+
+        Ok(self.conn.as_mut().unwrap())
+    }
+}
+
+/// Returns `None` if the score has no top-level `id`.
+fn parse_score(bytes: Bytes) -> Option<Score> {
+    let Entry::Found(i) = json::find_entry(&bytes, 0, b"id")? else {
+        return None;
+    };
+
+    let id = std::str::from_utf8(json::value(&bytes, i)?)
+        .ok()?
+        .parse()
+        .ok()?;
+
+    Some(Score::new(id, bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let score = parse_score(Bytes::from_static(br#"{"user":{"id":2}, "id" : 123 }"#));
+        assert_eq!(score.map(|score| score.id()), Some(123));
+
+        assert!(parse_score(Bytes::from_static(br#"{"user":{"id":2}}"#)).is_none());
+        assert!(parse_score(Bytes::from_static(br#"{"id":"abc"}"#)).is_none());
+    }
+}
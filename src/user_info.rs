@@ -0,0 +1,217 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+
+use crate::{
+    config::UserInfoConfig,
+    json::{self, Entry},
+    osu::{Osu, Score, Scores, RULESETS},
+};
+
+/// Maximum amount of ids the osu!api's users endpoint accepts per request.
+const USERS_PER_REQUEST: usize = 50;
+
+/// Adds `"user_info":{"username":...,"country_code":...,"global_rank":...}`
+/// to scores with up-to-date info from the osu!api since the user embedded in
+/// scores may lack or have outdated fields.
+///
+/// Users are cached for a while to keep requests down. The global rank is the
+/// one in the score's ruleset.
+pub struct UserInfo {
+    osu: Arc<Osu>,
+    ttl: Duration,
+    requests_per_fetch: usize,
+    cache: Mutex<HashMap<u64, CachedUser>>,
+}
+
+struct CachedUser {
+    /// Raw JSON of the username and country code.
+    username: Box<str>,
+    country_code: Box<str>,
+    /// Raw JSON of the global rank per ruleset.
+    global_ranks: [Box<str>; RULESETS.len()],
+    fetched_at: Instant,
+}
+
+impl UserInfo {
+    pub fn new(osu: Arc<Osu>, config: &UserInfoConfig) -> Self {
+        Self {
+            osu,
+            ttl: Duration::from_secs(config.ttl_secs),
+            requests_per_fetch: config.requests_per_fetch,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Adds user info to all scores from `start` onwards. Scores of users that
+    /// couldn't be fetched are left as they are.
+    pub async fn enrich(&self, scores: &mut Scores, start: &Score) {
+        let now = Instant::now();
+
+        let mut missing: Vec<u64> = {
+            let cache = self.cache.lock().unwrap();
+
+            scores
+                .range(start..)
+                .filter_map(Score::user_id)
+                .filter(|user_id| {
+                    cache
+                        .get(user_id)
+                        .is_none_or(|user| now.duration_since(user.fetched_at) >= self.ttl)
+                })
+                .collect()
+        };
+
+        missing.sort_unstable();
+        missing.dedup();
+
+        let chunks = missing
+            .chunks(USERS_PER_REQUEST)
+            .take(self.requests_per_fetch);
+
+        for user_ids in chunks {
+            match self.osu.fetch_users(user_ids).await {
+                Ok(bytes) => self.cache_users(&bytes, now),
+                Err(err) => warn!(?err, "Failed to fetch user info"),
+            }
+        }
+
+        let mut cache = self.cache.lock().unwrap();
+        cache.retain(|_, user| now.duration_since(user.fetched_at) < self.ttl);
+
+        let enriched: Vec<Score> = scores
+            .range(start..)
+            .filter_map(|score| {
+                let user = cache.get(&score.user_id()?)?;
+
+                add_user_info(score, user)
+            })
+            .collect();
+
+        drop(cache);
+
+        for score in enriched {
+            scores.replace(score);
+        }
+    }
+
+    fn cache_users(&self, bytes: &[u8], now: Instant) {
+        let Some(users) = parse_users(bytes, now) else {
+            return warn!("Failed to parse users response");
+        };
+
+        self.cache.lock().unwrap().extend(users);
+    }
+}
+
+/// Parses `{"users":[...]}` into each user's id and info.
+fn parse_users(bytes: &[u8], now: Instant) -> Option<Vec<(u64, CachedUser)>> {
+    let Entry::Found(start) = json::find_entry(bytes, 0, b"users")? else {
+        return None;
+    };
+
+    let mut users = Vec::new();
+
+    for i in json::elements(bytes, start)? {
+        let field = |key: &[u8]| match json::find_entry(bytes, i, key)? {
+            Entry::Found(start) => json::value(bytes, start),
+            Entry::Missing => None,
+        };
+
+        let raw = |value: Option<&[u8]>| {
+            Box::from(value.map_or("null", |value| std::str::from_utf8(value).unwrap_or("null")))
+        };
+
+        let Some(user_id) = field(b"id").and_then(|id| std::str::from_utf8(id).ok()?.parse().ok())
+        else {
+            continue;
+        };
+
+        let global_ranks = RULESETS.map(|ruleset| {
+            let rank = match json::find_entry(bytes, i, b"statistics_rulesets") {
+                Some(Entry::Found(start)) => global_rank(bytes, start, ruleset),
+                _ => None,
+            };
+
+            raw(rank)
+        });
+
+        let user = CachedUser {
+            username: raw(field(b"username")),
+            country_code: raw(field(b"country_code")),
+            global_ranks,
+            fetched_at: now,
+        };
+
+        users.push((user_id, user));
+    }
+
+    Some(users)
+}
+
+/// The `global_rank` within `{"osu":{...},"taiko":{...},...}`.
+fn global_rank<'a>(bytes: &'a [u8], start: usize, ruleset: &str) -> Option<&'a [u8]> {
+    let Entry::Found(stats) = json::find_entry(bytes, start, ruleset.as_bytes())? else {
+        return None;
+    };
+
+    let Entry::Found(rank) = json::find_entry(bytes, stats, b"global_rank")? else {
+        return None;
+    };
+
+    json::value(bytes, rank)
+}
+
+/// Inserts the user info as last field of the score.
+fn add_user_info(score: &Score, user: &CachedUser) -> Option<Score> {
+    let bytes = score.bytes().trim_ascii_end().strip_suffix(b"}")?;
+
+    let global_rank = score
+        .ruleset_id()
+        .and_then(|id| user.global_ranks.get(usize::from(id)))
+        .map_or("null", AsRef::as_ref);
+
+    let mut enriched = Vec::with_capacity(bytes.len() + 96);
+    enriched.extend_from_slice(bytes);
+    enriched.extend_from_slice(br#","user_info":{"username":"#);
+    enriched.extend_from_slice(user.username.as_bytes());
+    enriched.extend_from_slice(br#","country_code":"#);
+    enriched.extend_from_slice(user.country_code.as_bytes());
+    enriched.extend_from_slice(br#","global_rank":"#);
+    enriched.extend_from_slice(global_rank.as_bytes());
+    enriched.extend_from_slice(b"}}");
+
+    Some(score.with_bytes(Bytes::from(enriched)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enrich() {
+        let response = br#"{"users":[{"avatar_url":"a","country_code":"DE","id":2,"username":"pep\"py","country":{"code":"DE"},"statistics_rulesets":{"osu":{"global_rank":null,"pp":0},"taiko":{"pp":1,"global_rank":123}}},{"id":3}]}"#;
+
+        let users = parse_users(response, Instant::now()).unwrap();
+        assert_eq!(users.len(), 2);
+
+        let (user_id, ref user) = users[0];
+        assert_eq!(user_id, 2);
+        assert_eq!(&*user.username, r#""pep\"py""#);
+        assert_eq!(&*user.global_ranks[1], "123");
+        assert_eq!(&*user.global_ranks[3], "null");
+
+        let score = Score::new(1, Bytes::from_static(br#"{"id":1,"ruleset_id":1} "#));
+        let enriched = add_user_info(&score, user).unwrap();
+        assert_eq!(
+            enriched.bytes().as_ref(),
+            br#"{"id":1,"ruleset_id":1,"user_info":{"username":"pep\"py","country_code":"DE","global_rank":123}}"#
+        );
+
+        assert!(parse_users(b"[]", Instant::now()).is_none());
+    }
+}
@@ -0,0 +1,114 @@
+use std::{io::Result as IoResult, path::PathBuf};
+
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    json,
+    osu::{Score, Scores},
+};
+
+/// Removes malformed scores before they're broadcasted.
+///
+/// Removed scores are logged and, if configured, appended to a quarantine
+/// file with one JSON object per line.
+pub struct Validator {
+    quarantine_file: Option<PathBuf>,
+}
+
+impl Validator {
+    pub const fn new(quarantine_file: Option<PathBuf>) -> Self {
+        Self { quarantine_file }
+    }
+
+    pub async fn remove_invalid(&self, scores: &mut Scores) {
+        let mut invalid = Vec::new();
+
+        scores.retain(|score| match validate(score) {
+            Ok(()) => true,
+            Err(reason) => {
+                let score_id = score.id();
+                warn!(score_id, reason, "Quarantining malformed score");
+                invalid.push(score.clone());
+
+                false
+            }
+        });
+
+        let Some(ref path) = self.quarantine_file else {
+            return;
+        };
+
+        if invalid.is_empty() {
+            return;
+        }
+
+        if let Err(err) = Self::append(path, &invalid).await {
+            warn!(?err, "Failed to write quarantined scores");
+        }
+    }
+
+    async fn append(path: &PathBuf, scores: &[Score]) -> IoResult<()> {
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+
+        let mut buf = Vec::new();
+
+        for score in scores {
+            buf.extend_from_slice(score.bytes());
+            buf.push(b'\n');
+        }
+
+        file.write_all(&buf).await
+    }
+}
+
+/// Returns why the score is malformed, if it is.
+fn validate(score: &Score) -> Result<(), &'static str> {
+    if !is_balanced(score.bytes()) {
+        Err("unbalanced json")
+    } else if score.user_id().is_none() {
+        Err("missing `user_id`")
+    } else if score.ruleset_id().is_none() {
+        Err("missing `ruleset_id`")
+    } else if score.ended_at().is_none() {
+        Err("missing or invalid `ended_at`")
+    } else {
+        Ok(())
+    }
+}
+
+/// Whether all braces and brackets outside of strings are closed and the
+/// object ends with its last byte.
+fn is_balanced(bytes: &[u8]) -> bool {
+    bytes.first() == Some(&b'{') && json::value_end(bytes, 0) == Some(bytes.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+
+    fn score(json: &'static str) -> Score {
+        Score::new(1, Bytes::from_static(json.as_bytes()))
+    }
+
+    #[test]
+    fn malformed() {
+        let valid = score(
+            r#"{"id":1,"user_id":2,"ruleset_id":0,"ended_at":"2025-01-09T12:34:56Z","mods":[{"acronym":"}"}]}"#,
+        );
+        assert_eq!(validate(&valid), Ok(()));
+
+        let truncated = score(
+            r#"{"id":1,"user_id":2,"ruleset_id":0,"ended_at":"2025-01-09T12:34:56Z","mods":[{"#,
+        );
+        assert_eq!(validate(&truncated), Err("unbalanced json"));
+
+        let missing = score(r#"{"id":1,"user_id":2,"ended_at":"2025-01-09T12:34:56Z"}"#);
+        assert_eq!(validate(&missing), Err("missing `ruleset_id`"));
+    }
+}
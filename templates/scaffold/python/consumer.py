@@ -0,0 +1,65 @@
+# Consumer for scores-ws, generated through `scores-ws scaffold python`.
+
+import asyncio
+import json
+import os
+from pathlib import Path
+
+from websockets.asyncio.client import connect
+from websockets.exceptions import WebSocketException
+
+URL = os.environ.get("SCORES_WS_URL", "{{url}}")
+KEY = os.environ.get("SCORES_WS_KEY")
+# The id of the last processed score is stored here so that a restarted
+# consumer resumes where it left off instead of missing or repeating scores.
+STATE_FILE = Path(os.environ.get("STATE_FILE", "last_score_id"))
+
+
+def load_last_score_id():
+    try:
+        return STATE_FILE.read_text().strip() or None
+    except FileNotFoundError:
+        return None
+
+
+def store_last_score_id(score_id):
+    tmp = STATE_FILE.with_suffix(".tmp")
+    tmp.write_text(str(score_id))
+    tmp.replace(STATE_FILE)
+
+
+def process(score):
+    # Replace this with your own processing
+    print(f"{score.get('user_id')} set score {score['id']}")
+
+
+async def run():
+    headers = {"Authorization": f"Bearer {KEY}"} if KEY else None
+    backoff = 1
+
+    while True:
+        try:
+            async with connect(URL, additional_headers=headers) as websocket:
+                # Must be sent within 5 seconds; resume if we processed
+                # scores before, otherwise start with new scores.
+                await websocket.send(load_last_score_id() or "connect")
+                backoff = 1
+
+                async for message in websocket:
+                    if isinstance(message, str):
+                        # Text messages are errors or notices, not scores
+                        print(f"scores-ws: {message}")
+                        continue
+
+                    score = json.loads(message)
+                    process(score)
+                    store_last_score_id(score["id"])
+        except (OSError, WebSocketException) as err:
+            print(f"Connection lost ({err}), reconnecting in {backoff}s...")
+
+        await asyncio.sleep(backoff)
+        backoff = min(backoff * 2, 60)
+
+
+if __name__ == "__main__":
+    asyncio.run(run())
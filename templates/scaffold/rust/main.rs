@@ -0,0 +1,90 @@
+//! Consumer for scores-ws, generated through `scores-ws scaffold rust`.
+
+use std::{env, time::Duration};
+
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Message};
+
+#[derive(Deserialize)]
+struct Score {
+    id: u64,
+    user_id: Option<u64>,
+}
+
+/// The id of the last processed score is stored in this file so that a
+/// restarted consumer resumes where it left off instead of missing or
+/// repeating scores.
+fn state_file() -> String {
+    env::var("STATE_FILE").unwrap_or_else(|_| "last_score_id".to_owned())
+}
+
+async fn load_last_score_id() -> Option<String> {
+    let content = tokio::fs::read_to_string(state_file()).await.ok()?;
+    let id = content.trim();
+
+    (!id.is_empty()).then(|| id.to_owned())
+}
+
+async fn store_last_score_id(id: u64) -> std::io::Result<()> {
+    let path = state_file();
+    let tmp = format!("{path}.tmp");
+    tokio::fs::write(&tmp, id.to_string()).await?;
+
+    tokio::fs::rename(tmp, path).await
+}
+
+fn process(score: &Score) {
+    // Replace this with your own processing
+    println!("{:?} set score {}", score.user_id, score.id);
+}
+
+async fn consume(url: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut req = url.into_client_request()?;
+
+    if let Ok(key) = env::var("SCORES_WS_KEY") {
+        req.headers_mut()
+            .insert("authorization", format!("Bearer {key}").parse()?);
+    }
+
+    let (ws_stream, _) = tokio_tungstenite::connect_async(req).await?;
+    let (mut write, mut read) = ws_stream.split();
+
+    // Must be sent within 5 seconds; resume if we processed scores before,
+    // otherwise start with new scores.
+    let initial = load_last_score_id()
+        .await
+        .unwrap_or_else(|| "connect".to_owned());
+    write.send(Message::from(initial)).await?;
+
+    while let Some(msg) = read.next().await {
+        match msg? {
+            Message::Binary(data) => {
+                let score: Score = serde_json::from_slice(&data)?;
+                process(&score);
+                store_last_score_id(score.id).await?;
+            }
+            // Text messages are errors or notices, not scores
+            Message::Text(text) => println!("scores-ws: {text}"),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let url = env::var("SCORES_WS_URL").unwrap_or_else(|_| "{{url}}".to_owned());
+    let mut backoff = 1;
+
+    loop {
+        match consume(&url).await {
+            Ok(()) => println!("Connection closed, reconnecting in {backoff}s..."),
+            Err(err) => println!("Connection lost ({err}), reconnecting in {backoff}s..."),
+        }
+
+        tokio::time::sleep(Duration::from_secs(backoff)).await;
+        backoff = (backoff * 2).min(60);
+    }
+}